@@ -0,0 +1,149 @@
+/*
+ * Copyright 2024 Google LLC
+ * SPDX-License-Identifier: MIT
+ */
+
+#include "hbm_capi.h"
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+static void
+die(const char *msg)
+{
+    fprintf(stderr, "%s\n", msg);
+    abort();
+}
+
+static struct hbmc_device_info
+get_device_info(void)
+{
+    uint32_t dev_count = hbmc_enumerate_devices(0, NULL);
+    printf("found %u render nodes\n", dev_count);
+
+    struct hbmc_device_info info = {
+        .struct_size = sizeof(info),
+        .backend = dev_count ? HBMC_BACKEND_VULKAN : HBMC_BACKEND_UDMABUF,
+        .name_template = "hbm-capi-test-{serial}",
+    };
+    if (dev_count)
+        hbmc_enumerate_devices(1, &info.dev);
+
+    return info;
+}
+
+static struct hbmc_device *
+create_device(void)
+{
+    const struct hbmc_device_info info = get_device_info();
+
+    struct hbmc_device *dev;
+    if (hbmc_device_create(&info, &dev) != HBMC_RESULT_SUCCESS)
+        die("failed to create device");
+
+    return dev;
+}
+
+static void
+reload_device(struct hbmc_device *dev)
+{
+    struct hbmc_device_info info = get_device_info();
+    info.name_template = "hbm-capi-reload-{serial}";
+    if (hbmc_device_reload(dev, &info) != HBMC_RESULT_SUCCESS)
+        die("failed to reload device");
+
+    /* a bad info leaves the device unchanged */
+    info.struct_size = 0;
+    if (hbmc_device_reload(dev, &info) == HBMC_RESULT_SUCCESS)
+        die("unexpectedly reloaded device");
+}
+
+static uint32_t
+find_memory_type(struct hbmc_bo *bo, uint32_t required)
+{
+    uint32_t mts[8];
+    const uint32_t mt_count = hbmc_bo_get_memory_types(bo, 8, mts);
+    for (uint32_t i = 0; i < mt_count; i++) {
+        if ((mts[i] & required) == required)
+            return mts[i];
+    }
+
+    die("failed to find memory type");
+    return 0;
+}
+
+static void
+test_buffer(struct hbmc_device *dev, bool gpu)
+{
+    const struct hbmc_description desc = {
+        .flags = HBMC_FLAG_EXTERNAL | HBMC_FLAG_MAP,
+        .usage = gpu ? HBMC_USAGE_GPU_TRANSFER : HBMC_USAGE_CPU_WRITE_OFTEN,
+    };
+    const union hbmc_extent extent = {
+        .buffer = { .size = 4096 },
+    };
+
+    struct hbmc_bo *bo;
+    if (hbmc_bo_create_with_constraint(dev, &desc, &extent, NULL, &bo) != HBMC_RESULT_SUCCESS)
+        die("failed to create bo");
+
+    const uint32_t mt = find_memory_type(bo, HBMC_MEMORY_TYPE_MAPPABLE);
+    if (hbmc_bo_bind_memory(bo, mt, -1) != HBMC_RESULT_SUCCESS)
+        die("failed to bind memory");
+
+    void *ptr;
+    if (hbmc_bo_map(bo, &ptr) != HBMC_RESULT_SUCCESS)
+        die("failed to map bo");
+    memset(ptr, 0x42, extent.buffer.size);
+    hbmc_bo_flush(bo, 0, 0);
+    hbmc_bo_unmap(bo);
+
+    int dmabuf;
+    if (hbmc_bo_export_dma_buf(bo, NULL, &dmabuf) != HBMC_RESULT_SUCCESS)
+        die("failed to export dma-buf");
+
+    struct hbmc_layout layout;
+    hbmc_bo_get_layout(bo, &layout);
+
+    struct hbmc_bo *imported;
+    if (hbmc_bo_create_with_layout(dev, &desc, &extent, &layout, dmabuf, &imported) !=
+        HBMC_RESULT_SUCCESS)
+        die("failed to create imported bo");
+    if (hbmc_bo_bind_memory(imported, find_memory_type(imported, HBMC_MEMORY_TYPE_MAPPABLE),
+                            dmabuf) != HBMC_RESULT_SUCCESS)
+        die("failed to import memory");
+
+    if (hbmc_bo_map(imported, &ptr) != HBMC_RESULT_SUCCESS)
+        die("failed to map imported bo");
+    hbmc_bo_invalidate(imported, 0, 0);
+    if (((const uint8_t *)ptr)[extent.buffer.size - 1] != 0x42)
+        die("unexpected imported bo content");
+    hbmc_bo_unmap(imported);
+
+    hbmc_bo_destroy(imported);
+    hbmc_bo_destroy(bo);
+}
+
+int
+main(void)
+{
+    uint32_t major;
+    uint32_t minor;
+    hbmc_get_api_version(&major, &minor);
+    if (major != HBMC_API_VERSION_MAJOR || minor < HBMC_API_VERSION_MINOR)
+        die("incompatible library version");
+
+    const bool gpu = hbmc_enumerate_devices(0, NULL) > 0;
+    struct hbmc_device *dev = create_device();
+
+    test_buffer(dev, gpu);
+
+    reload_device(dev);
+    test_buffer(dev, gpu);
+
+    hbmc_device_destroy(dev);
+
+    return 0;
+}
@@ -0,0 +1,1269 @@
+// Copyright 2024 Google LLC
+// SPDX-License-Identifier: MIT
+
+//! Stable C API.
+//!
+//! Implements a versioned C API.  Backward-compatible additions bump `HBMC_API_VERSION_MINOR`.
+//! Incompatible changes bump `HBMC_API_VERSION_MAJOR` and the soname of the shared library.
+//! Until version 1 is released, incompatible changes, such as `fixed_rate` of `hbmc_description`,
+//! are folded into version 1 instead.
+//!
+//! Functions that can fail return an `hbmc_result`, and their outputs are only written on
+//! success.  Device functions are thread-safe.  BO functions that bind, map, or unmap a BO require
+//! external synchronization on the BO.
+
+use std::collections::{hash_map::Entry, HashMap};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::sync::{Arc, Mutex};
+use std::{ffi, fs, mem, slice};
+
+/// The major version of the API.
+pub const HBMC_API_VERSION_MAJOR: u32 = 1;
+/// The minor version of the API.
+pub const HBMC_API_VERSION_MINOR: u32 = 0;
+
+/// The result of a function.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum hbmc_result {
+    /// The function succeeded.
+    Success = 0,
+    /// A parameter is invalid.
+    ErrorInvalid = -1,
+    /// The operation is unsupported.
+    ErrorUnsupported = -2,
+    /// A runtime device error.
+    ErrorDevice = -3,
+    /// A system IO error.
+    ErrorIo = -4,
+    /// An unknown error.
+    ErrorUnknown = -5,
+}
+
+/// The Vulkan backend.
+pub const HBMC_BACKEND_VULKAN: u32 = 0;
+/// The dma-heap backend.
+pub const HBMC_BACKEND_DMA_HEAP: u32 = 1;
+/// The udmabuf backend.
+pub const HBMC_BACKEND_UDMABUF: u32 = 2;
+
+// the backend of a device, validated from HBMC_BACKEND_*
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Backend {
+    Vulkan,
+    DmaHeap,
+    Udmabuf,
+}
+
+/// Enables `VK_EXT_debug_utils` message logging for the Vulkan backend.
+pub const HBMC_DEVICE_FLAG_DEBUG: u32 = 1 << 0;
+/// Accepts software implementations for the Vulkan backend.
+pub const HBMC_DEVICE_FLAG_ALLOW_SOFTWARE: u32 = 1 << 1;
+
+/// The default global priority of the driver for the copy queues of the Vulkan backend.
+pub const HBMC_QUEUE_PRIORITY_DEFAULT: u32 = 0;
+/// A low global priority for the copy queues of the Vulkan backend.
+pub const HBMC_QUEUE_PRIORITY_LOW: u32 = 1;
+/// A medium global priority for the copy queues of the Vulkan backend.
+pub const HBMC_QUEUE_PRIORITY_MEDIUM: u32 = 2;
+/// A high global priority for the copy queues of the Vulkan backend.  This can require privileges
+/// and is ignored without them.
+pub const HBMC_QUEUE_PRIORITY_HIGH: u32 = 3;
+/// A realtime global priority for the copy queues of the Vulkan backend.  This can require
+/// privileges and is ignored without them.
+pub const HBMC_QUEUE_PRIORITY_REALTIME: u32 = 4;
+
+/// The BO can be exported/imported.
+pub const HBMC_FLAG_EXTERNAL: u32 = 1 << 0;
+/// The BO can be mapped.
+pub const HBMC_FLAG_MAP: u32 = 1 << 1;
+/// The BO can be copied to or copied from.
+pub const HBMC_FLAG_COPY: u32 = 1 << 2;
+/// The BO must be on a protected heap.
+pub const HBMC_FLAG_PROTECTED: u32 = 1 << 3;
+/// The BO must not be compressed.
+pub const HBMC_FLAG_NO_COMPRESSION: u32 = 1 << 4;
+
+/// The BO is read by the CPU often.  Only meaningful for CPU-only backends.
+pub const HBMC_USAGE_CPU_READ_OFTEN: u64 = 1u64 << 0;
+/// The BO is read by the CPU rarely.  Only meaningful for CPU-only backends.
+pub const HBMC_USAGE_CPU_READ_RARELY: u64 = 1u64 << 1;
+/// The BO is written by the CPU often.  Only meaningful for CPU-only backends.
+pub const HBMC_USAGE_CPU_WRITE_OFTEN: u64 = 1u64 << 2;
+/// The BO is written by the CPU rarely.  Only meaningful for CPU-only backends.
+pub const HBMC_USAGE_CPU_WRITE_RARELY: u64 = 1u64 << 3;
+/// The BO can be used for GPU copies.
+pub const HBMC_USAGE_GPU_TRANSFER: u64 = 1u64 << 16;
+/// The BO can be used as a GPU uniform buffer.
+pub const HBMC_USAGE_GPU_UNIFORM: u64 = 1u64 << 17;
+/// The BO can be used as a GPU storage buffer or image.
+pub const HBMC_USAGE_GPU_STORAGE: u64 = 1u64 << 18;
+/// The BO can be used as a GPU sampled image.
+pub const HBMC_USAGE_GPU_SAMPLED: u64 = 1u64 << 19;
+/// The BO can be used as a GPU color image.
+pub const HBMC_USAGE_GPU_COLOR: u64 = 1u64 << 20;
+/// The BO can be scanned out.  This is only meaningful when HBM lacks modifier support.
+pub const HBMC_USAGE_GPU_SCANOUT_HACK: u64 = 1u64 << 21;
+/// The BO can be sampled with a sampler YCbCr conversion, and its planes can be viewed
+/// individually.
+pub const HBMC_USAGE_GPU_YCBCR_SAMPLER: u64 = 1u64 << 22;
+/// The BO can be viewed as sRGB.  This is only valid for UNORM formats with sRGB equivalents.
+pub const HBMC_USAGE_GPU_SRGB_VIEW: u64 = 1u64 << 23;
+/// The BO is exported and imported as an opaque fd for sharing between Vulkan devices.  This
+/// requires HBMC_FLAG_EXTERNAL.
+pub const HBMC_USAGE_GPU_OPAQUE_FD: u64 = 1u64 << 24;
+/// The BO is exported and imported as a dma-buf.  This requires HBMC_FLAG_EXTERNAL.
+pub const HBMC_USAGE_GPU_DMA_BUF: u64 = 1u64 << 25;
+
+/// The memory type is local to the device.
+pub const HBMC_MEMORY_TYPE_LOCAL: u32 = 1 << 0;
+/// The memory type is mappable.
+pub const HBMC_MEMORY_TYPE_MAPPABLE: u32 = 1 << 1;
+/// The memory type is coherent.
+pub const HBMC_MEMORY_TYPE_COHERENT: u32 = 1 << 2;
+/// The memory type is cached.
+pub const HBMC_MEMORY_TYPE_CACHED: u32 = 1 << 3;
+/// The memory type is protected.
+pub const HBMC_MEMORY_TYPE_PROTECTED: u32 = 1 << 4;
+
+/// The memory heap is local to the device.
+pub const HBMC_MEMORY_HEAP_LOCAL: u32 = 1 << 0;
+
+/// A hardware device.
+///
+/// This opaque struct represents a device.  There are module-level functions to query device info
+/// and allocate BOs from the device.
+#[repr(C)]
+pub struct hbmc_device {
+    _data: [u8; 0],
+}
+
+/// A hardware buffer object (BO).
+///
+/// This opaque struct represents a BO.  A BO can be allocated by HBM or imported from a dma-buf.
+/// A BO can only be manipulated with module-level functions.
+#[repr(C)]
+pub struct hbmc_bo {
+    _data: [u8; 0],
+}
+
+/// The parameters to create a device.
+///
+/// New fields will only be appended to the struct, and `struct_size` tells the library which
+/// fields are present.
+#[repr(C)]
+pub struct hbmc_device_info {
+    /// The size of the struct in bytes, `sizeof(struct hbmc_device_info)`.
+    pub struct_size: u32,
+    /// The backend of the device, one of `HBMC_BACKEND_*`.
+    pub backend: u32,
+    /// A bitmask of `HBMC_DEVICE_FLAG_*`.
+    pub flags: u32,
+    /// The device id (`st_rdev`) of the device to use, as returned by `hbmc_enumerate_devices`.
+    /// Only used by the Vulkan backend, where 0 selects the first device.
+    pub dev: libc::dev_t,
+    /// The name of the dma-heap.  Required by the dma-heap backend and ignored otherwise.
+    pub heap_name: *const ffi::c_char,
+    /// An optional template for the names of the exported dma-bufs.  `{process}`, `{usage}`, and
+    /// `{serial}` are expanded.
+    pub name_template: *const ffi::c_char,
+    /// The global priority of the copy queues, one of `HBMC_QUEUE_PRIORITY_*`.  Only used by the
+    /// Vulkan backend.
+    pub queue_priority: u32,
+}
+
+/// The description of a BO.
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+#[repr(C)]
+pub struct hbmc_description {
+    /// A bitmask of `HBMC_FLAG_*`.
+    pub flags: u32,
+
+    /// When the format is `DRM_FORMAT_INVALID`, the BO is a buffer.  Otherwise,
+    /// the BO is an image.
+    pub format: u32,
+
+    /// When the modifier is `DRM_FORMAT_MOD_INVALID`, HBM will pick the optimal modifier.
+    /// Otherwise, HBM will use the specified modifier.
+    pub modifier: u64,
+
+    /// A bitmask of `HBMC_USAGE_*`.  The Vulkan backend only accepts `HBMC_USAGE_GPU_*` and the
+    /// other backends only accept `HBMC_USAGE_CPU_*`.
+    pub usage: u64,
+
+    /// A bitmask of `VkImageCompressionFixedRateFlagBitsEXT`.  When non-zero, the BO must be an
+    /// image and is compressed at one of the fixed rates.
+    pub fixed_rate: u32,
+}
+
+/// The extent of a buffer BO.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct hbmc_extent_buffer {
+    /// Size of the buffer in bytes.
+    pub size: u64,
+}
+
+/// The extent of an image BO.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct hbmc_extent_image {
+    /// Width of the image in texels.
+    pub width: u32,
+    /// Height of the image in texels.
+    pub height: u32,
+}
+
+/// The extent of a BO.
+#[repr(C)]
+pub union hbmc_extent {
+    /// Used when the BO is a buffer.
+    pub buffer: hbmc_extent_buffer,
+    /// Used when the BO is an image.
+    pub image: hbmc_extent_image,
+}
+
+/// A BO allocation constraint.
+///
+/// A constraint describes additional requirements that the BO layout must follow.
+#[repr(C)]
+pub struct hbmc_constraint {
+    /// Alignment for plane offsets in bytes.
+    pub offset_align: u64,
+    /// Alignment for row strides in bytes.
+    pub stride_align: u64,
+    /// Alignment for plane sizes in bytes.
+    pub size_align: u64,
+
+    /// An optional array of allowed modifiers.
+    pub modifiers: *const u64,
+    /// The size of the modifier array.
+    pub modifier_count: u32,
+}
+
+/// The physical layout of a BO.
+#[repr(C)]
+pub struct hbmc_layout {
+    /// Size of the BO in bytes.
+    pub size: u64,
+    /// Modifier of the BO.  If the BO is a buffer, this is `DRM_FORMAT_MOD_INVALID`.
+    pub modifier: u64,
+    /// Memory plane count, which can be equal to or greater than the format plane count.  If the
+    /// BO is a buffer, this is 0.
+    pub plane_count: u32,
+    /// Plane offsets.
+    pub offsets: [u64; 4],
+    /// Plane row strides.
+    pub strides: [u64; 4],
+}
+
+/// The size and the budget of a memory heap.
+#[repr(C)]
+pub struct hbmc_memory_heap {
+    /// Size of the heap in bytes.
+    pub size: u64,
+    /// How much memory the process can use from the heap in bytes.  This is equal to `size` if
+    /// unknown.
+    pub budget: u64,
+    /// How much memory the process uses from the heap in bytes.  This is 0 if unknown.
+    pub usage: u64,
+    /// A bitmask of `HBMC_MEMORY_HEAP_*`.
+    pub flags: u32,
+}
+
+// helpers to convert parameters to/from C
+mod c {
+    use super::*;
+    use std::os::fd::{BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+
+    pub fn result_from(err: hbm::Error) -> hbmc_result {
+        match err {
+            hbm::Error::User | hbm::Error::IntegerConversion | hbm::Error::StringConversion => {
+                hbmc_result::ErrorInvalid
+            }
+            hbm::Error::Unsupported => hbmc_result::ErrorUnsupported,
+            hbm::Error::Device | hbm::Error::TooManyAllocations | hbm::Error::OutOfDeviceMemory => {
+                hbmc_result::ErrorDevice
+            }
+            hbm::Error::Io(_) => hbmc_result::ErrorIo,
+            _ => hbmc_result::ErrorUnknown,
+        }
+    }
+
+    pub fn result_into(res: hbm::Result<()>) -> hbmc_result {
+        match res {
+            Ok(()) => hbmc_result::Success,
+            Err(err) => result_from(err),
+        }
+    }
+
+    pub fn out_ptr<'a, T>(out: *mut T) -> hbm::Result<&'a mut T> {
+        // SAFETY: out is NULL or valid
+        unsafe { out.as_mut() }.ok_or(hbm::Error::User)
+    }
+
+    pub fn in_ptr<'a, T>(val: *const T) -> hbm::Result<&'a T> {
+        // SAFETY: val is NULL or valid
+        unsafe { val.as_ref() }.ok_or(hbm::Error::User)
+    }
+
+    pub fn dev_ret(dev: CDevice) -> *mut hbmc_device {
+        Box::into_raw(Box::new(dev)) as *mut hbmc_device
+    }
+
+    pub fn dev_take(dev: *mut hbmc_device) -> Option<Box<CDevice>> {
+        if dev.is_null() {
+            return None;
+        }
+
+        // SAFETY: dev was created by dev_ret
+        Some(unsafe { Box::from_raw(dev as *mut CDevice) })
+    }
+
+    pub fn dev_borrow<'a>(dev: *mut hbmc_device) -> hbm::Result<&'a CDevice> {
+        in_ptr(dev as *const CDevice)
+    }
+
+    pub fn bo_ret(bo: hbm::Bo) -> *mut hbmc_bo {
+        Box::into_raw(Box::new(bo)) as *mut hbmc_bo
+    }
+
+    pub fn bo_take(bo: *mut hbmc_bo) -> Option<Box<hbm::Bo>> {
+        if bo.is_null() {
+            return None;
+        }
+
+        // SAFETY: bo was created by bo_ret
+        Some(unsafe { Box::from_raw(bo as *mut hbm::Bo) })
+    }
+
+    pub fn bo_borrow<'a>(bo: *mut hbmc_bo) -> hbm::Result<&'a hbm::Bo> {
+        in_ptr(bo as *const hbm::Bo)
+    }
+
+    pub fn bo_borrow_mut<'a>(bo: *mut hbmc_bo) -> hbm::Result<&'a mut hbm::Bo> {
+        out_ptr(bo as *mut hbm::Bo)
+    }
+
+    pub fn backend_from(c_backend: u32) -> hbm::Result<Backend> {
+        let backend = match c_backend {
+            HBMC_BACKEND_VULKAN => Backend::Vulkan,
+            HBMC_BACKEND_DMA_HEAP => Backend::DmaHeap,
+            HBMC_BACKEND_UDMABUF => Backend::Udmabuf,
+            _ => return Err(hbm::Error::User),
+        };
+
+        Ok(backend)
+    }
+
+    pub fn queue_priority_from(c_priority: u32) -> hbm::Result<Option<hbm::vulkan::QueuePriority>> {
+        let priority = match c_priority {
+            HBMC_QUEUE_PRIORITY_DEFAULT => None,
+            HBMC_QUEUE_PRIORITY_LOW => Some(hbm::vulkan::QueuePriority::Low),
+            HBMC_QUEUE_PRIORITY_MEDIUM => Some(hbm::vulkan::QueuePriority::Medium),
+            HBMC_QUEUE_PRIORITY_HIGH => Some(hbm::vulkan::QueuePriority::High),
+            HBMC_QUEUE_PRIORITY_REALTIME => Some(hbm::vulkan::QueuePriority::Realtime),
+            _ => return Err(hbm::Error::User),
+        };
+
+        Ok(priority)
+    }
+
+    pub fn flags_from(c_flags: u32) -> hbm::Result<hbm::Flags> {
+        let mut flags = hbm::Flags::empty();
+        for (c_flag, flag) in [
+            (HBMC_FLAG_EXTERNAL, hbm::Flags::EXTERNAL),
+            (HBMC_FLAG_MAP, hbm::Flags::MAP),
+            (HBMC_FLAG_COPY, hbm::Flags::COPY),
+            (HBMC_FLAG_PROTECTED, hbm::Flags::PROTECTED),
+            (HBMC_FLAG_NO_COMPRESSION, hbm::Flags::NO_COMPRESSION),
+        ] {
+            if (c_flags & c_flag) > 0 {
+                flags |= flag;
+            }
+        }
+
+        if c_flags >> 5 != 0 {
+            return Err(hbm::Error::User);
+        }
+
+        Ok(flags)
+    }
+
+    const CPU_USAGE_MASK: u64 = 0xffff;
+
+    pub fn usage_from(c_usage: u64, backend: Backend) -> hbm::Result<hbm::Usage> {
+        let (c_cpu_usage, c_gpu_usage) = (c_usage & CPU_USAGE_MASK, c_usage & !CPU_USAGE_MASK);
+
+        let usage = if backend == Backend::Vulkan {
+            if c_cpu_usage != 0 {
+                return Err(hbm::Error::User);
+            }
+
+            let mut vk_usage = hbm::vulkan::Usage::empty();
+            for (c_bit, bit) in [
+                (HBMC_USAGE_GPU_TRANSFER, hbm::vulkan::Usage::TRANSFER),
+                (HBMC_USAGE_GPU_UNIFORM, hbm::vulkan::Usage::UNIFORM),
+                (HBMC_USAGE_GPU_STORAGE, hbm::vulkan::Usage::STORAGE),
+                (HBMC_USAGE_GPU_SAMPLED, hbm::vulkan::Usage::SAMPLED),
+                (HBMC_USAGE_GPU_COLOR, hbm::vulkan::Usage::COLOR),
+                (
+                    HBMC_USAGE_GPU_SCANOUT_HACK,
+                    hbm::vulkan::Usage::SCANOUT_HACK,
+                ),
+                (
+                    HBMC_USAGE_GPU_YCBCR_SAMPLER,
+                    hbm::vulkan::Usage::YCBCR_SAMPLER,
+                ),
+                (HBMC_USAGE_GPU_SRGB_VIEW, hbm::vulkan::Usage::SRGB_VIEW),
+                (HBMC_USAGE_GPU_OPAQUE_FD, hbm::vulkan::Usage::OPAQUE_FD),
+                (HBMC_USAGE_GPU_DMA_BUF, hbm::vulkan::Usage::DMA_BUF),
+            ] {
+                if (c_gpu_usage & c_bit) > 0 {
+                    vk_usage |= bit;
+                }
+            }
+
+            hbm::Usage::Vulkan(vk_usage)
+        } else {
+            if c_gpu_usage != 0 {
+                return Err(hbm::Error::User);
+            }
+
+            let cpu_usage = hbm::CpuUsage::from_bits(c_cpu_usage as u32).ok_or(hbm::Error::User)?;
+
+            hbm::Usage::Cpu(cpu_usage)
+        };
+
+        Ok(usage)
+    }
+
+    pub fn array_copy_out<T, U, F>(out: *mut T, max: u32, vals: Vec<U>, f: F) -> u32
+    where
+        F: Fn(U) -> T,
+    {
+        let count = vals.len() as u32;
+        if max == 0 || out.is_null() {
+            return count;
+        }
+
+        let count = count.min(max);
+
+        // SAFETY: out is large enough for count elements
+        let out = unsafe { slice::from_raw_parts_mut(out, count as usize) };
+
+        for (dst, src) in out.iter_mut().zip(vals) {
+            *dst = f(src);
+        }
+
+        count
+    }
+
+    pub fn extent_from(extent: *const hbmc_extent, fmt: u32) -> hbm::Result<hbm::Extent> {
+        let extent = in_ptr(extent)?;
+
+        const DRM_FORMAT_INVALID: u32 = 0;
+        let extent = if fmt == DRM_FORMAT_INVALID {
+            // SAFETY: extent is for a buffer
+            let buf = unsafe { extent.buffer };
+            hbm::Extent::Buffer(buf.size)
+        } else {
+            // SAFETY: extent is for an image
+            let img = unsafe { extent.image };
+            hbm::Extent::Image(img.width, img.height)
+        };
+
+        Ok(extent)
+    }
+
+    pub fn con_optional_from(con: *const hbmc_constraint) -> Option<hbm::Constraint> {
+        // SAFETY: con is NULL or valid
+        let con = unsafe { con.as_ref() }?;
+
+        let mut hbm_con = hbm::Constraint::new()
+            .offset_align(con.offset_align)
+            .stride_align(con.stride_align)
+            .size_align(con.size_align);
+        if con.modifier_count > 0 {
+            // SAFETY: con.modifiers is large enough for con.modifier_count modifiers
+            let mods = unsafe { slice::from_raw_parts(con.modifiers, con.modifier_count as usize) };
+            let mods: Vec<hbm::Modifier> = mods.iter().copied().map(hbm::Modifier::from).collect();
+            hbm_con = hbm_con.modifiers(mods);
+        }
+
+        Some(hbm_con)
+    }
+
+    pub fn layout_from(layout: *const hbmc_layout) -> hbm::Result<hbm::Layout> {
+        let layout = in_ptr(layout)?;
+
+        let layout = hbm::Layout::new()
+            .size(layout.size)
+            .modifier(hbm::Modifier(layout.modifier))
+            .plane_count(layout.plane_count)
+            .offsets(layout.offsets)
+            .strides(layout.strides);
+
+        Ok(layout)
+    }
+
+    pub fn layout_into(layout: hbm::Layout) -> hbmc_layout {
+        hbmc_layout {
+            size: layout.size,
+            modifier: layout.modifier.0,
+            plane_count: layout.plane_count,
+            offsets: layout.offsets,
+            strides: layout.strides,
+        }
+    }
+
+    pub fn heap_into(heap: hbm::MemoryHeap) -> hbmc_memory_heap {
+        hbmc_memory_heap {
+            size: heap.size,
+            budget: heap.budget,
+            usage: heap.usage,
+            flags: if heap.local {
+                HBMC_MEMORY_HEAP_LOCAL
+            } else {
+                0
+            },
+        }
+    }
+
+    pub fn range_size(bo: &hbm::Bo, offset: u64, size: u64) -> hbm::Result<u64> {
+        if size > 0 {
+            return Ok(size);
+        }
+
+        bo.layout().size.checked_sub(offset).ok_or(hbm::Error::User)
+    }
+
+    pub fn mt_from(c_mt: u32) -> hbm::MemoryType {
+        let mut mt = hbm::MemoryType::empty();
+        if (c_mt & HBMC_MEMORY_TYPE_LOCAL) > 0 {
+            mt |= hbm::MemoryType::LOCAL;
+        }
+        if (c_mt & HBMC_MEMORY_TYPE_MAPPABLE) > 0 {
+            mt |= hbm::MemoryType::MAPPABLE;
+        }
+        if (c_mt & HBMC_MEMORY_TYPE_COHERENT) > 0 {
+            mt |= hbm::MemoryType::COHERENT;
+        }
+        if (c_mt & HBMC_MEMORY_TYPE_CACHED) > 0 {
+            mt |= hbm::MemoryType::CACHED;
+        }
+        if (c_mt & HBMC_MEMORY_TYPE_PROTECTED) > 0 {
+            mt |= hbm::MemoryType::PROTECTED;
+        }
+
+        mt
+    }
+
+    pub fn mt_into(mt: hbm::MemoryType) -> u32 {
+        let mut c_mt = 0;
+        if mt.contains(hbm::MemoryType::LOCAL) {
+            c_mt |= HBMC_MEMORY_TYPE_LOCAL;
+        }
+        if mt.contains(hbm::MemoryType::MAPPABLE) {
+            c_mt |= HBMC_MEMORY_TYPE_MAPPABLE;
+        }
+        if mt.contains(hbm::MemoryType::COHERENT) {
+            c_mt |= HBMC_MEMORY_TYPE_COHERENT;
+        }
+        if mt.contains(hbm::MemoryType::CACHED) {
+            c_mt |= HBMC_MEMORY_TYPE_CACHED;
+        }
+        if mt.contains(hbm::MemoryType::PROTECTED) {
+            c_mt |= HBMC_MEMORY_TYPE_PROTECTED;
+        }
+
+        c_mt
+    }
+
+    pub fn fd_borrow<'a>(fd: RawFd) -> Option<BorrowedFd<'a>> {
+        if fd < 0 {
+            return None;
+        }
+
+        // SAFETY: fd is valid
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        Some(fd)
+    }
+
+    pub fn fd_optional_from(fd: RawFd) -> Option<OwnedFd> {
+        if fd < 0 {
+            return None;
+        }
+
+        // SAFETY: fd is valid
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Some(fd)
+    }
+
+    pub fn fd_into(fd: OwnedFd) -> RawFd {
+        fd.into_raw_fd()
+    }
+
+    pub fn str_optional_from<'a>(s: *const ffi::c_char) -> hbm::Result<Option<&'a str>> {
+        if s.is_null() {
+            return Ok(None);
+        }
+
+        // SAFETY: s is valid
+        let s = unsafe { ffi::CStr::from_ptr(s) };
+
+        s.to_str()
+            .map(Some)
+            .map_err(|_| hbm::Error::StringConversion)
+    }
+}
+
+type ClassCache = HashMap<hbmc_description, Arc<hbm::Class>>;
+
+// the parameters to build an hbm::Device, owned such that the device can be rebuilt
+struct DeviceConfig {
+    backend: Backend,
+    flags: u32,
+    dev: libc::dev_t,
+    heap_name: Option<String>,
+    name_template: Option<String>,
+    queue_priority: Option<hbm::vulkan::QueuePriority>,
+}
+
+impl DeviceConfig {
+    fn new(info: &hbmc_device_info) -> hbm::Result<Self> {
+        // all fields are known to the first version of the struct
+        if (info.struct_size as usize) < mem::size_of::<hbmc_device_info>() {
+            return Err(hbm::Error::User);
+        }
+
+        let backend = c::backend_from(info.backend)?;
+        let valid_flags = HBMC_DEVICE_FLAG_DEBUG | HBMC_DEVICE_FLAG_ALLOW_SOFTWARE;
+        if info.flags & !valid_flags != 0 {
+            return Err(hbm::Error::User);
+        }
+
+        let heap_name = c::str_optional_from(info.heap_name)?.map(String::from);
+        if backend == Backend::DmaHeap && heap_name.is_none() {
+            return Err(hbm::Error::User);
+        }
+
+        let config = Self {
+            backend,
+            flags: info.flags,
+            dev: info.dev,
+            heap_name,
+            name_template: c::str_optional_from(info.name_template)?.map(String::from),
+            queue_priority: c::queue_priority_from(info.queue_priority)?,
+        };
+
+        Ok(config)
+    }
+
+    fn build(&self) -> hbm::Result<Arc<hbm::Device>> {
+        let mut builder = hbm::Builder::new();
+        builder = match self.backend {
+            Backend::Vulkan => {
+                let mut backend = hbm::vulkan::Builder::new()
+                    .debug((self.flags & HBMC_DEVICE_FLAG_DEBUG) > 0)
+                    .allow_software((self.flags & HBMC_DEVICE_FLAG_ALLOW_SOFTWARE) > 0);
+                if self.dev != 0 {
+                    backend = backend.device_id(self.dev as _);
+                }
+                if let Some(priority) = self.queue_priority {
+                    backend = backend.queue_priority(priority);
+                }
+                builder.add_backend(backend.build()?)
+            }
+            Backend::DmaHeap => {
+                let heap_name = self.heap_name.as_deref().ok_or(hbm::Error::User)?;
+                let backend = hbm::dma_heap::Builder::new().heap_name(heap_name);
+                builder.add_backend(backend.build()?)
+            }
+            Backend::Udmabuf => builder.add_backend(hbm::udmabuf::Builder::new().build()?),
+        };
+
+        if let Some(template) = &self.name_template {
+            builder = builder.name_template(template);
+        }
+
+        builder.build()
+    }
+}
+
+// the config, the device, and its classes, which are replaced together on reload
+struct DeviceState {
+    config: DeviceConfig,
+    device: Arc<hbm::Device>,
+    class_cache: ClassCache,
+}
+
+impl DeviceState {
+    fn new(config: DeviceConfig) -> hbm::Result<Self> {
+        let state = Self {
+            device: config.build()?,
+            config,
+            class_cache: HashMap::new(),
+        };
+
+        Ok(state)
+    }
+}
+
+struct CDevice {
+    state: Mutex<DeviceState>,
+}
+
+impl CDevice {
+    fn device(&self) -> Arc<hbm::Device> {
+        self.state.lock().unwrap().device.clone()
+    }
+
+    fn classify(
+        device: &hbm::Device,
+        backend: Backend,
+        desc: &hbmc_description,
+    ) -> hbm::Result<hbm::Class> {
+        let usage = c::usage_from(desc.usage, backend)?;
+        let desc = hbm::Description::new()
+            .flags(c::flags_from(desc.flags)?)
+            .format(hbm::Format(desc.format))
+            .modifier(hbm::Modifier(desc.modifier))
+            .fixed_rate(hbm::FixedRate::from_bits(desc.fixed_rate).ok_or(hbm::Error::User)?);
+
+        device.classify(desc, slice::from_ref(&usage))
+    }
+
+    // returns the class along with the device that the class belongs to
+    fn get_class(
+        &self,
+        desc: hbmc_description,
+    ) -> hbm::Result<(Arc<hbm::Device>, Arc<hbm::Class>)> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let device = state.device.clone();
+        let class = match state.class_cache.entry(desc) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let class = Self::classify(&device, state.config.backend, e.key())?;
+                e.insert(Arc::new(class))
+            }
+        };
+
+        Ok((device, class.clone()))
+    }
+
+    fn reload(&self, info: &hbmc_device_info) -> hbm::Result<()> {
+        // build the new state before replacing the current one
+        let state = DeviceState::new(DeviceConfig::new(info)?)?;
+        *self.state.lock().unwrap() = state;
+
+        Ok(())
+    }
+}
+
+fn build_device(info: &hbmc_device_info) -> hbm::Result<CDevice> {
+    let state = DeviceState::new(DeviceConfig::new(info)?)?;
+    let dev = CDevice {
+        state: Mutex::new(state),
+    };
+
+    Ok(dev)
+}
+
+/// Queries the version of the API implemented by the library.
+///
+/// The major version must match `HBMC_API_VERSION_MAJOR` and the minor version must be equal to
+/// or greater than `HBMC_API_VERSION_MINOR` for the header to be compatible with the library.
+///
+/// # Safety
+///
+/// `out_major` and `out_minor` must be non-NULL.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_get_api_version(out_major: *mut u32, out_minor: *mut u32) {
+    // SAFETY: out_major is non-NULL
+    unsafe { *out_major = HBMC_API_VERSION_MAJOR };
+    // SAFETY: out_minor is non-NULL
+    unsafe { *out_minor = HBMC_API_VERSION_MINOR };
+}
+
+/// Enumerates the device ids (`st_rdev`) of the DRM render nodes.
+///
+/// If `dev_max` is 0, the number of render nodes is returned.  Otherwise, the number of device
+/// ids written to `out_devs` is returned.
+///
+/// # Safety
+///
+/// `out_devs` must point to an array of at least `dev_max` device ids.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_enumerate_devices(dev_max: u32, out_devs: *mut libc::dev_t) -> u32 {
+    let mut devs: Vec<(String, libc::dev_t)> = fs::read_dir("/dev/dri")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with("renderD") {
+                return None;
+            }
+
+            let metadata = fs::metadata(entry.path()).ok()?;
+            if !metadata.file_type().is_char_device() {
+                return None;
+            }
+
+            Some((name, metadata.rdev() as libc::dev_t))
+        })
+        .collect();
+    devs.sort();
+
+    c::array_copy_out(out_devs, dev_max, devs, |(_, dev)| dev)
+}
+
+/// Creates a device.
+///
+/// # Safety
+///
+/// `info` must be valid.  `out_dev` must be non-NULL.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_device_create(
+    info: *const hbmc_device_info,
+    out_dev: *mut *mut hbmc_device,
+) -> hbmc_result {
+    let res = c::in_ptr(info).and_then(build_device).map(|dev| {
+        // SAFETY: out_dev is non-NULL
+        unsafe { *out_dev = c::dev_ret(dev) };
+    });
+
+    c::result_into(res)
+}
+
+/// Destroys a device.
+///
+/// BOs created from the device remain valid and keep the underlying device alive.
+///
+/// # Safety
+///
+/// `dev` must be NULL or valid.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_device_destroy(dev: *mut hbmc_device) {
+    let _ = c::dev_take(dev);
+}
+
+/// Reloads a device with new parameters.
+///
+/// The device is rebuilt from `info`, as if it was created by `hbmc_device_create`, and cached BO
+/// classes are discarded.  This allows tuning the configuration of a running device during
+/// bring-up.  BOs created before the reload remain valid and keep the previous underlying device
+/// alive.  On failure, the device is unchanged.
+///
+/// # Safety
+///
+/// `dev` and `info` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_device_reload(
+    dev: *mut hbmc_device,
+    info: *const hbmc_device_info,
+) -> hbmc_result {
+    let res = c::dev_borrow(dev).and_then(|dev| c::in_ptr(info).and_then(|info| dev.reload(info)));
+
+    c::result_into(res)
+}
+
+/// Queries the memory plane count for the specified format modifier.  Returns 0 if the format or
+/// the modifier is not supported.
+///
+/// # Safety
+///
+/// `dev` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_device_get_plane_count(
+    dev: *mut hbmc_device,
+    fmt: u32,
+    modifier: u64,
+) -> u32 {
+    let Ok(dev) = c::dev_borrow(dev) else {
+        return 0;
+    };
+
+    dev.device()
+        .memory_plane_count(hbm::Format(fmt), hbm::Modifier(modifier))
+        .unwrap_or(0)
+}
+
+/// Queries supported modifiers for a BO description.
+///
+/// If the BO description is not supported or refers to a buffer, there is no supported modifier
+/// and 0 is always returned.
+///
+/// If `mod_max` is 0, the number of supported modifiers is returned.  Otherwise, the number of
+/// supported modifiers written to `out_mods` is returned.
+///
+/// # Safety
+///
+/// `dev` and `desc` must be valid.
+///
+/// `out_mods` must point to an array of at least `mod_max` modifiers.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_device_get_modifiers(
+    dev: *mut hbmc_device,
+    desc: *const hbmc_description,
+    mod_max: u32,
+    out_mods: *mut u64,
+) -> u32 {
+    let Ok(dev) = c::dev_borrow(dev) else {
+        return 0;
+    };
+    let Ok((device, class)) = c::in_ptr(desc).and_then(|desc| dev.get_class(*desc)) else {
+        return 0;
+    };
+
+    let mods = device.modifiers(&class).to_vec();
+    c::array_copy_out(out_mods, mod_max, mods, |m| m.0)
+}
+
+/// Queries the memory heaps of a device.
+///
+/// The budget and usage of each memory heap are queried anew on each call.
+///
+/// If `heap_max` is 0, the number of memory heaps is returned.  Otherwise, the number of memory
+/// heaps written to `out_heaps` is returned.
+///
+/// # Safety
+///
+/// `dev` must be valid.
+///
+/// `out_heaps` must point to an array of at least `heap_max` memory heaps.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_device_get_memory_heaps(
+    dev: *mut hbmc_device,
+    heap_max: u32,
+    out_heaps: *mut hbmc_memory_heap,
+) -> u32 {
+    let Ok(dev) = c::dev_borrow(dev) else {
+        return 0;
+    };
+
+    let heaps = dev.device().memory_heaps();
+    c::array_copy_out(out_heaps, heap_max, heaps, c::heap_into)
+}
+
+/// Creates a BO with a constraint.
+///
+/// `con` is optional.  The BO has no memory bound until `hbmc_bo_bind_memory` is called.
+///
+/// # Safety
+///
+/// `dev`, `desc`, and `extent` must be valid.  If non-NULL, `con` must be valid.
+///
+/// `out_bo` must be non-NULL.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_create_with_constraint(
+    dev: *mut hbmc_device,
+    desc: *const hbmc_description,
+    extent: *const hbmc_extent,
+    con: *const hbmc_constraint,
+    out_bo: *mut *mut hbmc_bo,
+) -> hbmc_result {
+    let res = || -> hbm::Result<()> {
+        let dev = c::dev_borrow(dev)?;
+        let desc = *c::in_ptr(desc)?;
+        let (device, class) = dev.get_class(desc)?;
+        let extent = c::extent_from(extent, desc.format)?;
+        let con = c::con_optional_from(con);
+
+        let bo = hbm::Bo::with_constraint(device, &class, extent, con)?;
+        *c::out_ptr(out_bo)? = c::bo_ret(bo);
+
+        Ok(())
+    }();
+
+    c::result_into(res)
+}
+
+/// Creates a BO with an explicit layout.
+///
+/// This is typically followed by `hbmc_bo_bind_memory` to import a dma-buf.  If `dmabuf` is
+/// non-negative, it restricts the supported memory types.  Ownership of `dmabuf` is never
+/// transferred.
+///
+/// # Safety
+///
+/// `dev`, `desc`, `extent`, and `layout` must be valid.  `out_bo` must be non-NULL.
+///
+/// If `dmabuf` is non-negative, it must be a valid dma-buf.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_create_with_layout(
+    dev: *mut hbmc_device,
+    desc: *const hbmc_description,
+    extent: *const hbmc_extent,
+    layout: *const hbmc_layout,
+    dmabuf: i32,
+    out_bo: *mut *mut hbmc_bo,
+) -> hbmc_result {
+    let res = || -> hbm::Result<()> {
+        let dev = c::dev_borrow(dev)?;
+        let desc = *c::in_ptr(desc)?;
+        let (device, class) = dev.get_class(desc)?;
+        let extent = c::extent_from(extent, desc.format)?;
+        let layout = c::layout_from(layout)?;
+        let dmabuf = c::fd_borrow(dmabuf);
+
+        let bo = hbm::Bo::with_layout(device, &class, extent, layout, dmabuf)?;
+        *c::out_ptr(out_bo)? = c::bo_ret(bo);
+
+        Ok(())
+    }();
+
+    c::result_into(res)
+}
+
+/// Destroys a BO.
+///
+/// # Safety
+///
+/// `bo` must be NULL or valid.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_destroy(bo: *mut hbmc_bo) {
+    let _ = c::bo_take(bo);
+}
+
+/// Queries the physical layout of a BO.
+///
+/// # Safety
+///
+/// `bo` must be valid.  `out_layout` must be non-NULL.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_get_layout(
+    bo: *mut hbmc_bo,
+    out_layout: *mut hbmc_layout,
+) -> hbmc_result {
+    let res = c::bo_borrow(bo).and_then(|bo| {
+        *c::out_ptr(out_layout)? = c::layout_into(bo.layout());
+        Ok(())
+    });
+
+    c::result_into(res)
+}
+
+/// Queries supported memory types of a BO.
+///
+/// Each memory type is a bitmask of `HBMC_MEMORY_TYPE_*`.  If `mt_max` is 0, the number of
+/// supported memory types is returned.  Otherwise, the number of supported memory types written
+/// to `out_mts` is returned.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+///
+/// `out_mts` must point to an array of at least `mt_max` memory types.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_get_memory_types(
+    bo: *mut hbmc_bo,
+    mt_max: u32,
+    out_mts: *mut u32,
+) -> u32 {
+    let Ok(bo) = c::bo_borrow(bo) else {
+        return 0;
+    };
+
+    let mts = bo.memory_types();
+    c::array_copy_out(out_mts, mt_max, mts, c::mt_into)
+}
+
+/// Binds a memory to a BO.
+///
+/// `mt` must be one of the memory types returned by `hbmc_bo_get_memory_types`.  If `dmabuf` is
+/// negative, the memory is allocated.  Otherwise, the BO must have `HBMC_FLAG_EXTERNAL` and the
+/// memory is imported from `dmabuf`.  Ownership of `dmabuf` is always transferred.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+///
+/// If `dmabuf` is non-negative, it must be a valid dma-buf.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_bind_memory(
+    bo: *mut hbmc_bo,
+    mt: u32,
+    dmabuf: i32,
+) -> hbmc_result {
+    // take ownership first such that dmabuf is closed on errors
+    let dmabuf = c::fd_optional_from(dmabuf);
+    let mt = c::mt_from(mt);
+
+    let res = c::bo_borrow_mut(bo).and_then(|bo| bo.bind_memory(mt, dmabuf));
+
+    c::result_into(res)
+}
+
+/// Exports a dma-buf from a BO.
+///
+/// The BO must have `HBMC_FLAG_EXTERNAL` and must have a memory bound.  `name` is optional and
+/// overrides the name template of the device.
+///
+/// # Safety
+///
+/// `bo` must be valid.  `out_dmabuf` must be non-NULL.
+///
+/// If `name` is non-NULL, it must be a valid C-string.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_export_dma_buf(
+    bo: *mut hbmc_bo,
+    name: *const ffi::c_char,
+    out_dmabuf: *mut i32,
+) -> hbmc_result {
+    let res = || -> hbm::Result<()> {
+        let bo = c::bo_borrow(bo)?;
+        let name = c::str_optional_from(name)?;
+        let out_dmabuf = c::out_ptr(out_dmabuf)?;
+
+        *out_dmabuf = c::fd_into(bo.export_dma_buf(name)?);
+
+        Ok(())
+    }();
+
+    c::result_into(res)
+}
+
+/// Maps a BO for direct CPU access.
+///
+/// The BO must have `HBMC_FLAG_MAP` and must have an `HBMC_MEMORY_TYPE_MAPPABLE` memory bound.
+///
+/// # Safety
+///
+/// `bo` must be valid.  `out_ptr` must be non-NULL.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_map(
+    bo: *mut hbmc_bo,
+    out_ptr: *mut *mut ffi::c_void,
+) -> hbmc_result {
+    let res = || -> hbm::Result<()> {
+        let bo = c::bo_borrow_mut(bo)?;
+        let out_ptr = c::out_ptr(out_ptr)?;
+
+        *out_ptr = bo.map()?.into_raw().ptr.as_ptr();
+
+        Ok(())
+    }();
+
+    c::result_into(res)
+}
+
+/// Unmaps a mapped BO.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_unmap(bo: *mut hbmc_bo) {
+    if let Ok(bo) = c::bo_borrow_mut(bo) {
+        bo.unmap();
+    }
+}
+
+/// Flushes the CPU cache for a range of a non-coherent BO.
+///
+/// If `size` is 0, the range extends to the end of the BO.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_flush(bo: *mut hbmc_bo, offset: u64, size: u64) -> hbmc_result {
+    let res = c::bo_borrow(bo).and_then(|bo| {
+        let size = c::range_size(bo, offset, size)?;
+        bo.flush_range(offset, size)
+    });
+
+    c::result_into(res)
+}
+
+/// Invalidates the CPU cache for a range of a non-coherent BO.
+///
+/// If `size` is 0, the range extends to the end of the BO.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn hbmc_bo_invalidate(
+    bo: *mut hbmc_bo,
+    offset: u64,
+    size: u64,
+) -> hbmc_result {
+    let res = c::bo_borrow(bo).and_then(|bo| {
+        let size = c::range_size(bo, offset, size)?;
+        bo.invalidate_range(offset, size)
+    });
+
+    c::result_into(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_from() {
+        let usage = c::usage_from(HBMC_USAGE_GPU_SAMPLED, Backend::Vulkan).unwrap();
+        assert_eq!(usage, hbm::Usage::Vulkan(hbm::vulkan::Usage::SAMPLED));
+        assert!(c::usage_from(HBMC_USAGE_CPU_READ_OFTEN, Backend::Vulkan).is_err());
+
+        let usage = c::usage_from(HBMC_USAGE_GPU_OPAQUE_FD, Backend::Vulkan).unwrap();
+        assert_eq!(usage, hbm::Usage::Vulkan(hbm::vulkan::Usage::OPAQUE_FD));
+
+        let usage = c::usage_from(HBMC_USAGE_CPU_READ_OFTEN, Backend::Udmabuf).unwrap();
+        assert_eq!(usage, hbm::Usage::Cpu(hbm::CpuUsage::READ_OFTEN));
+        assert!(c::usage_from(HBMC_USAGE_GPU_COLOR, Backend::DmaHeap).is_err());
+    }
+
+    #[test]
+    fn test_flags_from() {
+        let flags = c::flags_from(HBMC_FLAG_EXTERNAL | HBMC_FLAG_MAP).unwrap();
+        assert_eq!(flags, hbm::Flags::EXTERNAL | hbm::Flags::MAP);
+        assert!(c::flags_from(1 << 31).is_err());
+    }
+
+    #[test]
+    fn test_device_config() {
+        let mut info = hbmc_device_info {
+            struct_size: mem::size_of::<hbmc_device_info>() as u32,
+            backend: HBMC_BACKEND_DMA_HEAP,
+            flags: 0,
+            dev: 0,
+            heap_name: std::ptr::null(),
+            name_template: std::ptr::null(),
+            queue_priority: HBMC_QUEUE_PRIORITY_DEFAULT,
+        };
+        assert!(DeviceConfig::new(&info).is_err());
+
+        let heap_name = ffi::CString::new("system").unwrap();
+        info.heap_name = heap_name.as_ptr();
+        let config = DeviceConfig::new(&info).unwrap();
+        assert_eq!(config.heap_name.as_deref(), Some("system"));
+        assert!(config.name_template.is_none());
+
+        info.flags = 1 << 31;
+        assert!(DeviceConfig::new(&info).is_err());
+
+        info.flags = 0;
+
+        info.queue_priority = HBMC_QUEUE_PRIORITY_HIGH;
+        let config = DeviceConfig::new(&info).unwrap();
+        assert_eq!(
+            config.queue_priority,
+            Some(hbm::vulkan::QueuePriority::High)
+        );
+        info.queue_priority = HBMC_QUEUE_PRIORITY_REALTIME + 1;
+        assert!(DeviceConfig::new(&info).is_err());
+        info.queue_priority = HBMC_QUEUE_PRIORITY_DEFAULT;
+
+        info.backend = 3;
+        assert!(DeviceConfig::new(&info).is_err());
+        info.backend = HBMC_BACKEND_DMA_HEAP;
+
+        info.struct_size = 4;
+        assert!(DeviceConfig::new(&info).is_err());
+    }
+}
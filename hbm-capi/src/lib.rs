@@ -0,0 +1,16 @@
+// Copyright 2024 Google LLC
+// SPDX-License-Identifier: MIT
+
+#![warn(missing_docs)]
+
+//! A stable HBM C API.
+//!
+//! This crate provides a stable C API for C/C++ projects, such as compositors and codecs, to
+//! allocate, export/import, and map hardware buffers with HBM.  Unlike the API provided by
+//! `hbm-minigbm`, this API is versioned and its ABI is only broken when the major version, and
+//! thus the soname of the shared library, changes.
+//!
+//! All symbols are prefixed by `hbmc_` such that the shared library can coexist with
+//! `hbm-minigbm` in the same process.
+
+pub mod capi;
@@ -2,6 +2,8 @@
 // Copyright 2025 The LineageOS Project
 // SPDX-License-Identifier: MIT
 
+use super::metadata;
+use android_hardware_common::aidl::android::hardware::common::NativeHandle::NativeHandle;
 use android_hardware_graphics_allocator::aidl::android::hardware::graphics::allocator::{
     AllocationError::AllocationError,
     AllocationResult::AllocationResult,
@@ -9,8 +11,13 @@ use android_hardware_graphics_allocator::aidl::android::hardware::graphics::allo
     IAllocator::BnAllocator,
     IAllocator::IAllocator,
 };
-use binder::{BinderFeatures, ExceptionCode, Interface, Result, Status, Strong};
-use log::{LevelFilter, info};
+use android_hardware_graphics_common::aidl::android::hardware::graphics::common::PixelFormat::PixelFormat;
+use binder::{
+    BinderFeatures, ExceptionCode, Interface, ParcelFileDescriptor, Result, Status, Strong,
+};
+use hbm::android;
+use log::{LevelFilter, error, info};
+use std::sync::Arc;
 
 const LOG_TAG: &str = "graphics_allocator_service_hbm";
 
@@ -34,17 +41,141 @@ pub fn main() {
     binder::ProcessState::join_thread_pool()
 }
 
+fn to_status(err: AllocationError) -> Status {
+    Status::new_service_specific_error(err.0, None)
+}
+
+fn to_allocation_error(err: hbm::Error) -> Status {
+    let err = match err {
+        hbm::Error::User => AllocationError::BAD_DESCRIPTOR,
+        hbm::Error::Unsupported => AllocationError::UNSUPPORTED,
+        _ => AllocationError::NO_RESOURCES,
+    };
+
+    to_status(err)
+}
+
+// returns the DRM format and the bytes per pixel of a pixel format
+fn to_format(format: PixelFormat) -> Option<(hbm::Format, hbm::Size)> {
+    let (fourcc, cpp) = match format {
+        PixelFormat::RGBA_8888 => (b"AB24", 4),
+        PixelFormat::RGBX_8888 => (b"XB24", 4),
+        PixelFormat::BGRA_8888 => (b"AR24", 4),
+        PixelFormat::RGB_888 => (b"BG24", 3),
+        PixelFormat::RGB_565 => (b"RG16", 2),
+        PixelFormat::RGBA_FP16 => (b"AB4H", 8),
+        PixelFormat::RGBA_1010102 => (b"AB30", 4),
+        PixelFormat::R_8 => (b"R8  ", 1),
+        _ => return None,
+    };
+
+    Some((hbm::Format(u32::from_le_bytes(*fourcc)), cpp))
+}
+
+// a buffer descriptor translated to hbm
+struct Descriptor {
+    desc: hbm::Description,
+    extent: hbm::Extent,
+    // the bytes per pixel of an image
+    cpp: hbm::Size,
+    name: String,
+}
+
+impl Descriptor {
+    fn new(info: &BufferDescriptorInfo) -> Result<Self> {
+        if info.width <= 0 || info.height <= 0 {
+            return Err(to_status(AllocationError::BAD_DESCRIPTOR));
+        }
+        if info.layerCount != 1 || info.reservedSize != 0 || !info.additionalOptions.is_empty() {
+            return Err(to_status(AllocationError::UNSUPPORTED));
+        }
+
+        // the system heap is always mappable, and the mapper requires Flags::MAP to lock
+        let desc = hbm::Description::new().flags(hbm::Flags::EXTERNAL | hbm::Flags::MAP);
+        let (desc, extent, cpp) = if info.format == PixelFormat::BLOB {
+            if info.height != 1 {
+                return Err(to_status(AllocationError::BAD_DESCRIPTOR));
+            }
+            (desc, hbm::Extent::Buffer(info.width as hbm::Size), 1)
+        } else {
+            let (format, cpp) =
+                to_format(info.format).ok_or_else(|| to_status(AllocationError::UNSUPPORTED))?;
+            let desc = desc.format(format).modifier(hbm::Modifier(0));
+            (desc, hbm::Extent::Image(info.width as u32, info.height as u32), cpp)
+        };
+
+        let name_len = info.name.iter().position(|&c| c == 0).unwrap_or(info.name.len());
+        let name = String::from_utf8_lossy(&info.name[..name_len]).into_owned();
+
+        Ok(Self { desc, extent, cpp, name })
+    }
+}
+
 pub struct AllocatorService {
-    // Add any necessary fields here
+    // None when the system heap is unavailable
+    device: Option<Arc<hbm::Device>>,
 }
 
 impl Interface for AllocatorService {}
 
 impl AllocatorService {
     fn new() -> Self {
-        Self {
-            // Initialize fields here
+        let device = hbm::dma_heap::Builder::new()
+            .heap_name("system")
+            .build()
+            .and_then(|backend| hbm::Builder::new().add_backend(backend).build());
+        if let Err(err) = &device {
+            error!("failed to create device: {err}");
         }
+
+        Self { device: device.ok() }
+    }
+
+    fn classify(
+        &self,
+        info: &BufferDescriptorInfo,
+    ) -> Result<(Arc<hbm::Device>, Descriptor, hbm::Class)> {
+        let dev = self.device.clone().ok_or_else(|| to_status(AllocationError::NO_RESOURCES))?;
+        let desc = Descriptor::new(info)?;
+
+        // the same usage as the mapper, such that the mapper imports the same layout
+        let usage = hbm::Usage::Cpu(hbm::CpuUsage::READ_OFTEN | hbm::CpuUsage::WRITE_OFTEN);
+        let class = dev.classify(desc.desc, &[usage]).map_err(to_allocation_error)?;
+
+        Ok((dev, desc, class))
+    }
+
+    // allocates a buffer and returns its native handle and its stride in pixels
+    fn allocate_one(
+        dev: &Arc<hbm::Device>,
+        desc: &Descriptor,
+        class: &hbm::Class,
+    ) -> Result<(NativeHandle, i32)> {
+        let mut bo = hbm::Bo::with_constraint(dev.clone(), class, desc.extent, None)
+            .map_err(to_allocation_error)?;
+        bo.bind_memory(hbm::MemoryType::MAPPABLE, None).map_err(to_allocation_error)?;
+
+        let name = (!desc.name.is_empty()).then_some(desc.name.as_str());
+        let dmabuf = bo.export_dma_buf(name).map_err(to_allocation_error)?;
+        let metadata =
+            metadata::Mapping::create().map_err(|_| to_status(AllocationError::NO_RESOURCES))?;
+
+        let layout = bo.layout();
+        let ints = android::encode(desc.desc, desc.extent, &layout, android::ByteOrder::native())
+            .map_err(to_allocation_error)?;
+
+        let stride = match desc.extent {
+            hbm::Extent::Buffer(size) => size,
+            _ => layout.strides[0] / desc.cpp,
+        };
+        let stride = i32::try_from(stride).map_err(|_| to_status(AllocationError::UNSUPPORTED))?;
+
+        let handle = NativeHandle {
+            fds: vec![ParcelFileDescriptor::new(dmabuf), ParcelFileDescriptor::new(metadata)],
+            ints: ints.to_vec(),
+        };
+
+        Ok((handle, stride))
     }
 }
 
@@ -62,12 +193,26 @@ impl IAllocator for AllocatorService {
 
     fn allocate2(&self, descriptor: &BufferDescriptorInfo, count: i32) -> Result<AllocationResult> {
         info!("Allocator allocate2 called with count={}", count);
-        Err(Status::new_exception(ExceptionCode::UNSUPPORTED_OPERATION, None))
+
+        let (dev, desc, class) = self.classify(descriptor)?;
+        if count <= 0 {
+            return Err(to_status(AllocationError::BAD_DESCRIPTOR));
+        }
+
+        let mut stride = 0;
+        let mut buffers = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let (handle, handle_stride) = Self::allocate_one(&dev, &desc, &class)?;
+            stride = handle_stride;
+            buffers.push(handle);
+        }
+
+        Ok(AllocationResult { stride, buffers })
     }
 
     fn isSupported(&self, descriptor: &BufferDescriptorInfo) -> Result<bool> {
         info!("Allocator isSupported called");
-        Err(Status::new_exception(ExceptionCode::UNSUPPORTED_OPERATION, None))
+        Ok(self.classify(descriptor).is_ok())
     }
 
     fn getIMapperLibrarySuffix(&self) -> Result<String> {
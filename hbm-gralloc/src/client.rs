@@ -0,0 +1,317 @@
+// Copyright 2024 Google LLC
+// SPDX-License-Identifier: MIT
+
+use android_hardware_common::aidl::android::hardware::common::NativeHandle::NativeHandle;
+use android_hardware_graphics_allocator::aidl::android::hardware::graphics::allocator::{
+    BufferDescriptorInfo::BufferDescriptorInfo,
+    IAllocator::{BpAllocator, IAllocator},
+};
+use android_hardware_graphics_common::aidl::android::hardware::graphics::common::{
+    BufferUsage::BufferUsage, Dataspace::Dataspace, PixelFormat::PixelFormat,
+    StandardMetadataType::StandardMetadataType,
+};
+use binder::Strong;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::{ffi, mem, process, ptr};
+
+#[cfg(feature = "builtin-imapper-stablec-bindgen")]
+use super::imapper_stablec_bindgen;
+
+use imapper_stablec_bindgen::{
+    buffer_handle_t, native_handle_t, AIMapper, AIMapperV5, AIMapper_Error, ARect,
+};
+
+const WIDTH: i32 = 64;
+const HEIGHT: i32 = 64;
+
+// the mapper libraries are installed to the hw directory of the vendor libraries
+const MAPPER_DIR: &str = if cfg!(target_pointer_width = "64") {
+    "/vendor/lib64/hw"
+} else {
+    "/vendor/lib/hw"
+};
+
+type LoadIMapper = unsafe extern "C" fn(*mut *mut AIMapper) -> AIMapper_Error;
+
+type Result<T> = std::result::Result<T, String>;
+
+fn check(act: &str, err: AIMapper_Error) -> Result<()> {
+    if err != AIMapper_Error::AIMAPPER_ERROR_NONE {
+        return Err(format!("failed to {act}: {err:?}"));
+    }
+
+    Ok(())
+}
+
+// a native_handle_t borrowing the fds of a NativeHandle
+struct RawHandle {
+    data: Vec<ffi::c_int>,
+}
+
+impl RawHandle {
+    fn new(handle: &NativeHandle) -> Self {
+        let mut data = vec![
+            mem::size_of::<native_handle_t>() as ffi::c_int,
+            handle.fds.len() as ffi::c_int,
+            handle.ints.len() as ffi::c_int,
+        ];
+        data.extend(handle.fds.iter().map(|fd| fd.as_raw_fd()));
+        data.extend(handle.ints.iter().copied());
+
+        Self { data }
+    }
+
+    fn as_ptr(&self) -> *const native_handle_t {
+        self.data.as_ptr() as *const native_handle_t
+    }
+}
+
+struct Mapper {
+    v5: AIMapperV5,
+}
+
+impl Mapper {
+    fn load(suffix: &str) -> Result<Self> {
+        let path = format!("{MAPPER_DIR}/mapper.{suffix}.so");
+        let c_path = ffi::CString::new(path.as_str()).map_err(|err| err.to_string())?;
+
+        // the library is never unloaded because the mapper is never freed
+        // SAFETY: c_path is a valid C string
+        let lib = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_LOCAL | libc::RTLD_NOW) };
+        if lib.is_null() {
+            return Err(format!("failed to load {path}"));
+        }
+
+        let sym_name = b"AIMapper_loadIMapper\0";
+        // SAFETY: lib is a valid library handle and sym_name is a valid C string
+        let sym = unsafe { libc::dlsym(lib, sym_name.as_ptr().cast()) };
+        if sym.is_null() {
+            return Err(format!("failed to find AIMapper_loadIMapper in {path}"));
+        }
+        // SAFETY: the mapper library defines AIMapper_loadIMapper as a LoadIMapper
+        let load_imapper = unsafe { mem::transmute::<*mut ffi::c_void, LoadIMapper>(sym) };
+
+        let mut mapper: *mut AIMapper = ptr::null_mut();
+        // SAFETY: mapper is a valid out pointer
+        let err = unsafe { load_imapper(&mut mapper) };
+        check("load mapper", err)?;
+
+        // SAFETY: mapper was returned by AIMapper_loadIMapper and is never freed
+        let mapper = unsafe { &*mapper };
+
+        Ok(Self { v5: mapper.v5 })
+    }
+
+    fn import_buffer(&self, handle: &RawHandle) -> Result<buffer_handle_t> {
+        let import_buffer = self.v5.importBuffer.ok_or("no importBuffer")?;
+
+        let mut buf: buffer_handle_t = ptr::null();
+        // SAFETY: handle is a valid native handle and buf is a valid out pointer
+        let err = unsafe { import_buffer(handle.as_ptr(), &mut buf) };
+        check("import buffer", err)?;
+
+        Ok(buf)
+    }
+
+    fn free_buffer(&self, buf: buffer_handle_t) -> Result<()> {
+        let free_buffer = self.v5.freeBuffer.ok_or("no freeBuffer")?;
+
+        // SAFETY: buf was returned by importBuffer
+        let err = unsafe { free_buffer(buf) };
+        check("free buffer", err)
+    }
+
+    fn lock(&self, buf: buffer_handle_t, usage: BufferUsage) -> Result<*mut ffi::c_void> {
+        let lock = self.v5.lock.ok_or("no lock")?;
+
+        let region = ARect {
+            left: 0,
+            top: 0,
+            right: WIDTH,
+            bottom: HEIGHT,
+        };
+        let mut data = ptr::null_mut();
+        // SAFETY: buf was returned by importBuffer and data is a valid out pointer
+        let err = unsafe { lock(buf, usage.0 as u64, region, -1, &mut data) };
+        check("lock", err)?;
+
+        if data.is_null() {
+            return Err(String::from("failed to lock: NULL pointer"));
+        }
+
+        Ok(data)
+    }
+
+    fn unlock(&self, buf: buffer_handle_t) -> Result<()> {
+        let unlock = self.v5.unlock.ok_or("no unlock")?;
+
+        let mut release_fence = -1;
+        // SAFETY: buf was returned by importBuffer and release_fence is a valid out pointer
+        let err = unsafe { unlock(buf, &mut release_fence) };
+        check("unlock", err)?;
+
+        if release_fence >= 0 {
+            // SAFETY: release_fence is owned by us
+            let _ = unsafe { OwnedFd::from_raw_fd(release_fence) };
+        }
+
+        Ok(())
+    }
+
+    fn get_standard_metadata(
+        &self,
+        buf: buffer_handle_t,
+        ty: StandardMetadataType,
+    ) -> Result<Vec<u8>> {
+        let get_standard_metadata = self
+            .v5
+            .getStandardMetadata
+            .ok_or("no getStandardMetadata")?;
+
+        // SAFETY: buf was returned by importBuffer and a NULL dest buffer queries the size
+        let size = unsafe { get_standard_metadata(buf, ty.0, ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(format!("failed to get metadata {ty:?}: {size}"));
+        }
+
+        let mut val = vec![0u8; size as usize];
+        // SAFETY: buf was returned by importBuffer and val is large enough
+        let size = unsafe { get_standard_metadata(buf, ty.0, val.as_mut_ptr().cast(), val.len()) };
+        if size < 0 || size as usize != val.len() {
+            return Err(format!("failed to get metadata {ty:?}: {size}"));
+        }
+
+        Ok(val)
+    }
+
+    fn set_standard_metadata(
+        &self,
+        buf: buffer_handle_t,
+        ty: StandardMetadataType,
+        val: &[u8],
+    ) -> Result<()> {
+        let set_standard_metadata = self
+            .v5
+            .setStandardMetadata
+            .ok_or("no setStandardMetadata")?;
+
+        // SAFETY: buf was returned by importBuffer and val is valid
+        let err = unsafe { set_standard_metadata(buf, ty.0, val.as_ptr().cast(), val.len()) };
+        check(&format!("set metadata {ty:?}"), err)
+    }
+}
+
+fn get_allocator() -> Result<Strong<dyn IAllocator>> {
+    let service_name = format!("{}/default", <BpAllocator as IAllocator>::get_descriptor());
+    binder::get_interface(&service_name)
+        .map_err(|err| format!("failed to get {service_name}: {err:?}"))
+}
+
+fn allocate(allocator: &Strong<dyn IAllocator>) -> Result<NativeHandle> {
+    let mut name = [0u8; 128];
+    let test_name = b"hbm-gralloc-selftest";
+    name[..test_name.len()].copy_from_slice(test_name);
+
+    let desc = BufferDescriptorInfo {
+        name,
+        width: WIDTH,
+        height: HEIGHT,
+        layerCount: 1,
+        format: PixelFormat::RGBA_8888,
+        usage: BufferUsage(BufferUsage::CPU_READ_OFTEN.0 | BufferUsage::CPU_WRITE_OFTEN.0),
+        ..Default::default()
+    };
+
+    let mut res = allocator
+        .allocate2(&desc, 1)
+        .map_err(|err| format!("failed to allocate2: {err:?}"))?;
+
+    res.buffers
+        .pop()
+        .ok_or(String::from("failed to allocate2: no buffer"))
+}
+
+fn test_lock(mapper: &Mapper, buf: buffer_handle_t) -> Result<()> {
+    let usage = BufferUsage(BufferUsage::CPU_READ_OFTEN.0 | BufferUsage::CPU_WRITE_OFTEN.0);
+    let pattern = [0x12u8, 0x34, 0x56, 0x78];
+
+    let data = mapper.lock(buf, usage)?;
+    // SAFETY: the buffer is locked and is at least 4 bytes
+    unsafe { ptr::copy_nonoverlapping(pattern.as_ptr(), data.cast(), pattern.len()) };
+    mapper.unlock(buf)?;
+
+    let data = mapper.lock(buf, usage)?;
+    let mut readback = [0u8; 4];
+    // SAFETY: the buffer is locked and is at least 4 bytes
+    unsafe { ptr::copy_nonoverlapping(data.cast(), readback.as_mut_ptr(), readback.len()) };
+    mapper.unlock(buf)?;
+
+    if readback != pattern {
+        return Err(format!("failed to read back: {readback:x?}"));
+    }
+
+    Ok(())
+}
+
+fn test_metadata(mapper: &Mapper, buf: buffer_handle_t) -> Result<()> {
+    let dataspace = Dataspace::SRGB.0.to_le_bytes();
+    mapper.set_standard_metadata(buf, StandardMetadataType::DATASPACE, &dataspace)?;
+    let val = mapper.get_standard_metadata(buf, StandardMetadataType::DATASPACE)?;
+    if val != dataspace {
+        return Err(format!("unexpected dataspace: {val:?}"));
+    }
+
+    // an arbitrary HDR10+ blob
+    let blob = [0xb5u8, 0x00, 0x3c, 0x00, 0x01, 0x04];
+    mapper.set_standard_metadata(buf, StandardMetadataType::SMPTE2094_40, &blob)?;
+    let val = mapper.get_standard_metadata(buf, StandardMetadataType::SMPTE2094_40)?;
+    if val != blob {
+        return Err(format!("unexpected SMPTE2094_40: {val:?}"));
+    }
+
+    mapper.set_standard_metadata(buf, StandardMetadataType::SMPTE2094_40, &[])?;
+    let val = mapper.get_standard_metadata(buf, StandardMetadataType::SMPTE2094_40)?;
+    if !val.is_empty() {
+        return Err(format!("unexpected SMPTE2094_40: {val:?}"));
+    }
+
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    let allocator = get_allocator()?;
+    let suffix = allocator
+        .getIMapperLibrarySuffix()
+        .map_err(|err| format!("failed to getIMapperLibrarySuffix: {err:?}"))?;
+    let mapper = Mapper::load(&suffix)?;
+    println!("load mapper.{suffix}.so: ok");
+
+    let handle = allocate(&allocator)?;
+    println!("allocate2: ok");
+
+    let buf = mapper.import_buffer(&RawHandle::new(&handle))?;
+    println!("importBuffer: ok");
+
+    let res = test_lock(&mapper, buf).and_then(|_| {
+        println!("lock/unlock: ok");
+        test_metadata(&mapper, buf)
+    });
+    if res.is_ok() {
+        println!("metadata: ok");
+    }
+
+    let free = mapper.free_buffer(buf);
+    res.and(free)?;
+    println!("freeBuffer: ok");
+
+    Ok(())
+}
+
+pub fn main() {
+    if let Err(err) = run() {
+        eprintln!("FAIL: {err}");
+        process::exit(1);
+    }
+
+    println!("PASS");
+}
@@ -1,6 +1,8 @@
 // Copyright 2024 Google LLC
 // SPDX-License-Identifier: MIT
 
+pub mod metadata;
+
 #[cfg(target_os = "android")]
 mod mapper;
 
@@ -3,6 +3,11 @@
 
 #[cfg(target_os = "android")]
 mod allocator;
+// the allocator only creates the shared metadata regions
+#[cfg(target_os = "android")]
+#[allow(dead_code)]
+#[path = "metadata.rs"]
+mod metadata;
 
 #[cfg(target_os = "android")]
 use allocator::main;
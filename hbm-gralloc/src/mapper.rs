@@ -16,31 +16,201 @@
 #[cfg(feature = "builtin-imapper-stablec-bindgen")]
 mod builtin_imapper_stablec_bindgen;
 #[cfg(feature = "builtin-imapper-stablec-bindgen")]
-use builtin_imapper_stablec_bindgen as imapper_stablec_bindgen;
+pub(crate) use builtin_imapper_stablec_bindgen as imapper_stablec_bindgen;
 
+use super::metadata;
+use hbm::android;
 use imapper_stablec_bindgen::{
     buffer_handle_t, native_handle_t, AIMapper, AIMapperV5, AIMapper_BeginDumpBufferCallback,
     AIMapper_DumpBufferCallback, AIMapper_Error, AIMapper_MetadataType,
     AIMapper_MetadataTypeDescription, AIMapper_Version, ARect,
 };
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::slice;
+use std::sync::{Arc, Mutex, OnceLock};
+
+// BufferUsage::CPU_READ_MASK and BufferUsage::CPU_WRITE_MASK
+const CPU_READ_MASK: u64 = 0xf;
+const CPU_WRITE_MASK: u64 = 0xf0;
+
+// the byte range of a locked buffer
+struct Lock {
+    offset: hbm::Size,
+    size: hbm::Size,
+    write: bool,
+}
+
+// an imported buffer
+struct Buffer {
+    // the cloned native handle, whose fds are owned by _fds
+    handle: Box<[c_int]>,
+    _fds: Vec<OwnedFd>,
+    // None when the buffer cannot be locked, such as when it is tiled
+    bo: Option<hbm::Bo>,
+    image: bool,
+    lock: Option<Lock>,
+    // None when the buffer has no shared metadata region
+    metadata: Option<Arc<metadata::Mapping>>,
+}
+
+fn device() -> Option<&'static Arc<hbm::Device>> {
+    static DEVICE: OnceLock<Option<Arc<hbm::Device>>> = OnceLock::new();
+
+    DEVICE
+        .get_or_init(|| {
+            let backend = hbm::dma_heap::Builder::new()
+                .heap_name("system")
+                .build()
+                .ok()?;
+            hbm::Builder::new().add_backend(backend).build().ok()
+        })
+        .as_ref()
+}
+
+// imported buffers, keyed by the addresses of their cloned native handles
+fn buffers() -> &'static Mutex<HashMap<usize, Buffer>> {
+    static BUFFERS: OnceLock<Mutex<HashMap<usize, Buffer>>> = OnceLock::new();
+
+    BUFFERS.get_or_init(Default::default)
+}
+
+fn with_buffer<F>(buffer: buffer_handle_t, f: F) -> AIMapper_Error
+where
+    F: FnOnce(&mut Buffer) -> AIMapper_Error,
+{
+    let mut buffers = buffers().lock().unwrap();
+    match buffers.get_mut(&(buffer as usize)) {
+        Some(buf) => f(buf),
+        None => AIMapper_Error::AIMAPPER_ERROR_BAD_BUFFER,
+    }
+}
+
+// Returns the metadata mapping of a buffer.  The region is accessed without the buffers lock
+// held, because another process can hold the region lock for a while.
+fn metadata_mapping(buffer: buffer_handle_t) -> Result<Arc<metadata::Mapping>, AIMapper_Error> {
+    let buffers = buffers().lock().unwrap();
+    let buf = buffers
+        .get(&(buffer as usize))
+        .ok_or(AIMapper_Error::AIMAPPER_ERROR_BAD_BUFFER)?;
+
+    buf.metadata
+        .clone()
+        .ok_or(AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED)
+}
+
+fn to_mapper_error(err: hbm::Error) -> AIMapper_Error {
+    match err {
+        hbm::Error::User => AIMapper_Error::AIMAPPER_ERROR_BAD_VALUE,
+        hbm::Error::Unsupported => AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED,
+        _ => AIMapper_Error::AIMAPPER_ERROR_NO_RESOURCES,
+    }
+}
+
+fn to_mapper_metadata_error(err: metadata::Error) -> AIMapper_Error {
+    match err {
+        metadata::Error::BadValue => AIMapper_Error::AIMAPPER_ERROR_BAD_VALUE,
+        metadata::Error::NoResources => AIMapper_Error::AIMAPPER_ERROR_NO_RESOURCES,
+        metadata::Error::Unsupported => AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED,
+    }
+}
+
+fn import_bo(handle: android::NativeHandle) -> hbm::Result<hbm::Bo> {
+    let dev = device().ok_or(hbm::Error::Unsupported)?;
+
+    // the description might leave the modifier to the allocator
+    let desc = handle.desc.modifier(handle.layout.modifier);
+    let usage = hbm::Usage::Cpu(hbm::CpuUsage::READ_OFTEN | hbm::CpuUsage::WRITE_OFTEN);
+    let class = dev.classify(desc, &[usage])?;
+
+    let mut bo = hbm::Bo::with_layout(
+        dev.clone(),
+        &class,
+        handle.extent,
+        handle.layout,
+        Some(handle.dmabuf.as_fd()),
+    )?;
+    bo.bind_memory(hbm::MemoryType::MAPPABLE, Some(handle.dmabuf))?;
+
+    Ok(bo)
+}
+
+// returns the byte range of the access region
+fn access_range(buf: &Buffer, bo: &hbm::Bo, region: ARect) -> hbm::Result<(hbm::Size, hbm::Size)> {
+    let width = region.right.saturating_sub(region.left);
+    let height = region.bottom.saturating_sub(region.top);
+
+    // an empty region or a buffer is accessed entirely
+    if !buf.image || (width == 0 && height == 0) {
+        return Ok((0, bo.layout().size));
+    }
+
+    bo.access_range(
+        u32::try_from(region.left)?,
+        u32::try_from(region.top)?,
+        u32::try_from(width)?,
+        u32::try_from(height)?,
+    )
+}
 
 unsafe extern "C" fn import_buffer(
-    _handle: *const native_handle_t,
-    _out_buffer_handle: *mut buffer_handle_t,
+    handle: *const native_handle_t,
+    out_buffer_handle: *mut buffer_handle_t,
 ) -> AIMapper_Error {
-    // validate(handle);
-    // buf = native_handle_clone(handle);
-    // import(buf); // validate and setup buf->bo mapping
-    // return buf;
-    AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED
+    if handle.is_null() {
+        return AIMapper_Error::AIMAPPER_ERROR_BAD_BUFFER;
+    }
+
+    let Ok(mut decoded) = android::decode_raw(handle as *const c_int) else {
+        return AIMapper_Error::AIMAPPER_ERROR_BAD_BUFFER;
+    };
+    let metadata_fd = decoded.metadata.take();
+
+    let Ok(fds) = Some(&decoded.dmabuf)
+        .into_iter()
+        .chain(metadata_fd.as_ref())
+        .map(|fd| fd.try_clone())
+        .collect::<Result<Vec<_>, _>>()
+    else {
+        return AIMapper_Error::AIMAPPER_ERROR_NO_RESOURCES;
+    };
+
+    // clone the native handle with the duplicated fds
+    let src = &*handle;
+    let len = 3 + (src.numFds + src.numInts) as usize;
+    let mut cloned: Box<[c_int]> = slice::from_raw_parts(handle as *const c_int, len).into();
+    for (raw, fd) in cloned[3..].iter_mut().zip(&fds) {
+        *raw = fd.as_raw_fd();
+    }
+
+    let image = matches!(decoded.extent, hbm::Extent::Image(..));
+    let bo = import_bo(decoded).ok();
+    let metadata = metadata_fd
+        .and_then(|fd| metadata::Mapping::new(fd).ok())
+        .map(Arc::new);
+
+    let buf = Buffer {
+        handle: cloned,
+        _fds: fds,
+        bo,
+        image,
+        lock: None,
+        metadata,
+    };
+    let key = buf.handle.as_ptr() as usize;
+    buffers().lock().unwrap().insert(key, buf);
+
+    *out_buffer_handle = key as buffer_handle_t;
+    AIMapper_Error::AIMAPPER_ERROR_NONE
 }
 
-unsafe extern "C" fn free_buffer(_buffer: buffer_handle_t) -> AIMapper_Error {
-    // bo = lookup(buf);
-    // delete(bo);
-    // native_handle_close(buf);
-    // native_handle_delete(buf);
-    AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED
+unsafe extern "C" fn free_buffer(buffer: buffer_handle_t) -> AIMapper_Error {
+    // this closes the fds of the cloned native handle
+    match buffers().lock().unwrap().remove(&(buffer as usize)) {
+        Some(_) => AIMapper_Error::AIMAPPER_ERROR_NONE,
+        None => AIMapper_Error::AIMAPPER_ERROR_BAD_BUFFER,
+    }
 }
 
 unsafe extern "C" fn get_transport_size(
@@ -55,40 +225,102 @@ unsafe extern "C" fn get_transport_size(
 }
 
 unsafe extern "C" fn lock(
-    _buffer: buffer_handle_t,
-    _cpu_usage: u64,
-    _access_region: ARect,
-    _acquire_fence: std::ffi::c_int,
-    _out_data: *mut *mut std::ffi::c_void,
+    buffer: buffer_handle_t,
+    cpu_usage: u64,
+    access_region: ARect,
+    acquire_fence: c_int,
+    out_data: *mut *mut std::ffi::c_void,
 ) -> AIMapper_Error {
-    // bo = lookup(buf);
-    // wait(acquire_fence);
-    // map(bo);
-    // sync(bo, start);
-    AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED
+    // take the ownership of the fence and wait for it without holding the buffers lock
+    if acquire_fence >= 0 {
+        let fence = OwnedFd::from_raw_fd(acquire_fence);
+        if !matches!(android::wait_fence(fence, None), Ok(true)) {
+            return AIMapper_Error::AIMAPPER_ERROR_NO_RESOURCES;
+        }
+    }
+
+    with_buffer(buffer, |buf| {
+        if buf.lock.is_some() {
+            return AIMapper_Error::AIMAPPER_ERROR_BAD_BUFFER;
+        }
+
+        let Some(bo) = buf.bo.as_ref() else {
+            return AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED;
+        };
+        let (offset, size) = match access_range(buf, bo, access_region) {
+            Ok(range) => range,
+            Err(err) => return to_mapper_error(err),
+        };
+
+        let bo = buf.bo.as_mut().unwrap();
+        let mapping = match bo.map() {
+            Ok(guard) => guard.into_raw(),
+            Err(err) => return to_mapper_error(err),
+        };
+
+        // only the access region needs cache maintenance
+        if cpu_usage & CPU_READ_MASK != 0 && bo.invalidate_range(offset, size).is_err() {
+            bo.unmap();
+            return AIMapper_Error::AIMAPPER_ERROR_BAD_VALUE;
+        }
+
+        buf.lock = Some(Lock {
+            offset,
+            size,
+            write: cpu_usage & CPU_WRITE_MASK != 0,
+        });
+
+        *out_data = mapping.ptr.as_ptr();
+        AIMapper_Error::AIMAPPER_ERROR_NONE
+    })
 }
 
-unsafe extern "C" fn unlock(
-    _buffer: buffer_handle_t,
-    release_fence: *mut std::ffi::c_int,
-) -> AIMapper_Error {
-    // bo = lookup(buf);
-    // sync(bo, end);
-    // unmap(bo);
+unsafe extern "C" fn unlock(buffer: buffer_handle_t, release_fence: *mut c_int) -> AIMapper_Error {
+    // the cache maintenance is synchronous and there is no release fence
     *release_fence = -1;
-    AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED
+
+    with_buffer(buffer, |buf| {
+        let Some(lock) = buf.lock.take() else {
+            return AIMapper_Error::AIMAPPER_ERROR_BAD_BUFFER;
+        };
+
+        let bo = buf.bo.as_mut().unwrap();
+        if lock.write {
+            // the range was validated by lock
+            let _ = bo.flush_range(lock.offset, lock.size);
+        }
+        bo.unmap();
+
+        AIMapper_Error::AIMAPPER_ERROR_NONE
+    })
 }
 
-unsafe extern "C" fn flush_locked_buffer(_buffer: buffer_handle_t) -> AIMapper_Error {
-    // bo = lookup(buf);
-    // flush(bo);
-    AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED
+unsafe extern "C" fn flush_locked_buffer(buffer: buffer_handle_t) -> AIMapper_Error {
+    with_buffer(buffer, |buf| {
+        let Some(lock) = buf.lock.as_ref() else {
+            return AIMapper_Error::AIMAPPER_ERROR_BAD_BUFFER;
+        };
+
+        if lock.write {
+            let bo = buf.bo.as_ref().unwrap();
+            let _ = bo.flush_range(lock.offset, lock.size);
+        }
+
+        AIMapper_Error::AIMAPPER_ERROR_NONE
+    })
 }
 
-unsafe extern "C" fn reread_locked_buffer(_buffer: buffer_handle_t) -> AIMapper_Error {
-    // bo = lookup(buf);
-    // invalidate(bo);
-    AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED
+unsafe extern "C" fn reread_locked_buffer(buffer: buffer_handle_t) -> AIMapper_Error {
+    with_buffer(buffer, |buf| {
+        let Some(lock) = buf.lock.as_ref() else {
+            return AIMapper_Error::AIMAPPER_ERROR_BAD_BUFFER;
+        };
+
+        let bo = buf.bo.as_ref().unwrap();
+        let _ = bo.invalidate_range(lock.offset, lock.size);
+
+        AIMapper_Error::AIMAPPER_ERROR_NONE
+    })
 }
 
 unsafe extern "C" fn get_metadata(
@@ -100,22 +332,39 @@ unsafe extern "C" fn get_metadata(
     let c_name = std::ffi::CStr::from_ptr(metadata_type.name);
     let name = c_name.to_str().unwrap();
     if name != "android.hardware.graphics.common.StandardMetadataType" {
-        return AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED as i32;
+        return -(AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED as i32);
     }
 
     get_standard_metadata(buffer, metadata_type.value, dest_buffer, dest_buffer_size)
 }
 
 unsafe extern "C" fn get_standard_metadata(
-    _buffer: buffer_handle_t,
-    _standard_metadata_type: i64,
-    _dest_buffer: *mut std::ffi::c_void,
-    _dest_buffer_size: usize,
+    buffer: buffer_handle_t,
+    standard_metadata_type: i64,
+    dest_buffer: *mut std::ffi::c_void,
+    dest_buffer_size: usize,
 ) -> i32 {
-    // bo = lookup(buf);
-    // val = get_metadata(bo); // ro metadata are embedded and rw metadata are on shmem
-    // encode(val, dest_buffer, dest_buffer_size);
-    AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED as i32
+    // TODO ro metadata are embedded and other rw metadata are on shmem
+    //
+    // dynamic hdr metadata are already encoded on shmem
+    let dst: &mut [u8] = if dest_buffer.is_null() {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(dest_buffer as *mut u8, dest_buffer_size)
+    };
+
+    let mapping = match metadata_mapping(buffer) {
+        Ok(mapping) => mapping,
+        Err(err) => return -(err as i32),
+    };
+
+    match mapping
+        .region()
+        .and_then(|region| region.get_standard(standard_metadata_type, dst))
+    {
+        Ok(size) => size as i32,
+        Err(err) => -(to_mapper_metadata_error(err) as i32),
+    }
 }
 
 unsafe extern "C" fn set_metadata(
@@ -134,22 +383,40 @@ unsafe extern "C" fn set_metadata(
 }
 
 unsafe extern "C" fn set_standard_metadata(
-    _buffer: buffer_handle_t,
-    _standard_metadata_type: i64,
-    _metadata: *const std::ffi::c_void,
-    _metadata_size: usize,
+    buffer: buffer_handle_t,
+    standard_metadata_type: i64,
+    metadata: *const std::ffi::c_void,
+    metadata_size: usize,
 ) -> AIMapper_Error {
-    // bo = lookup(buf);
-    // val = decode(metadata, metadata_size);
-    // set_metadata(bo, val); // ro metadata are embedded and rw metadata are on shmem
-    AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED
+    // TODO ro metadata are embedded and other rw metadata are on shmem
+    //
+    // dynamic hdr metadata are set per frame and are stored as is
+    let src: &[u8] = if metadata.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(metadata as *const u8, metadata_size)
+    };
+
+    let mapping = match metadata_mapping(buffer) {
+        Ok(mapping) => mapping,
+        Err(err) => return err,
+    };
+
+    // the region serializes the accesses of all processes
+    match mapping
+        .region()
+        .and_then(|region| region.set_standard(standard_metadata_type, src))
+    {
+        Ok(()) => AIMapper_Error::AIMAPPER_ERROR_NONE,
+        Err(err) => to_mapper_metadata_error(err),
+    }
 }
 
 unsafe extern "C" fn list_supported_metadata_types(
     out_description_list: *mut *const AIMapper_MetadataTypeDescription,
     out_number_of_descriptions: *mut usize,
 ) -> AIMapper_Error {
-    // list std metadata
+    // list std metadata, including metadata::SMPTE2094_40 and metadata::SMPTE2094_10
     *out_description_list = std::ptr::null();
     *out_number_of_descriptions = 0;
     AIMapper_Error::AIMAPPER_ERROR_UNSUPPORTED
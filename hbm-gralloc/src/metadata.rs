@@ -0,0 +1,432 @@
+// Copyright 2024 Google LLC
+// SPDX-License-Identifier: MIT
+
+//! The shared metadata region of a buffer.
+//!
+//! Read-only metadata are embedded in the native handle.  Read-write metadata are stored in a
+//! shared memory region that is mapped by every process importing the buffer, such that a value
+//! set by a producer is visible to all consumers.
+//!
+//! Dynamic HDR metadata (SMPTE 2094-10 and SMPTE 2094-40) are opaque blobs that can change every
+//! frame.  They are stored in fixed-capacity slots of the region.  The dataspace is stored in a
+//! slot that always holds an encoded `Dataspace`.
+//!
+//! The header of the region has a lock word.  Processes hold the lock while accessing the slots,
+//! such that a reader never sees a blob that is partially written by another process.  The region
+//! is shared with untrusted processes, and the lock is only tried for a bounded time such that a
+//! process that dies while holding the lock cannot block others forever.
+
+use std::ffi::c_void;
+use std::fs::File;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+use std::{mem, slice, thread};
+
+/// `StandardMetadataType::DATASPACE`.
+pub const DATASPACE: i64 = 17;
+
+/// `StandardMetadataType::SMPTE2094_40`.
+pub const SMPTE2094_40: i64 = 21;
+
+/// `StandardMetadataType::SMPTE2094_10`.
+pub const SMPTE2094_10: i64 = 22;
+
+/// The maximum size of a dynamic HDR metadata blob.
+pub const MAX_DYNAMIC_HDR_SIZE: usize = 4096;
+
+// a Dataspace is encoded as an i32
+const DATASPACE_SIZE: usize = mem::size_of::<i32>();
+
+const MAGIC: u32 = u32::from_le_bytes(*b"hbmm");
+const VERSION: u32 = 2;
+
+const LOCK_OFFSET: usize = mem::size_of::<u32>() * 2;
+const HEADER_SIZE: usize = mem::size_of::<u32>() * 3;
+const SLOT_SIZE: usize = mem::size_of::<u32>() + MAX_DYNAMIC_HDR_SIZE;
+const SLOT_COUNT: usize = 2;
+const DATASPACE_OFFSET: usize = HEADER_SIZE + SLOT_SIZE * SLOT_COUNT;
+
+// how long the lock is tried before giving up
+const LOCK_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// The size of the shared metadata region.
+pub const REGION_SIZE: usize = DATASPACE_OFFSET + mem::size_of::<u32>() + DATASPACE_SIZE;
+
+/// Metadata errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The region or the metadata value is malformed.
+    BadValue,
+    /// The metadata value exceeds the capacity of its slot, or the region is locked by another
+    /// process for too long.
+    NoResources,
+    /// The metadata type is not stored in the region.
+    Unsupported,
+}
+
+/// Result of metadata operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn read_bytes(data: &[AtomicU8], offset: usize, dst: &mut [u8]) {
+    for (dst, src) in dst.iter_mut().zip(&data[offset..]) {
+        *dst = src.load(Ordering::Relaxed);
+    }
+}
+
+fn write_bytes(data: &[AtomicU8], offset: usize, src: &[u8]) {
+    for (dst, src) in data[offset..offset + src.len()].iter().zip(src) {
+        dst.store(*src, Ordering::Relaxed);
+    }
+}
+
+fn read_u32(data: &[AtomicU8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    read_bytes(data, offset, &mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+fn write_u32(data: &[AtomicU8], offset: usize, val: u32) {
+    write_bytes(data, offset, &val.to_le_bytes());
+}
+
+// returns the offset and the capacity of the slot of a metadata type
+fn slot(ty: i64) -> Result<(usize, usize)> {
+    let slot = match ty {
+        SMPTE2094_40 => 0,
+        SMPTE2094_10 => 1,
+        DATASPACE => return Ok((DATASPACE_OFFSET, DATASPACE_SIZE)),
+        _ => return Err(Error::Unsupported),
+    };
+
+    Ok((HEADER_SIZE + SLOT_SIZE * slot, MAX_DYNAMIC_HDR_SIZE))
+}
+
+fn is_aligned(data: &[AtomicU8]) -> bool {
+    data.as_ptr() as usize % mem::align_of::<AtomicU32>() == 0
+}
+
+/// A view of a mapped shared metadata region.
+///
+/// The region is accessed atomically and can be shared by threads and processes.
+pub struct Region<'a> {
+    data: &'a [AtomicU8],
+}
+
+impl<'a> Region<'a> {
+    /// Initializes a newly allocated region.
+    ///
+    /// All dynamic HDR metadata are initially absent and the dataspace is initially
+    /// `Dataspace::UNKNOWN`.
+    pub fn init(data: &'a [AtomicU8]) -> Result<Self> {
+        if data.len() < REGION_SIZE || !is_aligned(data) {
+            return Err(Error::BadValue);
+        }
+
+        for byte in &data[..REGION_SIZE] {
+            byte.store(0, Ordering::Relaxed);
+        }
+        write_u32(data, 0, MAGIC);
+        write_u32(data, 4, VERSION);
+        write_u32(data, DATASPACE_OFFSET, DATASPACE_SIZE as u32);
+
+        Ok(Self { data })
+    }
+
+    /// Wraps a region that has been initialized, possibly by another process.
+    pub fn new(data: &'a [AtomicU8]) -> Result<Self> {
+        if data.len() < REGION_SIZE
+            || !is_aligned(data)
+            || read_u32(data, 0) != MAGIC
+            || read_u32(data, 4) != VERSION
+        {
+            return Err(Error::BadValue);
+        }
+
+        Ok(Self { data })
+    }
+
+    fn lock_word(&self) -> &AtomicU32 {
+        let ptr = self.data[LOCK_OFFSET..].as_ptr() as *const AtomicU32;
+        // SAFETY: the lock word is aligned and is only accessed atomically
+        unsafe { &*ptr }
+    }
+
+    fn lock(&self) -> Result<()> {
+        let word = self.lock_word();
+        let deadline = Instant::now() + LOCK_TIMEOUT;
+        while word
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            if Instant::now() >= deadline {
+                return Err(Error::NoResources);
+            }
+            thread::yield_now();
+        }
+
+        Ok(())
+    }
+
+    fn unlock(&self) {
+        self.lock_word().store(0, Ordering::Release);
+    }
+
+    // runs f with the lock held
+    fn locked<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        self.lock()?;
+        let ret = f();
+        self.unlock();
+
+        ret
+    }
+
+    /// Gets a standard metadata value that is stored in the region.
+    ///
+    /// This follows `getStandardMetadata`: the size of the encoded value is returned, and `dst` is
+    /// written only when it is large enough.  An absent dynamic HDR blob is encoded as an empty
+    /// value.
+    pub fn get_standard(&self, ty: i64, dst: &mut [u8]) -> Result<usize> {
+        let (offset, capacity) = slot(ty)?;
+
+        self.locked(|| {
+            let len = read_u32(self.data, offset) as usize;
+            if len > capacity {
+                return Err(Error::BadValue);
+            }
+
+            if let Some(dst) = dst.get_mut(..len) {
+                read_bytes(self.data, offset + mem::size_of::<u32>(), dst);
+            }
+
+            Ok(len)
+        })
+    }
+
+    /// Sets a standard metadata value that is stored in the region.
+    ///
+    /// An empty value removes the dynamic HDR blob.  A new blob replaces the blob of the previous
+    /// frame.  The dataspace cannot be removed.
+    pub fn set_standard(&self, ty: i64, src: &[u8]) -> Result<()> {
+        let (offset, capacity) = slot(ty)?;
+        if ty == DATASPACE && src.len() != DATASPACE_SIZE {
+            return Err(Error::BadValue);
+        }
+        if src.len() > capacity {
+            return Err(Error::NoResources);
+        }
+
+        self.locked(|| {
+            write_bytes(self.data, offset + mem::size_of::<u32>(), src);
+            write_u32(self.data, offset, src.len() as u32);
+
+            Ok(())
+        })
+    }
+}
+
+/// A shared mapping of a metadata region.
+pub struct Mapping {
+    ptr: NonNull<c_void>,
+}
+
+// SAFETY: the mapping is only accessed atomically
+unsafe impl Send for Mapping {}
+// SAFETY: the mapping is only accessed atomically
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    /// Creates the shared memory of a new region and initializes the region.
+    pub fn create() -> Result<OwnedFd> {
+        let name = b"hbm-metadata\0";
+        // SAFETY: name is a valid C string
+        let raw = unsafe { libc::memfd_create(name.as_ptr().cast(), libc::MFD_CLOEXEC) };
+        if raw < 0 {
+            return Err(Error::NoResources);
+        }
+        // SAFETY: raw is a valid fd that we own
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        File::from(fd.try_clone().map_err(|_| Error::NoResources)?)
+            .set_len(REGION_SIZE as u64)
+            .map_err(|_| Error::NoResources)?;
+        Region::init(Self::new(&fd)?.as_slice())?;
+
+        Ok(fd)
+    }
+
+    /// Maps the region in a shared memory.
+    ///
+    /// The shared memory is from `Mapping::create`, possibly in another process.
+    pub fn new(fd: impl AsFd) -> Result<Self> {
+        // accessing a mapping beyond the end of the file raises SIGBUS
+        let fd = fd.as_fd();
+        let len = File::from(fd.try_clone_to_owned().map_err(|_| Error::NoResources)?)
+            .metadata()
+            .map_err(|_| Error::BadValue)?
+            .len();
+        if len < REGION_SIZE as u64 {
+            return Err(Error::BadValue);
+        }
+
+        // SAFETY: the fd is valid and the mapping is unmapped on drop
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                REGION_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::NoResources);
+        }
+
+        NonNull::new(ptr)
+            .map(|ptr| Self { ptr })
+            .ok_or(Error::NoResources)
+    }
+
+    fn as_slice(&self) -> &[AtomicU8] {
+        let ptr = self.ptr.as_ptr() as *const AtomicU8;
+        // SAFETY: the mapping is REGION_SIZE bytes and is only accessed atomically
+        unsafe { slice::from_raw_parts(ptr, REGION_SIZE) }
+    }
+
+    /// Returns the region of the mapping.
+    pub fn region(&self) -> Result<Region<'_>> {
+        Region::new(self.as_slice())
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by mmap
+        unsafe { libc::munmap(self.ptr.as_ptr(), REGION_SIZE) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_data(len: usize) -> Vec<AtomicU8> {
+        (0..len).map(|_| AtomicU8::new(0xff)).collect()
+    }
+
+    #[test]
+    fn test_dynamic_hdr() {
+        let data = new_data(REGION_SIZE);
+        assert_eq!(Region::new(&data).err(), Some(Error::BadValue));
+
+        let region = Region::init(&data).unwrap();
+        assert_eq!(region.get_standard(SMPTE2094_40, &mut []), Ok(0));
+        assert_eq!(region.get_standard(3, &mut []), Err(Error::Unsupported));
+
+        let blob = [0xb5u8, 0x00, 0x3c, 0x00, 0x01, 0x04];
+        region.set_standard(SMPTE2094_40, &blob).unwrap();
+        let too_big = vec![0u8; MAX_DYNAMIC_HDR_SIZE + 1];
+        assert_eq!(
+            region.set_standard(SMPTE2094_10, &too_big),
+            Err(Error::NoResources)
+        );
+
+        let region = Region::new(&data).unwrap();
+        let mut small = [0u8; 2];
+        assert_eq!(
+            region.get_standard(SMPTE2094_40, &mut small),
+            Ok(blob.len())
+        );
+        assert_eq!(small, [0, 0]);
+
+        let mut dst = [0u8; 16];
+        assert_eq!(region.get_standard(SMPTE2094_40, &mut dst), Ok(blob.len()));
+        assert_eq!(dst[..blob.len()], blob);
+        assert_eq!(region.get_standard(SMPTE2094_10, &mut dst), Ok(0));
+
+        region.set_standard(SMPTE2094_40, &[]).unwrap();
+        assert_eq!(region.get_standard(SMPTE2094_40, &mut dst), Ok(0));
+    }
+
+    #[test]
+    fn test_dataspace() {
+        let data = new_data(REGION_SIZE);
+        let region = Region::init(&data).unwrap();
+
+        let mut dst = [0xffu8; 4];
+        assert_eq!(region.get_standard(DATASPACE, &mut dst), Ok(4));
+        assert_eq!(i32::from_le_bytes(dst), 0);
+
+        // Dataspace::SRGB
+        let srgb = 142671872i32.to_le_bytes();
+        region.set_standard(DATASPACE, &srgb).unwrap();
+        assert_eq!(region.get_standard(DATASPACE, &mut dst), Ok(4));
+        assert_eq!(dst, srgb);
+
+        assert_eq!(region.set_standard(DATASPACE, &[]), Err(Error::BadValue));
+        assert_eq!(
+            region.set_standard(DATASPACE, &[0; 8]),
+            Err(Error::BadValue)
+        );
+        assert_eq!(region.get_standard(DATASPACE, &mut dst), Ok(4));
+        assert_eq!(dst, srgb);
+    }
+
+    #[test]
+    fn test_region_lock() {
+        let data = new_data(REGION_SIZE + 1);
+        assert_eq!(Region::init(&data[1..]).err(), Some(Error::BadValue));
+
+        let region = Region::init(&data[..REGION_SIZE]).unwrap();
+        region.set_standard(SMPTE2094_10, &[1, 2, 3]).unwrap();
+        assert_eq!(region.get_standard(SMPTE2094_10, &mut []), Ok(3));
+        assert_eq!(region.get_standard(3, &mut []), Err(Error::Unsupported));
+
+        // the lock is released after every access
+        assert_eq!(read_u32(&data, LOCK_OFFSET), 0);
+
+        // a lock that is never released times out
+        write_u32(&data, LOCK_OFFSET, 1);
+        assert_eq!(
+            region.get_standard(SMPTE2094_10, &mut []),
+            Err(Error::NoResources)
+        );
+        assert_eq!(
+            region.set_standard(SMPTE2094_10, &[]),
+            Err(Error::NoResources)
+        );
+    }
+
+    #[test]
+    fn test_mapping() {
+        let fd = Mapping::create().unwrap();
+
+        // the mappings share the region
+        let producer = Mapping::new(&fd).unwrap();
+        let consumer = Mapping::new(&fd).unwrap();
+        let blob = [1u8, 2, 3, 4];
+        producer
+            .region()
+            .unwrap()
+            .set_standard(SMPTE2094_40, &blob)
+            .unwrap();
+        let mut dst = [0u8; 4];
+        assert_eq!(
+            consumer
+                .region()
+                .unwrap()
+                .get_standard(SMPTE2094_40, &mut dst),
+            Ok(blob.len())
+        );
+        assert_eq!(dst, blob);
+
+        let file = File::open("/dev/null").unwrap();
+        assert_eq!(Mapping::new(&file).err(), Some(Error::BadValue));
+    }
+}
@@ -0,0 +1,21 @@
+// Copyright 2024 Google LLC
+// SPDX-License-Identifier: MIT
+
+//! An end-to-end self test for the allocator service and the mapper.
+//!
+//! It allocates a buffer from the running allocator service and exercises the mapper with it.
+//! The mapper is loaded from the library named by the allocator service, as the platform does.
+//! This is meant to be run on-device to validate the whole stack before running Android VTS.
+
+#[cfg(target_os = "android")]
+mod client;
+#[cfg(all(target_os = "android", feature = "builtin-imapper-stablec-bindgen"))]
+#[path = "mapper/builtin_imapper_stablec_bindgen.rs"]
+mod imapper_stablec_bindgen;
+
+#[cfg(target_os = "android")]
+use client::main;
+#[cfg(not(target_os = "android"))]
+fn main() {
+    println!("This self test is Android-only.");
+}
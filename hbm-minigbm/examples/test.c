@@ -332,6 +332,29 @@ test_buffer(struct hbm_device *dev)
     hbm_bo_destroy(buf_bo);
 }
 
+static void
+test_memory_budget(struct hbm_device *dev)
+{
+    uint32_t heap_count = hbm_device_get_memory_budget(dev, 0, NULL, NULL);
+    struct hbm_memory_heap *heaps = malloc(sizeof(*heaps) * heap_count);
+    if (!heaps)
+        die("failed to alloc memory heaps");
+
+    uint64_t allocated_size;
+    heap_count = hbm_device_get_memory_budget(dev, heap_count, heaps, &allocated_size);
+    if (!heap_count)
+        die("failed to get memory heaps");
+    if (allocated_size)
+        die("unexpected allocated size");
+
+    for (uint32_t i = 0; i < heap_count; i++) {
+        if (heaps[i].budget > heaps[i].size)
+            die("budget larger than heap");
+    }
+
+    free(heaps);
+}
+
 static void
 test_log(enum hbm_log_level lv, const char *msg, void *data)
 {
@@ -351,6 +374,7 @@ main(void)
 
     test_buffer(dev);
     test_image(dev);
+    test_memory_budget(dev);
 
     hbm_device_destroy(dev);
 
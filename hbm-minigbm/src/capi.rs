@@ -4,11 +4,18 @@
 //! Unstable C API.
 //!
 //! Implements an unstable C API for minigbm drivers.
+//!
+//! When the `validate-handles` feature is enabled, `hbm_device` and `hbm_bo` pointers are
+//! validated before they are dereferenced.  Passing a destroyed or bogus handle to a function then
+//! logs an error and fails the function, rather than causing undefined behavior.
 
+use super::handle::Registry;
 use super::log::LogError;
 use std::collections::{hash_map::Entry, HashMap};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-use std::{ffi, ptr, slice};
+use std::time::Duration;
+use std::{env, ffi, ptr, slice};
 
 /// Log level of a message or the message filter.
 #[repr(C)]
@@ -55,6 +62,13 @@ pub const HBM_USAGE_GPU_COLOR: u64 = 1u64 << 4;
 /// The BO can be scanned out.  This is only meaningful when HBM lacks modifier support.
 pub const HBM_USAGE_GPU_SCANOUT_HACK: u64 = 1u64 << 5;
 
+/// The channels are not remapped.
+pub const HBM_SWIZZLE_NONE: u32 = 0;
+/// The RGB channels are not remapped, and the alpha channel is 1.
+pub const HBM_SWIZZLE_RGB1: u32 = 1;
+/// The R and B channels, or the Cr and Cb channels, are swapped.
+pub const HBM_SWIZZLE_BGRA: u32 = 2;
+
 /// The memory type is local to the device.
 pub const HBM_MEMORY_TYPE_LOCAL: u32 = 1 << 0;
 /// The memory type is mappable.
@@ -63,6 +77,11 @@ pub const HBM_MEMORY_TYPE_MAPPABLE: u32 = 1 << 1;
 pub const HBM_MEMORY_TYPE_COHERENT: u32 = 1 << 2;
 /// The memory type is cached.
 pub const HBM_MEMORY_TYPE_CACHED: u32 = 1 << 3;
+/// The memory type is protected.
+pub const HBM_MEMORY_TYPE_PROTECTED: u32 = 1 << 4;
+
+/// The memory heap is local to the device.
+pub const HBM_MEMORY_HEAP_LOCAL: u32 = 1 << 0;
 
 /// A hardware device.
 ///
@@ -99,6 +118,10 @@ pub struct hbm_description {
 
     /// A bitmask of `HBM_USAGE_*`.
     pub usage: u64,
+
+    /// A bitmask of `VkImageCompressionFixedRateFlagBitsEXT`.  When non-zero, the BO must be an
+    /// image and is compressed at one of the fixed rates.
+    pub fixed_rate: u32,
 }
 
 /// The extent of a buffer BO.
@@ -160,6 +183,23 @@ pub struct hbm_layout {
     pub offsets: [u64; 4],
     /// Plane row strides.
     pub strides: [u64; 4],
+    /// One of `HBM_SWIZZLE_*`, the channel swizzle to view the BO as its Vulkan format.  It is
+    /// ignored on import.
+    pub swizzle: u32,
+}
+
+/// The size and the budget of a memory heap.
+#[repr(C)]
+pub struct hbm_memory_heap {
+    /// Size of the heap in bytes.
+    pub size: u64,
+    /// How much memory the process can use from the heap in bytes.  This is equal to `size` if
+    /// unknown.
+    pub budget: u64,
+    /// How much memory the process uses from the heap in bytes.  This is 0 if unknown.
+    pub usage: u64,
+    /// A bitmask of `HBM_MEMORY_HEAP_*`.
+    pub flags: u32,
 }
 
 /// Describes a buffer-buffer copy.
@@ -218,19 +258,26 @@ mod c {
         }
     }
 
+    static DEVICES: Registry<CDevice> = Registry::new();
+    static BOS: Registry<hbm::Bo> = Registry::new();
+
     pub fn dev_ret(dev: CDevice) -> *mut hbm_device {
-        let dev = Box::new(dev);
-        Box::into_raw(dev) as *mut hbm_device
+        DEVICES
+            .insert(dev)
+            .map_or(ptr::null_mut(), |dev| dev.as_ptr() as *mut hbm_device)
     }
 
-    pub fn dev_take(dev: *mut hbm_device) -> Box<CDevice> {
-        // SAFETY: dev was created by dev_ret
-        unsafe { Box::from_raw(dev as *mut CDevice) }
+    pub fn dev_take(dev: *mut hbm_device) -> hbm::Result<Box<CDevice>> {
+        DEVICES.remove(dev as *mut CDevice).ok_or(hbm::Error::User)
     }
 
-    pub fn dev_borrow<'a>(dev: *mut hbm_device) -> &'a mut CDevice {
+    pub fn dev_borrow<'a>(dev: *mut hbm_device) -> hbm::Result<&'a CDevice> {
+        let Some(dev) = DEVICES.get(dev as *mut CDevice) else {
+            return Err(hbm::Error::User);
+        };
+
         // SAFETY: dev was created by dev_ret
-        unsafe { &mut *(dev as *mut CDevice) }
+        Ok(unsafe { dev.as_ref() })
     }
 
     pub fn desc_from(desc: *const hbm_description) -> hbm_description {
@@ -303,6 +350,35 @@ mod c {
         mod_count
     }
 
+    pub fn heap_copy_out(
+        out_heaps: *mut hbm_memory_heap,
+        heap_max: u32,
+        heaps: Vec<hbm::MemoryHeap>,
+    ) -> u32 {
+        let mut heap_count = heaps.len() as u32;
+        if heap_max == 0 {
+            return heap_count;
+        }
+
+        if heap_count > heap_max {
+            heap_count = heap_max;
+        }
+
+        // SAFETY: out_heaps is large enough for heap_count heaps
+        let out_heaps = unsafe { slice::from_raw_parts_mut(out_heaps, heap_count as usize) };
+
+        for (dst, src) in out_heaps.iter_mut().zip(heaps) {
+            *dst = hbm_memory_heap {
+                size: src.size,
+                budget: src.budget,
+                usage: src.usage,
+                flags: if src.local { HBM_MEMORY_HEAP_LOCAL } else { 0 },
+            };
+        }
+
+        heap_count
+    }
+
     pub fn extent_from(extent: *const hbm_extent, fmt: u32) -> hbm::Extent {
         // SAFETY: extent is valid
         let extent = unsafe { &*extent };
@@ -353,6 +429,14 @@ mod c {
             .strides(layout.strides)
     }
 
+    pub fn swizzle_into(swizzle: hbm::Swizzle) -> u32 {
+        match swizzle {
+            hbm::Swizzle::None => HBM_SWIZZLE_NONE,
+            hbm::Swizzle::Rgb1 => HBM_SWIZZLE_RGB1,
+            hbm::Swizzle::Bgra => HBM_SWIZZLE_BGRA,
+        }
+    }
+
     pub fn layout_copy_out(out_layout: *mut hbm_layout, layout: hbm::Layout) {
         // SAFETY: out_layout is non-NULL
         let out_layout = unsafe { &mut *out_layout };
@@ -363,27 +447,35 @@ mod c {
             plane_count: layout.plane_count,
             offsets: layout.offsets,
             strides: layout.strides,
+            swizzle: swizzle_into(layout.swizzle),
         };
     }
 
     pub fn bo_ret(bo: hbm::Bo) -> *mut hbm_bo {
-        let bo = Box::new(bo);
-        Box::into_raw(bo) as *mut hbm_bo
+        BOS.insert(bo)
+            .map_or(ptr::null_mut(), |bo| bo.as_ptr() as *mut hbm_bo)
     }
 
-    pub fn bo_take(bo: *mut hbm_bo) -> Box<hbm::Bo> {
-        // SAFETY: bo was created by bo_ret
-        unsafe { Box::from_raw(bo as *mut hbm::Bo) }
+    pub fn bo_take(bo: *mut hbm_bo) -> hbm::Result<Box<hbm::Bo>> {
+        BOS.remove(bo as *mut hbm::Bo).ok_or(hbm::Error::User)
     }
 
-    pub fn bo_borrow<'a>(bo: *mut hbm_bo) -> &'a hbm::Bo {
+    pub fn bo_borrow<'a>(bo: *mut hbm_bo) -> hbm::Result<&'a hbm::Bo> {
+        let Some(bo) = BOS.get(bo as *mut hbm::Bo) else {
+            return Err(hbm::Error::User);
+        };
+
         // SAFETY: bo was created by bo_ret
-        unsafe { &*(bo as *const hbm::Bo) }
+        Ok(unsafe { bo.as_ref() })
     }
 
-    pub fn bo_borrow_mut<'a>(bo: *mut hbm_bo) -> &'a mut hbm::Bo {
+    pub fn bo_borrow_mut<'a>(bo: *mut hbm_bo) -> hbm::Result<&'a mut hbm::Bo> {
+        let Some(mut bo) = BOS.get(bo as *mut hbm::Bo) else {
+            return Err(hbm::Error::User);
+        };
+
         // SAFETY: bo was created by bo_ret
-        unsafe { &mut *(bo as *mut hbm::Bo) }
+        Ok(unsafe { bo.as_mut() })
     }
 
     pub fn mt_from(c_mt: u32) -> hbm::MemoryType {
@@ -400,6 +492,9 @@ mod c {
         if (c_mt & HBM_MEMORY_TYPE_CACHED) > 0 {
             mt |= hbm::MemoryType::CACHED;
         }
+        if (c_mt & HBM_MEMORY_TYPE_PROTECTED) > 0 {
+            mt |= hbm::MemoryType::PROTECTED;
+        }
 
         mt
     }
@@ -418,6 +513,9 @@ mod c {
         if mt.contains(hbm::MemoryType::CACHED) {
             c_mt |= HBM_MEMORY_TYPE_CACHED;
         }
+        if mt.contains(hbm::MemoryType::PROTECTED) {
+            c_mt |= HBM_MEMORY_TYPE_PROTECTED;
+        }
 
         c_mt
     }
@@ -435,7 +533,7 @@ mod c {
         // SAFETY: out_mts is large enough for mt_count memory types
         let out_mts = unsafe { slice::from_raw_parts_mut(out_mts, mt_count as usize) };
 
-        for (dst, src) in out_mts.iter_mut().zip(mts.into_iter()) {
+        for (dst, src) in out_mts.iter_mut().zip(mts) {
             *dst = mt_into(src);
         }
 
@@ -477,6 +575,10 @@ mod c {
         *out_fd = fd.map_or(-1, |fd| fd.into_raw_fd());
     }
 
+    pub fn timeout_optional_from(timeout_ns: i64) -> Option<Duration> {
+        u64::try_from(timeout_ns).ok().map(Duration::from_nanos)
+    }
+
     pub fn str_optional_from<'a>(s: *const ffi::c_char) -> Option<&'a str> {
         if s.is_null() {
             return None;
@@ -506,11 +608,14 @@ mod c {
         hbm::CopyBufferImage {
             offset: copy.offset,
             stride: copy.stride,
+            slice_stride: 0,
             plane: copy.plane,
             x: copy.x,
             y: copy.y,
+            z: 0,
             width: copy.width,
             height: copy.height,
+            depth: 1,
         }
     }
 }
@@ -561,7 +666,8 @@ impl CDevice {
         let desc = hbm::Description::new()
             .flags(c::flags_from(desc.flags))
             .format(hbm::Format(desc.format))
-            .modifier(hbm::Modifier(desc.modifier));
+            .modifier(hbm::Modifier(desc.modifier))
+            .fixed_rate(hbm::FixedRate::from_bits(desc.fixed_rate).ok_or(hbm::Error::User)?);
 
         self.device.classify(desc, slice::from_ref(&usage))
     }
@@ -582,17 +688,19 @@ impl CDevice {
 
 /// Creates a device.
 ///
+/// When `HBM_PROBE_CACHE` is set, it is the path of the probe cache of the device.
+///
 /// # Safety
 ///
 /// This function is always safe.
 #[no_mangle]
 pub unsafe extern "C" fn hbm_device_create(dev: libc::dev_t, debug: bool) -> *mut hbm_device {
-    let Ok(backend) = hbm::vulkan::Builder::new()
-        .device_id(dev as _)
-        .debug(debug)
-        .build()
-        .log_err("create backend")
-    else {
+    let mut builder = hbm::vulkan::Builder::new().device_id(dev as _).debug(debug);
+    if let Ok(path) = env::var("HBM_PROBE_CACHE") {
+        builder = builder.probe_cache(Path::new(&path));
+    }
+
+    let Ok(backend) = builder.build().log_err("create backend") else {
         return ptr::null_mut();
     };
 
@@ -619,7 +727,7 @@ pub unsafe extern "C" fn hbm_device_create(dev: libc::dev_t, debug: bool) -> *mu
 /// `dev` must be valid.
 #[no_mangle]
 pub unsafe extern "C" fn hbm_device_destroy(dev: *mut hbm_device) {
-    let _ = c::dev_take(dev);
+    let _ = c::dev_take(dev).log_err("validate device");
 }
 
 /// Queries the memory plane count for the speicifed format modifier.  Returns 0 if the format or
@@ -636,7 +744,9 @@ pub unsafe extern "C" fn hbm_device_get_plane_count(
     fmt: u32,
     modifier: u64,
 ) -> u32 {
-    let dev = c::dev_borrow(dev);
+    let Ok(dev) = c::dev_borrow(dev).log_err("validate device") else {
+        return 0;
+    };
 
     dev.device
         .memory_plane_count(hbm::Format(fmt), hbm::Modifier(modifier))
@@ -663,7 +773,9 @@ pub unsafe extern "C" fn hbm_device_get_modifiers(
     mod_max: u32,
     out_mods: *mut u64,
 ) -> u32 {
-    let dev = c::dev_borrow(dev);
+    let Ok(dev) = c::dev_borrow(dev).log_err("validate device") else {
+        return 0;
+    };
     let desc = c::desc_from(desc);
 
     let Ok(class) = dev.get_class(desc) else {
@@ -685,7 +797,9 @@ pub unsafe extern "C" fn hbm_device_has_modifier(
     desc: *const hbm_description,
     modifier: u64,
 ) -> bool {
-    let dev = c::dev_borrow(dev);
+    let Ok(dev) = c::dev_borrow(dev).log_err("validate device") else {
+        return false;
+    };
     let desc = c::desc_from(desc);
 
     let Ok(class) = dev.get_class(desc) else {
@@ -695,6 +809,42 @@ pub unsafe extern "C" fn hbm_device_has_modifier(
     dev.device.modifiers(&class).iter().any(|m| m.0 == modifier)
 }
 
+/// Queries the memory budget of a device.
+///
+/// The budget and usage of each memory heap are queried anew on each call, such that this can be
+/// polled to react to memory pressure.
+///
+/// If `heap_max` is 0, the number of memory heaps is returned.  Otherwise, the number of memory
+/// heaps written to `out_heaps` is returned.
+///
+/// `out_allocated_size` is optional.  If non-NULL, it is set to the total size of the memories
+/// allocated for BOs of the device, excluding imported memories.
+///
+/// # Safety
+///
+/// `dev` must be valid.
+///
+/// `out_heaps` must point to an array of at least `heap_max` memory heaps.
+#[no_mangle]
+pub unsafe extern "C" fn hbm_device_get_memory_budget(
+    dev: *mut hbm_device,
+    heap_max: u32,
+    out_heaps: *mut hbm_memory_heap,
+    out_allocated_size: *mut u64,
+) -> u32 {
+    let Ok(dev) = c::dev_borrow(dev).log_err("validate device") else {
+        return 0;
+    };
+
+    if !out_allocated_size.is_null() {
+        // SAFETY: out_allocated_size is non-NULL
+        unsafe { *out_allocated_size = dev.device.allocated_size() };
+    }
+
+    let heaps = dev.device.memory_heaps();
+    c::heap_copy_out(out_heaps, heap_max, heaps)
+}
+
 /// Create a BO with a constraint.
 ///
 /// `con` is optional.
@@ -709,7 +859,9 @@ pub unsafe extern "C" fn hbm_bo_create_with_constraint(
     extent: *const hbm_extent,
     con: *const hbm_constraint,
 ) -> *mut hbm_bo {
-    let dev = c::dev_borrow(dev);
+    let Ok(dev) = c::dev_borrow(dev).log_err("validate device") else {
+        return ptr::null_mut();
+    };
     let desc = c::desc_from(desc);
     let extent = c::extent_from(extent, desc.format);
     let con = c::con_optional_from(con);
@@ -745,7 +897,9 @@ pub unsafe extern "C" fn hbm_bo_create_with_layout(
     layout: *const hbm_layout,
     dmabuf: i32,
 ) -> *mut hbm_bo {
-    let dev = c::dev_borrow(dev);
+    let Ok(dev) = c::dev_borrow(dev).log_err("validate device") else {
+        return ptr::null_mut();
+    };
     let desc = c::desc_from(desc);
     let extent = c::extent_from(extent, desc.format);
     let layout = c::layout_from(layout);
@@ -771,7 +925,7 @@ pub unsafe extern "C" fn hbm_bo_create_with_layout(
 /// `bo` must be valid.
 #[no_mangle]
 pub unsafe extern "C" fn hbm_bo_destroy(bo: *mut hbm_bo) {
-    let _ = c::bo_take(bo);
+    let _ = c::bo_take(bo).log_err("validate bo");
 }
 
 /// Queries the physical layout of a BO.
@@ -783,7 +937,9 @@ pub unsafe extern "C" fn hbm_bo_destroy(bo: *mut hbm_bo) {
 /// `out_layout` must be non-NULL.
 #[no_mangle]
 pub unsafe extern "C" fn hbm_bo_layout(bo: *mut hbm_bo, out_layout: *mut hbm_layout) {
-    let bo = c::bo_borrow(bo);
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return;
+    };
 
     let layout = bo.layout();
     c::layout_copy_out(out_layout, layout);
@@ -805,7 +961,9 @@ pub unsafe extern "C" fn hbm_bo_memory_types(
     mt_max: u32,
     out_mts: *mut u32,
 ) -> u32 {
-    let bo = c::bo_borrow(bo);
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return 0;
+    };
 
     let mts = bo.memory_types();
     c::mt_copy_out(out_mts, mt_max, mts)
@@ -823,9 +981,12 @@ pub unsafe extern "C" fn hbm_bo_memory_types(
 /// If `dmabuf` is non-negative, it must be a valid dma-buf.
 #[no_mangle]
 pub unsafe extern "C" fn hbm_bo_bind_memory(bo: *mut hbm_bo, mt: u32, dmabuf: i32) -> bool {
-    let bo = c::bo_borrow_mut(bo);
-    let mt = c::mt_from(mt);
+    // take ownership first such that dmabuf is closed on errors
     let dmabuf = c::fd_optional_from(dmabuf);
+    let Ok(bo) = c::bo_borrow_mut(bo).log_err("validate bo") else {
+        return false;
+    };
+    let mt = c::mt_from(mt);
 
     let act = if dmabuf.is_some() {
         "import memory"
@@ -836,6 +997,38 @@ pub unsafe extern "C" fn hbm_bo_bind_memory(bo: *mut hbm_bo, mt: u32, dmabuf: i3
     bo.bind_memory(mt, dmabuf).log_err(act).is_ok()
 }
 
+/// Resizes a buffer BO.
+///
+/// `desc` must be the description the BO was created with.  If the BO has a memory bound and the
+/// memory is large enough, only the size of the BO is updated.  Otherwise, the memory is
+/// reallocated and the contents are undefined.  A BO that is mapped or has an imported memory
+/// cannot be reallocated.
+///
+/// # Safety
+///
+/// `dev`, `bo`, and `desc` must be valid.  `bo` must belong to `dev`.
+#[no_mangle]
+pub unsafe extern "C" fn hbm_bo_resize(
+    dev: *mut hbm_device,
+    bo: *mut hbm_bo,
+    desc: *const hbm_description,
+    size: u64,
+) -> bool {
+    let Ok(dev) = c::dev_borrow(dev).log_err("validate device") else {
+        return false;
+    };
+    let Ok(bo) = c::bo_borrow_mut(bo).log_err("validate bo") else {
+        return false;
+    };
+    let desc = c::desc_from(desc);
+
+    let Ok(class) = dev.get_class(desc).log_err("get bo class") else {
+        return false;
+    };
+
+    bo.resize(&class, size).log_err("resize bo").is_ok()
+}
+
 /// Exports a dma-buf from a BO.
 ///
 /// The BO must have `HBM_FLAG_EXTERNAL` and must have a memory bound.
@@ -847,7 +1040,9 @@ pub unsafe extern "C" fn hbm_bo_bind_memory(bo: *mut hbm_bo, mt: u32, dmabuf: i3
 /// If `name` is non-NULL, it must be a valid C-string.
 #[no_mangle]
 pub unsafe extern "C" fn hbm_bo_export_dma_buf(bo: *mut hbm_bo, name: *const ffi::c_char) -> i32 {
-    let bo = c::bo_borrow(bo);
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return -1;
+    };
     let name = c::str_optional_from(name);
 
     let Ok(dmabuf) = bo.export_dma_buf(name).log_err("export") else {
@@ -857,6 +1052,49 @@ pub unsafe extern "C" fn hbm_bo_export_dma_buf(bo: *mut hbm_bo, name: *const ffi
     c::fd_into(dmabuf)
 }
 
+/// Exports the implicit fences of a BO as a sync file.
+///
+/// The BO must have `HBM_FLAG_EXTERNAL` and must have a memory bound.
+///
+/// If `write` is true, the sync file signals when all pending device accesses complete.
+/// Otherwise, it signals when pending device writes complete.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn hbm_bo_export_sync_fd(bo: *mut hbm_bo, write: bool) -> i32 {
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return -1;
+    };
+
+    let Ok(sync_fd) = bo.export_sync_fd(write).log_err("export sync fd") else {
+        return -1;
+    };
+
+    c::fd_into(sync_fd)
+}
+
+/// Waits for the implicit fences of a BO.
+///
+/// The BO must have `HBM_FLAG_EXTERNAL` and must have a memory bound.
+///
+/// `write` has the same meaning as in `hbm_bo_export_sync_fd`.  If `timeout_ns` is negative, this
+/// waits indefinitely.  Returns false if the timeout expires or on errors.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn hbm_bo_wait(bo: *mut hbm_bo, write: bool, timeout_ns: i64) -> bool {
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return false;
+    };
+    let timeout = c::timeout_optional_from(timeout_ns);
+
+    bo.wait(write, timeout).log_err("wait").unwrap_or(false)
+}
+
 /// Map a BO for direct CPU access.
 ///
 /// The BO must have `HBM_FLAG_MAP` and must have an `HBM_MEMORY_TYPE_MAPPABLE` memory bound.
@@ -866,9 +1104,11 @@ pub unsafe extern "C" fn hbm_bo_export_dma_buf(bo: *mut hbm_bo, name: *const ffi
 /// `bo` must be valid.
 #[no_mangle]
 pub unsafe extern "C" fn hbm_bo_map(bo: *mut hbm_bo) -> *mut ffi::c_void {
-    let bo = c::bo_borrow_mut(bo);
+    let Ok(bo) = c::bo_borrow_mut(bo).log_err("validate bo") else {
+        return ptr::null_mut();
+    };
 
-    let Ok(mapping) = bo.map().log_err("map") else {
+    let Ok(mapping) = bo.map().map(hbm::MappingGuard::into_raw).log_err("map") else {
         return ptr::null_mut();
     };
 
@@ -882,33 +1122,46 @@ pub unsafe extern "C" fn hbm_bo_map(bo: *mut hbm_bo) -> *mut ffi::c_void {
 /// `bo` must be valid.
 #[no_mangle]
 pub unsafe extern "C" fn hbm_bo_unmap(bo: *mut hbm_bo) {
-    let bo = c::bo_borrow_mut(bo);
+    let Ok(bo) = c::bo_borrow_mut(bo).log_err("validate bo") else {
+        return;
+    };
 
     bo.unmap();
 }
 
-/// Flush the CPU cache for a non-coherent mapped BO.
+/// Flush the CPU cache for a non-coherent BO.
+///
+/// If the BO is not mapped but has `HBM_FLAG_EXTERNAL`, the memory is flushed through its dma-buf.
+/// Returns false on errors.
 ///
 /// # Safety
 ///
 /// `bo` must be valid.
 #[no_mangle]
-pub unsafe extern "C" fn hbm_bo_flush(bo: *mut hbm_bo) {
-    let bo = c::bo_borrow(bo);
+pub unsafe extern "C" fn hbm_bo_flush(bo: *mut hbm_bo) -> bool {
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return false;
+    };
 
-    bo.flush();
+    bo.flush().log_err("flush bo").is_ok()
 }
 
-/// Invalidate the CPU cache for a non-coherent mapped BO.
+/// Invalidate the CPU cache for a non-coherent BO.
+///
+/// If the BO is not mapped but has `HBM_FLAG_EXTERNAL`, the memory is invalidated through its
+/// dma-buf.
+/// Returns false on errors.
 ///
 /// # Safety
 ///
 /// `bo` must be valid.
 #[no_mangle]
-pub unsafe extern "C" fn hbm_bo_invalidate(bo: *mut hbm_bo) {
-    let bo = c::bo_borrow(bo);
+pub unsafe extern "C" fn hbm_bo_invalidate(bo: *mut hbm_bo) -> bool {
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return false;
+    };
 
-    bo.invalidate();
+    bo.invalidate().log_err("invalidate bo").is_ok()
 }
 
 /// Performs a buffer-buffer copy from `src` to `bo`.
@@ -937,10 +1190,15 @@ pub unsafe extern "C" fn hbm_bo_copy_buffer(
     in_sync_fd: i32,
     out_sync_fd: *mut i32,
 ) -> bool {
-    let bo = c::bo_borrow(bo);
-    let src = c::bo_borrow(src);
-    let copy = c::copybuffer_from(copy);
+    // take ownership first such that in_sync_fd is closed on errors
     let in_sync_fd = c::fd_optional_from(in_sync_fd);
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return false;
+    };
+    let Ok(src) = c::bo_borrow(src).log_err("validate source bo") else {
+        return false;
+    };
+    let copy = c::copybuffer_from(copy);
 
     let wait = out_sync_fd.is_null();
     bo.copy_buffer(src, copy, in_sync_fd, wait)
@@ -969,10 +1227,15 @@ pub unsafe extern "C" fn hbm_bo_copy_buffer_image(
     in_sync_fd: i32,
     out_sync_fd: *mut i32,
 ) -> bool {
-    let bo = c::bo_borrow(bo);
-    let src = c::bo_borrow(src);
-    let copy = c::copybufferimage_from(copy);
+    // take ownership first such that in_sync_fd is closed on errors
     let in_sync_fd = c::fd_optional_from(in_sync_fd);
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return false;
+    };
+    let Ok(src) = c::bo_borrow(src).log_err("validate source bo") else {
+        return false;
+    };
+    let copy = c::copybufferimage_from(copy);
 
     let wait = out_sync_fd.is_null();
     bo.copy_buffer_image(src, copy, in_sync_fd, wait)
@@ -980,3 +1243,108 @@ pub unsafe extern "C" fn hbm_bo_copy_buffer_image(
         .map(|sync_fd| c::fd_copy_out(out_sync_fd, sync_fd))
         .is_ok()
 }
+
+/// Fills a buffer BO with a repeated 32-bit value.
+///
+/// The BO must have `HBM_FLAG_COPY` and must have a memory bound.  `in_sync_fd` and
+/// `out_sync_fd` are handled as in `hbm_bo_copy_buffer`.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+///
+/// If `in_sync_fd` is non-negative, it must be a valid sync file.
+///
+/// If `out_sync_fd` is non-NULL, it must be point to an i32.
+#[no_mangle]
+pub unsafe extern "C" fn hbm_bo_fill_buffer(
+    bo: *mut hbm_bo,
+    value: u32,
+    in_sync_fd: i32,
+    out_sync_fd: *mut i32,
+) -> bool {
+    // take ownership first such that in_sync_fd is closed on errors
+    let in_sync_fd = c::fd_optional_from(in_sync_fd);
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return false;
+    };
+
+    let wait = out_sync_fd.is_null();
+    bo.fill(hbm::Fill::Buffer(value), in_sync_fd, wait)
+        .log_err("fill buffer")
+        .map(|sync_fd| c::fd_copy_out(out_sync_fd, sync_fd))
+        .is_ok()
+}
+
+/// Clears an image BO to an RGBA color in normalized floats.
+///
+/// The BO must have `HBM_FLAG_COPY`, must have a memory bound, and must have a single-plane
+/// non-integer format.  Integer formats are cleared with `hbm_bo_clear_image_int`.  `in_sync_fd`
+/// and `out_sync_fd` are handled as in `hbm_bo_copy_buffer`.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+///
+/// `color` must point to 4 floats.
+///
+/// If `in_sync_fd` is non-negative, it must be a valid sync file.
+///
+/// If `out_sync_fd` is non-NULL, it must be point to an i32.
+#[no_mangle]
+pub unsafe extern "C" fn hbm_bo_clear_image(
+    bo: *mut hbm_bo,
+    color: *const f32,
+    in_sync_fd: i32,
+    out_sync_fd: *mut i32,
+) -> bool {
+    // take ownership first such that in_sync_fd is closed on errors
+    let in_sync_fd = c::fd_optional_from(in_sync_fd);
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return false;
+    };
+    // SAFETY: color points to 4 floats
+    let color = unsafe { *color.cast::<[f32; 4]>() };
+
+    let wait = out_sync_fd.is_null();
+    bo.fill(hbm::Fill::Color(color), in_sync_fd, wait)
+        .log_err("clear image")
+        .map(|sync_fd| c::fd_copy_out(out_sync_fd, sync_fd))
+        .is_ok()
+}
+
+/// Clears an image BO to an RGBA color in 32-bit integers.
+///
+/// This is the same as `hbm_bo_clear_image`, except that the BO must have a single-plane integer
+/// format.  Signed components are in two's complement.
+///
+/// # Safety
+///
+/// `bo` must be valid.
+///
+/// `color` must point to 4 uint32_t.
+///
+/// If `in_sync_fd` is non-negative, it must be a valid sync file.
+///
+/// If `out_sync_fd` is non-NULL, it must be point to an i32.
+#[no_mangle]
+pub unsafe extern "C" fn hbm_bo_clear_image_int(
+    bo: *mut hbm_bo,
+    color: *const u32,
+    in_sync_fd: i32,
+    out_sync_fd: *mut i32,
+) -> bool {
+    // take ownership first such that in_sync_fd is closed on errors
+    let in_sync_fd = c::fd_optional_from(in_sync_fd);
+    let Ok(bo) = c::bo_borrow(bo).log_err("validate bo") else {
+        return false;
+    };
+    // SAFETY: color points to 4 uint32_t
+    let color = unsafe { *color.cast::<[u32; 4]>() };
+
+    let wait = out_sync_fd.is_null();
+    bo.fill(hbm::Fill::ColorInt(color), in_sync_fd, wait)
+        .log_err("clear image")
+        .map(|sync_fd| c::fd_copy_out(out_sync_fd, sync_fd))
+        .is_ok()
+}
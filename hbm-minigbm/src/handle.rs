@@ -0,0 +1,223 @@
+// Copyright 2024 Google LLC
+// SPDX-License-Identifier: MIT
+
+//! Opaque handles for C API objects.
+//!
+//! By default, a handle is the address of a boxed object.  With the `validate-handles` feature,
+//! a handle is instead a slot index and a generation counter into a registry, and stale or bogus
+//! handles are rejected before they are dereferenced.
+
+use std::ptr::NonNull;
+
+#[cfg(feature = "validate-handles")]
+mod imp {
+    use std::ptr::NonNull;
+    use std::sync::Mutex;
+
+    // A handle has the slot index in the high bits and the generation in the low bits.  The
+    // index is kept narrow such that the generation is as wide as possible.  A slot is retired
+    // rather than reused when its generation would wrap, such that a stale handle never refers
+    // to a new object.
+    const INDEX_BITS: u32 = if usize::BITS > 32 { 24 } else { 16 };
+    const GENERATION_BITS: u32 = usize::BITS - INDEX_BITS;
+    const GENERATION_MASK: usize = (1 << GENERATION_BITS) - 1;
+
+    struct Slot<T> {
+        generation: usize,
+        obj: Option<Box<T>>,
+    }
+
+    struct Slots<T> {
+        slots: Vec<Slot<T>>,
+        free: Vec<usize>,
+    }
+
+    pub struct Registry<T> {
+        slots: Mutex<Slots<T>>,
+    }
+
+    impl<T> Registry<T> {
+        pub const fn new() -> Self {
+            Self {
+                slots: Mutex::new(Slots {
+                    slots: Vec::new(),
+                    free: Vec::new(),
+                }),
+            }
+        }
+
+        fn encode(index: usize, generation: usize) -> Option<usize> {
+            // index 0 is reserved such that a handle is never NULL
+            let index = index + 1;
+            if index >> INDEX_BITS != 0 {
+                return None;
+            }
+
+            Some((index << GENERATION_BITS) | generation)
+        }
+
+        fn decode(handle: usize) -> Option<(usize, usize)> {
+            let index = handle >> GENERATION_BITS;
+            if index == 0 {
+                return None;
+            }
+
+            Some((index - 1, handle & GENERATION_MASK))
+        }
+
+        pub fn insert(&self, obj: T) -> Option<NonNull<T>> {
+            let mut slots = self.slots.lock().unwrap();
+
+            let index = match slots.free.pop() {
+                Some(index) => index,
+                None => {
+                    slots.slots.push(Slot {
+                        generation: 0,
+                        obj: None,
+                    });
+                    slots.slots.len() - 1
+                }
+            };
+
+            let slot = &mut slots.slots[index];
+            let Some(handle) = Self::encode(index, slot.generation) else {
+                slots.free.push(index);
+                return None;
+            };
+
+            slot.obj = Some(Box::new(obj));
+
+            NonNull::new(handle as *mut T)
+        }
+
+        fn lookup(slots: &mut Slots<T>, handle: *mut T) -> Option<&mut Slot<T>> {
+            let (index, generation) = Self::decode(handle as usize)?;
+
+            slots
+                .slots
+                .get_mut(index)
+                .filter(|slot| slot.generation == generation && slot.obj.is_some())
+        }
+
+        pub fn get(&self, handle: *mut T) -> Option<NonNull<T>> {
+            let mut slots = self.slots.lock().unwrap();
+
+            let slot = Self::lookup(&mut slots, handle)?;
+            slot.obj.as_deref_mut().map(NonNull::from)
+        }
+
+        pub fn remove(&self, handle: *mut T) -> Option<Box<T>> {
+            let mut slots = self.slots.lock().unwrap();
+
+            let slot = Self::lookup(&mut slots, handle)?;
+            let obj = slot.obj.take();
+            if slot.generation == GENERATION_MASK {
+                return obj;
+            }
+            slot.generation += 1;
+
+            let (index, _) = Self::decode(handle as usize)?;
+            slots.free.push(index);
+
+            obj
+        }
+    }
+
+    #[test]
+    fn test_retired_slot() {
+        let reg = Registry::new();
+
+        reg.insert(1).unwrap();
+        reg.slots.lock().unwrap().slots[0].generation = GENERATION_MASK;
+        let first = Registry::<i32>::encode(0, GENERATION_MASK).unwrap() as *mut i32;
+        assert_eq!(reg.remove(first).as_deref(), Some(&1));
+
+        // the slot is not reused once its generation is exhausted
+        let second = reg.insert(2).unwrap();
+        assert_eq!(
+            Registry::<i32>::decode(second.as_ptr() as usize),
+            Some((1, 0))
+        );
+        assert!(reg.get(first).is_none());
+    }
+}
+
+#[cfg(not(feature = "validate-handles"))]
+mod imp {
+    use std::marker::PhantomData;
+    use std::ptr::NonNull;
+
+    pub struct Registry<T> {
+        _marker: PhantomData<fn(T)>,
+    }
+
+    impl<T> Registry<T> {
+        pub const fn new() -> Self {
+            Self {
+                _marker: PhantomData,
+            }
+        }
+
+        pub fn insert(&self, obj: T) -> Option<NonNull<T>> {
+            let obj = Box::new(obj);
+            Some(NonNull::from(Box::leak(obj)))
+        }
+
+        pub fn get(&self, handle: *mut T) -> Option<NonNull<T>> {
+            NonNull::new(handle)
+        }
+
+        pub fn remove(&self, handle: *mut T) -> Option<Box<T>> {
+            let handle = NonNull::new(handle)?;
+
+            // SAFETY: handle was created by insert
+            let obj = unsafe { Box::from_raw(handle.as_ptr()) };
+            Some(obj)
+        }
+    }
+}
+
+/// A registry of objects owned by C callers.
+pub struct Registry<T>(imp::Registry<T>);
+
+impl<T> Registry<T> {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        Self(imp::Registry::new())
+    }
+
+    /// Moves an object into the registry and returns its handle.
+    pub fn insert(&self, obj: T) -> Option<NonNull<T>> {
+        self.0.insert(obj)
+    }
+
+    /// Returns a pointer to the object referred to by a handle.
+    pub fn get(&self, handle: *mut T) -> Option<NonNull<T>> {
+        self.0.get(handle)
+    }
+
+    /// Moves the object referred to by a handle out of the registry.
+    pub fn remove(&self, handle: *mut T) -> Option<Box<T>> {
+        self.0.remove(handle)
+    }
+}
+
+#[cfg(feature = "validate-handles")]
+#[test]
+fn test_stale_handle() {
+    let reg = Registry::new();
+
+    let first = reg.insert(1).unwrap();
+    assert!(reg.get(first.as_ptr()).is_some());
+    assert_eq!(reg.remove(first.as_ptr()).as_deref(), Some(&1));
+
+    assert!(reg.get(first.as_ptr()).is_none());
+    assert!(reg.remove(first.as_ptr()).is_none());
+
+    // the slot is reused with a new generation
+    let second = reg.insert(2).unwrap();
+    assert_ne!(first, second);
+    assert!(reg.get(first.as_ptr()).is_none());
+    assert!(reg.get(std::ptr::null_mut()).is_none());
+    assert_eq!(reg.remove(second.as_ptr()).as_deref(), Some(&2));
+}
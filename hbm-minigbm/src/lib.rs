@@ -9,4 +9,5 @@
 //! internal to minigbm.  There is no plan to stabilize the API at the moment.
 
 pub mod capi;
+mod handle;
 mod log;
@@ -0,0 +1,90 @@
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use hbm::{CpuUsage, Flags, Format, MemoryType, Modifier, Usage};
+use std::slice;
+use std::sync::Arc;
+
+fn fill(bo: &mut hbm::Bo, val: u8) {
+    let mut mapping = bo.map().unwrap();
+    mapping.as_mut_slice().fill(val);
+    mapping.bo().flush().unwrap();
+}
+
+fn read(bo: &mut hbm::Bo, offset: usize) -> u8 {
+    let mapping = bo.map().unwrap();
+    mapping.bo().invalidate().unwrap();
+
+    mapping.as_slice()[offset]
+}
+
+fn test_buffer(dev: Arc<hbm::Device>) -> hbm::Bo {
+    let buf_desc = hbm::Description::new().flags(Flags::MAP | Flags::COPY);
+    let buf_usage = Usage::Cpu(CpuUsage::READ_OFTEN | CpuUsage::WRITE_OFTEN);
+    let buf_class = dev.classify(buf_desc, slice::from_ref(&buf_usage)).unwrap();
+
+    let buf_size = 64 * 64;
+    let buf_extent = hbm::Extent::Buffer(buf_size);
+    let mut buf_src = hbm::Bo::with_constraint(dev.clone(), &buf_class, buf_extent, None).unwrap();
+    let mut buf_dst = hbm::Bo::with_constraint(dev.clone(), &buf_class, buf_extent, None).unwrap();
+    buf_src.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+    buf_dst.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+
+    fill(&mut buf_src, 0x42);
+    fill(&mut buf_dst, 0);
+
+    let buf_copy = hbm::CopyBuffer {
+        src_offset: 0,
+        dst_offset: 0,
+        size: buf_size,
+    };
+    buf_dst.copy_buffer(&buf_src, buf_copy, None, true).unwrap();
+    assert_eq!(read(&mut buf_dst, buf_size as usize - 1), 0x42);
+    println!("buffer copy ok");
+
+    buf_src
+}
+
+fn test_image(dev: Arc<hbm::Device>, buf_bo: &hbm::Bo) {
+    let img_desc = hbm::Description::new()
+        .flags(Flags::MAP | Flags::COPY)
+        .format(Format(DrmFourcc::R8 as u32))
+        .modifier(Modifier(DrmModifier::Linear.into()));
+    let img_usage = Usage::Cpu(CpuUsage::READ_RARELY);
+    let img_class = dev.classify(img_desc, slice::from_ref(&img_usage)).unwrap();
+
+    let img_extent = hbm::Extent::Image(64, 64);
+    let mut img_bo = hbm::Bo::with_constraint(dev.clone(), &img_class, img_extent, None).unwrap();
+    img_bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+    fill(&mut img_bo, 0);
+
+    let img_copy = hbm::CopyBufferImage {
+        offset: 0,
+        stride: 64,
+        slice_stride: 0,
+        plane: 0,
+        x: 0,
+        y: 0,
+        z: 0,
+        width: 64,
+        height: 64,
+        depth: 1,
+    };
+    img_bo
+        .copy_buffer_image(buf_bo, img_copy, None, true)
+        .unwrap();
+
+    let img_layout = img_bo.layout();
+    let last = img_layout.offsets[0] + img_layout.strides[0] * 63 + 63;
+    assert_eq!(read(&mut img_bo, last as usize), 0x42);
+    println!("buffer-to-image copy ok");
+}
+
+fn main() {
+    env_logger::init();
+
+    let backend = hbm::udmabuf::Builder::new().build().unwrap();
+    let dev = hbm::Builder::new().add_backend(backend).build().unwrap();
+    assert!(dev.features().contains(hbm::Features::COPY_ENGINE));
+
+    let buf_bo = test_buffer(dev.clone());
+    test_image(dev, &buf_bo);
+}
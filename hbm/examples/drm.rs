@@ -1,5 +1,8 @@
+#[cfg(feature = "drm")]
 use drm_fourcc::{DrmFourcc, DrmModifier};
+#[cfg(feature = "drm")]
 use hbm::{Flags, Format, MemoryType, Usage};
+#[cfg(feature = "drm")]
 use std::slice;
 
 #[cfg(feature = "drm")]
@@ -53,10 +56,10 @@ fn main() {
     .unwrap();
     bo2.bind_memory(MemoryType::MAPPABLE, Some(dmabuf)).unwrap();
 
-    bo.map().unwrap();
-    bo.flush();
-    bo.invalidate();
-    bo.unmap();
+    let mapping = bo.map().unwrap();
+    mapping.bo().flush().unwrap();
+    mapping.bo().invalidate().unwrap();
+    drop(mapping);
 }
 
 #[cfg(not(feature = "drm"))]
@@ -1,7 +1,9 @@
+#[cfg(all(feature = "drm", feature = "ash"))]
 use drm_fourcc::DrmFourcc;
+#[cfg(all(feature = "drm", feature = "ash"))]
 use hbm::{Flags, Format, MemoryType, Usage};
 
-#[cfg(feature = "drm")]
+#[cfg(all(feature = "drm", feature = "ash"))]
 fn main() {
     env_logger::init();
 
@@ -31,7 +33,7 @@ fn main() {
     bo.bind_memory(MemoryType::empty(), None).unwrap();
 }
 
-#[cfg(not(feature = "drm"))]
+#[cfg(not(all(feature = "drm", feature = "ash")))]
 fn main() {
-    println!("drm feature disabled");
+    println!("drm or ash feature disabled");
 }
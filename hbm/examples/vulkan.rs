@@ -1,6 +1,10 @@
+#[cfg(feature = "ash")]
 use drm_fourcc::DrmFourcc;
+#[cfg(feature = "ash")]
 use hbm::{Flags, Format, MemoryType, Usage};
+#[cfg(feature = "ash")]
 use std::slice;
+#[cfg(feature = "ash")]
 use std::sync::Arc;
 
 #[cfg(feature = "ash")]
@@ -47,19 +51,22 @@ fn test_image(dev: Arc<hbm::Device>) {
         .bind_memory(MemoryType::MAPPABLE, Some(img_dmabuf))
         .unwrap();
 
-    img_bo.map().unwrap();
-    img_bo.flush();
-    img_bo.invalidate();
-    img_bo.unmap();
+    let mapping = img_bo.map().unwrap();
+    mapping.bo().flush().unwrap();
+    mapping.bo().invalidate().unwrap();
+    drop(mapping);
 
     let img_copy = hbm::CopyBufferImage {
         offset: 0,
         stride: (img_width * 4) as _,
+        slice_stride: 0,
         plane: 0,
         x: 0,
         y: 0,
+        z: 0,
         width: img_width,
         height: img_height,
+        depth: 1,
     };
 
     let buf_desc = hbm::Description::new().flags(Flags::MAP | Flags::COPY);
@@ -77,6 +84,13 @@ fn test_image(dev: Arc<hbm::Device>) {
     img_bo
         .copy_buffer_image(&buf_bo, img_copy, None, true)
         .unwrap();
+
+    // the same round trip with transient staging buffers
+    let mut pixels = vec![0; buf_size as usize];
+    img_bo.write(&pixels, hbm::Region::Image(img_copy)).unwrap();
+    img_bo
+        .read(&mut pixels, hbm::Region::Image(img_copy))
+        .unwrap();
 }
 
 #[cfg(feature = "ash")]
@@ -107,10 +121,10 @@ fn test_buffer(dev: Arc<hbm::Device>) {
         .bind_memory(MemoryType::MAPPABLE, Some(buf_dmabuf))
         .unwrap();
 
-    buf_bo.map().unwrap();
-    buf_bo.flush();
-    buf_bo.invalidate();
-    buf_bo.unmap();
+    let mapping = buf_bo.map().unwrap();
+    mapping.bo().flush().unwrap();
+    mapping.bo().invalidate().unwrap();
+    drop(mapping);
 
     let buf_copy = hbm::CopyBuffer {
         src_offset: 0,
@@ -129,7 +143,17 @@ fn test_buffer(dev: Arc<hbm::Device>) {
 fn main() {
     env_logger::init();
 
-    let backend = hbm::vulkan::Builder::new().build().unwrap();
+    for (idx, info) in hbm::vulkan::enumerate_devices().unwrap().iter().enumerate() {
+        println!(
+            "device {}: {} driver {} primary {:?} render {:?}",
+            idx, info.name, info.driver_id, info.primary_id, info.render_id
+        );
+    }
+
+    let backend = hbm::vulkan::Builder::new()
+        .allow_software(true)
+        .build()
+        .unwrap();
     let dev = hbm::Builder::new().add_backend(backend).build().unwrap();
 
     test_buffer(dev.clone());
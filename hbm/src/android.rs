@@ -0,0 +1,323 @@
+// Copyright 2024 Google LLC
+// SPDX-License-Identifier: MIT
+
+//! Android native handle helpers.
+//!
+//! This module defines how the hbm-gralloc allocator encodes a BO in a gralloc `native_handle_t`,
+//! and provides helpers to decode such a native handle into a `Description`, an `Extent`, a `Layout`, and the
+//! dma-buf.  Middleware written in Rust can use the decoded values with `Bo::with_layout` and
+//! `Bo::bind_memory` to import gralloc buffers directly.
+//!
+//! A native handle also records the byte order of the BO data, and can be decoded on a host of
+//! either byte order.  This allows buffers to be shared across architectures, such as by replay
+//! tools.
+
+use super::backends::{Description, Extent, Flags, Layout};
+use super::types::{Access, Error, Format, Modifier, Result, Size};
+use super::utils;
+use std::ffi::c_int;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd, RawFd};
+use std::slice;
+use std::time::Duration;
+
+/// The magic number in the first int of a native handle ("hbm0").
+pub const MAGIC: i32 = i32::from_le_bytes(*b"hbm0");
+
+/// The number of fds in a native handle.
+pub const FD_COUNT: usize = 1;
+
+/// The number of fds in a native handle that also has a shared metadata region.
+pub const FD_COUNT_WITH_METADATA: usize = 2;
+
+/// The number of ints in a native handle.
+pub const INT_COUNT: usize = 22;
+
+const EXTENT_BUFFER: i32 = 0;
+const EXTENT_IMAGE: i32 = 1;
+
+const BYTE_ORDER_LITTLE: i32 = 0;
+const BYTE_ORDER_BIG: i32 = 1;
+
+/// The byte order of the multi-byte components of the BO data.
+///
+/// DRM formats are little-endian unless `DRM_FORMAT_BIG_ENDIAN` is set.  Buffers have no format
+/// and their byte order is defined by the producer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ByteOrder {
+    /// Little-endian.
+    Little,
+    /// Big-endian.
+    Big,
+}
+
+impl ByteOrder {
+    /// Returns the byte order of the host.
+    pub fn native() -> Self {
+        if cfg!(target_endian = "big") {
+            Self::Big
+        } else {
+            Self::Little
+        }
+    }
+}
+
+/// A decoded native handle.
+#[derive(Debug)]
+pub struct NativeHandle {
+    /// Description of the BO.
+    pub desc: Description,
+    /// Extent of the BO.
+    pub extent: Extent,
+    /// Physical layout of the BO.
+    pub layout: Layout,
+    /// The byte order of the BO data.
+    pub byte_order: ByteOrder,
+    /// The dma-buf of the BO.
+    pub dmabuf: OwnedFd,
+    /// The shared memory of the read-write metadata of the BO, if any.
+    pub metadata: Option<OwnedFd>,
+}
+
+fn split_u64(val: u64) -> [i32; 2] {
+    [val as u32 as i32, (val >> 32) as u32 as i32]
+}
+
+fn join_u64(lo: i32, hi: i32) -> u64 {
+    (lo as u32 as u64) | ((hi as u32 as u64) << 32)
+}
+
+/// Encodes a BO into the ints of a native handle.
+///
+/// The dma-buf of the BO is the first fd of the native handle.  It can optionally be followed by a
+/// shared memory for the read-write metadata.
+pub fn encode(
+    desc: Description,
+    extent: Extent,
+    layout: &Layout,
+    byte_order: ByteOrder,
+) -> Result<[i32; INT_COUNT]> {
+    let (extent_type, extent_vals) = match extent {
+        Extent::Buffer(size) => (EXTENT_BUFFER, split_u64(size)),
+        Extent::Image(width, height) => (EXTENT_IMAGE, [width as i32, height as i32]),
+        // the native handle has no room for the depth
+        Extent::Image3D(..) => return Error::unsupported(),
+    };
+
+    let mut ints = [0; INT_COUNT];
+    ints[0] = MAGIC;
+    ints[1] = desc.flags.bits() as i32;
+    ints[2] = desc.format.0 as i32;
+    ints[3..5].copy_from_slice(&split_u64(desc.modifier.0));
+    ints[5] = extent_type;
+    ints[6..8].copy_from_slice(&extent_vals);
+    ints[8..10].copy_from_slice(&split_u64(layout.size));
+    ints[10..12].copy_from_slice(&split_u64(layout.modifier.0));
+    ints[12] = layout.plane_count as i32;
+
+    let offsets = layout.offsets_u32()?;
+    let strides = layout.strides_u32()?;
+    for plane in 0..4 {
+        ints[13 + plane * 2] = offsets[plane] as i32;
+        ints[14 + plane * 2] = strides[plane] as i32;
+    }
+    ints[21] = match byte_order {
+        ByteOrder::Little => BYTE_ORDER_LITTLE,
+        ByteOrder::Big => BYTE_ORDER_BIG,
+    };
+
+    Ok(ints)
+}
+
+fn is_valid_count(fd_count: usize, int_count: usize) -> bool {
+    (fd_count == FD_COUNT || fd_count == FD_COUNT_WITH_METADATA) && int_count == INT_COUNT
+}
+
+/// Decodes the fds and ints of a native handle.
+///
+/// The ints can be in either byte order, such as when the native handle was encoded on a host of
+/// the other byte order.
+///
+/// The fds are duplicated and the caller keeps the ownership of `fds`.
+pub fn decode(fds: &[BorrowedFd], ints: &[i32]) -> Result<NativeHandle> {
+    if !is_valid_count(fds.len(), ints.len()) {
+        return Error::user();
+    }
+
+    let swapped: Vec<i32>;
+    let ints = if ints[0] == MAGIC {
+        ints
+    } else if ints[0] == MAGIC.swap_bytes() {
+        swapped = ints.iter().map(|val| val.swap_bytes()).collect();
+        &swapped
+    } else {
+        return Error::user();
+    };
+
+    let flags = Flags::from_bits(ints[1] as u32).ok_or(Error::User)?;
+    let desc = Description::new()
+        .flags(flags)
+        .format(Format(ints[2] as u32))
+        .modifier(Modifier(join_u64(ints[3], ints[4])));
+
+    let extent = match ints[5] {
+        EXTENT_BUFFER if desc.is_buffer() => Extent::Buffer(join_u64(ints[6], ints[7])),
+        EXTENT_IMAGE if !desc.is_buffer() => Extent::Image(ints[6] as u32, ints[7] as u32),
+        _ => return Error::user(),
+    };
+
+    let plane_count = u32::try_from(ints[12])?;
+    if plane_count > 4 {
+        return Error::user();
+    }
+
+    let mut layout = Layout::new()
+        .size(join_u64(ints[8], ints[9]))
+        .modifier(Modifier(join_u64(ints[10], ints[11])));
+    layout.plane_count = plane_count;
+    for plane in 0..4 {
+        layout.offsets[plane] = u32::try_from(ints[13 + plane * 2])? as Size;
+        layout.strides[plane] = u32::try_from(ints[14 + plane * 2])? as Size;
+    }
+
+    let byte_order = match ints[21] {
+        BYTE_ORDER_LITTLE => ByteOrder::Little,
+        BYTE_ORDER_BIG => ByteOrder::Big,
+        _ => return Error::user(),
+    };
+
+    let dmabuf = fds[0].try_clone_to_owned()?;
+    let metadata = fds.get(1).map(|fd| fd.try_clone_to_owned()).transpose()?;
+
+    Ok(NativeHandle {
+        desc,
+        extent,
+        layout,
+        byte_order,
+        dmabuf,
+        metadata,
+    })
+}
+
+/// Decodes a raw `native_handle_t`.
+///
+/// # Safety
+///
+/// `handle` must point to a valid `native_handle_t` whose fds remain open for the duration of the
+/// call.
+pub unsafe fn decode_raw(handle: *const c_int) -> Result<NativeHandle> {
+    // a native_handle_t is version, numFds, numInts, and then the fds and the ints
+    // SAFETY: handle points to a valid native_handle_t
+    let header = unsafe { slice::from_raw_parts(handle, 3) };
+    let fd_count = usize::try_from(header[1])?;
+    let int_count = usize::try_from(header[2])?;
+    // the counts are untrusted and are validated before the fds and the ints are read
+    if !is_valid_count(fd_count, int_count) {
+        return Error::user();
+    }
+
+    // SAFETY: the fds and the ints follow the header
+    let data = unsafe { slice::from_raw_parts(handle, 3 + fd_count + int_count) };
+    let (raw_fds, ints) = data[3..].split_at(fd_count);
+    let fds: Vec<BorrowedFd> = raw_fds
+        .iter()
+        .map(|&fd: &RawFd| {
+            // SAFETY: the fds remain open for the duration of the call
+            unsafe { BorrowedFd::borrow_raw(fd) }
+        })
+        .collect();
+
+    decode(&fds, ints)
+}
+
+/// Waits for an Android fence to signal.
+///
+/// A fence is a sync file.  If `timeout` is `None`, this waits indefinitely.  Returns false if the
+/// timeout expires first.
+pub fn wait_fence(fence: impl AsFd, timeout: Option<Duration>) -> Result<bool> {
+    utils::poll_timeout(fence, Access::Read, timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats;
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn test_native_handle() {
+        let file = File::open("/dev/null").unwrap();
+        let fds = [file.as_fd()];
+
+        let desc = Description::new()
+            .flags(Flags::EXTERNAL | Flags::MAP)
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let extent = Extent::Image(64, 32);
+        let mut layout = Layout::new().size(1 << 33).modifier(formats::MOD_LINEAR);
+        layout.plane_count = 1;
+        layout.offsets[0] = 128;
+        layout.strides[0] = 64;
+
+        let ints = encode(desc, extent, &layout, ByteOrder::Little).unwrap();
+        let handle = decode(&fds, &ints).unwrap();
+        assert_eq!(handle.desc, desc);
+        assert!(matches!(handle.extent, Extent::Image(64, 32)));
+        assert_eq!(handle.layout, layout);
+        assert_eq!(handle.byte_order, ByteOrder::Little);
+        assert!(handle.metadata.is_none());
+
+        let handle = decode(&[fds[0], fds[0]], &ints).unwrap();
+        assert!(handle.metadata.is_some());
+
+        let mut raw = vec![12, 1, INT_COUNT as c_int, 0];
+        raw[3] = fds[0].as_raw_fd();
+        raw.extend(ints);
+        // SAFETY: raw is a valid native_handle_t
+        let handle = unsafe { decode_raw(raw.as_ptr()) }.unwrap();
+        assert_eq!(handle.layout, layout);
+
+        // a bogus header is rejected before the fds and the ints are read
+        let raw = [12, 1, 1 << 20];
+        // SAFETY: only the header of raw is read
+        assert!(unsafe { decode_raw(raw.as_ptr()) }.is_err());
+
+        let buf_desc = Description::new().flags(Flags::MAP);
+        let ints = encode(
+            buf_desc,
+            Extent::Buffer(1 << 40),
+            &Layout::new(),
+            ByteOrder::Big,
+        )
+        .unwrap();
+        let handle = decode(&fds, &ints).unwrap();
+        assert!(matches!(handle.extent, Extent::Buffer(size) if size == 1 << 40));
+        assert_eq!(handle.byte_order, ByteOrder::Big);
+
+        // encoded on a host of the other byte order
+        let swapped: Vec<i32> = ints.iter().map(|val| val.swap_bytes()).collect();
+        let handle = decode(&fds, &swapped).unwrap();
+        assert!(matches!(handle.extent, Extent::Buffer(size) if size == 1 << 40));
+        assert_eq!(handle.byte_order, ByteOrder::Big);
+
+        // truncated
+        assert!(decode(&fds, &ints[..INT_COUNT - 1]).is_err());
+        let mut raw = vec![12, 1, INT_COUNT as c_int - 1, fds[0].as_raw_fd()];
+        raw.extend(&ints[..INT_COUNT - 1]);
+        // SAFETY: raw is a valid native_handle_t
+        assert!(unsafe { decode_raw(raw.as_ptr()) }.is_err());
+
+        let mut bad = ints;
+        bad[0] = 0;
+        assert!(decode(&fds, &bad).is_err());
+        assert!(decode(&[], &ints).is_err());
+    }
+
+    #[test]
+    fn test_wait_fence() {
+        // a signaled fence polls readable, like /dev/null
+        let file = File::open("/dev/null").unwrap();
+        assert!(wait_fence(&file, Some(Duration::ZERO)).unwrap());
+        assert!(wait_fence(&file, None).unwrap());
+    }
+}
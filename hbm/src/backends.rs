@@ -14,10 +14,11 @@ pub mod vulkan;
 
 use super::dma_buf;
 use super::formats;
-#[cfg(feature = "ash")]
-use super::sash;
 use super::types::{Error, Format, Mapping, Modifier, Result, Size};
+use std::any::Any;
 use std::os::fd::{BorrowedFd, OwnedFd};
+use std::time::Duration;
+use std::{ffi, ptr};
 
 bitflags::bitflags! {
     /// BO Flags.
@@ -38,6 +39,97 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    /// Fixed-rate compression levels, in bits per component.
+    ///
+    /// An image with fixed-rate compression is compressed at one of the specified levels.  The
+    /// bits match those of `VkImageCompressionFixedRateFlagBitsEXT`.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct FixedRate: u32 {
+        /// 1 bit per component.
+        const BPC_1 = 1 << 0;
+        /// 2 bits per component.
+        const BPC_2 = 1 << 1;
+        /// 3 bits per component.
+        const BPC_3 = 1 << 2;
+        /// 4 bits per component.
+        const BPC_4 = 1 << 3;
+        /// 5 bits per component.
+        const BPC_5 = 1 << 4;
+        /// 6 bits per component.
+        const BPC_6 = 1 << 5;
+        /// 7 bits per component.
+        const BPC_7 = 1 << 6;
+        /// 8 bits per component.
+        const BPC_8 = 1 << 7;
+        /// 9 bits per component.
+        const BPC_9 = 1 << 8;
+        /// 10 bits per component.
+        const BPC_10 = 1 << 9;
+        /// 11 bits per component.
+        const BPC_11 = 1 << 10;
+        /// 12 bits per component.
+        const BPC_12 = 1 << 11;
+        /// 13 bits per component.
+        const BPC_13 = 1 << 12;
+        /// 14 bits per component.
+        const BPC_14 = 1 << 13;
+        /// 15 bits per component.
+        const BPC_15 = 1 << 14;
+        /// 16 bits per component.
+        const BPC_16 = 1 << 15;
+        /// 17 bits per component.
+        const BPC_17 = 1 << 16;
+        /// 18 bits per component.
+        const BPC_18 = 1 << 17;
+        /// 19 bits per component.
+        const BPC_19 = 1 << 18;
+        /// 20 bits per component.
+        const BPC_20 = 1 << 19;
+        /// 21 bits per component.
+        const BPC_21 = 1 << 20;
+        /// 22 bits per component.
+        const BPC_22 = 1 << 21;
+        /// 23 bits per component.
+        const BPC_23 = 1 << 22;
+        /// 24 bits per component.
+        const BPC_24 = 1 << 23;
+    }
+}
+
+/// The compression state of a BO.
+///
+/// The compression state is what the implementation chose for an allocated image, which can
+/// differ from what was requested with `Description::no_compression` or
+/// `Description::fixed_rate`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Compression {
+    /// The compression state is unknown.
+    #[default]
+    Unknown,
+    /// The BO is not compressed.
+    Disabled,
+    /// The BO may be losslessly compressed, as the implementation sees fit.
+    Default,
+    /// The BO is compressed at the fixed rate, which has a single bit set.
+    FixedRate(FixedRate),
+}
+
+/// A memory priority.
+///
+/// Under memory pressure, memories of higher priorities are less likely to be moved out of
+/// device-local memory.  Long-lived BOs such as scanout buffers can be favored over transient
+/// BOs such as staging buffers.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MemoryPriority {
+    /// The memory is transient.
+    Low,
+    /// The default priority of most memories.
+    Medium,
+    /// The memory is long-lived and performance-critical.
+    High,
+}
+
 /// A BO Description.
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
 #[non_exhaustive]
@@ -55,6 +147,16 @@ pub struct Description {
     /// If the BO is an image, and if the modifier is `DRM_FORMAT_MOD_INVALID`, the device will
     /// pick the optimal modifier.  Otherwise, the device will use the specified modifier.
     pub modifier: Modifier,
+    /// Memory priority of a BO.
+    ///
+    /// This is a hint and is ignored by backends that do not support it.  If unset, the backend
+    /// default priority is used.
+    pub priority: Option<MemoryPriority>,
+    /// Fixed-rate compression levels of a BO.
+    ///
+    /// If the BO is an image, and if the levels are non-empty, the image is compressed at one of
+    /// the levels.  Otherwise, the levels must be empty.
+    pub fixed_rate: FixedRate,
 }
 
 impl Description {
@@ -81,6 +183,21 @@ impl Description {
         self
     }
 
+    /// Sets the memory priority.
+    ///
+    /// A BO with a priority has its own device memory, while small BOs without priorities might
+    /// be suballocated from shared memories.
+    pub fn priority(mut self, prio: MemoryPriority) -> Self {
+        self.priority = Some(prio);
+        self
+    }
+
+    /// Sets the fixed-rate compression levels.
+    pub fn fixed_rate(mut self, fixed_rate: FixedRate) -> Self {
+        self.fixed_rate = fixed_rate;
+        self
+    }
+
     pub(crate) fn is_valid(&self) -> bool {
         // the bo is useless if none of these flags is set
         let min_flags = Flags::EXTERNAL | Flags::MAP | Flags::COPY;
@@ -89,9 +206,10 @@ impl Description {
         }
 
         if self.is_buffer() {
-            self.modifier.is_invalid()
+            self.modifier.is_invalid() && self.fixed_rate.is_empty()
         } else {
-            true
+            // fixed-rate compression contradicts no compression
+            self.fixed_rate.is_empty() || !self.flags.contains(Flags::NO_COMPRESSION)
         }
     }
 
@@ -100,6 +218,37 @@ impl Description {
     }
 }
 
+bitflags::bitflags! {
+    /// A CPU usage.
+    ///
+    /// A CPU usage is not specific to any backend.  A non-empty CPU usage requires `Flags::MAP`,
+    /// and classification fails with `Error::User` without it.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct CpuUsage: u32 {
+        /// The BO is read by the CPU often.
+        const READ_OFTEN = 1 << 0;
+        /// The BO is read by the CPU rarely.
+        const READ_RARELY = 1 << 1;
+        /// The BO is written by the CPU often.
+        const WRITE_OFTEN = 1 << 2;
+        /// The BO is written by the CPU rarely.
+        const WRITE_RARELY = 1 << 3;
+    }
+}
+
+impl CpuUsage {
+    // returns the memory type that is preferred for the usage
+    pub(crate) fn preferred_memory_type(&self) -> MemoryType {
+        if self.is_empty() {
+            MemoryType::empty()
+        } else if self.contains(Self::READ_OFTEN) {
+            MemoryType::MAPPABLE | MemoryType::CACHED
+        } else {
+            MemoryType::MAPPABLE
+        }
+    }
+}
+
 /// A BO usage.
 ///
 /// Comparing to the generic `Flags`, a usage is specific to a backend.
@@ -108,6 +257,8 @@ impl Description {
 pub enum Usage {
     /// The backend is not used.
     Unused,
+    /// CPU-only.  This is supported by backends that do not have their own usage.
+    Cpu(CpuUsage),
     /// `drm_kms` backend-specific.
     #[cfg(feature = "drm")]
     DrmKms(drm_kms::Usage),
@@ -116,66 +267,146 @@ pub enum Usage {
     Vulkan(vulkan::Usage),
 }
 
+impl Usage {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Usage::Unused => "unused",
+            Usage::Cpu(_) => "cpu",
+            #[cfg(feature = "drm")]
+            Usage::DrmKms(_) => "drm_kms",
+            #[cfg(feature = "ash")]
+            Usage::Vulkan(_) => "vulkan",
+        }
+    }
+}
+
 /// An opaque BO class.
 ///
-/// A class is validated and is opaque to users.
+/// A class is validated and is opaque to users.  Backends build classes in `Backend::classify`
+/// with `Class::new` and the builder methods.
 #[derive(Clone, Debug)]
 pub struct Class {
     // these are copied from user inputs
     pub(crate) flags: Flags,
     pub(crate) format: Format,
     pub(crate) usage: Usage,
+    // only the vulkan backend supports memory priorities and fixed-rate compression
+    #[cfg_attr(not(feature = "ash"), allow(dead_code))]
+    pub(crate) priority: Option<MemoryPriority>,
+    #[cfg_attr(not(feature = "ash"), allow(dead_code))]
+    pub(crate) fixed_rate: FixedRate,
+    // this is the union of the CPU usages to all backends
+    pub(crate) cpu_usage: CpuUsage,
+    // the flags that backends dropped from the user inputs to support more modifiers
+    pub(crate) dropped_flags: Flags,
 
     // These express backend limits.  When there are multiple backends, limits from all backends
     // are merged.
     pub(crate) max_extent: Extent,
+    pub(crate) max_ranges: Vec<(Usage, Size)>,
     pub(crate) modifiers: Vec<Modifier>,
+    // these parallel the modifiers
+    pub(crate) modifier_hints: Vec<ModifierHint>,
+    pub(crate) modifier_features: Vec<ModifierFeatures>,
     pub(crate) constraint: Option<Constraint>,
     pub(crate) unknown_constraint: bool,
 
-    // this is set by Device
+    // these are set by Device
+    pub(crate) max_size: Size,
     pub(crate) backend_index: usize,
 }
 
 impl Class {
-    pub(crate) fn new(desc: Description) -> Self {
+    /// Creates a BO class for a BO description.
+    ///
+    /// The class has no usage, no modifier, and the max supported extent of the description.
+    pub fn new(desc: Description) -> Self {
         Self {
             flags: desc.flags,
             format: desc.format,
             usage: Usage::Unused,
+            priority: desc.priority,
+            fixed_rate: desc.fixed_rate,
+            cpu_usage: CpuUsage::empty(),
+            dropped_flags: Flags::empty(),
             max_extent: Extent::max_supported(&desc),
+            max_ranges: Vec::new(),
             modifiers: Vec::new(),
+            modifier_hints: Vec::new(),
+            modifier_features: Vec::new(),
             constraint: None,
             unknown_constraint: false,
+            max_size: Size::MAX,
             backend_index: 0,
         }
     }
 
-    pub(crate) fn usage(mut self, usage: Usage) -> Self {
+    /// Sets the BO usage of the class.
+    pub fn usage(mut self, usage: Usage) -> Self {
         self.usage = usage;
+        if let Usage::Cpu(cpu_usage) = usage {
+            self.cpu_usage |= cpu_usage;
+        }
+        self
+    }
+
+    /// Drops optional flags from the class.
+    ///
+    /// The dropped flags are reported by `Device::dropped_flags`.
+    pub fn drop_flags(mut self, flags: Flags) -> Self {
+        self.flags -= flags;
+        self.dropped_flags |= flags;
         self
     }
 
-    pub(crate) fn max_extent(mut self, max_extent: Extent) -> Self {
+    /// Sets the max extent of the class.
+    pub fn max_extent(mut self, max_extent: Extent) -> Self {
         self.max_extent = max_extent;
         self
     }
 
-    pub(crate) fn modifiers(mut self, mods: Vec<Modifier>) -> Self {
+    /// Adds the max range of the class that can be used for a usage at a time.
+    pub fn max_range(mut self, usage: Usage, range: Size) -> Self {
+        self.max_ranges.push((usage, range));
+        self
+    }
+
+    /// Sets the supported modifiers of the class.
+    ///
+    /// The modifier hints are derived from the modifiers, and the modifier features are reset.
+    pub fn modifiers(mut self, mods: Vec<Modifier>) -> Self {
+        self.modifier_hints = mods.iter().copied().map(formats::modifier_hint).collect();
+        self.modifier_features = vec![ModifierFeatures::empty(); mods.len()];
         self.modifiers = mods;
         self
     }
 
-    pub(crate) fn constraint(mut self, con: Constraint) -> Self {
+    /// Sets the modifier features of the class.
+    ///
+    /// There must be a feature for each of the modifiers, and this must be called after
+    /// `Class::modifiers`.
+    pub fn modifier_features(mut self, feats: Vec<ModifierFeatures>) -> Self {
+        assert_eq!(feats.len(), self.modifiers.len());
+        self.modifier_features = feats;
+        self
+    }
+
+    /// Sets the constraint that BOs of the class must satisfy.
+    pub fn constraint(mut self, con: Constraint) -> Self {
         self.constraint = Some(con);
         self
     }
 
-    pub(crate) fn unknown_constraint(mut self) -> Self {
+    /// Marks that the constraint of the class is unknown until a BO is created.
+    ///
+    /// When there are multiple backends, at most one backend can have an unknown constraint, and
+    /// BOs are allocated by that backend.
+    pub fn unknown_constraint(mut self) -> Self {
         self.unknown_constraint = true;
         self
     }
 
+    // this is set by Device
     pub(crate) fn backend_index(mut self, idx: usize) -> Self {
         self.backend_index = idx;
         self
@@ -197,15 +428,18 @@ impl Class {
             let width = extent.width();
             let height = extent.height();
 
-            (1..=max_width).contains(&width) && (1..=max_height).contains(&height)
+            // backends validate the depth
+            (1..=max_width).contains(&width)
+                && (1..=max_height).contains(&height)
+                && extent.depth() > 0
         }
     }
 }
 
 /// A BO extent.
 ///
-/// An extent is 1-dimentional or 2-dimentional depending on whether the BO is a buffer or an
-/// image.
+/// An extent is 1-dimentional, 2-dimentional, or 3-dimentional depending on whether the BO is a
+/// buffer, an image, or a 3D image.
 #[derive(Clone, Copy, Debug)]
 #[non_exhaustive]
 pub enum Extent {
@@ -213,6 +447,11 @@ pub enum Extent {
     Buffer(Size),
     /// The width and height of the BO, when it is an image.
     Image(u32, u32),
+    /// The width, height, and depth of the BO, when it is a 3D image.
+    ///
+    /// Only single-plane formats are supported.  Explicit modifiers other than
+    /// `DRM_FORMAT_MOD_LINEAR` are not supported.
+    Image3D(u32, u32, u32),
 }
 
 impl Extent {
@@ -238,25 +477,36 @@ impl Extent {
     }
 
     pub(crate) fn width(&self) -> u32 {
-        if let Extent::Image(width, _) = self {
-            *width
-        } else {
-            unreachable!();
+        match self {
+            Extent::Image(width, _) | Extent::Image3D(width, _, _) => *width,
+            Extent::Buffer(_) => unreachable!(),
         }
     }
 
     pub(crate) fn height(&self) -> u32 {
-        if let Extent::Image(_, height) = self {
-            *height
-        } else {
-            unreachable!();
+        match self {
+            Extent::Image(_, height) | Extent::Image3D(_, height, _) => *height,
+            Extent::Buffer(_) => unreachable!(),
         }
     }
 
+    pub(crate) fn depth(&self) -> u32 {
+        match self {
+            Extent::Image(_, _) => 1,
+            Extent::Image3D(_, _, depth) => *depth,
+            Extent::Buffer(_) => unreachable!(),
+        }
+    }
+
+    pub(crate) fn is_3d(&self) -> bool {
+        matches!(self, Extent::Image3D(_, _, _))
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         match self {
             Extent::Buffer(size) => *size == 0,
             Extent::Image(width, height) => *width == 0 || *height == 0,
+            Extent::Image3D(width, height, depth) => *width == 0 || *height == 0 || *depth == 0,
         }
     }
 
@@ -275,6 +525,18 @@ impl Extent {
                     *height = other.height();
                 }
             }
+            Extent::Image3D(width, height, depth) => {
+                if *width > other.width() {
+                    *width = other.width();
+                }
+                if *height > other.height() {
+                    *height = other.height();
+                }
+                // 2D extents do not limit the depth
+                if other.is_3d() && *depth > other.depth() {
+                    *depth = other.depth();
+                }
+            }
         };
     }
 }
@@ -290,6 +552,8 @@ pub struct Constraint {
 
     // no restriction when empty
     pub(crate) modifiers: Vec<Modifier>,
+    // no preference when empty
+    pub(crate) preferred_modifiers: Vec<Modifier>,
 }
 
 impl Default for Constraint {
@@ -299,6 +563,7 @@ impl Default for Constraint {
             stride_align: 1,
             size_align: 1,
             modifiers: Default::default(),
+            preferred_modifiers: Default::default(),
         }
     }
 }
@@ -339,6 +604,17 @@ impl Constraint {
         self
     }
 
+    /// Sets the preferred modifiers, from the most preferred to the least preferred.
+    ///
+    /// The allowed modifiers are ordered with the preferred ones first.  The first preferred
+    /// modifier that is allowed and supported is picked.  When none of them is, or when the
+    /// backend fails to use it, the backend picks one of the other allowed and supported
+    /// modifiers as usual.
+    pub fn preferred_modifiers(mut self, mods: Vec<Modifier>) -> Self {
+        self.preferred_modifiers = mods;
+        self
+    }
+
     fn to_tuple(&self) -> (Size, Size, Size) {
         (self.offset_align, self.stride_align, self.size_align)
     }
@@ -363,6 +639,10 @@ impl Constraint {
             assert!(self.modifiers.is_empty());
             self.modifiers = other.modifiers;
         }
+
+        if self.preferred_modifiers.is_empty() {
+            self.preferred_modifiers = other.preferred_modifiers;
+        }
     }
 
     pub(crate) fn unpack(con: Option<Constraint>) -> (Size, Size, Size) {
@@ -370,6 +650,25 @@ impl Constraint {
     }
 }
 
+/// A channel swizzle.
+///
+/// A swizzle is how the channels of a format are remapped when a backend views the format as
+/// another format, such as when the Vulkan backend views XRGB8888 as `VK_FORMAT_B8G8R8A8_UNORM`
+/// or NV21 as `VK_FORMAT_G8_B8R8_2PLANE_420_UNORM`.  Importers that view the BO the same way
+/// should set up matching component mappings.  It only applies to views, such as when the BO is
+/// sampled.  Copies preserve the plane and byte order of the format, such that an NV21 BO still
+/// holds NV21 data in its memory.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Swizzle {
+    /// The channels are not remapped.
+    #[default]
+    None,
+    /// The RGB channels are not remapped, and the alpha channel is 1.
+    Rgb1,
+    /// The R and B channels, or the Cr and Cb channels, are swapped.
+    Bgra,
+}
+
 /// A BO physical layout.
 ///
 /// A physical layout provides the necessary information for import and for CPU access.
@@ -386,6 +685,10 @@ pub struct Layout {
     pub offsets: [Size; 4],
     /// Row strides of memory planes, or 0.
     pub strides: [Size; 4],
+    /// Depth slice stride of a 3D image, or 0.
+    pub slice_stride: Size,
+    /// Channel swizzle of a BO.  It is ignored on import.
+    pub swizzle: Swizzle,
 }
 
 impl Layout {
@@ -436,6 +739,43 @@ impl Layout {
         self
     }
 
+    /// Sets the depth slice stride.
+    pub fn slice_stride(mut self, slice_stride: Size) -> Self {
+        self.slice_stride = slice_stride;
+        self
+    }
+
+    /// Sets the channel swizzle.
+    pub fn swizzle(mut self, swizzle: Swizzle) -> Self {
+        self.swizzle = swizzle;
+        self
+    }
+
+    /// Returns the memory plane offsets as 32-bit values.
+    ///
+    /// This is for interop with APIs such as DRM KMS, GBM, or V4L2 that use 32-bit offsets.
+    /// `Error::IntegerConversion` is returned if any offset does not fit.
+    pub fn offsets_u32(&self) -> Result<[u32; 4]> {
+        Self::to_u32(self.offsets)
+    }
+
+    /// Returns the memory plane row strides as 32-bit values.
+    ///
+    /// This is for interop with APIs such as DRM KMS, GBM, or V4L2 that use 32-bit pitches.
+    /// `Error::IntegerConversion` is returned if any row stride does not fit.
+    pub fn strides_u32(&self) -> Result<[u32; 4]> {
+        Self::to_u32(self.strides)
+    }
+
+    fn to_u32(vals: [Size; 4]) -> Result<[u32; 4]> {
+        let mut vals_u32 = [0; 4];
+        for (val_u32, val) in vals_u32.iter_mut().zip(vals) {
+            *val_u32 = u32::try_from(val)?;
+        }
+
+        Ok(vals_u32)
+    }
+
     pub(crate) fn packed(class: &Class, extent: Extent, con: Option<Constraint>) -> Result<Self> {
         let layout = if class.is_buffer() {
             let (_, _, size_align) = Constraint::unpack(con);
@@ -447,13 +787,19 @@ impl Layout {
                 return Error::user();
             }
 
-            formats::packed_layout(class.format, extent.width(), extent.height(), con)?
+            formats::packed_layout(
+                class.format,
+                extent.width(),
+                extent.height(),
+                extent.depth(),
+                con,
+            )?
         };
 
         Ok(layout)
     }
 
-    #[cfg(feature = "drm")]
+    #[cfg(any(feature = "ash", feature = "drm", test))]
     pub(crate) fn fit(&self, con: Option<Constraint>) -> bool {
         if con.is_none() {
             return true;
@@ -500,30 +846,36 @@ impl Layout {
     }
 }
 
-pub(crate) enum HandlePayload {
-    DmaBuf(dma_buf::Resource),
-    #[cfg(feature = "ash")]
-    Buffer(sash::Buffer),
-    #[cfg(feature = "ash")]
-    Image(sash::Image),
-}
-
 /// An opaque BO handle.
 ///
-/// A BO handle consists of a backend-specific payload.
+/// A BO handle consists of a backend-specific payload.  The type of the payload is known only to
+/// the backend that creates the handle.
 pub struct Handle {
-    pub(crate) payload: HandlePayload,
+    payload: Box<dyn Any + Send + Sync>,
 }
 
 impl From<dma_buf::Resource> for Handle {
     fn from(res: dma_buf::Resource) -> Self {
-        Handle::new(HandlePayload::DmaBuf(res))
+        Handle::new(res)
     }
 }
 
 impl Handle {
-    pub(crate) fn new(payload: HandlePayload) -> Self {
-        Self { payload }
+    /// Creates a BO handle with a backend-specific payload.
+    pub fn new<T: Any + Send + Sync>(payload: T) -> Self {
+        Self {
+            payload: Box::new(payload),
+        }
+    }
+
+    /// Returns the payload if it is of type `T`.
+    pub fn payload<T: Any>(&self) -> Option<&T> {
+        self.payload.downcast_ref()
+    }
+
+    /// Returns the mutable payload if it is of type `T`.
+    pub fn payload_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.payload.downcast_mut()
     }
 }
 
@@ -541,6 +893,193 @@ bitflags::bitflags! {
         const COHERENT = 1 << 2;
         /// The memory mapping is cached.
         const CACHED = 1 << 3;
+        /// The memory is protected.
+        ///
+        /// Only BOs with `Flags::PROTECTED` can be bound to protected memory, and protected memory
+        /// cannot be mapped.
+        const PROTECTED = 1 << 4;
+    }
+}
+
+/// The requirements of a BO.
+///
+/// This is what a BO would be like if it were created, and is returned by dry runs.
+#[derive(Clone, Debug, Default)]
+pub struct Requirements {
+    /// Physical layout of the BO.
+    pub layout: Layout,
+    /// Supported memory types of the BO.
+    pub memory_types: Vec<MemoryType>,
+}
+
+/// The expected memory bandwidth of a modifier relative to other modifiers.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Bandwidth {
+    /// The modifier is compressed.
+    Low,
+    /// The modifier is tiled but not compressed.
+    Medium,
+    /// The modifier is linear.
+    High,
+}
+
+bitflags::bitflags! {
+    /// Per-modifier features of a BO class.
+    ///
+    /// Modifier features are what the backend supports for images of a BO class with a modifier,
+    /// beyond what the BO class requires.  They are empty when unknown.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct ModifierFeatures: u32 {
+        /// The image can be copied to and copied from.
+        const TRANSFER = 1 << 0;
+        /// The image can be sampled.
+        const SAMPLED = 1 << 1;
+        /// The image can be sampled with linear filtering.
+        const SAMPLED_LINEAR = 1 << 2;
+        /// The image can be used as a storage image.
+        const STORAGE = 1 << 3;
+        /// The image can be used as a color attachment.
+        const COLOR = 1 << 4;
+        /// The memory planes of the image can be bound to separate memories.
+        const DISJOINT = 1 << 5;
+    }
+}
+
+/// A per-modifier hint of a BO class.
+///
+/// A hint helps users choose between supported modifiers.  It is based on known vendor modifier
+/// encodings and driver quirks, and is not a guarantee.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ModifierHint {
+    /// The modifier.
+    pub modifier: Modifier,
+    /// The modifier is known to be compressed.
+    pub compressed: bool,
+    /// The expected bandwidth, or `None` if unknown.
+    pub bandwidth: Option<Bandwidth>,
+}
+
+/// A memory heap.
+///
+/// A memory heap describes the size and the budget of a heap that memories are allocated from.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MemoryHeap {
+    /// Size of the heap in bytes.
+    pub size: Size,
+    /// How much memory the process can use from the heap in bytes.  This is equal to `size` if
+    /// unknown.
+    pub budget: Size,
+    /// How much memory the process uses from the heap in bytes.  This is 0 if unknown.
+    pub usage: Size,
+    /// The heap is local to the device.
+    pub local: bool,
+}
+
+/// Device identification.
+///
+/// Device info identifies the device and the driver behind a backend.  It is meant for logging
+/// and for driver-specific quirks.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DeviceInfo {
+    /// Name of the device.
+    pub name: String,
+    /// Vendor id of the device, such as a PCI vendor id.
+    pub vendor_id: u32,
+    /// Device id of the device, such as a PCI device id.
+    pub device_id: u32,
+    /// Driver id (`VkDriverId`).  This is 0 if unknown.
+    pub driver_id: i32,
+    /// Name of the driver.
+    pub driver_name: String,
+    /// Additional driver information, such as the driver version string.
+    pub driver_info: String,
+    /// Driver version, in a driver-specific encoding.
+    pub driver_version: u32,
+    /// Vulkan conformance version that the driver passed, as major, minor, subminor and patch.
+    /// This is all zeros if the driver is not conformant or if unknown.
+    pub conformance_version: [u8; 4],
+}
+
+/// Copy statistics.
+///
+/// Copy statistics aggregate the GPU time of profiled copies of the same format and size class.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CopyStats {
+    /// Format of the image of the copies.  This is `Format::default()` for buffer-to-buffer
+    /// copies.
+    pub format: Format,
+    /// Size class of the copies in bytes.  This is a power of two, and copies of at least the
+    /// size class and less than twice the size class are aggregated.
+    pub size_class: Size,
+    /// Number of copies.
+    pub count: u64,
+    /// Total size of the copies in bytes.
+    pub size: Size,
+    /// Total GPU time of the copies.
+    pub duration: Duration,
+}
+
+impl CopyStats {
+    /// Returns the average copy bandwidth in bytes per second.
+    pub fn bandwidth(&self) -> f64 {
+        let secs = self.duration.as_secs_f64();
+        if secs > 0.0 {
+            self.size as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Device features.
+    ///
+    /// Features describe optional capabilities of a device.  They are aggregated across backends.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct Features: u32 {
+        /// Explicit DRM format modifiers are supported.
+        const MODIFIERS = 1 << 0;
+        /// `Flags::PROTECTED` is supported.
+        const PROTECTED = 1 << 1;
+        /// `Flags::NO_COMPRESSION` is supported without falling back to linear, and
+        /// `Description::fixed_rate` is supported.
+        const COMPRESSION_CONTROL = 1 << 2;
+        /// Copies can return sync files.
+        const SYNC_FD_EXPORT = 1 << 3;
+        /// BOs can be copied.
+        const COPY_ENGINE = 1 << 4;
+        /// Images can be accessed by the CPU without staging buffers.
+        ///
+        /// Non-linear images can be read and written with `Bo::read_image` and `Bo::write_image`.
+        const HOST_IMAGE_COPY = 1 << 5;
+        /// The device is a software implementation running on the CPU.
+        const SOFTWARE = 1 << 6;
+        /// The device belongs to a device group of multiple physical devices.
+        ///
+        /// Memories are allocated from the device alone.  Some external memory capabilities may
+        /// be unavailable.
+        const DEVICE_GROUP = 1 << 7;
+        /// Buffers can be bound to host allocations with `Bo::bind_host_memory`.
+        const HOST_MEMORY = 1 << 8;
+        /// Buffers can have sparse memory bindings with `Bo::bind_sparse_region`.
+        const SPARSE = 1 << 9;
+        /// BOs can be mapped at caller-provided addresses with `Bo::map_placed`.
+        const PLACED_MAP = 1 << 10;
+    }
+}
+
+bitflags::bitflags! {
+    /// External memory capabilities.
+    ///
+    /// External memory capabilities describe how memories of a BO class can be shared.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct ExternalMemory: u32 {
+        /// Memories can be exported as dma-bufs.
+        const EXPORT = 1 << 0;
+        /// Memories can be imported from dma-bufs.
+        const IMPORT = 1 << 1;
+        /// Memories must be dedicated to the BOs.
+        const DEDICATED_ONLY = 1 << 2;
     }
 }
 
@@ -566,6 +1105,9 @@ pub struct CopyBufferImage {
     pub offset: Size,
     /// Row stride of the buffer in bytes.
     pub stride: Size,
+    /// Depth slice stride of the buffer in bytes.  This is ignored unless `depth` is greater
+    /// than 1.
+    pub slice_stride: Size,
 
     /// Format plane index of the image.
     pub plane: u32,
@@ -573,16 +1115,152 @@ pub struct CopyBufferImage {
     pub x: u32,
     /// Starting Y coordinate of the image in texels.
     pub y: u32,
+    /// Starting Z coordinate of the image in texels.  This must be 0 unless the image is 3D.
+    pub z: u32,
+    /// Width to copy in texels.
+    pub width: u32,
+    /// Height to copy in texels.
+    pub height: u32,
+    /// Depth to copy in texels.  This must be 1 unless the image is 3D.
+    pub depth: u32,
+}
+
+/// An image-image copy.
+///
+/// This struct describes a copy between two images.  The coordinates are in texels of the
+/// respective format planes.
+#[derive(Clone, Copy, Debug)]
+pub struct CopyImage {
+    /// Format plane index of the src.
+    pub src_plane: u32,
+    /// Starting X coordinate of the src in texels.
+    pub src_x: u32,
+    /// Starting Y coordinate of the src in texels.
+    pub src_y: u32,
+    /// Starting Z coordinate of the src in texels.
+    pub src_z: u32,
+
+    /// Format plane index of the dst.
+    pub dst_plane: u32,
+    /// Starting X coordinate of the dst in texels.
+    pub dst_x: u32,
+    /// Starting Y coordinate of the dst in texels.
+    pub dst_y: u32,
+    /// Starting Z coordinate of the dst in texels.
+    pub dst_z: u32,
+
     /// Width to copy in texels.
     pub width: u32,
     /// Height to copy in texels.
     pub height: u32,
+    /// Depth to copy in texels.  This must be 1 unless both images are 3D.
+    pub depth: u32,
+}
+
+/// A copy between two BO handles in a batch.
+///
+/// Each variant holds the dst handle, the src handle, and the copy.
+#[derive(Clone, Copy)]
+pub enum CopyOp<'a> {
+    /// A copy between two BO handles that are both buffers.
+    Buffer(&'a Handle, &'a Handle, CopyBuffer),
+    /// A copy between two BO handles where one is a buffer and one is an image.
+    BufferImage(&'a Handle, &'a Handle, CopyBufferImage),
+    /// A copy between two BO handles that are both images.
+    Image(&'a Handle, &'a Handle, CopyImage),
+}
+
+/// A region of a BO to read or write from host memory.
+///
+/// This is used by `Bo::read` and `Bo::write`.
+#[derive(Clone, Copy, Debug)]
+pub enum Region {
+    /// A byte range of a buffer that starts at the offset.  The range is as large as the host
+    /// memory.
+    Buffer(Size),
+    /// A region of an image, and where the region is in host memory as if the host memory were a
+    /// buffer.
+    Image(CopyBufferImage),
+}
+
+/// A rectangle of an image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    /// Starting X coordinate in texels.
+    pub x: u32,
+    /// Starting Y coordinate in texels.
+    pub y: u32,
+    /// Width in texels.
+    pub width: u32,
+    /// Height in texels.
+    pub height: u32,
+}
+
+/// A filter for scaled blits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Nearest-neighbor filtering.
+    #[default]
+    Nearest,
+    /// Bilinear filtering.
+    Linear,
+}
+
+/// A fill value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fill {
+    /// A 32-bit value repeated over a buffer.
+    Buffer(u32),
+    /// An RGBA color, in normalized floats, that an image with a non-integer format is cleared
+    /// to.
+    Color([f32; 4]),
+    /// An RGBA color, in 32-bit integers, that an image with an integer format is cleared to.
+    ///
+    /// Signed components are in two's complement.
+    ColorInt([u32; 4]),
 }
 
 /// A trait that all backends must implement.
 ///
 /// `Device` and `Bo` are the user-facing wrappers for this trait.
 pub trait Backend: Send + Sync {
+    /// Returns the features of the backend.
+    ///
+    /// Backends have no features by default, and copies are unsupported unless backends
+    /// implement them.
+    fn features(&self) -> Features {
+        Features::empty()
+    }
+
+    /// Returns the memory heaps of the backend.
+    fn memory_heaps(&self) -> Vec<MemoryHeap> {
+        Vec::new()
+    }
+
+    /// Returns the device info of the backend, or `None` if the backend has no device.
+    fn device_info(&self) -> Option<DeviceInfo> {
+        None
+    }
+
+    /// Returns the copy statistics of the backend.
+    ///
+    /// This is empty unless the backend profiles copies.
+    fn copy_stats(&self) -> Vec<CopyStats> {
+        Vec::new()
+    }
+
+    /// Sets the timeout of the waits that the backend performs for copies.
+    ///
+    /// This is called by `Builder::build` with `Builder::copy_timeout`.  When the timeout expires,
+    /// the copy fails with `Error::Device`.  `None` means no timeout.
+    fn set_copy_timeout(&mut self, _timeout: Option<Duration>) {}
+
+    /// Returns the total size and the free size of the memories that the backend suballocates
+    /// BOs from.
+    fn slab_sizes(&self) -> (Size, Size) {
+        (0, 0)
+    }
+
     /// Returns the memory plane count of a format and a modifier.
     fn memory_plane_count(&self, _fmt: Format, _modifier: Modifier) -> Result<u32> {
         Error::unsupported()
@@ -593,6 +1271,11 @@ pub trait Backend: Send + Sync {
         dma_buf::classify(desc, usage)
     }
 
+    /// Returns the external memory capabilities of a BO class and a modifier.
+    fn external_memory(&self, class: &Class, modifier: Modifier) -> Result<ExternalMemory> {
+        dma_buf::external_memory(class, modifier)
+    }
+
     /// Creates a BO handle with an optional constraint.
     fn with_constraint(
         &self,
@@ -603,6 +1286,19 @@ pub trait Backend: Send + Sync {
         dma_buf::with_constraint(class, extent, con)
     }
 
+    /// Computes the requirements of a BO handle that `with_constraint` would create.
+    ///
+    /// No memory nor kernel object is allocated.  `Error::Unsupported` is returned if the backend
+    /// cannot compute the requirements without allocating.
+    fn dry_run(
+        &self,
+        class: &Class,
+        extent: Extent,
+        con: Option<Constraint>,
+    ) -> Result<Requirements> {
+        dma_buf::dry_run(class, extent, con)
+    }
+
     /// Creates a BO handle with an explicit physical layout.
     fn with_layout(
         &self,
@@ -637,11 +1333,79 @@ pub trait Backend: Send + Sync {
         Error::unsupported()
     }
 
+    /// Allocates or imports a memory for each memory plane, and binds the memories to a BO
+    /// handle.
+    fn bind_memory_planes(
+        &self,
+        _handle: &mut Handle,
+        _mt: MemoryType,
+        _dmabufs: Option<Vec<OwnedFd>>,
+    ) -> Result<()> {
+        Error::unsupported()
+    }
+
+    /// Returns the compression state of a BO handle.
+    fn compression(&self, _handle: &Handle) -> Compression {
+        Compression::Unknown
+    }
+
+    /// Returns the sparse block size of a BO handle, or `None` if the BO handle is not sparse.
+    fn sparse_block_size(&self, _handle: &Handle) -> Option<Size> {
+        None
+    }
+
+    /// Allocates a memory, and binds the memory to a region of a sparse BO handle.
+    fn bind_sparse_region(
+        &self,
+        _handle: &mut Handle,
+        _mt: MemoryType,
+        _offset: Size,
+        _size: Size,
+    ) -> Result<()> {
+        Error::unsupported()
+    }
+
+    /// Unbinds and frees the memories bound to a region of a sparse BO handle.
+    ///
+    /// This returns the total size of the memories that are freed.
+    fn unbind_sparse_region(
+        &self,
+        _handle: &mut Handle,
+        _offset: Size,
+        _size: Size,
+    ) -> Result<Size> {
+        Error::unsupported()
+    }
+
+    /// Imports a host allocation, and binds the memory to a BO handle.
+    ///
+    /// # Safety
+    ///
+    /// The host allocation must stay valid until the BO handle is freed.
+    unsafe fn bind_host_memory(
+        &self,
+        _handle: &mut Handle,
+        _mt: MemoryType,
+        _ptr: ptr::NonNull<ffi::c_void>,
+    ) -> Result<()> {
+        Error::unsupported()
+    }
+
+    /// Sets the debug name of a BO handle.
+    ///
+    /// The name is for debugging only, and backends without debugging facilities ignore it.
+    fn set_name(&self, _handle: &Handle, _name: &str) {}
+
     /// Exports a BO handle as a dma-buf.
     fn export_dma_buf(&self, handle: &Handle, name: Option<&str>) -> Result<OwnedFd> {
         dma_buf::export_dma_buf(handle, name)
     }
 
+    /// Exports a BO handle as a dma-buf for each memory.
+    fn export_dma_bufs(&self, handle: &Handle, name: Option<&str>) -> Result<Vec<OwnedFd>> {
+        self.export_dma_buf(handle, name).map(|dmabuf| vec![dmabuf])
+    }
+
     /// Maps a BO handle for CPU access.
     fn map(&self, handle: &Handle) -> Result<Mapping> {
         dma_buf::map(handle)
@@ -652,6 +1416,26 @@ pub trait Backend: Send + Sync {
         dma_buf::unmap(handle, mapping)
     }
 
+    /// Maps a BO handle for CPU access at a fixed address.
+    ///
+    /// # Safety
+    ///
+    /// See `Bo::map_placed`.
+    unsafe fn map_placed(
+        &self,
+        handle: &Handle,
+        addr: ptr::NonNull<ffi::c_void>,
+    ) -> Result<Mapping> {
+        dma_buf::map_placed(handle, addr)
+    }
+
+    /// Unmaps a BO handle mapped by `map_placed`.
+    ///
+    /// The address range of the mapping stays reserved by an inaccessible mapping.
+    fn unmap_placed(&self, handle: &Handle, mapping: Mapping) {
+        dma_buf::unmap_placed(handle, mapping)
+    }
+
     /// Flushes the CPU cache for the BO mapping.
     fn flush(&self, handle: &Handle) {
         dma_buf::flush(handle);
@@ -662,6 +1446,20 @@ pub trait Backend: Send + Sync {
         dma_buf::invalidate(handle);
     }
 
+    /// Flushes the CPU cache for a range of the BO mapping.
+    ///
+    /// Backends that cannot flush ranges flush the entire BO mapping.
+    fn flush_range(&self, handle: &Handle, _offset: Size, _size: Size) {
+        dma_buf::flush(handle);
+    }
+
+    /// Invalidates the CPU cache for a range of the BO mapping.
+    ///
+    /// Backends that cannot invalidate ranges invalidate the entire BO mapping.
+    fn invalidate_range(&self, handle: &Handle, _offset: Size, _size: Size) {
+        dma_buf::invalidate(handle);
+    }
+
     /// Copies between two BO handles that are both buffers.
     fn copy_buffer(
         &self,
@@ -683,6 +1481,69 @@ pub trait Backend: Send + Sync {
     ) -> Result<Option<OwnedFd>> {
         Error::unsupported()
     }
+
+    /// Copies between two BO handles that are both images.
+    fn copy_image(
+        &self,
+        _dst: &Handle,
+        _src: &Handle,
+        _copy: CopyImage,
+        _sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        Error::unsupported()
+    }
+
+    /// Copies between BO handles in a batch.
+    ///
+    /// The copies are executed in order.  Backends that cannot batch copies execute them one by
+    /// one, with each copy waiting for the previous one.
+    fn copy_batch(&self, copies: &[CopyOp], sync_fd: Option<OwnedFd>) -> Result<Option<OwnedFd>> {
+        let mut sync_fd = sync_fd;
+        for &op in copies {
+            sync_fd = match op {
+                CopyOp::Buffer(dst, src, copy) => self.copy_buffer(dst, src, copy, sync_fd),
+                CopyOp::BufferImage(dst, src, copy) => {
+                    self.copy_buffer_image(dst, src, copy, sync_fd)
+                }
+                CopyOp::Image(dst, src, copy) => self.copy_image(dst, src, copy, sync_fd),
+            }?;
+        }
+
+        Ok(sync_fd)
+    }
+
+    /// Copies from a BO handle that is an image to host memory.
+    fn read_image(&self, _handle: &Handle, _dst: &mut [u8], _copy: CopyBufferImage) -> Result<()> {
+        Error::unsupported()
+    }
+
+    /// Copies from host memory to a BO handle that is an image.
+    fn write_image(&self, _handle: &Handle, _src: &[u8], _copy: CopyBufferImage) -> Result<()> {
+        Error::unsupported()
+    }
+
+    /// Fills a BO handle.
+    fn fill(
+        &self,
+        _handle: &Handle,
+        _fill: Fill,
+        _sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        Error::unsupported()
+    }
+
+    /// Blits between two BO handles that are both images.
+    fn blit(
+        &self,
+        _dst: &Handle,
+        _src: &Handle,
+        _src_rect: Rect,
+        _dst_rect: Rect,
+        _filter: Filter,
+        _sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        Error::unsupported()
+    }
 }
 
 #[cfg(test)]
@@ -709,6 +1570,17 @@ mod tests {
         desc = desc.format(formats::R8);
         assert!(desc.is_valid());
         assert!(!desc.is_buffer());
+
+        // the priority is a hint
+        desc = desc.priority(MemoryPriority::High);
+        assert!(desc.is_valid());
+        assert_eq!(Class::new(desc).priority, Some(MemoryPriority::High));
+
+        // fixed-rate compression requires compression
+        desc = desc.fixed_rate(FixedRate::BPC_2 | FixedRate::BPC_4);
+        assert!(desc.is_valid());
+        assert!(!desc.flags(Flags::NO_COMPRESSION).is_valid());
+        assert!(!desc.format(formats::INVALID).is_valid());
     }
 
     #[test]
@@ -736,6 +1608,14 @@ mod tests {
         assert!(!img_class.validate(Extent::Image(6, 10)));
         assert!(!img_class.validate(Extent::Image(5, 11)));
         assert!(!img_class.validate(Extent::Image(6, 11)));
+
+        let cpu_usage = CpuUsage::READ_OFTEN | CpuUsage::WRITE_RARELY;
+        let cpu_class = Class::new(buf_desc).usage(Usage::Cpu(cpu_usage));
+        assert_eq!(cpu_class.cpu_usage, cpu_usage);
+        assert!(cpu_usage
+            .preferred_memory_type()
+            .contains(MemoryType::MAPPABLE | MemoryType::CACHED));
+        assert!(CpuUsage::empty().preferred_memory_type().is_empty());
     }
 
     #[test]
@@ -780,6 +1660,25 @@ mod tests {
             assert_eq!(extent.width(), cmp::min(w1, w2));
             assert_eq!(extent.height(), cmp::min(h1, h2));
         }
+
+        let extent = Extent::Image3D(5, 10, 3);
+        assert!(extent.is_3d());
+        assert_eq!(
+            (extent.width(), extent.height(), extent.depth()),
+            (5, 10, 3)
+        );
+        assert_eq!(Extent::Image(5, 10).depth(), 1);
+        assert!(Extent::Image3D(1, 1, 0).is_empty());
+
+        // 2D extents do not limit the depth
+        let mut extent = Extent::Image3D(20, 20, 20);
+        extent.intersect(Extent::Image(10, 30));
+        assert_eq!(
+            (extent.width(), extent.height(), extent.depth()),
+            (10, 20, 20)
+        );
+        extent.intersect(Extent::Image3D(30, 30, 5));
+        assert_eq!(extent.depth(), 5);
     }
 
     #[test]
@@ -812,6 +1711,25 @@ mod tests {
             .size_align(32);
         assert_eq!(Constraint::unpack(Some(con)), (8, 16, 32));
         assert_eq!(Constraint::unpack(None), (1, 1, 1));
+
+        // the preference of the constraint being merged into wins
+        let mut con = Constraint::new().preferred_modifiers(vec![formats::MOD_LINEAR]);
+        con.merge(Constraint::new().preferred_modifiers(vec![formats::MOD_INVALID]));
+        assert_eq!(con.preferred_modifiers, vec![formats::MOD_LINEAR]);
+    }
+
+    #[test]
+    fn test_copy_stats() {
+        let mut stats = CopyStats {
+            size_class: 1 << 20,
+            count: 2,
+            size: 3 << 20,
+            ..Default::default()
+        };
+        assert_eq!(stats.bandwidth(), 0.0);
+
+        stats.duration = Duration::from_millis(3);
+        assert_eq!(stats.bandwidth(), 1000.0 * (1 << 20) as f64);
     }
 
     #[test]
@@ -880,5 +1798,17 @@ mod tests {
         // for size align, we care about the size itself rather than its real alignment
         let con = Constraint::new().size_align(64);
         assert!(img_layout.fit(Some(con)));
+
+        assert_eq!(img_layout.strides_u32().unwrap(), [8, 0, 0, 0]);
+        assert_eq!(img_layout.offsets_u32().unwrap(), [0; 4]);
+        let img_layout = img_layout.stride(0, 1 << 32).offset(1, 1 << 32);
+        assert!(matches!(
+            img_layout.strides_u32(),
+            Err(Error::IntegerConversion)
+        ));
+        assert!(matches!(
+            img_layout.offsets_u32(),
+            Err(Error::IntegerConversion)
+        ));
     }
 }
@@ -5,7 +5,7 @@
 //!
 //! This module provides a backend for dma-heaps.
 
-use super::{Handle, MemoryType};
+use super::{CopyBuffer, CopyBufferImage, CopyImage, Features, Fill, Handle, MemoryType};
 use crate::dma_buf;
 use crate::types::{Error, Result};
 use crate::utils;
@@ -17,6 +17,10 @@ pub struct Backend {
 }
 
 impl super::Backend for Backend {
+    fn features(&self) -> Features {
+        dma_buf::features()
+    }
+
     fn bind_memory(
         &self,
         handle: &mut Handle,
@@ -26,6 +30,53 @@ impl super::Backend for Backend {
         let alloc = |size| utils::dma_heap_alloc(&self.fd, size);
         dma_buf::bind_memory(handle, mt, dmabuf, alloc)
     }
+
+    fn copy_buffer(
+        &self,
+        dst: &Handle,
+        src: &Handle,
+        copy: CopyBuffer,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        dma_buf::copy_buffer(dst, src, copy, sync_fd)
+    }
+
+    fn copy_buffer_image(
+        &self,
+        dst: &Handle,
+        src: &Handle,
+        copy: CopyBufferImage,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        dma_buf::copy_buffer_image(dst, src, copy, sync_fd)
+    }
+
+    fn copy_image(
+        &self,
+        dst: &Handle,
+        src: &Handle,
+        copy: CopyImage,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        dma_buf::copy_image(dst, src, copy, sync_fd)
+    }
+
+    fn read_image(&self, handle: &Handle, dst: &mut [u8], copy: CopyBufferImage) -> Result<()> {
+        dma_buf::read_image(handle, dst, copy)
+    }
+
+    fn write_image(&self, handle: &Handle, src: &[u8], copy: CopyBufferImage) -> Result<()> {
+        dma_buf::write_image(handle, src, copy)
+    }
+
+    fn fill(
+        &self,
+        handle: &Handle,
+        fill: Fill,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        dma_buf::fill(handle, fill, sync_fd)
+    }
 }
 
 /// A dma-heap backend builder.
@@ -5,7 +5,10 @@
 //!
 //! This module provides a backend for DRM KMS.
 
-use super::{Class, Constraint, Description, Extent, Handle, Layout, MemoryType};
+use super::{
+    Class, Constraint, CopyBufferImage, CopyImage, Description, Extent, Features, Fill, Handle,
+    Layout, MemoryType, Requirements,
+};
 use crate::dma_buf;
 use crate::formats;
 use crate::types::{Error, Format, Modifier, Result, Size};
@@ -65,7 +68,7 @@ fn get_drm_usage(usage: super::Usage) -> Result<Usage> {
 struct Device(OwnedFd);
 
 impl AsFd for Device {
-    fn as_fd(&self) -> BorrowedFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
         self.0.as_fd()
     }
 }
@@ -81,6 +84,7 @@ pub struct Backend {
 
     max_width: u32,
     max_height: u32,
+    in_formats: bool,
     primary_formats: FormatTable,
     cursor_formats: FormatTable,
 }
@@ -92,6 +96,7 @@ impl Backend {
             alloc_only,
             max_width: 0,
             max_height: 0,
+            in_formats: false,
             primary_formats: HashMap::new(),
             cursor_formats: HashMap::new(),
         };
@@ -151,16 +156,12 @@ impl Backend {
 
             let name = prop.name().to_str().unwrap();
             match prop.value_type() {
-                drm::control::property::ValueType::Enum(_) => {
-                    if name == "type" {
-                        ty = Some(val);
-                    }
+                drm::control::property::ValueType::Enum(_) if name == "type" => {
+                    ty = Some(val);
                 }
-                drm::control::property::ValueType::Blob => {
-                    if name == "IN_FORMATS" {
-                        let blob = self.device.get_property_blob(val)?;
-                        in_fmts = Some(blob);
-                    }
+                drm::control::property::ValueType::Blob if name == "IN_FORMATS" => {
+                    let blob = self.device.get_property_blob(val)?;
+                    in_fmts = Some(blob);
                 }
                 _ => (),
             }
@@ -201,6 +202,8 @@ impl Backend {
                 return;
             };
 
+            self.in_formats = true;
+
             for (modifier, fmt) in iter {
                 let mods = fmts.entry(Format(fmt)).or_default();
 
@@ -235,7 +238,7 @@ impl Backend {
         let mods = if modifier.is_invalid() {
             mods.clone()
         } else {
-            if !mods.iter().any(|m| *m == modifier) {
+            if !mods.contains(&modifier) {
                 return Error::unsupported();
             }
 
@@ -247,8 +250,17 @@ impl Backend {
 }
 
 impl super::Backend for Backend {
+    fn features(&self) -> Features {
+        let feats = dma_buf::features();
+        if self.in_formats {
+            feats | Features::MODIFIERS
+        } else {
+            feats
+        }
+    }
+
     fn classify(&self, desc: Description, usage: super::Usage) -> Result<Class> {
-        if desc.is_buffer() {
+        if desc.is_buffer() || !desc.fixed_rate.is_empty() {
             return Error::unsupported();
         }
 
@@ -270,6 +282,11 @@ impl super::Backend for Backend {
     ) -> Result<Handle> {
         assert!(!class.is_buffer());
 
+        // dumb buffers are 2D
+        if extent.is_3d() {
+            return Error::unsupported();
+        }
+
         let fmt_class = formats::format_class(class.format)?;
         let size = (extent.width(), extent.height());
         let fmt = DrmFourcc::try_from(class.format.0).or(Error::unsupported())?;
@@ -285,7 +302,7 @@ impl super::Backend for Backend {
         let dmabuf = dmabuf?;
 
         let layout = Layout::new()
-            .size((extent.height() * pitch) as Size)
+            .size(extent.height() as Size * pitch as Size)
             .modifier(formats::MOD_LINEAR)
             .plane_count(1)
             .stride(0, pitch as Size);
@@ -293,13 +310,23 @@ impl super::Backend for Backend {
             return Error::unsupported();
         }
 
-        let mut res = dma_buf::Resource::new(layout);
+        let mut res = dma_buf::Resource::new(class.format, layout);
         res.bind_memory(dmabuf);
         let handle = Handle::from(res);
 
         Ok(handle)
     }
 
+    fn dry_run(
+        &self,
+        _class: &Class,
+        _extent: Extent,
+        _con: Option<Constraint>,
+    ) -> Result<Requirements> {
+        // the pitch of a dumb buffer is decided by the kernel on allocation
+        Error::unsupported()
+    }
+
     fn bind_memory(
         &self,
         handle: &mut Handle,
@@ -309,6 +336,92 @@ impl super::Backend for Backend {
         let alloc = |_| Error::user();
         dma_buf::bind_memory(handle, mt, dmabuf, alloc)
     }
+
+    fn copy_buffer_image(
+        &self,
+        dst: &Handle,
+        src: &Handle,
+        copy: CopyBufferImage,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        dma_buf::copy_buffer_image(dst, src, copy, sync_fd)
+    }
+
+    fn copy_image(
+        &self,
+        dst: &Handle,
+        src: &Handle,
+        copy: CopyImage,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        dma_buf::copy_image(dst, src, copy, sync_fd)
+    }
+
+    fn read_image(&self, handle: &Handle, dst: &mut [u8], copy: CopyBufferImage) -> Result<()> {
+        dma_buf::read_image(handle, dst, copy)
+    }
+
+    fn write_image(&self, handle: &Handle, src: &[u8], copy: CopyBufferImage) -> Result<()> {
+        dma_buf::write_image(handle, src, copy)
+    }
+
+    fn fill(
+        &self,
+        handle: &Handle,
+        fill: Fill,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        dma_buf::fill(handle, fill, sync_fd)
+    }
+}
+
+// a DRM device borrowed from legacy users
+#[cfg(feature = "drm-legacy")]
+struct LegacyDevice<'a>(BorrowedFd<'a>);
+
+#[cfg(feature = "drm-legacy")]
+impl AsFd for LegacyDevice<'_> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0
+    }
+}
+#[cfg(feature = "drm-legacy")]
+impl DrmDevice for LegacyDevice<'_> {}
+#[cfg(feature = "drm-legacy")]
+impl DrmControlDevice for LegacyDevice<'_> {}
+
+/// Returns a GEM handle of a BO on a DRM device.
+///
+/// This is for legacy users that cannot use dma-bufs.  The BO must have `Flags::EXTERNAL` and
+/// must have a memory bound.  The GEM handle is owned by the caller, who must close it with
+/// `close_gem_handle`.  Because GEM handles are per-device rather than per-import, importing the
+/// same BO twice on the same device returns the same GEM handle.
+#[cfg(feature = "drm-legacy")]
+pub fn gem_handle(node_fd: BorrowedFd, bo: &crate::Bo) -> Result<u32> {
+    let dmabuf = bo.export_dma_buf(None)?;
+    let handle = LegacyDevice(node_fd).prime_fd_to_buffer(dmabuf.as_fd())?;
+
+    Ok(handle.into())
+}
+
+/// Closes a GEM handle returned by `gem_handle`.
+#[cfg(feature = "drm-legacy")]
+pub fn close_gem_handle(node_fd: BorrowedFd, handle: u32) -> Result<()> {
+    let handle: drm::buffer::Handle = drm::control::from_u32(handle).ok_or(Error::User)?;
+    LegacyDevice(node_fd).close_buffer(handle)?;
+
+    Ok(())
+}
+
+/// Returns a global GEM name of a GEM handle.
+///
+/// GEM names are global and can be guessed.  Any process that can open the DRM device can access
+/// the BO by its GEM name.  This is only for legacy users that cannot use dma-bufs.
+///
+/// The GEM name is valid until all GEM handles of the BO are closed.
+#[cfg(feature = "drm-legacy")]
+pub fn gem_flink(node_fd: BorrowedFd, handle: u32) -> Result<u32> {
+    utils::drm_gem_flink(node_fd, handle)
 }
 
 /// A DRM KMS backend builder.
@@ -6,17 +6,20 @@
 //! This module provides a backend for Vulkan.
 
 use super::{
-    Class, Constraint, CopyBuffer, CopyBufferImage, Description, Extent, Flags, Handle,
-    HandlePayload, Layout, MemoryType,
+    Class, Compression, Constraint, CopyBuffer, CopyBufferImage, CopyImage, CopyOp, CopyStats,
+    Description, Extent, ExternalMemory, Features, Fill, Filter, FixedRate, Flags, Handle, Layout,
+    MemoryHeap, MemoryPriority, MemoryType, ModifierFeatures, Rect, Requirements,
 };
 use crate::formats;
 use crate::sash;
-use crate::types::{Access, Error, Format, Mapping, Modifier, Result};
+use crate::types::{Error, Format, Mapping, Modifier, Result, Size};
 use crate::utils;
 use ash::vk;
 use std::os::fd::{BorrowedFd, OwnedFd};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::{num, ptr};
+use std::time::Duration;
+use std::{ffi, num, ptr};
 
 bitflags::bitflags! {
     /// A Vulkan backend usage.
@@ -36,9 +39,73 @@ bitflags::bitflags! {
         ///
         /// This is a hack until we can require `VK_EXT_image_drm_format_modifier`.
         const SCANOUT_HACK = 1 << 5;
+        /// The BO can be sampled with a sampler YCbCr conversion.
+        ///
+        /// This implies `Usage::SAMPLED` and is only valid for YCbCr formats.  Multi-planar
+        /// formats are further created with `VK_IMAGE_CREATE_MUTABLE_FORMAT_BIT` and a format list
+        /// of the per-plane formats, such that the planes can also be viewed individually.
+        const YCBCR_SAMPLER = 1 << 6;
+        /// The memory planes of the BO are bound to separate memories.
+        ///
+        /// This is only valid for multi-planar formats.  The BO must be bound with
+        /// `Bo::bind_memory_planes`, must be exported with `Bo::export_dma_bufs`, and cannot be
+        /// mapped.  The layout offsets are relative to the plane memories, and the layout size is
+        /// the total size.
+        const DISJOINT = 1 << 7;
+        /// The BO can be copied to and from host memory by the CPU.
+        ///
+        /// This requires `Features::HOST_IMAGE_COPY` and is only valid for images.  It allows
+        /// `Bo::read_image` and `Bo::write_image` on non-linear images, but can limit the
+        /// supported modifiers on some drivers.
+        const HOST_TRANSFER = 1 << 8;
+        /// The BO can be bound to a host allocation.
+        ///
+        /// This requires `Features::HOST_MEMORY`, is only valid for buffers, and cannot be combined
+        /// with `Flags::EXTERNAL`.  It allows `Bo::bind_host_memory`.
+        const HOST_MEMORY = 1 << 9;
+        /// The BO has sparse memory bindings.
+        ///
+        /// This requires `Features::SPARSE` and is only valid for buffers.  The BO must be bound
+        /// with `Bo::bind_sparse_region` and cannot be external, mapped, protected, nor bound to a
+        /// host allocation.
+        const SPARSE = 1 << 10;
+        /// The BO can be viewed as sRGB.
+        ///
+        /// This is only valid for UNORM formats that have sRGB equivalents, such as
+        /// `DRM_FORMAT_ABGR8888`.  The image is created with `VK_IMAGE_CREATE_MUTABLE_FORMAT_BIT` and
+        /// a format list of the UNORM and sRGB formats, which keeps compression enabled on drivers
+        /// that honor format lists.  It requires `VK_KHR_image_format_list`.
+        const SRGB_VIEW = 1 << 11;
+        /// The memory of the BO is exported and imported as an opaque fd.
+        ///
+        /// This requires `Flags::EXTERNAL` and cannot be combined with `Usage::DMA_BUF`.  An opaque
+        /// fd can only be imported by Vulkan devices with the same device and driver UUIDs, and is
+        /// meant for Vulkan-to-Vulkan sharing.  `Bo::export_dma_buf` returns the opaque fd, which
+        /// is not necessarily a dma-buf.
+        const OPAQUE_FD = 1 << 12;
+        /// The memory of the BO is exported and imported as a dma-buf.
+        ///
+        /// This requires `Flags::EXTERNAL` and cannot be combined with `Usage::OPAQUE_FD`.  By
+        /// default, the handle type is chosen by the backend, and is an opaque fd that is also a
+        /// dma-buf when `VK_EXT_image_drm_format_modifier` is not supported.
+        const DMA_BUF = 1 << 13;
     }
 }
 
+/// Returns an external format for a Vulkan format (`VkFormat`).
+///
+/// An external format is an opaque format for Vulkan formats that have no DRM format equivalent,
+/// such as exotic camera formats.  It requires `VK_EXT_image_drm_format_modifier`.  Only the format
+/// properties reported by the driver are known, and BOs of an external format are restricted to
+/// copy-through and sample-through: they can only have `Usage::TRANSFER`, `Usage::SAMPLED`, and
+/// `Usage::YCBCR_SAMPLER`, cannot be mapped, and can only be copied to and from BOs of the same
+/// external format and extent.
+///
+/// `Error::User` is returned if `vk_format` is not a valid Vulkan format.
+pub fn external_format(vk_format: i32) -> Result<Format> {
+    formats::external_format(vk_format as u32).ok_or(Error::User)
+}
+
 fn get_usage(usage: super::Usage, valid_usage: Usage) -> Result<Usage> {
     let usage = match usage {
         super::Usage::Vulkan(usage) => usage,
@@ -52,9 +119,49 @@ fn get_usage(usage: super::Usage, valid_usage: Usage) -> Result<Usage> {
     Ok(usage)
 }
 
+// returns the handle type of external memory that the usage requests, if any
+fn get_external_memory_type(
+    flags: Flags,
+    usage: Usage,
+) -> Result<Option<vk::ExternalMemoryHandleTypeFlags>> {
+    let external_memory_type = match (
+        usage.contains(Usage::OPAQUE_FD),
+        usage.contains(Usage::DMA_BUF),
+    ) {
+        (false, false) => return Ok(None),
+        (true, false) => vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD,
+        (false, true) => vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT,
+        (true, true) => return Error::user(),
+    };
+
+    if !flags.contains(Flags::EXTERNAL) {
+        return Error::user();
+    }
+
+    Ok(Some(external_memory_type))
+}
+
 fn get_buffer_info(flags: Flags, usage: super::Usage) -> Result<sash::BufferInfo> {
-    let valid_usage = Usage::TRANSFER | Usage::UNIFORM | Usage::STORAGE;
+    let valid_usage = Usage::TRANSFER
+        | Usage::UNIFORM
+        | Usage::STORAGE
+        | Usage::HOST_MEMORY
+        | Usage::SPARSE
+        | Usage::OPAQUE_FD
+        | Usage::DMA_BUF;
     let usage = get_usage(usage, valid_usage)?;
+    let external_memory_type = get_external_memory_type(flags, usage)?;
+
+    let host_pointer = usage.contains(Usage::HOST_MEMORY);
+    if host_pointer && flags.contains(Flags::EXTERNAL) {
+        return Error::user();
+    }
+
+    let sparse = usage.contains(Usage::SPARSE);
+    if sparse && (host_pointer || flags.intersects(Flags::EXTERNAL | Flags::MAP | Flags::PROTECTED))
+    {
+        return Error::user();
+    }
 
     let mut buf_flags = vk::BufferCreateFlags::empty();
     let mut buf_usage = vk::BufferUsageFlags::empty();
@@ -62,6 +169,9 @@ fn get_buffer_info(flags: Flags, usage: super::Usage) -> Result<sash::BufferInfo
     if flags.contains(Flags::PROTECTED) {
         buf_flags |= vk::BufferCreateFlags::PROTECTED;
     }
+    if sparse {
+        buf_flags |= vk::BufferCreateFlags::SPARSE_BINDING;
+    }
 
     if flags.contains(Flags::COPY) || usage.contains(Usage::TRANSFER) {
         buf_usage |= vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST;
@@ -82,18 +192,71 @@ fn get_buffer_info(flags: Flags, usage: super::Usage) -> Result<sash::BufferInfo
         flags: buf_flags,
         usage: buf_usage,
         external: flags.contains(Flags::EXTERNAL),
+        external_memory_type,
+        host_pointer,
+        priority: None,
     };
 
     Ok(buf_info)
 }
 
-fn get_image_info(flags: Flags, fmt: Format, usage: super::Usage) -> Result<sash::ImageInfo> {
-    let valid_usage =
-        Usage::TRANSFER | Usage::STORAGE | Usage::SAMPLED | Usage::COLOR | Usage::SCANOUT_HACK;
+fn get_modifier_features(tiling_feats: vk::FormatFeatureFlags2) -> ModifierFeatures {
+    let mut feats = ModifierFeatures::empty();
+    if tiling_feats
+        .contains(vk::FormatFeatureFlags2::TRANSFER_SRC | vk::FormatFeatureFlags2::TRANSFER_DST)
+    {
+        feats |= ModifierFeatures::TRANSFER;
+    }
+    if tiling_feats.contains(vk::FormatFeatureFlags2::SAMPLED_IMAGE) {
+        feats |= ModifierFeatures::SAMPLED;
+    }
+    if tiling_feats.contains(vk::FormatFeatureFlags2::SAMPLED_IMAGE_FILTER_LINEAR) {
+        feats |= ModifierFeatures::SAMPLED_LINEAR;
+    }
+    if tiling_feats.contains(vk::FormatFeatureFlags2::STORAGE_IMAGE) {
+        feats |= ModifierFeatures::STORAGE;
+    }
+    if tiling_feats.contains(vk::FormatFeatureFlags2::COLOR_ATTACHMENT) {
+        feats |= ModifierFeatures::COLOR;
+    }
+    if tiling_feats.contains(vk::FormatFeatureFlags2::DISJOINT) {
+        feats |= ModifierFeatures::DISJOINT;
+    }
+
+    feats
+}
+
+fn get_image_info(
+    flags: Flags,
+    fixed_rate: FixedRate,
+    fmt: Format,
+    usage: super::Usage,
+) -> Result<sash::ImageInfo> {
+    let valid_usage = Usage::TRANSFER
+        | Usage::STORAGE
+        | Usage::SAMPLED
+        | Usage::COLOR
+        | Usage::SCANOUT_HACK
+        | Usage::YCBCR_SAMPLER
+        | Usage::DISJOINT
+        | Usage::HOST_TRANSFER
+        | Usage::SRGB_VIEW
+        | Usage::OPAQUE_FD
+        | Usage::DMA_BUF;
     let usage = get_usage(usage, valid_usage)?;
+    let external_memory_type = get_external_memory_type(flags, usage)?;
+
+    // only copy-through and sample-through are supported for external formats
+    if formats::is_external(fmt) {
+        let external_usage = Usage::TRANSFER | Usage::SAMPLED | Usage::YCBCR_SAMPLER;
+        if !external_usage.contains(usage) || flags.contains(Flags::MAP) {
+            return Error::user();
+        }
+    }
 
     let mut img_flags = vk::ImageCreateFlags::empty();
     let mut img_usage = vk::ImageUsageFlags::empty();
+    let mut view_formats = Vec::new();
     let (img_fmt, _) = formats::to_vk(fmt)?;
 
     if flags.contains(Flags::PROTECTED) {
@@ -112,6 +275,35 @@ fn get_image_info(flags: Flags, fmt: Format, usage: super::Usage) -> Result<sash
     if usage.contains(Usage::COLOR) {
         img_usage |= vk::ImageUsageFlags::COLOR_ATTACHMENT;
     }
+    if usage.contains(Usage::HOST_TRANSFER) {
+        img_usage |= vk::ImageUsageFlags::HOST_TRANSFER_EXT;
+    }
+    if usage.contains(Usage::YCBCR_SAMPLER) {
+        let plane_fmts = formats::vk_plane_formats(img_fmt).ok_or(Error::Unsupported)?;
+
+        img_usage |= vk::ImageUsageFlags::SAMPLED;
+        if plane_fmts.len() > 1 {
+            img_flags |= vk::ImageCreateFlags::MUTABLE_FORMAT;
+            view_formats.push(img_fmt);
+            view_formats.extend_from_slice(plane_fmts);
+        }
+    }
+
+    if usage.contains(Usage::SRGB_VIEW) {
+        let srgb_fmt = formats::vk_srgb_format(img_fmt).ok_or(Error::User)?;
+
+        img_flags |= vk::ImageCreateFlags::MUTABLE_FORMAT;
+        view_formats.push(img_fmt);
+        view_formats.push(srgb_fmt);
+    }
+
+    if usage.contains(Usage::DISJOINT) {
+        if formats::vk_plane_formats(img_fmt).map_or(true, |fmts| fmts.len() < 2) {
+            return Error::user();
+        }
+
+        img_flags |= vk::ImageCreateFlags::DISJOINT;
+    }
 
     // vulkan requires img_usage to be non-empty
     if img_usage.is_empty() {
@@ -122,19 +314,112 @@ fn get_image_info(flags: Flags, fmt: Format, usage: super::Usage) -> Result<sash
         flags: img_flags,
         usage: img_usage,
         format: img_fmt,
+        drm_format: fmt,
+        image_type: vk::ImageType::TYPE_2D,
         external: flags.contains(Flags::EXTERNAL),
+        external_memory_type,
         no_compression: flags.contains(Flags::NO_COMPRESSION),
+        // the bits are identical
+        fixed_rate: vk::ImageCompressionFixedRateFlagsEXT::from_raw(fixed_rate.bits()),
         scanout_hack: usage.contains(Usage::SCANOUT_HACK),
+        priority: None,
+        ycbcr_conversion: usage.contains(Usage::YCBCR_SAMPLER),
+        view_formats,
     };
 
     Ok(img_info)
 }
 
+fn get_copy_region(op: CopyOp) -> Result<sash::CopyRegion> {
+    let region = match op {
+        CopyOp::Buffer(dst, src, copy) => {
+            let region = vk::BufferCopy::default()
+                .src_offset(copy.src_offset)
+                .dst_offset(copy.dst_offset)
+                .size(copy.size);
+
+            sash::CopyRegion::Buffer(get_buffer(src)?, get_buffer(dst)?, region)
+        }
+        CopyOp::BufferImage(dst, src, copy) => {
+            if let HandlePayload::Buffer(_) = get_payload(dst)? {
+                let src_img = get_image(src)?;
+                let region = src_img.get_copy_region(copy);
+
+                sash::CopyRegion::ImageToBuffer(src_img, get_buffer(dst)?, region)
+            } else {
+                let dst_img = get_image(dst)?;
+                let region = dst_img.get_copy_region(copy);
+
+                sash::CopyRegion::BufferToImage(get_buffer(src)?, dst_img, region)
+            }
+        }
+        CopyOp::Image(dst, src, copy) => {
+            let dst = get_image(dst)?;
+            let src = get_image(src)?;
+
+            // the planes of external formats are not validated by Bo
+            if copy.src_plane >= src.format_plane_count()
+                || copy.dst_plane >= dst.format_plane_count()
+            {
+                return Error::user();
+            }
+
+            let region = src.get_image_copy_region(dst, copy);
+
+            sash::CopyRegion::Image(src, dst, region)
+        }
+    };
+
+    Ok(region)
+}
+
+fn image_type(extent: Extent) -> vk::ImageType {
+    if extent.is_3d() {
+        vk::ImageType::TYPE_3D
+    } else {
+        vk::ImageType::TYPE_2D
+    }
+}
+
+fn image_extent(extent: Extent) -> vk::Extent3D {
+    vk::Extent3D {
+        width: extent.width(),
+        height: extent.height(),
+        depth: extent.depth(),
+    }
+}
+
+fn memory_priority(class: &Class) -> Option<f32> {
+    class.priority.map(|prio| match prio {
+        MemoryPriority::Low => 0.0,
+        MemoryPriority::Medium => 0.5,
+        MemoryPriority::High => 1.0,
+    })
+}
+
+fn external_feats_to_external_memory(feats: vk::ExternalMemoryFeatureFlags) -> ExternalMemory {
+    let mut external = ExternalMemory::empty();
+    if feats.contains(vk::ExternalMemoryFeatureFlags::EXPORTABLE) {
+        external |= ExternalMemory::EXPORT;
+    }
+    if feats.contains(vk::ExternalMemoryFeatureFlags::IMPORTABLE) {
+        external |= ExternalMemory::IMPORT;
+    }
+    if feats.contains(vk::ExternalMemoryFeatureFlags::DEDICATED_ONLY) {
+        external |= ExternalMemory::DEDICATED_ONLY;
+    }
+
+    external
+}
+
 fn mt_flags_to_mt(mt_flags: vk::MemoryPropertyFlags) -> MemoryType {
     let mut mt = MemoryType::empty();
     if mt_flags.contains(vk::MemoryPropertyFlags::DEVICE_LOCAL) {
         mt |= MemoryType::LOCAL;
     }
+    if mt_flags.contains(vk::MemoryPropertyFlags::PROTECTED) {
+        mt |= MemoryType::PROTECTED;
+    }
     if mt_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
         mt |= MemoryType::MAPPABLE;
         if mt_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT) {
@@ -153,6 +438,9 @@ fn mt_flags_from_mt(mt: MemoryType) -> vk::MemoryPropertyFlags {
     if mt.contains(MemoryType::LOCAL) {
         mt_flags |= vk::MemoryPropertyFlags::DEVICE_LOCAL;
     }
+    if mt.contains(MemoryType::PROTECTED) {
+        mt_flags |= vk::MemoryPropertyFlags::PROTECTED;
+    }
     if mt.contains(MemoryType::MAPPABLE) {
         mt_flags |= vk::MemoryPropertyFlags::HOST_VISIBLE;
         if mt.contains(MemoryType::COHERENT) {
@@ -167,51 +455,241 @@ fn mt_flags_from_mt(mt: MemoryType) -> vk::MemoryPropertyFlags {
 }
 
 fn best_mt_index(
+    dev: &sash::Device,
     mts: Vec<(u32, vk::MemoryPropertyFlags)>,
     required_flags: vk::MemoryPropertyFlags,
 ) -> Result<u32> {
-    if mts.is_empty() {
-        return Error::user();
-    }
-
     let known_mt_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL
         | vk::MemoryPropertyFlags::HOST_VISIBLE
         | vk::MemoryPropertyFlags::HOST_COHERENT
-        | vk::MemoryPropertyFlags::HOST_CACHED;
-    // exact match or first
-    let mt_idx = mts
-        .iter()
-        .find_map(|(mt_idx, mt_flags)| {
-            if (*mt_flags & known_mt_flags) == required_flags {
-                Some(*mt_idx)
-            } else {
-                None
-            }
-        })
-        .unwrap_or(mts[0].0);
+        | vk::MemoryPropertyFlags::HOST_CACHED
+        | vk::MemoryPropertyFlags::PROTECTED;
+    let rebar_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE;
+
+    // Without ReBAR, only a small window of the VRAM is host-visible and the types in the window
+    // can come first.  Prefer the largest device-local heap for local and mappable requests, then
+    // an exact match, then the order of the types.
+    let score = |mt_idx: u32, mt_flags: vk::MemoryPropertyFlags| {
+        let heap_size = if required_flags.contains(rebar_flags) {
+            dev.memory_type_heap_size(mt_idx)
+        } else {
+            0
+        };
+        let exact = (mt_flags & known_mt_flags) == required_flags;
 
-    Ok(mt_idx)
+        (heap_size, exact)
+    };
+
+    let mut best = None;
+    for (mt_idx, mt_flags) in mts {
+        let mt_score = score(mt_idx, mt_flags);
+        if best.map_or(true, |(_, best_score)| mt_score > best_score) {
+            best = Some((mt_idx, mt_score));
+        }
+    }
+
+    best.map(|(mt_idx, _)| mt_idx).ok_or(Error::User)
+}
+
+// the payload of a BO handle
+enum HandlePayload {
+    Buffer(sash::Buffer),
+    Image(sash::Image),
+}
+
+// handles of other backends are rejected
+fn get_payload(handle: &Handle) -> Result<&HandlePayload> {
+    handle.payload().ok_or(Error::User)
 }
 
-fn get_memory(handle: &Handle) -> (&sash::Memory, vk::DeviceSize) {
-    match &handle.payload {
+fn get_payload_mut(handle: &mut Handle) -> Result<&mut HandlePayload> {
+    handle.payload_mut().ok_or(Error::User)
+}
+
+fn get_memory(handle: &Handle) -> Result<(&sash::Memory, vk::DeviceSize)> {
+    let mem = match get_payload(handle)? {
         HandlePayload::Buffer(buf) => (buf.memory(), buf.size()),
         HandlePayload::Image(img) => (img.memory(), img.size()),
-        _ => unreachable!(),
+    };
+
+    Ok(mem)
+}
+
+pub(crate) fn vk_buffer(handle: &Handle) -> Option<vk::Buffer> {
+    match handle.payload::<HandlePayload>()? {
+        HandlePayload::Buffer(buf) => Some(buf.handle()),
+        HandlePayload::Image(_) => None,
     }
 }
 
-fn get_buffer(handle: &Handle) -> &sash::Buffer {
-    match &handle.payload {
-        HandlePayload::Buffer(buf) => buf,
-        _ => unreachable!(),
+pub(crate) fn vk_image(handle: &Handle) -> Option<vk::Image> {
+    match handle.payload::<HandlePayload>()? {
+        HandlePayload::Buffer(_) => None,
+        HandlePayload::Image(img) => Some(img.handle()),
+    }
+}
+
+pub(crate) fn vk_device_memory(handle: &Handle) -> Option<(vk::DeviceMemory, vk::DeviceSize)> {
+    let mem = match handle.payload::<HandlePayload>()? {
+        HandlePayload::Buffer(buf) => buf.bound_memory(),
+        HandlePayload::Image(img) => img.bound_memory(),
+    };
+
+    mem.map(|mem| mem.handle())
+}
+
+fn is_disjoint(handle: &Handle) -> Result<bool> {
+    let disjoint = match get_payload(handle)? {
+        HandlePayload::Buffer(_) => false,
+        HandlePayload::Image(img) => img.is_disjoint(),
+    };
+
+    Ok(disjoint)
+}
+
+fn get_buffer(handle: &Handle) -> Result<&sash::Buffer> {
+    match get_payload(handle)? {
+        HandlePayload::Buffer(buf) => Ok(buf),
+        HandlePayload::Image(_) => Error::user(),
+    }
+}
+
+fn get_image(handle: &Handle) -> Result<&sash::Image> {
+    match get_payload(handle)? {
+        HandlePayload::Image(img) => Ok(img),
+        HandlePayload::Buffer(_) => Error::user(),
+    }
+}
+
+/// The device group of the physical device of a Vulkan backend.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DeviceGroup {
+    /// Index of the physical device in the device group.
+    pub index: u32,
+    /// Number of physical devices in the device group.
+    pub count: u32,
+}
+
+/// Information about a physical device.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceInfo {
+    /// Name of the physical device.
+    pub name: String,
+    /// Driver id of the physical device (`VkDriverId`).
+    pub driver_id: i32,
+    /// Device id (`st_rdev`) of the DRM primary node, if known.
+    pub primary_id: Option<u64>,
+    /// Device id (`st_rdev`) of the DRM render node, if known.
+    pub render_id: Option<u64>,
+    /// UUID of the physical device (`deviceUUID`).
+    pub uuid: [u8; 16],
+}
+
+bitflags::bitflags! {
+    /// Severities of Vulkan debug messages.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct DebugSeverity: u32 {
+        /// Diagnostic messages.
+        const VERBOSE = 1 << 0;
+        /// Informational messages.
+        const INFO = 1 << 1;
+        /// Messages about likely bugs.
+        const WARNING = 1 << 2;
+        /// Messages about invalid usage.
+        const ERROR = 1 << 3;
+    }
+}
+
+bitflags::bitflags! {
+    /// Types of Vulkan debug messages.
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub struct DebugType: u32 {
+        /// General messages.
+        const GENERAL = 1 << 0;
+        /// Messages about specification violations.
+        const VALIDATION = 1 << 1;
+        /// Messages about suboptimal usage.
+        const PERFORMANCE = 1 << 2;
+    }
+}
+
+/// Sets which Vulkan debug messages are logged.
+///
+/// Only messages of the given severities and types are logged, except those whose message ids
+/// (such as `VUID-...`) are in `suppressed_ids`.  An empty `severity` disables logging.  This is
+/// process-wide and applies to all Vulkan backends built with `Builder::debug`, including those
+/// already built.  By default, all messages are logged.
+pub fn set_debug_filter(severity: DebugSeverity, ty: DebugType, suppressed_ids: &[&str]) {
+    let mut vk_severity = vk::DebugUtilsMessageSeverityFlagsEXT::empty();
+    if severity.contains(DebugSeverity::VERBOSE) {
+        vk_severity |= vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE;
+    }
+    if severity.contains(DebugSeverity::INFO) {
+        vk_severity |= vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
+    }
+    if severity.contains(DebugSeverity::WARNING) {
+        vk_severity |= vk::DebugUtilsMessageSeverityFlagsEXT::WARNING;
+    }
+    if severity.contains(DebugSeverity::ERROR) {
+        vk_severity |= vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+    }
+
+    let mut vk_type = vk::DebugUtilsMessageTypeFlagsEXT::empty();
+    if ty.contains(DebugType::GENERAL) {
+        vk_type |= vk::DebugUtilsMessageTypeFlagsEXT::GENERAL;
     }
+    if ty.contains(DebugType::VALIDATION) {
+        vk_type |= vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION;
+    }
+    if ty.contains(DebugType::PERFORMANCE) {
+        vk_type |= vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE;
+    }
+
+    let suppressed_ids = suppressed_ids.iter().map(|id| id.to_string()).collect();
+    sash::set_debug_filter(vk_severity, vk_type, suppressed_ids);
+}
+
+/// Enumerates the physical devices.
+///
+/// The index of a physical device in the returned list can be passed to
+/// `Builder::device_index`, and either DRM device id can be passed to `Builder::device_id`.  A
+/// listed physical device is not necessarily supported by the Vulkan backend.
+pub fn enumerate_devices() -> Result<Vec<DeviceInfo>> {
+    let infos = sash::enumerate_physical_devices("hbm")?
+        .into_iter()
+        .map(|info| DeviceInfo {
+            name: info.name,
+            driver_id: info.driver_id.as_raw(),
+            primary_id: info.primary_id,
+            render_id: info.render_id,
+            uuid: info.uuid,
+        })
+        .collect();
+
+    Ok(infos)
+}
+
+/// A queue global priority.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QueuePriority {
+    /// `VK_QUEUE_GLOBAL_PRIORITY_LOW_KHR`.
+    Low,
+    /// `VK_QUEUE_GLOBAL_PRIORITY_MEDIUM_KHR`.
+    Medium,
+    /// `VK_QUEUE_GLOBAL_PRIORITY_HIGH_KHR`.
+    High,
+    /// `VK_QUEUE_GLOBAL_PRIORITY_REALTIME_KHR`.
+    Realtime,
 }
 
-fn get_image(handle: &Handle) -> &sash::Image {
-    match &handle.payload {
-        HandlePayload::Image(img) => img,
-        _ => unreachable!(),
+impl From<QueuePriority> for vk::QueueGlobalPriorityKHR {
+    fn from(prio: QueuePriority) -> Self {
+        match prio {
+            QueuePriority::Low => Self::LOW,
+            QueuePriority::Medium => Self::MEDIUM,
+            QueuePriority::High => Self::HIGH,
+            QueuePriority::Realtime => Self::REALTIME,
+        }
     }
 }
 
@@ -219,21 +697,113 @@ fn get_image(handle: &Handle) -> &sash::Image {
 pub struct Backend {
     device: Arc<sash::Device>,
     copy_queue: sash::CopyQueue,
+    minimize_usage: bool,
 }
 
 impl Backend {
-    fn new(device_index: Option<usize>, device_id: Option<u64>, debug: bool) -> Result<Self> {
-        let device = sash::Device::build("hbm", device_index, device_id, debug)?;
-        let copy_queue = sash::CopyQueue::new(device.clone());
-        let backend = Self { device, copy_queue };
+    fn new(
+        device: Arc<sash::Device>,
+        minimize_usage: bool,
+        semaphore_pool_size: usize,
+        profile_copies: bool,
+        concurrent_copies: bool,
+    ) -> Result<Self> {
+        let copy_queue = sash::CopyQueue::new(
+            device.clone(),
+            semaphore_pool_size,
+            profile_copies,
+            concurrent_copies,
+        );
+        let backend = Self {
+            device,
+            copy_queue,
+            minimize_usage,
+        };
 
         log::info!("vulkan backend initialized");
 
         Ok(backend)
     }
+
+    // A failure to initialize a newly allocated image is not fatal.  Copies then assume the
+    // GENERAL layout as they do for imported images.
+    fn initialize_image(&self, img: &sash::Image) {
+        if let Err(err) = self.copy_queue.initialize_image(img) {
+            log::warn!("failed to initialize image: {err:?}");
+        }
+    }
+
+    /// Returns the device group of the physical device.
+    ///
+    /// A physical device that is not linked with others is the only member of its device group.
+    /// When there are multiple members, `Features::DEVICE_GROUP` is reported and BO classes whose
+    /// memories cannot be reliably imported by other processes do not support external memory.
+    pub fn device_group(&self) -> DeviceGroup {
+        let (index, count) = self.device.device_group();
+        DeviceGroup { index, count }
+    }
+
+    // Flags::COPY implies transfer usages, which can exclude compressed modifiers on some drivers.
+    // When the usages are not explicitly requested and dropping them enlarges the modifier set,
+    // this returns the enlarged modifier set without Flags::COPY.
+    fn minimize_image_flags(
+        &self,
+        desc: &Description,
+        usage: super::Usage,
+        mods: &[Modifier],
+    ) -> Option<sash::ImageProperties> {
+        let explicit_transfer =
+            matches!(usage, super::Usage::Vulkan(usage) if usage.contains(Usage::TRANSFER));
+        if !desc.flags.contains(Flags::COPY) || explicit_transfer {
+            return None;
+        }
+
+        let flags = desc.flags - Flags::COPY;
+        let img_info = get_image_info(flags, desc.fixed_rate, desc.format, usage).ok()?;
+        let img_props = self.device.image_properties(img_info, desc.modifier).ok()?;
+        if img_props.modifiers.len() <= mods.len() {
+            return None;
+        }
+
+        Some(img_props)
+    }
 }
 
 impl super::Backend for Backend {
+    fn features(&self) -> Features {
+        self.device.features()
+    }
+
+    fn memory_heaps(&self) -> Vec<MemoryHeap> {
+        self.device.memory_heaps()
+    }
+
+    fn device_info(&self) -> Option<super::DeviceInfo> {
+        Some(self.device.device_info())
+    }
+
+    fn copy_stats(&self) -> Vec<CopyStats> {
+        self.copy_queue
+            .copy_profiles()
+            .into_iter()
+            .map(|(key, profile)| CopyStats {
+                format: key.format,
+                size_class: 1 << key.size_class,
+                count: profile.count,
+                size: profile.size,
+                duration: Duration::from_nanos(profile.duration),
+            })
+            .collect()
+    }
+
+    fn set_copy_timeout(&mut self, timeout: Option<Duration>) {
+        self.copy_queue.set_timeout(timeout);
+    }
+
+    fn slab_sizes(&self) -> (Size, Size) {
+        self.device.slab_sizes()
+    }
+
     fn memory_plane_count(&self, fmt: Format, modifier: Modifier) -> Result<u32> {
         let (fmt, _) = formats::to_vk(fmt)?;
         self.device.memory_plane_count(fmt, modifier)
@@ -244,24 +814,80 @@ impl super::Backend for Backend {
             let buf_info = get_buffer_info(desc.flags, usage)?;
             let buf_props = self.device.buffer_properties(buf_info)?;
 
-            Class::new(desc)
+            let mut class = Class::new(desc)
                 .usage(usage)
                 .max_extent(Extent::Buffer(buf_props.max_size))
-                .unknown_constraint()
+                .unknown_constraint();
+            if let Some(range) = buf_props.max_uniform_range {
+                class = class.max_range(super::Usage::Vulkan(Usage::UNIFORM), range);
+            }
+            if let Some(range) = buf_props.max_storage_range {
+                class = class.max_range(super::Usage::Vulkan(Usage::STORAGE), range);
+            }
+
+            class
         } else {
-            let img_info = get_image_info(desc.flags, desc.format, usage)?;
-            let img_props = self.device.image_properties(img_info, desc.modifier)?;
+            let img_info = get_image_info(desc.flags, desc.fixed_rate, desc.format, usage)?;
+            // this records the driver's format properties of an external format
+            if formats::is_external(desc.format) {
+                self.device.probe_external_format(img_info.format)?;
+            }
+
+            let mut img_props = match self.device.image_properties(img_info, desc.modifier) {
+                Ok(img_props) => img_props,
+                Err(err) if !self.minimize_usage => return Err(err),
+                Err(_) => sash::ImageProperties {
+                    modifiers: Vec::new(),
+                    modifier_features: Vec::new(),
+                },
+            };
+
+            let mut dropped_flags = Flags::empty();
+            if self.minimize_usage {
+                if let Some(min_props) =
+                    self.minimize_image_flags(&desc, usage, &img_props.modifiers)
+                {
+                    dropped_flags = Flags::COPY;
+                    img_props = min_props;
+                }
+            }
+
+            if img_props.modifiers.is_empty() {
+                return Error::unsupported();
+            }
+
+            let max_extent = self.device.max_image_extent();
+            let mod_feats = img_props
+                .modifier_features
+                .into_iter()
+                .map(get_modifier_features)
+                .collect();
 
             Class::new(desc)
                 .usage(usage)
-                .max_extent(Extent::Image(img_props.max_extent, img_props.max_extent))
+                .drop_flags(dropped_flags)
+                .max_extent(Extent::Image(max_extent, max_extent))
                 .modifiers(img_props.modifiers)
+                .modifier_features(mod_feats)
                 .unknown_constraint()
         };
 
         Ok(class)
     }
 
+    fn external_memory(&self, class: &Class, modifier: Modifier) -> Result<ExternalMemory> {
+        let feats = if class.is_buffer() {
+            let buf_info = get_buffer_info(class.flags, class.usage)?;
+            self.device.buffer_external_memory(&buf_info)
+        } else {
+            let img_info =
+                get_image_info(class.flags, class.fixed_rate, class.format, class.usage)?;
+            self.device.image_external_memory(&img_info, modifier)?
+        };
+
+        Ok(external_feats_to_external_memory(feats))
+    }
+
     fn with_constraint(
         &self,
         class: &Class,
@@ -269,19 +895,25 @@ impl super::Backend for Backend {
         con: Option<Constraint>,
     ) -> Result<Handle> {
         let handle = if class.is_buffer() {
-            let buf_info = get_buffer_info(class.flags, class.usage)?;
+            let buf_info = sash::BufferInfo {
+                priority: memory_priority(class),
+                ..get_buffer_info(class.flags, class.usage)?
+            };
             let buf =
                 sash::Buffer::with_constraint(self.device.clone(), buf_info, extent.size(), con)?;
 
             Handle::new(HandlePayload::Buffer(buf))
         } else {
-            let img_info = get_image_info(class.flags, class.format, class.usage)?;
+            let img_info = sash::ImageInfo {
+                image_type: image_type(extent),
+                priority: memory_priority(class),
+                ..get_image_info(class.flags, class.fixed_rate, class.format, class.usage)?
+            };
 
             let img = sash::Image::with_constraint(
                 self.device.clone(),
                 img_info,
-                extent.width(),
-                extent.height(),
+                image_extent(extent),
                 &class.modifiers,
                 con,
             )?;
@@ -300,7 +932,10 @@ impl super::Backend for Backend {
         dmabuf: Option<BorrowedFd>,
     ) -> Result<Handle> {
         let handle = if class.is_buffer() {
-            let buf_info = get_buffer_info(class.flags, class.usage)?;
+            let buf_info = sash::BufferInfo {
+                priority: memory_priority(class),
+                ..get_buffer_info(class.flags, class.usage)?
+            };
             let buf = sash::Buffer::with_layout(
                 self.device.clone(),
                 buf_info,
@@ -311,12 +946,15 @@ impl super::Backend for Backend {
 
             Handle::new(HandlePayload::Buffer(buf))
         } else {
-            let img_info = get_image_info(class.flags, class.format, class.usage)?;
+            let img_info = sash::ImageInfo {
+                image_type: image_type(extent),
+                priority: memory_priority(class),
+                ..get_image_info(class.flags, class.fixed_rate, class.format, class.usage)?
+            };
             let img = sash::Image::with_layout(
                 self.device.clone(),
                 img_info,
-                extent.width(),
-                extent.height(),
+                image_extent(extent),
                 layout,
                 dmabuf,
             )?;
@@ -327,20 +965,48 @@ impl super::Backend for Backend {
         Ok(handle)
     }
 
+    fn dry_run(
+        &self,
+        class: &Class,
+        extent: Extent,
+        con: Option<Constraint>,
+    ) -> Result<Requirements> {
+        // Vulkan decides image layouts on image creation, and only buffer requirements can be
+        // queried without creating objects
+        if !class.is_buffer() {
+            return Error::unsupported();
+        }
+
+        let buf_info = sash::BufferInfo {
+            priority: memory_priority(class),
+            ..get_buffer_info(class.flags, class.usage)?
+        };
+        let (layout, mts) = sash::Buffer::dry_run(&self.device, &buf_info, extent.size(), con)?;
+        let reqs = Requirements {
+            layout,
+            memory_types: mts
+                .into_iter()
+                .map(|(_, mt_flags)| mt_flags_to_mt(mt_flags))
+                .collect(),
+        };
+
+        Ok(reqs)
+    }
+
     fn layout(&self, handle: &Handle) -> Layout {
-        match &handle.payload {
-            HandlePayload::Buffer(buf) => buf.layout(),
-            HandlePayload::Image(img) => img.layout(),
-            _ => unreachable!(),
+        match get_payload(handle) {
+            Ok(HandlePayload::Buffer(buf)) => buf.layout(),
+            Ok(HandlePayload::Image(img)) => img.layout(),
+            Err(_) => Default::default(),
         }
     }
 
     fn memory_types(&self, handle: &Handle) -> Vec<MemoryType> {
         let required_flags = vk::MemoryPropertyFlags::empty();
-        let mts = match handle.payload {
-            HandlePayload::Buffer(ref buf) => buf.memory_types(required_flags),
-            HandlePayload::Image(ref img) => img.memory_types(required_flags),
-            _ => unreachable!(),
+        let mts = match get_payload(handle) {
+            Ok(HandlePayload::Buffer(buf)) => buf.memory_types(required_flags),
+            Ok(HandlePayload::Image(img)) => img.memory_types(required_flags),
+            Err(_) => Vec::new(),
         };
 
         mts.into_iter()
@@ -355,34 +1021,168 @@ impl super::Backend for Backend {
         dmabuf: Option<OwnedFd>,
     ) -> Result<()> {
         let required_flags = mt_flags_from_mt(mt);
-        match handle.payload {
-            HandlePayload::Buffer(ref mut buf) => {
+        match get_payload_mut(handle)? {
+            HandlePayload::Buffer(buf) => {
                 let mts = buf.memory_types(required_flags);
-                let mt_idx = best_mt_index(mts, required_flags)?;
+                let mt_idx = best_mt_index(&self.device, mts, required_flags)?;
                 buf.bind_memory(mt_idx, dmabuf)
             }
-            HandlePayload::Image(ref mut img) => {
+            HandlePayload::Image(img) => {
+                let import = dmabuf.is_some();
                 let mts = img.memory_types(required_flags);
-                let mt_idx = best_mt_index(mts, required_flags)?;
-                img.bind_memory(mt_idx, dmabuf)
+                let mt_idx = best_mt_index(&self.device, mts, required_flags)?;
+                img.bind_memory(mt_idx, dmabuf)?;
+                if !import {
+                    self.initialize_image(img);
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn bind_memory_planes(
+        &self,
+        handle: &mut Handle,
+        mt: MemoryType,
+        dmabufs: Option<Vec<OwnedFd>>,
+    ) -> Result<()> {
+        let HandlePayload::Image(img) = get_payload_mut(handle)? else {
+            return Error::user();
+        };
+
+        let required_flags = mt_flags_from_mt(mt);
+        let mts = img.memory_types(required_flags);
+        let mt_idx = best_mt_index(&self.device, mts, required_flags)?;
+        let import = dmabufs.is_some();
+        img.bind_disjoint_memories(mt_idx, dmabufs)?;
+        if !import {
+            self.initialize_image(img);
+        }
+
+        Ok(())
+    }
+
+    fn compression(&self, handle: &Handle) -> Compression {
+        let img = match get_payload(handle) {
+            Ok(HandlePayload::Buffer(_)) => return Compression::Disabled,
+            Ok(HandlePayload::Image(img)) => img,
+            Err(_) => return Compression::Unknown,
+        };
+
+        match img.compression() {
+            Some((vk::ImageCompressionFlagsEXT::DISABLED, _)) => Compression::Disabled,
+            Some((vk::ImageCompressionFlagsEXT::DEFAULT, _)) => Compression::Default,
+            Some((vk::ImageCompressionFlagsEXT::FIXED_RATE_EXPLICIT, fixed_rate)) => {
+                Compression::FixedRate(FixedRate::from_bits_truncate(fixed_rate.as_raw()))
             }
-            _ => Error::unsupported(),
+            _ => Compression::Unknown,
+        }
+    }
+
+    fn sparse_block_size(&self, handle: &Handle) -> Option<Size> {
+        match get_payload(handle) {
+            Ok(HandlePayload::Buffer(buf)) => buf.sparse_block_size(),
+            _ => None,
+        }
+    }
+
+    fn bind_sparse_region(
+        &self,
+        handle: &mut Handle,
+        mt: MemoryType,
+        offset: Size,
+        size: Size,
+    ) -> Result<()> {
+        let HandlePayload::Buffer(buf) = get_payload_mut(handle)? else {
+            return Error::user();
+        };
+
+        let required_flags = mt_flags_from_mt(mt);
+        let mts = buf.memory_types(required_flags);
+        let mt_idx = best_mt_index(&self.device, mts, required_flags)?;
+        buf.bind_sparse(&self.copy_queue, mt_idx, offset, size)
+    }
+
+    fn unbind_sparse_region(&self, handle: &mut Handle, offset: Size, size: Size) -> Result<Size> {
+        let HandlePayload::Buffer(buf) = get_payload_mut(handle)? else {
+            return Error::user();
+        };
+
+        buf.unbind_sparse(&self.copy_queue, offset, size)
+    }
+
+    unsafe fn bind_host_memory(
+        &self,
+        handle: &mut Handle,
+        mt: MemoryType,
+        ptr: ptr::NonNull<ffi::c_void>,
+    ) -> Result<()> {
+        let HandlePayload::Buffer(buf) = get_payload_mut(handle)? else {
+            return Error::user();
+        };
+
+        let required_flags = mt_flags_from_mt(mt);
+        let mts = buf.host_pointer_memory_types(ptr, required_flags);
+        let mt_idx = best_mt_index(&self.device, mts, required_flags)?;
+        buf.bind_host_pointer(mt_idx, ptr)
+    }
+
+    fn set_name(&self, handle: &Handle, name: &str) {
+        match get_payload(handle) {
+            Ok(HandlePayload::Buffer(buf)) => buf.set_name(name),
+            Ok(HandlePayload::Image(img)) => img.set_name(name),
+            Err(_) => (),
         }
     }
 
     fn export_dma_buf(&self, handle: &Handle, name: Option<&str>) -> Result<OwnedFd> {
-        let (mem, _) = get_memory(handle);
+        if is_disjoint(handle)? {
+            return Error::user();
+        }
+
+        let (mem, _) = get_memory(handle)?;
         let dmabuf = mem.export_dma_buf()?;
 
         if let Some(name) = name {
             let _ = utils::dma_buf_set_name(&dmabuf, name);
+            self.set_name(handle, name);
         }
 
         Ok(dmabuf)
     }
 
+    fn export_dma_bufs(&self, handle: &Handle, name: Option<&str>) -> Result<Vec<OwnedFd>> {
+        if !is_disjoint(handle)? {
+            return self.export_dma_buf(handle, name).map(|dmabuf| vec![dmabuf]);
+        }
+
+        let dmabufs = get_image(handle)?
+            .disjoint_memories()
+            .iter()
+            .map(|mem| {
+                let dmabuf = mem.export_dma_buf()?;
+                if let Some(name) = name {
+                    let _ = utils::dma_buf_set_name(&dmabuf, name);
+                }
+
+                Ok(dmabuf)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(name) = name {
+            self.set_name(handle, name);
+        }
+
+        Ok(dmabufs)
+    }
+
     fn map(&self, handle: &Handle) -> Result<Mapping> {
-        let (mem, size) = get_memory(handle);
+        if is_disjoint(handle)? {
+            return Error::user();
+        }
+
+        let (mem, size) = get_memory(handle)?;
 
         let len = num::NonZeroUsize::try_from(usize::try_from(size)?)?;
         let ptr = mem.map(0, size)?;
@@ -393,20 +1193,66 @@ impl super::Backend for Backend {
     }
 
     fn unmap(&self, handle: &Handle, _mapping: Mapping) {
-        let (mem, _) = get_memory(handle);
+        let Ok((mem, _)) = get_memory(handle) else {
+            return;
+        };
+        mem.unmap();
+    }
+
+    unsafe fn map_placed(
+        &self,
+        handle: &Handle,
+        addr: ptr::NonNull<ffi::c_void>,
+    ) -> Result<Mapping> {
+        if is_disjoint(handle)? {
+            return Error::user();
+        }
+
+        let (mem, size) = get_memory(handle)?;
+
+        let len = num::NonZeroUsize::try_from(usize::try_from(size)?)?;
+        let ptr = mem.map_placed(addr)?;
+        let ptr = ptr::NonNull::new(ptr).unwrap();
+        let mapping = Mapping { ptr, len };
+
+        Ok(mapping)
+    }
+
+    fn unmap_placed(&self, handle: &Handle, _mapping: Mapping) {
+        let Ok((mem, _)) = get_memory(handle) else {
+            return;
+        };
         mem.unmap();
     }
 
     fn flush(&self, handle: &Handle) {
-        let (mem, size) = get_memory(handle);
+        let Ok((mem, size)) = get_memory(handle) else {
+            return;
+        };
         mem.flush(0, size);
     }
 
     fn invalidate(&self, handle: &Handle) {
-        let (mem, size) = get_memory(handle);
+        let Ok((mem, size)) = get_memory(handle) else {
+            return;
+        };
         mem.invalidate(0, size);
     }
 
+    fn flush_range(&self, handle: &Handle, offset: Size, size: Size) {
+        let Ok((mem, _)) = get_memory(handle) else {
+            return;
+        };
+        mem.flush(offset, size);
+    }
+
+    fn invalidate_range(&self, handle: &Handle, offset: Size, size: Size) {
+        let Ok((mem, _)) = get_memory(handle) else {
+            return;
+        };
+        mem.invalidate(offset, size);
+    }
+
     fn copy_buffer(
         &self,
         dst: &Handle,
@@ -414,18 +1260,7 @@ impl super::Backend for Backend {
         copy: CopyBuffer,
         sync_fd: Option<OwnedFd>,
     ) -> Result<Option<OwnedFd>> {
-        if let Some(sync_fd) = sync_fd {
-            utils::poll(sync_fd, Access::Read)?;
-        }
-
-        let dst = get_buffer(dst);
-        let src = get_buffer(src);
-        let region = vk::BufferCopy::default()
-            .src_offset(copy.src_offset)
-            .dst_offset(copy.dst_offset)
-            .size(copy.size);
-
-        self.copy_queue.copy_buffer(src, dst, region).and(Ok(None))
+        self.copy_batch(&[CopyOp::Buffer(dst, src, copy)], sync_fd)
     }
 
     fn copy_buffer_image(
@@ -435,35 +1270,129 @@ impl super::Backend for Backend {
         copy: CopyBufferImage,
         sync_fd: Option<OwnedFd>,
     ) -> Result<Option<OwnedFd>> {
-        if let Some(sync_fd) = sync_fd {
-            utils::poll(sync_fd, Access::Read)?;
-        }
+        self.copy_batch(&[CopyOp::BufferImage(dst, src, copy)], sync_fd)
+    }
 
-        if let HandlePayload::Buffer(_) = &dst.payload {
-            let dst_buf = get_buffer(dst);
-            let src_img = get_image(src);
-            let region = src_img.get_copy_region(copy);
+    fn copy_image(
+        &self,
+        dst: &Handle,
+        src: &Handle,
+        copy: CopyImage,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        self.copy_batch(&[CopyOp::Image(dst, src, copy)], sync_fd)
+    }
 
-            self.copy_queue
-                .copy_image_to_buffer(src_img, dst_buf, region)
-        } else {
-            let dst_img = get_image(dst);
-            let src_buf = get_buffer(src);
-            let region = dst_img.get_copy_region(copy);
+    fn copy_batch(&self, copies: &[CopyOp], sync_fd: Option<OwnedFd>) -> Result<Option<OwnedFd>> {
+        let regions = copies
+            .iter()
+            .map(|&op| get_copy_region(op))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.copy_queue.copy_batch(&regions, sync_fd)
+    }
+
+    fn read_image(&self, handle: &Handle, dst: &mut [u8], copy: CopyBufferImage) -> Result<()> {
+        let img = get_image(handle)?;
+        let region = img.get_copy_region(copy);
+
+        img.copy_to_memory(region, dst)
+    }
 
-            self.copy_queue
-                .copy_buffer_to_image(src_buf, dst_img, region)
+    fn write_image(&self, handle: &Handle, src: &[u8], copy: CopyBufferImage) -> Result<()> {
+        let img = get_image(handle)?;
+        let region = img.get_copy_region(copy);
+
+        img.copy_from_memory(region, src)
+    }
+
+    fn fill(
+        &self,
+        handle: &Handle,
+        fill: Fill,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        match (get_payload(handle)?, fill) {
+            (HandlePayload::Buffer(buf), Fill::Buffer(value)) => {
+                self.copy_queue.fill_buffer(buf, value, sync_fd)
+            }
+            (HandlePayload::Image(img), Fill::Color(color)) => {
+                let color = vk::ClearColorValue { float32: color };
+                self.copy_queue.clear_image(img, color, false, sync_fd)
+            }
+            (HandlePayload::Image(img), Fill::ColorInt(color)) => {
+                let color = vk::ClearColorValue { uint32: color };
+                self.copy_queue.clear_image(img, color, true, sync_fd)
+            }
+            _ => Error::user(),
         }
-        .and(Ok(None))
+    }
+
+    fn blit(
+        &self,
+        dst: &Handle,
+        src: &Handle,
+        src_rect: Rect,
+        dst_rect: Rect,
+        filter: Filter,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        let dst = get_image(dst)?;
+        let src = get_image(src)?;
+        let region = src.get_blit_region(src_rect, dst_rect);
+
+        self.copy_queue
+            .blit_image(src, dst, region, filter, sync_fd)
     }
 }
 
+/// Vulkan handles created by the application.
+///
+/// A backend can adopt the handles rather than creating its own instance and device.  The
+/// handles remain owned by the application and are not destroyed by the backend.
+pub struct ExternalDevice {
+    /// The instance, which must support Vulkan 1.1 or later.
+    pub instance: ash::Instance,
+    /// The physical device.
+    pub physical_device: vk::PhysicalDevice,
+    /// The device, which must be created from `physical_device`.
+    pub device: ash::Device,
+    /// The queue family of the queue for copies.
+    ///
+    /// The queue family must support transfers with a minimum image transfer granularity of 1.
+    pub queue_family: u32,
+    /// The index of the queue for copies.
+    ///
+    /// The backend submits to the queue from its own threads.  The queue must not be used by
+    /// the application.
+    pub queue_index: u32,
+    /// The device extensions enabled at device creation.
+    ///
+    /// These must include `VK_KHR_external_memory_fd`, `VK_KHR_maintenance4`, and
+    /// `VK_EXT_external_memory_dma_buf`.  `VK_EXT_queue_family_foreign` should be included when
+    /// BOs are shared with non-Vulkan users.  Optional device features, such as protected memory,
+    /// are assumed disabled and are not used.
+    pub extensions: Vec<&'static ffi::CStr>,
+}
+
 /// A Vulkan backend builder.
+///
+/// Backends that are alive at the same time share a `VkInstance` when they have the same debug
+/// setting.
 #[derive(Default)]
 pub struct Builder {
     device_index: Option<usize>,
     device_id: Option<u64>,
     debug: bool,
+    allow_software: bool,
+    minimize_usage: bool,
+    disabled_features: Features,
+    semaphore_pool_size: Option<usize>,
+    queue_priority: Option<QueuePriority>,
+    external_device: Option<ExternalDevice>,
+    profile_copies: bool,
+    concurrent_copies: bool,
+    probe_cache: Option<PathBuf>,
 }
 
 impl Builder {
@@ -473,6 +1402,9 @@ impl Builder {
     }
 
     /// Sets the index of the physical device to use.
+    ///
+    /// When neither the index nor the device id is set, the first supported physical device is
+    /// used.  See `enumerate_devices`.
     pub fn device_index(mut self, device_index: usize) -> Self {
         self.device_index = Some(device_index);
         self
@@ -485,23 +1417,164 @@ impl Builder {
     }
 
     /// Enables `VK_EXT_debug_utils` message logging.
+    ///
+    /// The logged messages can be filtered at runtime with `set_debug_filter`.
     pub fn debug(mut self, debug: bool) -> Self {
         self.debug = debug;
         self
     }
 
+    /// Accepts software implementations, such as lavapipe.
+    ///
+    /// Physical devices of type `VK_PHYSICAL_DEVICE_TYPE_CPU` are skipped unless allowed.  When
+    /// allowed and used, `Features::SOFTWARE` is reported.  This is mainly useful for testing
+    /// without real hardware.
+    pub fn allow_software(mut self, allow_software: bool) -> Self {
+        self.allow_software = allow_software;
+        self
+    }
+
+    /// Drops optional image usages when they limit the supported modifiers.
+    ///
+    /// When enabled and `Flags::COPY` is specified without `Usage::TRANSFER`, `Flags::COPY` is
+    /// dropped from the BO class if that enlarges the set of supported modifiers.  BOs of the
+    /// class can then not be copied, which is reported by `Device::dropped_flags`.
+    pub fn minimize_usage(mut self, minimize_usage: bool) -> Self {
+        self.minimize_usage = minimize_usage;
+        self
+    }
+
+    /// Disables protected memory.
+    ///
+    /// When disabled, the protected memory feature is not enabled even when it is supported, and
+    /// `Flags::PROTECTED` is unsupported.  This avoids protected queue creation overhead on some
+    /// drivers.
+    pub fn disable_protected(mut self, disable: bool) -> Self {
+        self.disabled_features.set(Features::PROTECTED, disable);
+        self
+    }
+
+    /// Disables image compression control.
+    ///
+    /// When disabled, `VK_EXT_image_compression_control` is not enabled even when it is
+    /// supported, and `Flags::NO_COMPRESSION` limits the supported modifiers to
+    /// `DRM_FORMAT_MOD_LINEAR`.
+    pub fn disable_compression_control(mut self, disable: bool) -> Self {
+        self.disabled_features
+            .set(Features::COMPRESSION_CONTROL, disable);
+        self
+    }
+
+    /// Sets the max number of idle semaphores to keep for sync fd imports.
+    ///
+    /// When supported, sync fds passed to copies are imported as semaphores and are waited on by
+    /// the GPU.  Idle semaphores are pooled rather than destroyed, up to the max number.  The
+    /// default is 4, and 0 disables pooling.
+    pub fn semaphore_pool_size(mut self, size: usize) -> Self {
+        self.semaphore_pool_size = Some(size);
+        self
+    }
+
+    /// Sets the global priority of the queues used for copies.
+    ///
+    /// A priority above those of application queues keeps copies on the compositor path from
+    /// being starved.  The priority is a hint.  It is ignored when `VK_EXT_global_priority` is
+    /// unsupported, or when the process lacks the privilege for the priority.
+    pub fn queue_priority(mut self, prio: QueuePriority) -> Self {
+        self.queue_priority = Some(prio);
+        self
+    }
+
+    /// Submits copies of different threads to different queues.
+    ///
+    /// Concurrent copies scale better with multi-threaded callers, but copies are then executed
+    /// in submission order only within a thread.  Callers must order copies of different threads
+    /// that access the same BO with sync fds.  This is disabled by default, and all copies are
+    /// submitted to a single queue.
+    pub fn concurrent_copies(mut self, concurrent: bool) -> Self {
+        self.concurrent_copies = concurrent;
+        self
+    }
+
+    /// Profiles copies with GPU timestamps.
+    ///
+    /// When enabled, timestamps are written around each copy, and the GPU time and the size of
+    /// completed copies are aggregated by formats and size classes.  See `Device::copy_stats`.
+    /// This adds overhead to copies and is meant for debugging.  It is ignored when the queue
+    /// does not support timestamps, and copies to protected BOs are not profiled.
+    pub fn profile_copies(mut self, profile: bool) -> Self {
+        self.profile_copies = profile;
+        self
+    }
+
+    /// Sets the path of the probe cache.
+    ///
+    /// The format properties of the physical device are loaded from the cache, and formats that
+    /// are probed later are written back once, when the backend is dropped.  This saves many
+    /// Vulkan queries in processes that build short-lived backends.  The cache is keyed by the
+    /// device and driver UUIDs, the driver version, the driver id, and the device limits, and is
+    /// ignored when it is stale.
+    pub fn probe_cache(mut self, path: &Path) -> Self {
+        self.probe_cache = Some(PathBuf::from(path));
+        self
+    }
+
+    /// Adopts Vulkan handles created by the application.
+    ///
+    /// This avoids a second instance and device in applications that already use Vulkan.  The
+    /// device index and the device id must not be set.  `debug`, `allow_software`, the disabled
+    /// features, and `queue_priority` do not apply to adopted handles.
+    ///
+    /// # Safety
+    ///
+    /// The handles must be valid and must meet the requirements documented in `ExternalDevice`.
+    /// They must outlive the backend and all BOs allocated from the backend.
+    pub unsafe fn external_device(mut self, dev: ExternalDevice) -> Self {
+        self.external_device = Some(dev);
+        self
+    }
+
     /// Builds a Vulkan backend.
-    pub fn build(mut self) -> Result<Backend> {
-        match self.device_index.is_some() as i32 + self.device_id.is_some() as i32 {
-            0 => {
-                self.device_index = Some(0);
-            }
-            1 => (),
-            _ => {
+    pub fn build(self) -> Result<Backend> {
+        if self.device_index.is_some() && self.device_id.is_some() {
+            return Error::user();
+        }
+
+        let device = if let Some(dev) = self.external_device {
+            if self.device_index.is_some() || self.device_id.is_some() {
                 return Error::user();
             }
+
+            sash::Device::adopt(
+                dev.instance,
+                dev.physical_device,
+                dev.device,
+                dev.queue_family,
+                dev.queue_index,
+                &dev.extensions,
+            )?
+        } else {
+            sash::Device::build(
+                "hbm",
+                self.device_index,
+                self.device_id,
+                self.debug,
+                self.allow_software,
+                self.disabled_features,
+                self.queue_priority.map(vk::QueueGlobalPriorityKHR::from),
+            )?
         };
 
-        Backend::new(self.device_index, self.device_id, self.debug)
+        if let Some(path) = &self.probe_cache {
+            device.load_probe_cache(path);
+        }
+
+        Backend::new(
+            device,
+            self.minimize_usage,
+            self.semaphore_pool_size.unwrap_or(4),
+            self.profile_copies,
+            self.concurrent_copies,
+        )
     }
 }
@@ -6,22 +6,90 @@
 //! This module defines `Bo`.
 
 use super::backends::{
-    Backend, Class, Constraint, CopyBuffer, CopyBufferImage, Extent, Flags, Handle, Layout,
-    MemoryType,
+    Backend, Class, Compression, Constraint, CopyBuffer, CopyBufferImage, CopyImage, CopyOp,
+    CpuUsage, Description, Extent, Fill, Filter, Flags, Handle, Layout, MemoryType, Rect, Region,
+    Requirements, Usage,
 };
 use super::device::Device;
 use super::formats;
-use super::types::{Access, Error, Format, Mapping, Result, Size};
+use super::types::{Access, Error, Format, Mapping, Modifier, Result, Size};
 use super::utils;
-use std::os::fd::{BorrowedFd, OwnedFd};
-use std::sync::{Arc, Mutex};
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use std::{ffi, ptr, slice};
 
 struct BoState {
+    // a sparse BO is bound only when all of its regions are bound
     bound: bool,
     mt: MemoryType,
+    // this is 0 if the memory is imported
+    allocated_size: Size,
+    // the size of the bound regions of a sparse BO
+    sparse_size: Size,
+    // the dma-buf has been named, or is imported and has been named by its exporter
+    named: bool,
+    // the dma-buf exported to sync the memory while unmapped, until the memory changes
+    sync_dmabuf: Option<OwnedFd>,
 
     mapping: Option<Mapping>,
     map_count: u32,
+    // the mapping is at a caller-provided address
+    placed: bool,
+}
+
+// SAFETY: the mapping is only dereferenced by the owners of the BO
+unsafe impl Send for BoState {}
+
+// the staging buffer association of an image BO
+struct Staging {
+    class: Class,
+    size: Size,
+    mt: MemoryType,
+    copies: Vec<CopyBufferImage>,
+    // an idle staging buffer to be reused
+    idle: Option<Bo>,
+}
+
+// the copy of a snapshot, shared by the snapshot and the source BO
+type SnapshotCopy = Mutex<Option<Bo>>;
+
+/// A copy-on-write snapshot of a BO.
+///
+/// The contents of the source BO are copied lazily, when the source BO is about to be written.
+/// A snapshot that is dropped before then costs no copy.
+pub struct Snapshot {
+    device: Arc<Device>,
+    serial: u64,
+    copy: Arc<SnapshotCopy>,
+}
+
+/// A CPU mapping of a BO.
+///
+/// The BO is mutably borrowed for the lifetime of the guard and is unmapped when the guard is
+/// dropped.
+pub struct MappingGuard<'a> {
+    bo: &'a mut Bo,
+    mapping: Mapping,
+}
+
+/// A batch of copies between BOs.
+///
+/// The copies are recorded by the builder methods and are submitted together by `submit`.
+/// Backends that support batching execute all copies in a single submission.  All BOs of a batch
+/// must belong to the same device and backend.
+#[derive(Default)]
+pub struct CopyBatch<'a> {
+    // the dst BO, the src BO, and the copy
+    copies: Vec<(&'a Bo, &'a Bo, BatchCopy)>,
+}
+
+#[derive(Clone, Copy)]
+enum BatchCopy {
+    Buffer(CopyBuffer),
+    BufferImage(CopyBufferImage),
+    Image(CopyImage),
 }
 
 /// A buffer object (BO).
@@ -32,11 +100,20 @@ pub struct Bo {
     handle: Handle,
 
     flags: Flags,
+    usage: Usage,
+    cpu_usage: CpuUsage,
     format: Format,
     backend_index: usize,
     extent: Extent,
+    // the size of the handle of a buffer BO, which can be larger than the extent after a resize
+    capacity: Size,
+    // the constraint the handle was created with, which is reused by resize
+    con: Option<Constraint>,
+    serial: u64,
 
     state: Mutex<BoState>,
+    staging: Mutex<Option<Box<Staging>>>,
+    snapshots: Mutex<Vec<Weak<SnapshotCopy>>>,
 }
 
 fn merge_class_to_constraint(con: Option<Constraint>, class: &Class) -> Result<Option<Constraint>> {
@@ -57,26 +134,283 @@ fn merge_class_to_constraint(con: Option<Constraint>, class: &Class) -> Result<O
         }
     }
 
+    // move the preferred modifiers to the front, from the most preferred to the least preferred
+    let allowed = if con.modifiers.is_empty() {
+        &class.modifiers
+    } else {
+        &con.modifiers
+    };
+    let mut mods: Vec<Modifier> = Vec::with_capacity(allowed.len());
+    for m in con.preferred_modifiers.iter().chain(allowed.iter()) {
+        if allowed.contains(m) && !mods.contains(m) {
+            mods.push(*m);
+        }
+    }
+    if mods != *allowed {
+        con.modifiers = mods;
+    }
+
     Ok(Some(con))
 }
 
+// External BOs are shared with APIs such as DRM KMS that use 32-bit offsets and strides.  Fail
+// early rather than letting them truncate the values.
+fn validate_external_layout(layout: &Layout) -> Result<()> {
+    layout.offsets_u32()?;
+    layout.strides_u32()?;
+
+    Ok(())
+}
+
+// Checks the size of the layout that the backend would create against the class limit, before
+// the backend creates anything.  Backends that cannot dry run are checked after the fact by the
+// callers.
+fn validate_requirements(
+    backend: &dyn Backend,
+    class: &Class,
+    extent: Extent,
+    con: &Option<Constraint>,
+) -> Result<()> {
+    if class.max_size == Size::MAX {
+        return Ok(());
+    }
+
+    match backend.dry_run(class, extent, con.clone()) {
+        Ok(reqs) if reqs.layout.size > class.max_size => Error::user(),
+        Ok(_) | Err(Error::Unsupported) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+// Validates a copy region between a buffer of `size` bytes and an image plane of
+// `width`x`height`x`depth` blocks of `bpp` bytes.  Buffers can be larger than 4GiB and all
+// arithmetic is checked.
+fn validate_copy_region(
+    copy: &CopyBufferImage,
+    size: Size,
+    width: u32,
+    height: u32,
+    depth: u32,
+    bpp: Size,
+) -> bool {
+    if copy.width == 0
+        || copy.height == 0
+        || copy.depth == 0
+        || copy.x > width
+        || copy.y > height
+        || copy.z > depth
+        || copy.width > width - copy.x
+        || copy.height > height - copy.y
+        || copy.depth > depth - copy.z
+    {
+        return false;
+    }
+
+    if copy.offset % bpp != 0 || copy.stride % bpp != 0 {
+        return false;
+    }
+
+    // the row length in blocks must fit in 32 bits for the backends
+    match u32::try_from(copy.stride / bpp) {
+        Ok(row_len) if row_len >= copy.width => (),
+        _ => return false,
+    }
+
+    // the slice stride must be a multiple of the stride, and the slice height in rows must fit
+    // in 32 bits for the backends
+    if copy.depth > 1 {
+        if copy.slice_stride % copy.stride != 0 {
+            return false;
+        }
+        match u32::try_from(copy.slice_stride / copy.stride) {
+            Ok(slice_height) if slice_height >= copy.height => (),
+            _ => return false,
+        }
+    }
+
+    // the last row does not need to be padded to the stride, nor the last slice to the slice
+    // stride
+    let row_size = copy.width as Size * bpp;
+    let slices_size = if copy.depth > 1 {
+        copy.slice_stride.checked_mul(copy.depth as Size - 1)
+    } else {
+        Some(0)
+    };
+    let end = copy
+        .stride
+        .checked_mul(copy.height as Size - 1)
+        .and_then(|rows_size| rows_size.checked_add(row_size))
+        .zip(slices_size)
+        .and_then(|(slice_size, slices_size)| slice_size.checked_add(slices_size))
+        .and_then(|region_size| region_size.checked_add(copy.offset));
+
+    matches!(end, Some(end) if end <= size)
+}
+
+// Returns the byte range that covers an image region of a linear layout.  The region must have
+// been validated.
+fn linear_region_range(
+    layout: &Layout,
+    fmt: Format,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Result<(Size, Size)> {
+    let fmt_class = formats::format_class(fmt)?;
+    let mut start = Size::MAX;
+    let mut end = 0;
+    for plane in 0..(fmt_class.plane_count as usize) {
+        let bpp = fmt_class.block_size[plane] as Size;
+        let (block_width, block_height) = fmt_class.block_extent[plane];
+        let (block_width, block_height) = (block_width as u32, block_height as u32);
+
+        let x0 = (x / block_width) as Size;
+        let y0 = (y / block_height) as Size;
+        let x1 = (x + width).div_ceil(block_width) as Size;
+        let y1 = (y + height).div_ceil(block_height) as Size;
+
+        let offset = layout.offsets[plane];
+        let stride = layout.strides[plane];
+        start = start.min(offset + stride * y0 + bpp * x0);
+        end = end.max(offset + stride * (y1 - 1) + bpp * x1);
+    }
+
+    Ok((start, end - start))
+}
+
+// Returns the tightly packed per-plane copy regions of an image and the size of the buffer that
+// holds all planes.
+fn packed_copy_regions(
+    fmt: Format,
+    width: u32,
+    height: u32,
+    depth: u32,
+) -> Result<(Vec<CopyBufferImage>, Size)> {
+    let fmt_class = formats::format_class(fmt)?;
+    let mut copies = Vec::new();
+    let mut size: Size = 0;
+    for plane in 0..(fmt_class.plane_count as usize) {
+        let bpp = fmt_class.block_size[plane] as Size;
+        let (block_width, block_height) = fmt_class.block_extent[plane];
+        let plane_width = width / block_width as u32;
+        let plane_height = height / block_height as u32;
+
+        // buffer offsets must be aligned to the block size
+        let offset = size.next_multiple_of(bpp);
+        let stride = plane_width as Size * bpp;
+        let slice_stride = stride
+            .checked_mul(plane_height as Size)
+            .ok_or(Error::User)?;
+        size = slice_stride
+            .checked_mul(depth as Size)
+            .and_then(|plane_size| plane_size.checked_add(offset))
+            .ok_or(Error::User)?;
+
+        copies.push(CopyBufferImage {
+            offset,
+            stride,
+            slice_stride,
+            plane: plane as u32,
+            x: 0,
+            y: 0,
+            z: 0,
+            width: plane_width,
+            height: plane_height,
+            depth,
+        });
+    }
+
+    Ok((copies, size))
+}
+
+// returns the host memory offset, the staging buffer offset, and the size of each row of a region
+fn staged_rows(region: Region, staged: Region, len: usize) -> Vec<(usize, usize, usize)> {
+    let (Region::Image(copy), Region::Image(staged)) = (region, staged) else {
+        return vec![(0, 0, len)];
+    };
+
+    let mut rows = Vec::new();
+    for slice in 0..copy.depth as Size {
+        for row in 0..copy.height as Size {
+            let offset = copy.offset + copy.slice_stride * slice + copy.stride * row;
+            let staged_offset = staged.slice_stride * slice + staged.stride * row;
+            rows.push((
+                offset as usize,
+                staged_offset as usize,
+                staged.stride as usize,
+            ));
+        }
+    }
+
+    rows
+}
+
+// filters and sorts memory types for a CPU usage
+fn filter_memory_types(mut mts: Vec<MemoryType>, cpu_usage: CpuUsage) -> Vec<MemoryType> {
+    let preferred = cpu_usage.preferred_memory_type();
+    if !preferred.is_empty() {
+        mts.retain(|mt| mt.contains(MemoryType::MAPPABLE));
+        mts.sort_by_key(|mt| !mt.contains(preferred));
+    }
+
+    mts
+}
+
+// returns the memory types to retry with when mt is out of device memory, which are on the other
+// kind of heap and have the same host access
+fn fallback_memory_types(mts: Vec<MemoryType>, mt: MemoryType) -> Vec<MemoryType> {
+    let local = mt.contains(MemoryType::LOCAL);
+    let host_access = mt - MemoryType::LOCAL;
+
+    let mut fallbacks: Vec<MemoryType> = Vec::new();
+    for candidate in mts {
+        if candidate.contains(MemoryType::LOCAL) != local
+            && candidate.contains(host_access)
+            && !fallbacks.iter().any(|mt| mt.bits() == candidate.bits())
+        {
+            fallbacks.push(candidate);
+        }
+    }
+
+    fallbacks
+}
+
 impl Bo {
     fn new(device: Arc<Device>, handle: Handle, class: &Class, extent: Extent) -> Self {
         let state = BoState {
             bound: false,
             mt: MemoryType::empty(),
+            allocated_size: 0,
+            sparse_size: 0,
+            named: false,
+            sync_dmabuf: None,
             mapping: None,
             map_count: 0,
+            placed: false,
         };
 
+        let serial = device.next_bo_serial();
+
         Self {
             device,
             handle,
             flags: class.flags,
+            usage: class.usage,
+            cpu_usage: class.cpu_usage,
             format: class.format,
             backend_index: class.backend_index,
             extent,
+            capacity: if let Extent::Buffer(size) = extent {
+                size
+            } else {
+                0
+            },
+            con: None,
+            serial,
             state: Mutex::new(state),
+            staging: Mutex::new(None),
+            snapshots: Mutex::new(Vec::new()),
         }
     }
 
@@ -94,12 +428,54 @@ impl Bo {
         let con = merge_class_to_constraint(con, class)?;
 
         let backend = device.backend(class.backend_index);
-        let handle = backend.with_constraint(class, extent, con)?;
-        let bo = Self::new(device, handle, class, extent);
+        validate_requirements(backend, class, extent, &con)?;
+        let handle = backend.with_constraint(class, extent, con.clone())?;
+        let mut bo = Self::new(device, handle, class, extent);
+        bo.con = con;
+
+        let layout = bo.layout();
+        if layout.size > class.max_size {
+            return Error::user();
+        }
+        if bo.can_external() {
+            validate_external_layout(&layout)?;
+        }
 
         Ok(bo)
     }
 
+    /// Computes the requirements of a BO that `with_constraint` would create.
+    ///
+    /// This is a dry run of `with_constraint`.  The layout and the supported memory types are
+    /// computed, but no BO is created and no memory is allocated.  This allows allocation brokers
+    /// to answer queries cheaply during negotiation.  `Error::Unsupported` is returned if the
+    /// backend cannot compute the requirements without allocating.
+    pub fn dry_run(
+        device: &Device,
+        class: &Class,
+        extent: Extent,
+        con: Option<Constraint>,
+    ) -> Result<Requirements> {
+        if !class.validate(extent) {
+            return Error::user();
+        }
+
+        let con = merge_class_to_constraint(con, class)?;
+
+        let backend = device.backend(class.backend_index);
+        let mut reqs = backend.dry_run(class, extent, con)?;
+
+        if reqs.layout.size > class.max_size {
+            return Error::user();
+        }
+        if class.flags.contains(Flags::EXTERNAL) {
+            validate_external_layout(&reqs.layout)?;
+        }
+        reqs.memory_types = filter_memory_types(reqs.memory_types, class.cpu_usage);
+
+        Ok(reqs)
+    }
+
     /// Creates a BO with an explicit physical layout.
     ///
     /// When importing, `dmabuf` can be specified to further restrict the supported memory types.
@@ -110,10 +486,14 @@ impl Bo {
         layout: Layout,
         dmabuf: Option<BorrowedFd>,
     ) -> Result<Self> {
-        if !class.validate(extent) {
+        if !class.validate(extent) || layout.size > class.max_size {
             return Error::user();
         }
 
+        if class.flags.contains(Flags::EXTERNAL) {
+            validate_external_layout(&layout)?;
+        }
+
         let backend = device.backend(class.backend_index);
         let handle = backend.with_layout(class, extent, layout, dmabuf)?;
         let bo = Self::new(device, handle, class, extent);
@@ -146,6 +526,15 @@ impl Bo {
         self.backend().layout(&self.handle)
     }
 
+    /// Returns the compression state.
+    ///
+    /// This tells whether the implementation actually compressed the BO, and at which rate.
+    /// `Compression::Unknown` is returned when the backend cannot tell, such as when the device
+    /// lacks `Features::COMPRESSION_CONTROL`.
+    pub fn compression(&self) -> Compression {
+        self.backend().compression(&self.handle)
+    }
+
     /// Returns the supported memory types.
     ///
     /// When not importing, the supported memory types can be pre-determined to some degree.  If
@@ -157,246 +546,2903 @@ impl Bo {
     /// the second's.
     ///
     /// When importing, the supported memory types are further restricted by the imported dma-bufs.
+    ///
+    /// If the BO has a CPU usage, only mappable memory types are returned, and those that suit the
+    /// CPU usage better are returned first.
     pub fn memory_types(&self) -> Vec<MemoryType> {
-        self.backend().memory_types(&self.handle)
+        let mts = self.backend().memory_types(&self.handle);
+        filter_memory_types(mts, self.cpu_usage)
+    }
+
+    /// Returns the memory type bound to a BO.
+    ///
+    /// `None` is returned when the BO is not bound.  This can differ from the memory type passed
+    /// to `Bo::bind_memory` after a fallback.
+    pub fn memory_type(&self) -> Option<MemoryType> {
+        let state = self.state.lock().unwrap();
+        state.bound.then_some(state.mt)
+    }
+
+    // protected memory is only for protected BOs and is never mappable
+    fn validate_protected_memory_type(&self, mt: MemoryType) -> bool {
+        !mt.contains(MemoryType::PROTECTED)
+            || (self.flags.contains(Flags::PROTECTED) && !mt.contains(MemoryType::MAPPABLE))
     }
 
     /// Allocates or imports a memory, and binds the memory to a BO.
     ///
     /// A BO without a memory bound cannot be exported, mapped, nor copied.
     ///
+    /// When an allocation fails with `Error::OutOfDeviceMemory`, the allocation is retried with the
+    /// supported memory types that are on the other kind of heap and have the same host access,
+    /// unless disabled by `Builder::disable_memory_fallback`.  `Bo::memory_type` returns the memory
+    /// type that is actually bound.
+    ///
+    /// `MemoryType::PROTECTED` is only valid for BOs with `Flags::PROTECTED`, and cannot be combined
+    /// with `MemoryType::MAPPABLE`.
+    ///
     /// As a note, two HBM BOs can refer to the same kernel space BO due to export/import.
     pub fn bind_memory(&mut self, mt: MemoryType, dmabuf: Option<OwnedFd>) -> Result<()> {
         if dmabuf.is_some() && !self.can_external() {
             return Error::user();
         }
 
+        if !self.cpu_usage.is_empty() && !mt.contains(MemoryType::MAPPABLE) {
+            return Error::user();
+        }
+
+        if !self.validate_protected_memory_type(mt) {
+            return Error::user();
+        }
+
         let mut state = self.state.lock().unwrap();
         if state.bound {
             return Error::user();
         }
 
+        let import = dmabuf.is_some();
         let backend = self.device.backend(self.backend_index);
-        backend.bind_memory(&mut self.handle, mt, dmabuf)?;
+        let mut bound_mt = mt;
+        let mut res = backend.bind_memory(&mut self.handle, mt, dmabuf);
+        if !import {
+            self.device.record_allocation(res.is_ok());
+
+            // the original error is returned unless a fallback succeeds
+            if matches!(res, Err(Error::OutOfDeviceMemory)) && self.device.memory_fallback() {
+                for fallback_mt in fallback_memory_types(self.memory_types(), mt) {
+                    let fallback_res = backend.bind_memory(&mut self.handle, fallback_mt, None);
+                    self.device.record_allocation(fallback_res.is_ok());
+                    if fallback_res.is_ok() {
+                        res = fallback_res;
+                        bound_mt = fallback_mt;
+                        break;
+                    }
+                }
+            }
+        }
+        res?;
 
         state.bound = true;
-        state.mt = mt;
+        state.mt = bound_mt;
+        state.named = import;
+
+        if !import {
+            state.allocated_size = backend.layout(&self.handle).size;
+            self.device.add_allocated_size(state.allocated_size);
+        }
+        self.set_debug_name();
 
         Ok(())
     }
 
-    /// Exports a BO as a dma-buf.
+    /// Returns the `VkBuffer` of a buffer BO of a Vulkan backend.
     ///
-    /// A name can optionally be set for the dma-buf.
+    /// This allows in-process Vulkan users to use the BO directly when the backend adopts their
+    /// device.  See `vulkan::Builder::external_device`.  The buffer is owned by the BO and is
+    /// destroyed when the BO is dropped or resized.  It is owned by
+    /// `VK_QUEUE_FAMILY_FOREIGN_EXT` between copies, or by `VK_QUEUE_FAMILY_EXTERNAL` when
+    /// `VK_EXT_queue_family_foreign` is not supported.
+    #[cfg(feature = "ash")]
+    pub fn vk_buffer(&self) -> Option<ash::vk::Buffer> {
+        crate::vulkan::vk_buffer(&self.handle)
+    }
+
+    /// Returns the `VkImage` of an image BO of a Vulkan backend.
     ///
-    /// As a note, two userspace dma-buf fds can refer to the same kernel space dma-buf object.
-    /// The name is attached to the kernel space dma-buf object, not the userspace dma-buf fds.
-    pub fn export_dma_buf(&self, name: Option<&str>) -> Result<OwnedFd> {
-        if !self.can_external() {
+    /// Like `vk_buffer`, the image is owned by the BO.  It is owned by the same queue family as
+    /// buffers and is in `VK_IMAGE_LAYOUT_GENERAL` between copies.
+    #[cfg(feature = "ash")]
+    pub fn vk_image(&self) -> Option<ash::vk::Image> {
+        crate::vulkan::vk_image(&self.handle)
+    }
+
+    /// Returns the `VkDeviceMemory` bound to a BO of a Vulkan backend, and the offset of the BO in
+    /// the memory.
+    ///
+    /// The memory is owned by the BO and can be shared with other BOs, in which case the offset
+    /// can be non-zero.  `None` is returned when the BO is not bound, is sparse, or has disjoint
+    /// memory planes.
+    #[cfg(feature = "ash")]
+    pub fn vk_device_memory(&self) -> Option<(ash::vk::DeviceMemory, Size)> {
+        crate::vulkan::vk_device_memory(&self.handle)
+    }
+
+    /// Returns the sparse block size of a BO, or `None` if the BO is not sparse.
+    pub fn sparse_block_size(&self) -> Option<Size> {
+        self.backend().sparse_block_size(&self.handle)
+    }
+
+    /// Allocates a memory, and binds the memory to a region of a sparse BO.
+    ///
+    /// This requires `Features::SPARSE` and a sparse BO class, such as one with
+    /// `vulkan::Usage::SPARSE`.  A sparse BO can be very large because memories are bound to its
+    /// regions on demand, and it cannot be bound with `bind_memory`.  `offset` and `size` must be
+    /// aligned to `sparse_block_size`, except that the region can extend to the end of the BO
+    /// unaligned.  The region must not overlap bound regions, and all regions must use the same
+    /// memory type.  A sparse BO is bound, and can be copied or filled, only when all of its
+    /// regions are bound.
+    pub fn bind_sparse_region(&mut self, mt: MemoryType, offset: Size, size: Size) -> Result<()> {
+        let Some(block_size) = self.sparse_block_size() else {
+            return Error::user();
+        };
+
+        let layout_size = self.layout().size;
+        let end = offset.checked_add(size).ok_or(Error::User)?;
+        if size == 0
+            || end > layout_size
+            || offset % block_size != 0
+            || (size % block_size != 0 && end != layout_size)
+        {
             return Error::user();
         }
 
-        let state = self.state.lock().unwrap();
-        if !state.bound {
+        let mut state = self.state.lock().unwrap();
+        if state.sparse_size > 0 && state.mt.bits() != mt.bits() {
             return Error::user();
         }
 
-        self.backend().export_dma_buf(&self.handle, name)
+        let backend = self.device.backend(self.backend_index);
+        let res = backend.bind_sparse_region(&mut self.handle, mt, offset, size);
+        self.device.record_allocation(res.is_ok());
+        res?;
+
+        // bound regions never overlap
+        state.sparse_size += size;
+        state.bound = state.sparse_size == layout_size;
+        state.mt = mt;
+        state.allocated_size += size;
+        self.device.add_allocated_size(size);
+        self.set_debug_name();
+
+        Ok(())
     }
 
-    /// Maps a BO for CPU access.
+    /// Unbinds and frees the memories bound to a region of a sparse BO.
     ///
-    /// Recursive mapping is allowed and returns the same mapping.
-    pub fn map(&mut self) -> Result<Mapping> {
-        if !self.can_map() {
+    /// Bound regions that are in the region are unbound after pending copies complete.  Bound
+    /// regions that are partially in the region are not allowed.  The BO is no longer bound when
+    /// any region is unbound.
+    pub fn unbind_sparse_region(&mut self, offset: Size, size: Size) -> Result<()> {
+        if self.sparse_block_size().is_none() {
             return Error::user();
         }
 
         let mut state = self.state.lock().unwrap();
-        if !state.bound || !state.mt.contains(MemoryType::MAPPABLE) {
+        let backend = self.device.backend(self.backend_index);
+        let size = backend.unbind_sparse_region(&mut self.handle, offset, size)?;
+
+        state.sparse_size -= size;
+        state.bound = state.bound && size == 0;
+        state.allocated_size -= size;
+        state.sync_dmabuf = None;
+        self.device.sub_allocated_size(size);
+
+        Ok(())
+    }
+
+    /// Imports a host allocation, and binds the memory to a buffer BO.
+    ///
+    /// This requires `Features::HOST_MEMORY` and a BO class that allows host allocations, such as
+    /// one with `vulkan::Usage::HOST_MEMORY`.  The BO can then be a zero-copy source or
+    /// destination of copies without a dma-buf.  The memory is not counted as allocated.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be aligned to the device's minimum host pointer alignment, which is usually the
+    /// page size.  The host allocation must span at least `layout().size` bytes rounded up to the
+    /// alignment, and must stay valid until the BO is dropped.
+    pub unsafe fn bind_host_memory(
+        &mut self,
+        mt: MemoryType,
+        ptr: ptr::NonNull<ffi::c_void>,
+    ) -> Result<()> {
+        if !self.is_buffer() || self.can_external() {
             return Error::user();
         }
 
-        if state.map_count == 0 {
-            let mapping = self.backend().map(&self.handle)?;
-            state.mapping = Some(mapping);
-            state.map_count = 1;
-        } else {
-            state.map_count += 1;
+        if !self.cpu_usage.is_empty() && !mt.contains(MemoryType::MAPPABLE) {
+            return Error::user();
         }
 
-        Ok(state.mapping.unwrap())
-    }
-
-    /// Unmaps a BO.
-    pub fn unmap(&mut self) {
         let mut state = self.state.lock().unwrap();
-
-        match state.map_count {
-            0 => (),
-            1 => {
-                let mapping = state.mapping.take().unwrap();
-                self.backend().unmap(&self.handle, mapping);
-                state.map_count = 0;
-            }
-            _ => state.map_count -= 1,
+        if state.bound {
+            return Error::user();
         }
+
+        let backend = self.device.backend(self.backend_index);
+        // SAFETY: the caller guarantees that the host allocation outlives the BO
+        unsafe { backend.bind_host_memory(&mut self.handle, mt, ptr) }?;
+
+        state.bound = true;
+        state.mt = mt;
+        self.set_debug_name();
+
+        Ok(())
     }
 
-    /// Flushes the CPU cache for the BO mapping.
+    /// Resizes a buffer BO.
     ///
-    /// If the memory type is coherent, the CPU cache is not flushed.
-    pub fn flush(&self) {
-        let state = self.state.lock().unwrap();
+    /// `class` must be the class the BO was created with.  When the BO has a memory bound and the
+    /// memory is large enough, only the size of the BO is updated.  Otherwise, the BO is recreated
+    /// with the constraint it was created with and, if it had a memory allocated, a new memory of
+    /// the same memory type is allocated.  The
+    /// contents are undefined after a reallocation, and dma-bufs exported before refer to the old
+    /// memory.
+    ///
+    /// A BO that is mapped or has an imported memory cannot be reallocated.
+    pub fn resize(&mut self, class: &Class, size: Size) -> Result<()> {
+        if !self.is_buffer()
+            || class.backend_index != self.backend_index
+            || class.flags != self.flags
+            || class.format != self.format
+            || class.usage != self.usage
+        {
+            return Error::user();
+        }
 
-        if state.map_count > 0 && !state.mt.contains(MemoryType::COHERENT) {
-            self.backend().flush(&self.handle);
+        let extent = Extent::Buffer(size);
+        if !class.validate(extent) {
+            return Error::user();
         }
-    }
 
-    /// Invalidates the CPU cache for the BO mapping.
-    ///
-    /// If the memory type is coherent, the CPU cache is not invalidated.
-    pub fn invalidate(&self) {
         let state = self.state.lock().unwrap();
+        let (bound, mt, allocated_size) = (state.bound, state.mt, state.allocated_size);
+        if bound {
+            if size <= self.capacity {
+                drop(state);
+                self.extent = extent;
+                return Ok(());
+            }
 
-        if state.map_count > 0 && !state.mt.contains(MemoryType::COHERENT) {
-            self.backend().invalidate(&self.handle);
+            if allocated_size == 0 || state.map_count > 0 {
+                return Error::user();
+            }
         }
-    }
+        drop(state);
 
-    // this should not be used if the mutex needs to remain locked for synchronization
-    fn is_bound(&self) -> bool {
-        let state = self.state.lock().unwrap();
-        state.bound
-    }
+        // validate before the snapshots are resolved and the handle is recreated
+        validate_requirements(self.backend(), class, extent, &self.con)?;
 
-    fn validate_copy(&self, src: &Bo) -> bool {
-        self.can_copy() && self.is_bound() && src.can_copy() && src.is_bound()
-    }
+        // the old memory is about to be freed
+        if bound {
+            self.resolve_snapshots()?;
+        }
 
-    fn validate_copy_buffer(&self, src: &Bo, copy: &CopyBuffer) -> bool {
-        if !self.validate_copy(src) || !self.is_buffer() || !src.is_buffer() {
-            return false;
+        let backend = self.backend();
+        let mut handle = backend.with_constraint(class, extent, self.con.clone())?;
+
+        let layout = backend.layout(&handle);
+        let res = if layout.size > class.max_size {
+            Error::user()
+        } else if self.can_external() {
+            validate_external_layout(&layout)
+        } else {
+            Ok(())
+        };
+        let res = res.and_then(|_| {
+            if bound {
+                let res = backend.bind_memory(&mut handle, mt, None);
+                self.device.record_allocation(res.is_ok());
+                res
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(err) = res {
+            backend.free(&handle);
+            return Err(err);
         }
 
-        let src_size = src.extent.size();
-        let dst_size = self.extent.size();
+        let old_handle = std::mem::replace(&mut self.handle, handle);
+        self.backend().free(&old_handle);
 
-        copy.size > 0
-            && copy.src_offset <= src_size
-            && copy.size <= src_size - copy.src_offset
-            && copy.dst_offset <= dst_size
-            && copy.size <= dst_size - copy.dst_offset
+        if bound {
+            let mut state = self.state.lock().unwrap();
+            self.device.sub_allocated_size(state.allocated_size);
+            state.allocated_size = layout.size;
+            self.device.add_allocated_size(state.allocated_size);
+            state.named = false;
+            state.sync_dmabuf = None;
+            drop(state);
+
+            self.set_debug_name();
+        }
+
+        self.extent = extent;
+        self.capacity = size;
+
+        Ok(())
     }
 
-    fn validate_copy_buffer_image(&self, src: &Bo, copy: &CopyBufferImage) -> bool {
-        if !self.validate_copy(src) || (self.is_buffer() == src.is_buffer()) {
-            return false;
+    /// Allocates or imports a memory for each memory plane, and binds the memories to a BO.
+    ///
+    /// This is only supported for BOs whose memory planes are disjoint, such as those with
+    /// `vulkan::Usage::DISJOINT`.  When importing, `dmabufs` has a dma-buf for each memory plane,
+    /// and the layout offsets are relative to the dma-bufs.  Such BOs must be exported with
+    /// `export_dma_bufs` and cannot be mapped.
+    pub fn bind_memory_planes(
+        &mut self,
+        mt: MemoryType,
+        dmabufs: Option<Vec<OwnedFd>>,
+    ) -> Result<()> {
+        if dmabufs.is_some() && !self.can_external() {
+            return Error::user();
         }
 
-        let size;
-        let mut width;
-        let mut height;
-        let fmt;
-        if self.is_buffer() {
-            size = self.extent.size();
-            width = src.extent.width();
-            height = src.extent.height();
-            fmt = src.format;
-        } else {
-            size = src.extent.size();
-            width = self.extent.width();
-            height = self.extent.height();
-            fmt = self.format;
+        if self.is_buffer() || !self.cpu_usage.is_empty() {
+            return Error::user();
         }
 
-        let fmt_class = formats::format_class(fmt).unwrap();
-        let plane_count = fmt_class.plane_count as u32;
-        if copy.plane >= plane_count {
-            return false;
+        if !self.validate_protected_memory_type(mt) {
+            return Error::user();
         }
 
-        let bpp = fmt_class.block_size[copy.plane as usize] as Size;
-        width /= fmt_class.block_extent[copy.plane as usize].0 as u32;
-        height /= fmt_class.block_extent[copy.plane as usize].1 as u32;
+        let mut state = self.state.lock().unwrap();
+        if state.bound {
+            return Error::user();
+        }
 
-        if copy.offset % bpp != 0
-            || copy.stride % bpp != 0
-            || copy.stride / bpp < copy.width as Size
-        {
-            return false;
+        let import = dmabufs.is_some();
+        let backend = self.device.backend(self.backend_index);
+        let res = backend.bind_memory_planes(&mut self.handle, mt, dmabufs);
+        if !import {
+            self.device.record_allocation(res.is_ok());
         }
+        res?;
 
-        copy.width > 0
-            && copy.height > 0
-            && copy.offset <= size
-            && copy.stride <= (size - copy.offset) / copy.height as Size
-            && copy.x <= width
-            && copy.y <= height
-            && copy.width <= width - copy.x
-            && copy.height <= height - copy.y
-    }
-
-    fn wait_copy(&self, sync_fd: Option<OwnedFd>, wait: bool) -> Option<OwnedFd> {
-        if wait {
-            sync_fd.and_then(|sync_fd| {
-                let _ = utils::poll(sync_fd, Access::Read);
-                None
-            })
-        } else {
-            sync_fd
+        state.bound = true;
+        state.mt = mt;
+        state.named = import;
+
+        if !import {
+            state.allocated_size = backend.layout(&self.handle).size;
+            self.device.add_allocated_size(state.allocated_size);
         }
+        self.set_debug_name();
+
+        Ok(())
     }
 
-    /// Copies between two BOs that are both buffers.
+    // names the backend objects of a BO for debugging
+    fn set_debug_name(&self) {
+        let name = self
+            .device
+            .dma_buf_name(self.usage, self.serial)
+            .unwrap_or_else(|| format!("hbm-{}-{}", self.usage.name(), self.serial));
+        self.backend().set_name(&self.handle, &name);
+    }
+
+    /// Exports a BO as a dma-buf.
     ///
-    /// `sync_fd` is an optional sync file that the copy operation waits for.
+    /// A name can optionally be set for the dma-buf.  If no name is given and the device has a
+    /// name template, the dma-buf is named after the template when it is first exported.
+    /// Imported dma-bufs keep the names given by their exporters.
     ///
-    /// If `wait` is true, this function never returns any sync file.  Otherwise, it may
-    /// return a sync file associated with the copy operation.
-    pub fn copy_buffer(
-        &self,
-        src: &Bo,
-        copy: CopyBuffer,
-        sync_fd: Option<OwnedFd>,
-        wait: bool,
-    ) -> Result<Option<OwnedFd>> {
-        if !self.validate_copy_buffer(src, &copy) {
+    /// As a note, two userspace dma-buf fds can refer to the same kernel space dma-buf object.
+    /// The name is attached to the kernel space dma-buf object, not the userspace dma-buf fds.
+    pub fn export_dma_buf(&self, name: Option<&str>) -> Result<OwnedFd> {
+        let name = self.export_name(name);
+        self.export(name.as_deref())
+    }
+
+    /// Exports a BO as a dma-buf for each memory.
+    ///
+    /// A BO bound with `bind_memory_planes` has a dma-buf for each memory plane.  Other BOs have a
+    /// single dma-buf, the same as that returned by `export_dma_buf`.  Naming is the same as in
+    /// `export_dma_buf`.
+    pub fn export_dma_bufs(&self, name: Option<&str>) -> Result<Vec<OwnedFd>> {
+        if !self.can_external() {
+            return Error::user();
+        }
+
+        let name = self.export_name(name);
+
+        let state = self.state.lock().unwrap();
+        if !state.bound {
             return Error::user();
         }
 
         self.backend()
-            .copy_buffer(&self.handle, &src.handle, copy, sync_fd)
-            .map(|sync_fd| self.wait_copy(sync_fd, wait))
+            .export_dma_bufs(&self.handle, name.as_deref())
     }
 
-    /// Copies between two BOs where one is a buffer and one is an image.
+    // returns the name to set when the dma-buf is exported by the user
+    fn export_name(&self, name: Option<&str>) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        if !state.bound || (name.is_none() && state.named) {
+            return None;
+        }
+
+        // the name template is applied only once
+        let name = name
+            .map(String::from)
+            .or_else(|| self.device.dma_buf_name(self.usage, self.serial));
+        state.named |= name.is_some();
+
+        name
+    }
+
+    /// Creates a second BO that shares the memory of a BO.
     ///
-    /// `sync_fd` is an optional sync file that the copy operation waits for.
+    /// The BO must have `Flags::EXTERNAL` and a memory bound, and `class` must be the BO class
+    /// that the BO was created with.  The memory is exported as dma-bufs and imported into a new
+    /// BO with the same layout and memory type.  The new BO is independent of this BO.  It has its
+    /// own map state and can outlive this BO.  This suits multiple in-process users of one
+    /// buffer, such as buffers imported by gralloc mappers.
+    pub fn duplicate(&self, class: &Class) -> Result<Bo> {
+        if class.backend_index != self.backend_index
+            || class.flags != self.flags
+            || class.format != self.format
+            || class.usage != self.usage
+        {
+            return Error::user();
+        }
+
+        let mt = self.memory_type().ok_or(Error::User)?;
+        let mut dmabufs = self.export_dma_bufs(None)?;
+
+        let mut bo = Bo::with_layout(
+            self.device.clone(),
+            class,
+            self.extent,
+            self.layout(),
+            Some(dmabufs[0].as_fd()),
+        )?;
+        if dmabufs.len() > 1 {
+            bo.bind_memory_planes(mt, Some(dmabufs))?;
+        } else {
+            bo.bind_memory(mt, dmabufs.pop())?;
+        }
+
+        Ok(bo)
+    }
+
+    fn export(&self, name: Option<&str>) -> Result<OwnedFd> {
+        if !self.can_external() {
+            return Error::user();
+        }
+
+        let state = self.state.lock().unwrap();
+        if !state.bound {
+            return Error::user();
+        }
+
+        self.backend().export_dma_buf(&self.handle, name)
+    }
+
+    fn implicit_sync_access(write: bool) -> Access {
+        if write {
+            Access::Write
+        } else {
+            Access::Read
+        }
+    }
+
+    /// Exports the implicit fences of a BO as a sync file.
     ///
-    /// If `wait` is true, this function never returns any sync file.  Otherwise, it may
-    /// return a sync file associated with the copy operation.
-    pub fn copy_buffer_image(
-        &self,
-        src: &Bo,
-        copy: CopyBufferImage,
-        sync_fd: Option<OwnedFd>,
-        wait: bool,
-    ) -> Result<Option<OwnedFd>> {
-        if !self.validate_copy_buffer_image(src, &copy) {
+    /// If `write` is true, the sync file signals when all pending device accesses complete, as
+    /// required before the BO is written.  Otherwise, it signals when pending device writes
+    /// complete, as required before the BO is read.
+    pub fn export_sync_fd(&self, write: bool) -> Result<OwnedFd> {
+        let dmabuf = self.export(None)?;
+        utils::dma_buf_export_sync_file(dmabuf, Self::implicit_sync_access(write))
+    }
+
+    /// Waits for the implicit fences of a BO.
+    ///
+    /// `write` has the same meaning as in `export_sync_fd`.  If `timeout` is `None`, this waits
+    /// indefinitely.  Returns false if the timeout expires.
+    pub fn wait(&self, write: bool, timeout: Option<Duration>) -> Result<bool> {
+        let dmabuf = self.export(None)?;
+        utils::poll_timeout(dmabuf, Self::implicit_sync_access(write), timeout)
+    }
+
+    /// Maps a BO for CPU access.
+    ///
+    /// The BO is unmapped when the returned guard is dropped, unless the guard is converted with
+    /// `MappingGuard::into_raw`.  Recursive mapping is allowed and returns the same mapping.
+    ///
+    /// Pending snapshots are resolved first because the mapping is writable.
+    pub fn map(&mut self) -> Result<MappingGuard<'_>> {
+        let mapping = self.map_raw()?;
+
+        Ok(MappingGuard { bo: self, mapping })
+    }
+
+    fn map_raw(&mut self) -> Result<Mapping> {
+        if !self.can_map() {
             return Error::user();
         }
 
-        self.backend()
-            .copy_buffer_image(&self.handle, &src.handle, copy, sync_fd)
-            .map(|sync_fd| self.wait_copy(sync_fd, wait))
+        self.resolve_snapshots()?;
+
+        let mut state = self.state.lock().unwrap();
+        if !state.bound || !state.mt.contains(MemoryType::MAPPABLE) {
+            return Error::user();
+        }
+
+        if state.map_count == 0 {
+            let mapping = self.backend().map(&self.handle)?;
+            state.mapping = Some(mapping);
+            state.map_count = 1;
+        } else {
+            state.map_count += 1;
+        }
+
+        Ok(state.mapping.unwrap())
     }
-}
 
-impl Drop for Bo {
-    fn drop(&mut self) {
-        self.unmap();
-        self.backend().free(&self.handle);
+    /// Maps a BO for CPU access at a fixed address.
+    ///
+    /// This requires `Features::PLACED_MAP`.  It is similar to `map`, except that the BO must not
+    /// be mapped already and that the mapping is placed at `addr`.  This is useful when the
+    /// address must be stable across remappings, such as when the memory is exposed to a guest
+    /// by a VMM.  When the BO is unmapped, the address range stays reserved by an inaccessible
+    /// mapping.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be aligned to the page size, or to a larger alignment required by the
+    /// backend.  The address range of `layout().size` bytes rounded up to the page size must be
+    /// reserved by the caller, such as by an inaccessible anonymous mapping, and must not be used
+    /// by anything else.  Any existing mapping in the range is replaced.
+    pub unsafe fn map_placed(
+        &mut self,
+        addr: ptr::NonNull<ffi::c_void>,
+    ) -> Result<MappingGuard<'_>> {
+        if !self.can_map() {
+            return Error::user();
+        }
+
+        self.resolve_snapshots()?;
+
+        let mut state = self.state.lock().unwrap();
+        if !state.bound || !state.mt.contains(MemoryType::MAPPABLE) || state.map_count > 0 {
+            return Error::user();
+        }
+
+        // SAFETY: the caller assumes the responsibility
+        let mapping = unsafe { self.backend().map_placed(&self.handle, addr) }?;
+        state.mapping = Some(mapping);
+        state.map_count = 1;
+        state.placed = true;
+        drop(state);
+
+        Ok(MappingGuard { bo: self, mapping })
+    }
+
+    /// Unmaps a BO.
+    ///
+    /// This is only needed for mappings returned by `MappingGuard::into_raw`.
+    pub fn unmap(&mut self) {
+        let mut state = self.state.lock().unwrap();
+
+        match state.map_count {
+            0 => (),
+            1 => {
+                let mapping = state.mapping.take().unwrap();
+                if state.placed {
+                    self.backend().unmap_placed(&self.handle, mapping);
+                    state.placed = false;
+                } else {
+                    self.backend().unmap(&self.handle, mapping);
+                }
+                state.map_count = 0;
+            }
+            _ => state.map_count -= 1,
+        }
+    }
+
+    // Syncs the memory of an unmapped BO through its dma-buf.  The memory might be mapped
+    // elsewhere, such as by another process that imported it.
+    fn sync_unmapped(&self, state: &mut BoState, access: Access, start: bool) -> Result<()> {
+        if !self.can_external() {
+            return Ok(());
+        }
+
+        if state.sync_dmabuf.is_none() {
+            state.sync_dmabuf = Some(self.backend().export_dma_buf(&self.handle, None)?);
+        }
+
+        match &state.sync_dmabuf {
+            Some(dmabuf) => utils::dma_buf_sync(dmabuf, access, start),
+            None => Ok(()),
+        }
+    }
+
+    fn validate_range(&self, offset: Size, size: Size) -> bool {
+        let layout = self.layout();
+        size > 0 && offset <= layout.size && size <= layout.size - offset
+    }
+
+    fn flush_impl(&self, range: Option<(Size, Size)>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.bound || state.mt.contains(MemoryType::COHERENT) {
+            return Ok(());
+        }
+
+        if state.map_count > 0 {
+            match range {
+                Some((offset, size)) => self.backend().flush_range(&self.handle, offset, size),
+                None => self.backend().flush(&self.handle),
+            }
+        } else {
+            // only cpu writes need to be made available to the device
+            self.sync_unmapped(&mut state, Access::Write, false)?;
+        }
+
+        Ok(())
+    }
+
+    fn invalidate_impl(&self, range: Option<(Size, Size)>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.bound || state.mt.contains(MemoryType::COHERENT) {
+            return Ok(());
+        }
+
+        if state.map_count > 0 {
+            match range {
+                Some((offset, size)) => self.backend().invalidate_range(&self.handle, offset, size),
+                None => self.backend().invalidate(&self.handle),
+            }
+        } else {
+            // only the cpu cache for reads needs to be invalidated
+            self.sync_unmapped(&mut state, Access::Read, true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the CPU cache for the BO memory.
+    ///
+    /// If the memory type is coherent, the CPU cache is not flushed.
+    ///
+    /// If the BO is mapped, the BO mapping is flushed.  Otherwise, if the BO is bound and has
+    /// `Flags::EXTERNAL`, the memory is flushed through its dma-buf.  This is for CPU writes
+    /// through mappings of the same memory elsewhere.  The dma-buf is exported on the first such
+    /// flush and is reused until the memory changes.  An error is returned if the export or the
+    /// flush fails.
+    pub fn flush(&self) -> Result<()> {
+        self.flush_impl(None)
+    }
+
+    /// Invalidates the CPU cache for the BO memory.
+    ///
+    /// If the memory type is coherent, the CPU cache is not invalidated.
+    ///
+    /// Like `flush`, this also operates on bound but unmapped BOs that have `Flags::EXTERNAL`.
+    pub fn invalidate(&self) -> Result<()> {
+        self.invalidate_impl(None)
+    }
+
+    /// Flushes the CPU cache for a byte range of the BO memory.
+    ///
+    /// This is the same as `flush`, except that backends may limit the cache maintenance to the
+    /// range.  `access_range` can be used to get the range of an image region.
+    pub fn flush_range(&self, offset: Size, size: Size) -> Result<()> {
+        if !self.validate_range(offset, size) {
+            return Error::user();
+        }
+
+        self.flush_impl(Some((offset, size)))
+    }
+
+    /// Invalidates the CPU cache for a byte range of the BO memory.
+    ///
+    /// This is the same as `invalidate`, except that backends may limit the cache maintenance to
+    /// the range.
+    pub fn invalidate_range(&self, offset: Size, size: Size) -> Result<()> {
+        if !self.validate_range(offset, size) {
+            return Error::user();
+        }
+
+        self.invalidate_impl(Some((offset, size)))
+    }
+
+    /// Returns the byte range of the BO memory that covers an image region.
+    ///
+    /// The range is the union of the ranges of all memory planes.  If the BO is not linear or is a
+    /// 3D image, the range is the entire BO memory.
+    pub fn access_range(&self, x: u32, y: u32, width: u32, height: u32) -> Result<(Size, Size)> {
+        if self.is_buffer() || width == 0 || height == 0 {
+            return Error::user();
+        }
+
+        let (img_width, img_height) = (self.extent.width(), self.extent.height());
+        if x > img_width || y > img_height || width > img_width - x || height > img_height - y {
+            return Error::user();
+        }
+
+        let layout = self.layout();
+        if !layout.modifier.is_linear() || self.extent.is_3d() {
+            return Ok((0, layout.size));
+        }
+
+        linear_region_range(&layout, self.format, x, y, width, height)
+    }
+
+    fn staging_usage(&self) -> Usage {
+        match self.usage {
+            #[cfg(feature = "ash")]
+            Usage::Vulkan(_) => Usage::Vulkan(crate::vulkan::Usage::TRANSFER),
+            _ => Usage::Cpu(CpuUsage::WRITE_OFTEN),
+        }
+    }
+
+    fn init_staging(&self) -> Result<Box<Staging>> {
+        if self.is_buffer() || !self.can_copy() {
+            return Error::user();
+        }
+
+        let (copies, size) = packed_copy_regions(
+            self.format,
+            self.extent.width(),
+            self.extent.height(),
+            self.extent.depth(),
+        )?;
+
+        let (class, mt) = self.classify_staging(size)?;
+
+        let staging = Staging {
+            class,
+            size,
+            mt,
+            copies,
+            idle: None,
+        };
+
+        Ok(Box::new(staging))
+    }
+
+    /// Creates a staging buffer for an image BO.
+    ///
+    /// The staging buffer is a mappable buffer that is large enough to hold all planes of the
+    /// image tightly packed, and is bound to the memory type that suits CPU writes the best.  The
+    /// image BO must have `Flags::COPY`.
+    ///
+    /// The size, class, and memory type of the staging buffer are derived once and cached in the
+    /// image BO.  If a staging buffer has been returned with `recycle_staging`, it is reused
+    /// rather than allocating a new one.  This is suitable for repeated uploads such as video
+    /// frames.
+    pub fn create_staging(&self) -> Result<Bo> {
+        let mut staging = self.staging.lock().unwrap();
+        if staging.is_none() {
+            *staging = Some(self.init_staging()?);
+        }
+        let staging = staging.as_mut().unwrap();
+
+        if let Some(bo) = staging.idle.take() {
+            return Ok(bo);
+        }
+
+        let mut bo = Bo::with_constraint(
+            self.device.clone(),
+            &staging.class,
+            Extent::Buffer(staging.size),
+            None,
+        )?;
+        bo.bind_memory(staging.mt, None)?;
+
+        Ok(bo)
+    }
+
+    /// Returns the per-plane copy regions between an image BO and its staging buffers.
+    ///
+    /// Each region covers a whole plane, and the regions can be passed to `copy_buffer_image`
+    /// as they are.
+    pub fn staging_copies(&self) -> Result<Vec<CopyBufferImage>> {
+        let mut staging = self.staging.lock().unwrap();
+        if staging.is_none() {
+            *staging = Some(self.init_staging()?);
+        }
+
+        Ok(staging.as_ref().unwrap().copies.clone())
+    }
+
+    /// Returns a staging buffer created by `create_staging` for reuse.
+    ///
+    /// At most one staging buffer is kept.  The staging buffer is dropped if it does not match this
+    /// image BO or if one is already kept.  A mapped staging buffer stays mapped.
+    pub fn recycle_staging(&self, bo: Bo) {
+        let mut staging = self.staging.lock().unwrap();
+        let Some(staging) = staging.as_mut() else {
+            return;
+        };
+
+        let matched = Arc::ptr_eq(&bo.device, &self.device)
+            && bo.is_buffer()
+            && bo.extent.size() == staging.size
+            && bo.is_bound();
+        if matched && staging.idle.is_none() {
+            staging.idle = Some(bo);
+        }
+    }
+
+    // classifies a mappable buffer that the contents of this BO can be copied to
+    fn classify_staging(&self, size: Size) -> Result<(Class, MemoryType)> {
+        // Staging buffers are transient, but they have no priority such that small ones share
+        // slabs rather than having their own device memories.
+        let desc = Description::new().flags(Flags::MAP | Flags::COPY);
+        let usage: Vec<Usage> = (0..self.device.backend_count())
+            .map(|idx| {
+                if idx == self.backend_index {
+                    self.staging_usage()
+                } else {
+                    Usage::Unused
+                }
+            })
+            .collect();
+        let class = self.device.classify(desc, &usage)?;
+
+        let reqs = Bo::dry_run(&self.device, &class, Extent::Buffer(size), None);
+        let mts = match reqs {
+            Ok(reqs) => reqs.memory_types,
+            Err(Error::Unsupported) => {
+                Bo::with_constraint(self.device.clone(), &class, Extent::Buffer(size), None)?
+                    .memory_types()
+            }
+            Err(err) => return Err(err),
+        };
+
+        // staging buffers must be mappable
+        let mt = filter_memory_types(mts, CpuUsage::WRITE_OFTEN)
+            .into_iter()
+            .next()
+            .ok_or(Error::Unsupported)?;
+
+        Ok((class, mt))
+    }
+
+    // allocates a mappable buffer of `size` bytes that the contents of this BO can be copied to
+    fn alloc_staging(&self, size: Size) -> Result<Bo> {
+        let (class, mt) = self.classify_staging(size)?;
+        let mut bo = Bo::with_constraint(self.device.clone(), &class, Extent::Buffer(size), None)?;
+        bo.bind_memory(mt, None)?;
+
+        Ok(bo)
+    }
+
+    // copies the contents of this BO to a new mappable buffer
+    fn copy_contents(&self) -> Result<Bo> {
+        if !self.is_buffer() {
+            let bo = self.create_staging()?;
+            for copy in self.staging_copies()? {
+                bo.copy_buffer_image(self, copy, None, true)?;
+            }
+
+            return Ok(bo);
+        }
+
+        let size = self.extent.size();
+        let bo = self.alloc_staging(size)?;
+
+        let copy = CopyBuffer {
+            src_offset: 0,
+            dst_offset: 0,
+            size,
+        };
+        bo.copy_buffer(self, copy, None, true)?;
+
+        Ok(bo)
+    }
+
+    /// Takes a copy-on-write snapshot of a BO.
+    ///
+    /// The BO must have `Flags::COPY` and a memory bound, and must not be mapped.  The contents are
+    /// copied, by the backend of the BO, before the BO is mapped or is the destination of a copy.
+    /// Writes that bypass HBM, such as device writes to an exported dma-buf, cannot be detected.
+    /// Producers must call `resolve_snapshots` before such writes.
+    ///
+    /// The snapshot of an image BO is a staging buffer whose layout is given by
+    /// `staging_copies`.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        if !self.can_copy() {
+            return Error::user();
+        }
+
+        // writes through an existing mapping cannot be detected
+        let state = self.state.lock().unwrap();
+        if !state.bound || state.map_count > 0 {
+            return Error::user();
+        }
+        drop(state);
+
+        let copy = Arc::new(Mutex::new(None));
+
+        let mut snapshots = self.snapshots.lock().unwrap();
+        snapshots.retain(|snapshot| snapshot.strong_count() > 0);
+        snapshots.push(Arc::downgrade(&copy));
+
+        Ok(Snapshot {
+            device: self.device.clone(),
+            serial: self.serial,
+            copy,
+        })
+    }
+
+    /// Copies the contents of a BO to all of its pending snapshots.
+    ///
+    /// This is called implicitly before HBM writes the BO.
+    pub fn resolve_snapshots(&self) -> Result<()> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+
+        while let Some(snapshot) = snapshots.last() {
+            if let Some(snapshot) = snapshot.upgrade() {
+                let mut copy = snapshot.lock().unwrap();
+                if copy.is_none() {
+                    *copy = Some(self.copy_contents()?);
+                }
+            }
+            snapshots.pop();
+        }
+
+        Ok(())
+    }
+
+    // this should not be used if the mutex needs to remain locked for synchronization
+    fn is_bound(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.bound
+    }
+
+    fn validate_copy(&self, src: &Bo) -> bool {
+        self.can_copy() && self.is_bound() && src.can_copy() && src.is_bound()
+    }
+
+    fn validate_copy_buffer(&self, src: &Bo, copy: &CopyBuffer) -> bool {
+        if !self.validate_copy(src) || !self.is_buffer() || !src.is_buffer() {
+            return false;
+        }
+
+        let src_size = src.extent.size();
+        let dst_size = self.extent.size();
+
+        copy.size > 0
+            && copy.src_offset <= src_size
+            && copy.size <= src_size - copy.src_offset
+            && copy.dst_offset <= dst_size
+            && copy.size <= dst_size - copy.dst_offset
+    }
+
+    fn validate_copy_buffer_image(&self, src: &Bo, copy: &CopyBufferImage) -> bool {
+        if !self.validate_copy(src) || (self.is_buffer() == src.is_buffer()) {
+            return false;
+        }
+
+        if self.is_buffer() {
+            src.validate_image_region(copy, self.extent.size())
+        } else {
+            self.validate_image_region(copy, src.extent.size())
+        }
+    }
+
+    // validates a copy region between this image and a buffer of `size` bytes
+    fn validate_image_region(&self, copy: &CopyBufferImage, size: Size) -> bool {
+        // external formats have unknown block sizes
+        let Ok(fmt_class) = formats::format_class(self.format) else {
+            return false;
+        };
+        let plane_count = fmt_class.plane_count as u32;
+        if copy.plane >= plane_count {
+            return false;
+        }
+
+        let bpp = fmt_class.block_size[copy.plane as usize] as Size;
+        let width = self.extent.width() / fmt_class.block_extent[copy.plane as usize].0 as u32;
+        let height = self.extent.height() / fmt_class.block_extent[copy.plane as usize].1 as u32;
+        let depth = self.extent.depth();
+
+        validate_copy_region(copy, size, width, height, depth, bpp)
+    }
+
+    fn validate_host_copy(&self, copy: &CopyBufferImage, size: usize) -> bool {
+        self.can_copy()
+            && self.is_bound()
+            && !self.is_buffer()
+            && self.validate_image_region(copy, size as Size)
+    }
+
+    fn validate_copy_image(&self, src: &Bo, copy: &CopyImage) -> bool {
+        if !self.validate_copy(src) || self.is_buffer() || src.is_buffer() {
+            return false;
+        }
+
+        // external formats can only be copied whole, and the backend validates the planes
+        if formats::is_external(self.format) || formats::is_external(src.format) {
+            return self.format == src.format
+                && self.extent.width() == src.extent.width()
+                && self.extent.height() == src.extent.height()
+                && copy.src_plane == copy.dst_plane
+                && copy.src_x == 0
+                && copy.src_y == 0
+                && copy.src_z == 0
+                && copy.dst_x == 0
+                && copy.dst_y == 0
+                && copy.dst_z == 0
+                && copy.width == self.extent.width()
+                && copy.height == self.extent.height()
+                && copy.depth == 1;
+        }
+
+        // returns the block size and the extent in blocks of a plane
+        let plane_info = |bo: &Bo, plane: u32| {
+            let fmt_class = formats::format_class(bo.format).unwrap();
+            if plane >= fmt_class.plane_count as u32 {
+                return None;
+            }
+
+            let plane = plane as usize;
+            let (block_width, block_height) = fmt_class.block_extent[plane];
+            Some((
+                fmt_class.block_size[plane],
+                bo.extent.width() / block_width as u32,
+                bo.extent.height() / block_height as u32,
+                bo.extent.depth(),
+            ))
+        };
+
+        let Some((src_bpp, src_width, src_height, src_depth)) = plane_info(src, copy.src_plane)
+        else {
+            return false;
+        };
+        let Some((dst_bpp, dst_width, dst_height, dst_depth)) = plane_info(self, copy.dst_plane)
+        else {
+            return false;
+        };
+
+        // the planes must be size-compatible
+        if src_bpp != dst_bpp {
+            return false;
+        }
+
+        let in_bounds = |(x, y, z): (u32, u32, u32), (width, height, depth): (u32, u32, u32)| {
+            x <= width
+                && y <= height
+                && z <= depth
+                && copy.width <= width - x
+                && copy.height <= height - y
+                && copy.depth <= depth - z
+        };
+
+        copy.width > 0
+            && copy.height > 0
+            && copy.depth > 0
+            && in_bounds(
+                (copy.src_x, copy.src_y, copy.src_z),
+                (src_width, src_height, src_depth),
+            )
+            && in_bounds(
+                (copy.dst_x, copy.dst_y, copy.dst_z),
+                (dst_width, dst_height, dst_depth),
+            )
+    }
+
+    fn validate_fill(&self, fill: &Fill) -> bool {
+        if !self.can_copy() || !self.is_bound() {
+            return false;
+        }
+
+        match fill {
+            Fill::Buffer(_) => self.is_buffer(),
+            // only single-plane formats can be cleared
+            Fill::Color(_) | Fill::ColorInt(_) => {
+                !self.is_buffer()
+                    && formats::format_class(self.format)
+                        .is_ok_and(|fmt_class| fmt_class.plane_count == 1)
+            }
+        }
+    }
+
+    fn validate_blit(&self, src: &Bo, src_rect: &Rect, dst_rect: &Rect) -> bool {
+        if !self.validate_copy(src) || self.is_buffer() || src.is_buffer() {
+            return false;
+        }
+
+        // only single-plane 2D images can be blitted
+        let is_blittable = |bo: &Bo| {
+            !bo.extent.is_3d()
+                && formats::format_class(bo.format)
+                    .is_ok_and(|fmt_class| fmt_class.plane_count == 1)
+        };
+        if !is_blittable(src) || !is_blittable(self) {
+            return false;
+        }
+
+        let in_bounds = |bo: &Bo, rect: &Rect| {
+            let width = bo.extent.width();
+            let height = bo.extent.height();
+            rect.width > 0
+                && rect.height > 0
+                && rect.x <= width
+                && rect.y <= height
+                && rect.width <= width - rect.x
+                && rect.height <= height - rect.y
+        };
+
+        in_bounds(src, src_rect) && in_bounds(self, dst_rect)
+    }
+
+    fn wait_copy(
+        &self,
+        sync_fd: Option<OwnedFd>,
+        wait: bool,
+        start: Duration,
+    ) -> Result<Option<OwnedFd>> {
+        let sync_fd = match sync_fd {
+            Some(sync_fd) if wait => {
+                let timeout = self.device.copy_timeout();
+                if !utils::poll_timeout(&sync_fd, Access::Read, timeout)? {
+                    return Error::device();
+                }
+                None
+            }
+            sync_fd => sync_fd,
+        };
+
+        // the latencies of copies that are not waited for are recorded when they complete
+        match &sync_fd {
+            Some(sync_fd) => self.device.record_pending_copy(sync_fd, start),
+            None => self
+                .device
+                .record_copy(utils::monotonic_time().saturating_sub(start)),
+        }
+
+        Ok(sync_fd)
+    }
+
+    /// Copies between two BOs that are both buffers.
+    ///
+    /// `sync_fd` is an optional sync file that the copy operation waits for.
+    ///
+    /// If `wait` is true, this function never returns any sync file, and fails with
+    /// `Error::Device` if the copy does not complete before `Builder::copy_timeout`.  Otherwise,
+    /// it may return a sync file associated with the copy operation.
+    ///
+    /// Copies execute in submission order.  When the vulkan backend enables
+    /// `concurrent_copies`, the order holds only within a thread, and copies of different threads
+    /// that access the same BO must be ordered with `sync_fd`.
+    pub fn copy_buffer(
+        &self,
+        src: &Bo,
+        copy: CopyBuffer,
+        sync_fd: Option<OwnedFd>,
+        wait: bool,
+    ) -> Result<Option<OwnedFd>> {
+        if !self.validate_copy_buffer(src, &copy) {
+            return Error::user();
+        }
+
+        self.resolve_snapshots()?;
+
+        let start = utils::monotonic_time();
+        self.backend()
+            .copy_buffer(&self.handle, &src.handle, copy, sync_fd)
+            .and_then(|sync_fd| self.wait_copy(sync_fd, wait, start))
+    }
+
+    /// Copies between two BOs where one is a buffer and one is an image.
+    ///
+    /// The data in the buffer is in the plane and byte order of the DRM format of the image, even
+    /// when the backend stores the image in a swizzled format.  For example, the second plane of
+    /// an NV21 image is copied as interleaved V and U samples.
+    ///
+    /// `sync_fd` is an optional sync file that the copy operation waits for.
+    ///
+    /// If `wait` is true, this function never returns any sync file, and fails with
+    /// `Error::Device` if the copy does not complete before `Builder::copy_timeout`.  Otherwise,
+    /// it may return a sync file associated with the copy operation.
+    ///
+    /// Copies execute in submission order.  When the vulkan backend enables
+    /// `concurrent_copies`, the order holds only within a thread, and copies of different threads
+    /// that access the same BO must be ordered with `sync_fd`.
+    pub fn copy_buffer_image(
+        &self,
+        src: &Bo,
+        copy: CopyBufferImage,
+        sync_fd: Option<OwnedFd>,
+        wait: bool,
+    ) -> Result<Option<OwnedFd>> {
+        if !self.validate_copy_buffer_image(src, &copy) {
+            return Error::user();
+        }
+
+        self.resolve_snapshots()?;
+
+        let start = utils::monotonic_time();
+        self.backend()
+            .copy_buffer_image(&self.handle, &src.handle, copy, sync_fd)
+            .and_then(|sync_fd| self.wait_copy(sync_fd, wait, start))
+    }
+
+    /// Copies between two BOs that are both images.
+    ///
+    /// The format planes of the two images must have the same block size.
+    ///
+    /// `sync_fd` is an optional sync file that the copy operation waits for.
+    ///
+    /// If `wait` is true, this function never returns any sync file, and fails with
+    /// `Error::Device` if the copy does not complete before `Builder::copy_timeout`.  Otherwise,
+    /// it may return a sync file associated with the copy operation.
+    ///
+    /// Copies execute in submission order.  When the vulkan backend enables
+    /// `concurrent_copies`, the order holds only within a thread, and copies of different threads
+    /// that access the same BO must be ordered with `sync_fd`.
+    pub fn copy_image(
+        &self,
+        src: &Bo,
+        copy: CopyImage,
+        sync_fd: Option<OwnedFd>,
+        wait: bool,
+    ) -> Result<Option<OwnedFd>> {
+        if !self.validate_copy_image(src, &copy) {
+            return Error::user();
+        }
+
+        self.resolve_snapshots()?;
+
+        let start = utils::monotonic_time();
+        self.backend()
+            .copy_image(&self.handle, &src.handle, copy, sync_fd)
+            .and_then(|sync_fd| self.wait_copy(sync_fd, wait, start))
+    }
+
+    /// Copies from an image BO to host memory.
+    ///
+    /// `copy` describes the image region and where the region is in `dst`, as if `dst` were a
+    /// buffer BO.  The copy is performed by the CPU and the BO must be idle.
+    ///
+    /// Unlike `map`, this does not expose the physical layout of the image.  Non-linear images
+    /// are supported when the backend supports `Features::HOST_IMAGE_COPY` and the BO is
+    /// classified for it.
+    pub fn read_image(&self, dst: &mut [u8], copy: CopyBufferImage) -> Result<()> {
+        if !self.validate_host_copy(&copy, dst.len()) {
+            return Error::user();
+        }
+
+        self.backend().read_image(&self.handle, dst, copy)
+    }
+
+    /// Copies from host memory to an image BO.
+    ///
+    /// See `read_image`.
+    pub fn write_image(&self, src: &[u8], copy: CopyBufferImage) -> Result<()> {
+        if !self.validate_host_copy(&copy, src.len()) {
+            return Error::user();
+        }
+
+        self.resolve_snapshots()?;
+
+        self.backend().write_image(&self.handle, src, copy)
+    }
+
+    // Validates a region of `len` bytes of host memory, and returns the size of a staging buffer
+    // for the region and the region in the staging buffer.  The rows of an image region are
+    // tightly packed in the staging buffer.
+    fn staged_region(&self, region: Region, len: usize) -> Result<(Size, Region)> {
+        match region {
+            Region::Buffer(offset) => {
+                let size = len as Size;
+                if !self.is_buffer() || !self.validate_range(offset, size) {
+                    return Error::user();
+                }
+
+                Ok((size, Region::Buffer(0)))
+            }
+            Region::Image(copy) => {
+                if self.is_buffer() || !self.validate_image_region(&copy, len as Size) {
+                    return Error::user();
+                }
+
+                let fmt_class = formats::format_class(self.format)?;
+                let stride = copy.width as Size * fmt_class.block_size[copy.plane as usize] as Size;
+                let slice_stride = stride * copy.height as Size;
+                let staged = CopyBufferImage {
+                    offset: 0,
+                    stride,
+                    slice_stride,
+                    ..copy
+                };
+
+                Ok((slice_stride * copy.depth as Size, Region::Image(staged)))
+            }
+        }
+    }
+
+    // copies between this BO and a staging buffer, in the direction of `download`
+    fn copy_staged(
+        &self,
+        staging: &Bo,
+        region: Region,
+        staged: Region,
+        download: bool,
+    ) -> Result<()> {
+        let (dst, src) = if download {
+            (staging, self)
+        } else {
+            (self, staging)
+        };
+
+        match (region, staged) {
+            (Region::Buffer(offset), Region::Buffer(staged_offset)) => {
+                let (src_offset, dst_offset) = if download {
+                    (offset, staged_offset)
+                } else {
+                    (staged_offset, offset)
+                };
+                let copy = CopyBuffer {
+                    src_offset,
+                    dst_offset,
+                    size: staging.extent.size(),
+                };
+                dst.copy_buffer(src, copy, None, true)?;
+            }
+            (Region::Image(_), Region::Image(staged)) => {
+                dst.copy_buffer_image(src, staged, None, true)?;
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// Copies from a BO to host memory through a staging buffer.
+    ///
+    /// A buffer BO is read from the offset of `Region::Buffer` to fill `dst`.  An image BO is
+    /// read from the region of `Region::Image`, which also describes where the region is in
+    /// `dst`.  Bytes of `dst` outside of the region are left intact.
+    ///
+    /// The BO must have `Flags::COPY` and a memory bound.  A transient staging buffer is
+    /// allocated, the BO is copied to the staging buffer by the backend, and the staging buffer
+    /// is read by the CPU.  Unlike `map` and `read_image`, this works when the BO is not mappable
+    /// or is tiled, at the cost of an allocation and a copy.  Repeated uploads of whole images
+    /// should use `create_staging` instead.
+    pub fn read(&self, dst: &mut [u8], region: Region) -> Result<()> {
+        let (size, staged) = self.staged_region(region, dst.len())?;
+        let mut staging = self.alloc_staging(size)?;
+        self.copy_staged(&staging, region, staged, true)?;
+
+        let mapping = staging.map()?;
+        mapping.bo().invalidate()?;
+        let src = mapping.as_slice();
+        for (offset, staged_offset, len) in staged_rows(region, staged, dst.len()) {
+            dst[offset..offset + len].copy_from_slice(&src[staged_offset..staged_offset + len]);
+        }
+
+        Ok(())
+    }
+
+    /// Copies from host memory to a BO through a staging buffer.
+    ///
+    /// See `read`.  Bytes of the BO outside of the region are left intact.
+    pub fn write(&self, src: &[u8], region: Region) -> Result<()> {
+        let (size, staged) = self.staged_region(region, src.len())?;
+        let mut staging = self.alloc_staging(size)?;
+
+        {
+            let mut mapping = staging.map()?;
+            let dst = mapping.as_mut_slice();
+            for (offset, staged_offset, len) in staged_rows(region, staged, src.len()) {
+                dst[staged_offset..staged_offset + len].copy_from_slice(&src[offset..offset + len]);
+            }
+            mapping.bo().flush()?;
+        }
+
+        self.copy_staged(&staging, region, staged, false)
+    }
+
+    /// Fills a BO.
+    ///
+    /// A buffer BO is filled with `Fill::Buffer`, and an image BO with a single-plane format is
+    /// cleared with `Fill::Color`, or with `Fill::ColorInt` if the format is an integer format.
+    /// This allows BOs to be initialized without being mapped.
+    ///
+    /// `sync_fd` is an optional sync file that the fill operation waits for.
+    ///
+    /// If `wait` is true, this function never returns any sync file, and fails with
+    /// `Error::Device` if the fill does not complete before `Builder::copy_timeout`.  Otherwise,
+    /// it may return a sync file associated with the fill operation.
+    pub fn fill(
+        &self,
+        fill: Fill,
+        sync_fd: Option<OwnedFd>,
+        wait: bool,
+    ) -> Result<Option<OwnedFd>> {
+        if !self.validate_fill(&fill) {
+            return Error::user();
+        }
+
+        self.resolve_snapshots()?;
+
+        let start = utils::monotonic_time();
+        self.backend()
+            .fill(&self.handle, fill, sync_fd)
+            .and_then(|sync_fd| self.wait_copy(sync_fd, wait, start))
+    }
+
+    /// Blits a rectangle of an image BO to a rectangle of another image BO.
+    ///
+    /// Unlike copies, the rectangles can have different sizes and the formats can differ, in which
+    /// case the texels are scaled with `filter` and converted.  Only single-plane formats are
+    /// supported.  Integer formats can only be blitted to and from integer formats of the same
+    /// signedness, and other combinations fail with `Error::User`.
+    ///
+    /// `sync_fd` is an optional sync file that the blit operation waits for.
+    ///
+    /// If `wait` is true, this function never returns any sync file, and fails with
+    /// `Error::Device` if the blit does not complete before `Builder::copy_timeout`.  Otherwise,
+    /// it may return a sync file associated with the blit operation.
+    pub fn blit(
+        &self,
+        src: &Bo,
+        src_rect: Rect,
+        dst_rect: Rect,
+        filter: Filter,
+        sync_fd: Option<OwnedFd>,
+        wait: bool,
+    ) -> Result<Option<OwnedFd>> {
+        if !self.validate_blit(src, &src_rect, &dst_rect) {
+            return Error::user();
+        }
+
+        self.resolve_snapshots()?;
+
+        let start = utils::monotonic_time();
+        self.backend()
+            .blit(
+                &self.handle,
+                &src.handle,
+                src_rect,
+                dst_rect,
+                filter,
+                sync_fd,
+            )
+            .and_then(|sync_fd| self.wait_copy(sync_fd, wait, start))
+    }
+
+    /// Copies an image BO to another image BO of the same format and extent.
+    ///
+    /// The two BOs can have different modifiers.  This detiles an image to a linear image for
+    /// CPU readback, such as for screenshots, or retiles a linear image.  All format planes are
+    /// copied in a single batch by the backend.  Both BOs must have `Flags::COPY` and belong to
+    /// the same backend.
+    ///
+    /// `sync_fd` is an optional sync file that the copy operations wait for.
+    ///
+    /// If `wait` is true, this function never returns any sync file, and fails with
+    /// `Error::Device` if the copy does not complete before `Builder::copy_timeout`.  Otherwise,
+    /// it may return a sync file associated with the copy operations.
+    pub fn convert_to(
+        &self,
+        dst: &Bo,
+        sync_fd: Option<OwnedFd>,
+        wait: bool,
+    ) -> Result<Option<OwnedFd>> {
+        let (width, height, depth) = (
+            self.extent.width(),
+            self.extent.height(),
+            self.extent.depth(),
+        );
+        if self.is_buffer()
+            || dst.format != self.format
+            || dst.extent.width() != width
+            || dst.extent.height() != height
+            || dst.extent.depth() != depth
+        {
+            return Error::user();
+        }
+
+        let whole = CopyImage {
+            src_plane: 0,
+            src_x: 0,
+            src_y: 0,
+            src_z: 0,
+            dst_plane: 0,
+            dst_x: 0,
+            dst_y: 0,
+            dst_z: 0,
+            width,
+            height,
+            depth,
+        };
+
+        // external formats are copied whole and the backend copies their planes
+        let copies = if formats::is_external(self.format) {
+            vec![whole]
+        } else {
+            let (regions, _) = packed_copy_regions(self.format, width, height, depth)?;
+            regions
+                .into_iter()
+                .map(|region| CopyImage {
+                    src_plane: region.plane,
+                    dst_plane: region.plane,
+                    width: region.width,
+                    height: region.height,
+                    ..whole
+                })
+                .collect()
+        };
+
+        copies
+            .into_iter()
+            .fold(CopyBatch::new(), |batch, copy| {
+                batch.copy_image(dst, self, copy)
+            })
+            .submit(sync_fd, wait)
+    }
+}
+
+impl<'a> CopyBatch<'a> {
+    /// Creates an empty copy batch.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a copy between two BOs that are both buffers.
+    ///
+    /// See `Bo::copy_buffer`.
+    pub fn copy_buffer(mut self, dst: &'a Bo, src: &'a Bo, copy: CopyBuffer) -> Self {
+        self.copies.push((dst, src, BatchCopy::Buffer(copy)));
+        self
+    }
+
+    /// Adds a copy between two BOs where one is a buffer and one is an image.
+    ///
+    /// See `Bo::copy_buffer_image`.
+    pub fn copy_buffer_image(mut self, dst: &'a Bo, src: &'a Bo, copy: CopyBufferImage) -> Self {
+        self.copies.push((dst, src, BatchCopy::BufferImage(copy)));
+        self
+    }
+
+    /// Adds a copy between two BOs that are both images.
+    ///
+    /// See `Bo::copy_image`.
+    pub fn copy_image(mut self, dst: &'a Bo, src: &'a Bo, copy: CopyImage) -> Self {
+        self.copies.push((dst, src, BatchCopy::Image(copy)));
+        self
+    }
+
+    /// Submits the copies in the order they were added.
+    ///
+    /// All copies are validated before any of them is executed.  An empty batch is invalid.
+    ///
+    /// `sync_fd` is an optional sync file that the copy operations wait for.
+    ///
+    /// If `wait` is true, this function never returns any sync file, and fails with
+    /// `Error::Device` if the copy does not complete before `Builder::copy_timeout`.  Otherwise,
+    /// it may return a sync file associated with the copy operations.
+    pub fn submit(self, sync_fd: Option<OwnedFd>, wait: bool) -> Result<Option<OwnedFd>> {
+        let Some(&(first, _, _)) = self.copies.first() else {
+            return Error::user();
+        };
+
+        let same_backend = |bo: &Bo| {
+            Arc::ptr_eq(&bo.device, &first.device) && bo.backend_index == first.backend_index
+        };
+        for &(dst, src, copy) in &self.copies {
+            let valid = match copy {
+                BatchCopy::Buffer(copy) => dst.validate_copy_buffer(src, &copy),
+                BatchCopy::BufferImage(copy) => dst.validate_copy_buffer_image(src, &copy),
+                BatchCopy::Image(copy) => dst.validate_copy_image(src, &copy),
+            };
+            if !valid || !same_backend(dst) || !same_backend(src) {
+                return Error::user();
+            }
+        }
+
+        for &(dst, _, _) in &self.copies {
+            dst.resolve_snapshots()?;
+        }
+
+        let ops: Vec<CopyOp> = self
+            .copies
+            .iter()
+            .map(|&(dst, src, copy)| match copy {
+                BatchCopy::Buffer(copy) => CopyOp::Buffer(&dst.handle, &src.handle, copy),
+                BatchCopy::BufferImage(copy) => CopyOp::BufferImage(&dst.handle, &src.handle, copy),
+                BatchCopy::Image(copy) => CopyOp::Image(&dst.handle, &src.handle, copy),
+            })
+            .collect();
+
+        let start = utils::monotonic_time();
+        first
+            .backend()
+            .copy_batch(&ops, sync_fd)
+            .and_then(|sync_fd| first.wait_copy(sync_fd, wait, start))
+    }
+}
+
+impl MappingGuard<'_> {
+    /// Returns the mapped BO.
+    ///
+    /// This allows the CPU cache of the mapping to be flushed or invalidated.
+    pub fn bo(&self) -> &Bo {
+        self.bo
+    }
+
+    /// Returns the pointer of the mapping.
+    pub fn as_ptr(&self) -> *mut ffi::c_void {
+        self.mapping.ptr.as_ptr()
+    }
+
+    /// Returns the mapping as a byte slice.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: the mapping is valid until the guard is dropped
+        unsafe { slice::from_raw_parts(self.mapping.ptr.as_ptr().cast(), self.mapping.len.get()) }
+    }
+
+    /// Returns the mapping as a mutable byte slice.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: the mapping is valid until the guard is dropped and the guard is mutably
+        // borrowed
+        unsafe {
+            slice::from_raw_parts_mut(self.mapping.ptr.as_ptr().cast(), self.mapping.len.get())
+        }
+    }
+
+    /// Converts the guard to a raw mapping.
+    ///
+    /// The BO remains mapped until `Bo::unmap` is called.  This is for FFI.
+    pub fn into_raw(self) -> Mapping {
+        let guard = ManuallyDrop::new(self);
+        guard.mapping
+    }
+}
+
+impl Drop for MappingGuard<'_> {
+    fn drop(&mut self) {
+        self.bo.unmap();
+    }
+}
+
+impl Snapshot {
+    /// Returns true if the contents of the source BO have been copied.
+    pub fn is_copied(&self) -> bool {
+        self.copy.lock().unwrap().is_some()
+    }
+
+    /// Returns the snapshot as a mappable buffer.
+    ///
+    /// `src` must be the source BO.  If the source BO has not been written since the snapshot was
+    /// taken, its contents are copied now.
+    pub fn into_bo(self, src: &Bo) -> Result<Bo> {
+        if !Arc::ptr_eq(&self.device, &src.device) || self.serial != src.serial {
+            return Error::user();
+        }
+
+        let mut copy = self.copy.lock().unwrap();
+        match copy.take() {
+            Some(bo) => Ok(bo),
+            None => src.copy_contents(),
+        }
+    }
+}
+
+impl Drop for Bo {
+    fn drop(&mut self) {
+        self.unmap();
+        self.backend().free(&self.handle);
+
+        let state = self.state.lock().unwrap();
+        self.device.sub_allocated_size(state.allocated_size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dma_buf::tests::MemfdBackend;
+    use std::os::fd::AsRawFd;
+
+    // builds a device with a MemfdBackend and classifies a description for it
+    fn memfd_class(desc: Description, usage: Usage) -> (Arc<Device>, Class) {
+        let dev = crate::Builder::new()
+            .add_backend(MemfdBackend)
+            .build()
+            .unwrap();
+        let class = dev.classify(desc, &[usage]).unwrap();
+
+        (dev, class)
+    }
+
+    fn copy_region(offset: Size, stride: Size, width: u32, height: u32) -> CopyBufferImage {
+        CopyBufferImage {
+            offset,
+            stride,
+            slice_stride: 0,
+            plane: 0,
+            x: 0,
+            y: 0,
+            z: 0,
+            width,
+            height,
+            depth: 1,
+        }
+    }
+
+    #[test]
+    fn test_validate_copy_region() {
+        let size_8g: Size = 8 << 30;
+
+        // the last row is not padded
+        let copy = copy_region(0, 16, 3, 4);
+        assert!(validate_copy_region(&copy, 16 * 3 + 12, 3, 4, 1, 4));
+        assert!(!validate_copy_region(&copy, 16 * 3 + 11, 3, 4, 1, 4));
+
+        // a region beyond 4GiB
+        let copy = copy_region(5 << 30, 64 << 10, 16 << 10, 32 << 10);
+        assert!(validate_copy_region(
+            &copy,
+            size_8g,
+            16 << 10,
+            32 << 10,
+            1,
+            4
+        ));
+        let copy = copy_region(7 << 30, 64 << 10, 16 << 10, 32 << 10);
+        assert!(!validate_copy_region(
+            &copy,
+            size_8g,
+            16 << 10,
+            32 << 10,
+            1,
+            4
+        ));
+
+        // crafted inputs must not overflow
+        let copy = copy_region(Size::MAX - 3, 4, 1, 2);
+        assert!(!validate_copy_region(&copy, Size::MAX, 1, 2, 1, 4));
+        let copy = copy_region(0, 1 << 62, 1, u32::MAX);
+        assert!(!validate_copy_region(&copy, Size::MAX, 1, u32::MAX, 1, 1));
+
+        // the row length must fit in 32 bits
+        let copy = copy_region(0, 4 << 32, 1, 1);
+        assert!(!validate_copy_region(&copy, Size::MAX, 1, 1, 1, 4));
+
+        // the stride must cover the row
+        let copy = copy_region(0, 8, 3, 1);
+        assert!(!validate_copy_region(&copy, size_8g, 3, 1, 1, 4));
+
+        // the last slice is not padded
+        let copy = CopyBufferImage {
+            slice_stride: 16 * 4,
+            z: 1,
+            depth: 2,
+            ..copy_region(0, 16, 3, 2)
+        };
+        assert!(validate_copy_region(&copy, 16 * 4 + 16 + 12, 3, 4, 3, 4));
+        assert!(!validate_copy_region(&copy, 16 * 4 + 16 + 11, 3, 4, 3, 4));
+        assert!(!validate_copy_region(&copy, size_8g, 3, 4, 2, 4));
+
+        // the slice stride must be a multiple of the stride and cover the slice
+        let copy = CopyBufferImage {
+            slice_stride: 16 * 2 + 4,
+            ..copy
+        };
+        assert!(!validate_copy_region(&copy, size_8g, 3, 4, 3, 4));
+        let copy = CopyBufferImage {
+            slice_stride: 16,
+            ..copy
+        };
+        assert!(!validate_copy_region(&copy, size_8g, 3, 4, 3, 4));
+    }
+
+    #[test]
+    fn test_packed_copy_regions() {
+        let (copies, size) = packed_copy_regions(formats::NV12, 13, 7, 1).unwrap();
+        assert_eq!(size, 13 * 7 + 12 * 3 + 1);
+        assert_eq!(copies.len(), 2);
+        assert_eq!((copies[0].offset, copies[0].stride), (0, 13));
+        assert_eq!((copies[0].width, copies[0].height), (13, 7));
+        // the second plane is aligned to its 2-byte blocks
+        assert_eq!((copies[1].offset, copies[1].stride), (13 * 7 + 1, 12));
+        assert_eq!((copies[1].width, copies[1].height), (6, 3));
+
+        for copy in &copies {
+            let fmt_class = formats::format_class(formats::NV12).unwrap();
+            let plane = copy.plane as usize;
+            let bpp = fmt_class.block_size[plane] as Size;
+            assert!(validate_copy_region(
+                copy,
+                size,
+                copy.width,
+                copy.height,
+                1,
+                bpp
+            ));
+        }
+    }
+
+    #[test]
+    fn test_dry_run() {
+        struct DryRunBackend;
+        impl Backend for DryRunBackend {}
+
+        let dev = crate::Builder::new()
+            .add_backend(DryRunBackend)
+            .build()
+            .unwrap();
+
+        let desc = crate::Description::new()
+            .flags(Flags::MAP)
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let usage = Usage::Cpu(CpuUsage::READ_OFTEN);
+        let class = dev.classify(desc, &[usage]).unwrap();
+
+        let reqs = Bo::dry_run(&dev, &class, Extent::Image(13, 7), None).unwrap();
+        let bo = Bo::with_constraint(dev.clone(), &class, Extent::Image(13, 7), None).unwrap();
+        assert_eq!(reqs.layout, bo.layout());
+        let mts: Vec<u32> = bo.memory_types().iter().map(|mt| mt.bits()).collect();
+        assert!(reqs.memory_types.iter().map(|mt| mt.bits()).eq(mts));
+
+        assert!(Bo::dry_run(&dev, &class, Extent::Image(0, 7), None).is_err());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let desc = crate::Description::new().flags(Flags::MAP | Flags::COPY);
+        let usage = Usage::Cpu(CpuUsage::WRITE_OFTEN);
+        let (dev, class) = memfd_class(desc, usage);
+
+        let fill = |bo: &mut Bo, val: u8| {
+            let mut mapping = bo.map().unwrap();
+            mapping.as_mut_slice()[..64].fill(val);
+        };
+        let check = |bo: &mut Bo, val: u8| {
+            let mapping = bo.map().unwrap();
+            let ok = mapping.as_slice()[..64].iter().all(|&v| v == val);
+            ok
+        };
+
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        assert!(bo.snapshot().is_err());
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+        fill(&mut bo, 1);
+
+        // the contents are copied before the BO is written
+        let snapshot = bo.snapshot().unwrap();
+        assert!(!snapshot.is_copied());
+        fill(&mut bo, 2);
+        assert!(snapshot.is_copied());
+        let mut copy = snapshot.into_bo(&bo).unwrap();
+        assert!(check(&mut copy, 1));
+
+        // the contents are copied when the snapshot is taken as a BO
+        let snapshot = bo.snapshot().unwrap();
+        assert!(snapshot.into_bo(&copy).is_err());
+        let snapshot = bo.snapshot().unwrap();
+        let mut copy = snapshot.into_bo(&bo).unwrap();
+        assert!(check(&mut copy, 2));
+
+        // a mapped BO can be written without HBM noticing
+        let mapping = bo.map().unwrap().into_raw();
+        assert!(bo.snapshot().is_err());
+        // SAFETY: the mapping is valid until the BO is unmapped
+        unsafe { ptr::write_bytes(mapping.ptr.as_ptr().cast::<u8>(), 3, 64) };
+        bo.unmap();
+        let snapshot = bo.snapshot().unwrap();
+        let mut copy = snapshot.into_bo(&bo).unwrap();
+        assert!(check(&mut copy, 3));
+    }
+
+    #[test]
+    fn test_fill() {
+        let desc = crate::Description::new().flags(Flags::MAP | Flags::COPY);
+        let usage = Usage::Cpu(CpuUsage::WRITE_OFTEN);
+        let (dev, class) = memfd_class(desc, usage);
+        let check = |bo: &mut Bo, val: u8| {
+            let mapping = bo.map().unwrap();
+            let ok = mapping.as_slice()[..64].iter().all(|&v| v == val);
+            ok
+        };
+
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        assert!(bo.fill(Fill::Buffer(0), None, true).is_err());
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+        bo.map().unwrap().as_mut_slice()[..64].fill(2);
+
+        // the contents are copied before the BO is filled
+        let snapshot = bo.snapshot().unwrap();
+        bo.fill(Fill::Buffer(0x03030303), None, true).unwrap();
+        assert!(check(&mut bo, 3));
+        assert!(check(&mut snapshot.into_bo(&bo).unwrap(), 2));
+
+        // buffers cannot be cleared
+        assert!(bo.fill(Fill::Color([0.0; 4]), None, true).is_err());
+        assert!(bo.fill(Fill::ColorInt([0; 4]), None, true).is_err());
+    }
+
+    #[test]
+    fn test_resize() {
+        let desc = crate::Description::new().flags(Flags::MAP | Flags::COPY);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+
+        // an unbound BO is recreated
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        bo.resize(&class, 128).unwrap();
+        assert_eq!(bo.layout().size, 128);
+        assert!(bo.resize(&class, 0).is_err());
+
+        // a bound BO reuses its memory when shrinking
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+        bo.resize(&class, 32).unwrap();
+        assert_eq!(bo.layout().size, 128);
+        assert_eq!(dev.allocated_size(), 128);
+        bo.resize(&class, 128).unwrap();
+        assert_eq!(dev.stats().allocation_count, 1);
+
+        // a bound BO is reallocated when growing
+        bo.resize(&class, 256).unwrap();
+        assert_eq!(bo.layout().size, 256);
+        assert_eq!(dev.allocated_size(), 256);
+        assert_eq!(dev.stats().allocation_count, 2);
+        bo.map().unwrap().into_raw();
+        assert!(bo.resize(&class, 512).is_err());
+        bo.unmap();
+        assert_eq!(dev.allocated_size(), 256);
+
+        let img_desc = crate::Description::new()
+            .flags(Flags::MAP)
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let img_class = dev
+            .classify(img_desc, &[Usage::Cpu(CpuUsage::WRITE_OFTEN)])
+            .unwrap();
+        assert!(bo.resize(&img_class, 512).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_allocated_size() {
+        let desc = crate::Description::new().flags(Flags::EXTERNAL | Flags::MAP);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+        assert_eq!(dev.allocated_size(), 64);
+
+        // duplicates share the memory
+        let dup = bo.duplicate(&class).unwrap();
+        assert_eq!(dev.allocated_size(), 64);
+        drop(dup);
+        assert_eq!(dev.allocated_size(), 64);
+
+        // only the memory of the resized BO is counted
+        let dup = bo.duplicate(&class).unwrap();
+        bo.resize(&class, 128).unwrap();
+        assert_eq!(dev.allocated_size(), 128);
+        drop(dup);
+        assert_eq!(dev.allocated_size(), 128);
+        drop(bo);
+        assert_eq!(dev.allocated_size(), 0);
+    }
+
+    #[test]
+    fn test_resize_constraint() {
+        let dev = crate::Builder::new()
+            .add_backend(MemfdBackend)
+            .max_size(1000)
+            .build()
+            .unwrap();
+
+        let desc = crate::Description::new().flags(Flags::MAP);
+        let class = dev
+            .classify(desc, &[Usage::Cpu(CpuUsage::WRITE_OFTEN)])
+            .unwrap();
+
+        // the constraint of the BO is kept across resizes
+        let con = Constraint::new().size_align(256);
+        let mut bo =
+            Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), Some(con)).unwrap();
+        assert_eq!(bo.layout().size, 256);
+        bo.resize(&class, 300).unwrap();
+        assert_eq!(bo.layout().size, 512);
+
+        // a BO that is oversized after the alignment is rejected before the memory is replaced
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+        assert!(bo.resize(&class, 900).is_err());
+        assert_eq!(bo.layout().size, 512);
+        assert_eq!(dev.allocated_size(), 512);
+        assert_eq!(dev.stats().allocation_count, 1);
+    }
+
+    #[test]
+    fn test_map_placed() {
+        use nix::sys::mman;
+
+        let desc = crate::Description::new().flags(Flags::MAP);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+        assert!(dev.features().contains(crate::Features::PLACED_MAP));
+        let size = 4096;
+        let mut bo = Bo::with_constraint(dev, &class, Extent::Buffer(size), None).unwrap();
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+
+        let len = std::num::NonZeroUsize::new(size as usize).unwrap();
+        // SAFETY: the reservation is a new anonymous mapping
+        let addr = unsafe {
+            mman::mmap_anonymous(
+                None,
+                len,
+                mman::ProtFlags::PROT_NONE,
+                mman::MapFlags::MAP_PRIVATE,
+            )
+        }
+        .unwrap();
+
+        // SAFETY: the address range is reserved for the BO
+        let mut mapping = unsafe { bo.map_placed(addr) }.unwrap();
+        assert_eq!(mapping.as_ptr(), addr.as_ptr());
+        mapping.as_mut_slice()[0] = 1;
+        drop(mapping);
+
+        // SAFETY: the BO is unmapped
+        let mapping = unsafe { bo.map_placed(addr) }.unwrap();
+        assert_eq!(mapping.as_slice()[0], 1);
+        drop(mapping);
+
+        // the BO must not be mapped already
+        bo.map().unwrap().into_raw();
+        // SAFETY: the address range is reserved for the BO
+        assert!(unsafe { bo.map_placed(addr) }.is_err());
+        bo.unmap();
+
+        // SAFETY: the address range is no longer used
+        unsafe { mman::munmap(addr, len.get()) }.unwrap();
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let desc = crate::Description::new().flags(Flags::EXTERNAL | Flags::MAP);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        assert!(bo.duplicate(&class).is_err());
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+
+        let mut dup = bo.duplicate(&class).unwrap();
+        assert_eq!(dup.layout(), bo.layout());
+        assert_eq!(
+            dup.memory_type().map(|mt| mt.bits()),
+            Some(MemoryType::MAPPABLE.bits())
+        );
+
+        // the memory is shared but the map states are not
+        let mut mapping = bo.map().unwrap();
+        mapping.as_mut_slice()[..4].copy_from_slice(&[1, 2, 3, 4]);
+        {
+            let dup_mapping = dup.map().unwrap();
+            assert_eq!(dup_mapping.as_slice()[..4], [1, 2, 3, 4]);
+        }
+        assert_eq!(dup.state.lock().unwrap().map_count, 0);
+        drop(mapping);
+
+        // the duplicate outlives the BO
+        drop(bo);
+        assert_eq!(dup.map().unwrap().as_slice()[..4], [1, 2, 3, 4]);
+
+        let other_desc = crate::Description::new().flags(Flags::EXTERNAL);
+        let other_class = dev
+            .classify(other_desc, &[Usage::Cpu(CpuUsage::empty())])
+            .unwrap();
+        assert!(dup.duplicate(&other_class).is_err());
+    }
+
+    #[test]
+    fn test_export_dma_bufs() {
+        let desc = crate::Description::new().flags(Flags::EXTERNAL);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::empty()));
+
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        assert!(bo.export_dma_bufs(None).is_err());
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+
+        // the memory planes are not disjoint
+        assert_eq!(bo.export_dma_bufs(None).unwrap().len(), 1);
+        assert!(bo.bind_memory_planes(MemoryType::MAPPABLE, None).is_err());
+    }
+
+    #[test]
+    fn test_preferred_modifiers() {
+        let mod_a = Modifier(1);
+        let mod_b = Modifier(2);
+        let desc = crate::Description::new()
+            .format(formats::R8)
+            .modifier(formats::MOD_INVALID);
+        let class = Class::new(desc).modifiers(vec![formats::MOD_LINEAR, mod_a, mod_b]);
+        let merge = |con: Constraint| {
+            merge_class_to_constraint(Some(con), &class)
+                .unwrap()
+                .unwrap()
+                .modifiers
+        };
+
+        // the preferred modifiers are moved to the front rather than being the only ones
+        let con = Constraint::new().preferred_modifiers(vec![mod_b, formats::MOD_INVALID, mod_a]);
+        assert_eq!(merge(con), vec![mod_b, mod_a, formats::MOD_LINEAR]);
+
+        let con = Constraint::new()
+            .modifiers(vec![formats::MOD_LINEAR, mod_b])
+            .preferred_modifiers(vec![mod_a, mod_b]);
+        assert_eq!(merge(con), vec![mod_b, formats::MOD_LINEAR]);
+
+        // no restriction when none of the preferred modifiers is allowed
+        let con = Constraint::new().preferred_modifiers(vec![formats::MOD_INVALID]);
+        assert!(merge(con).is_empty());
+    }
+
+    #[test]
+    fn test_flush_unmapped() {
+        let desc = crate::Description::new().flags(Flags::EXTERNAL | Flags::MAP);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        assert!(bo.flush().is_ok());
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+        let sync_fd = |bo: &Bo| {
+            let state = bo.state.lock().unwrap();
+            state.sync_dmabuf.as_ref().map(|dmabuf| dmabuf.as_raw_fd())
+        };
+
+        // the dma-buf is exported once, and memfds fail DMA_BUF_IOCTL_SYNC
+        assert!(bo.flush().is_err());
+        let fd = sync_fd(&bo);
+        assert!(fd.is_some());
+        assert!(bo.invalidate_range(0, 64).is_err());
+        assert_eq!(sync_fd(&bo), fd);
+
+        // mapped BOs are synced through their mappings
+        let mapping = bo.map().unwrap();
+        assert!(mapping.bo().flush().is_ok());
+        assert!(mapping.bo().invalidate().is_ok());
+        drop(mapping);
+
+        // the memory changes on resize
+        bo.resize(&class, 128).unwrap();
+        assert!(sync_fd(&bo).is_none());
+    }
+
+    #[test]
+    fn test_export_name() {
+        let dev = crate::Builder::new()
+            .add_backend(MemfdBackend)
+            .name_template("{process}:{usage}:{serial}")
+            .build()
+            .unwrap();
+
+        let desc = crate::Description::new().flags(Flags::EXTERNAL | Flags::MAP);
+        let class = dev
+            .classify(desc, &[Usage::Cpu(CpuUsage::WRITE_OFTEN)])
+            .unwrap();
+
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+        let named = |bo: &Bo| bo.state.lock().unwrap().named;
+
+        // internal exports do not name the dma-buf, and memfds cannot be synced
+        assert!(bo.flush().is_err());
+        assert!(bo.invalidate().is_err());
+        assert!(!named(&bo));
+
+        bo.export_dma_buf(None).unwrap();
+        assert!(named(&bo));
+
+        // imported dma-bufs keep their names
+        let dup = bo.duplicate(&class).unwrap();
+        assert!(named(&dup));
+    }
+
+    #[test]
+    fn test_copy_image() {
+        let dev = crate::Builder::new()
+            .add_backend(MemfdBackend)
+            .build()
+            .unwrap();
+
+        let class_for = |fmt| {
+            let desc = crate::Description::new()
+                .flags(Flags::MAP | Flags::COPY)
+                .format(fmt)
+                .modifier(formats::MOD_LINEAR);
+            dev.classify(desc, &[Usage::Cpu(CpuUsage::WRITE_OFTEN)])
+                .unwrap()
+        };
+        let alloc = |class: &Class, width, height| {
+            let mut bo =
+                Bo::with_constraint(dev.clone(), class, Extent::Image(width, height), None)
+                    .unwrap();
+            bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+            bo
+        };
+
+        let r8 = class_for(formats::R8);
+        let nv12 = class_for(formats::NV12);
+        let mut src = alloc(&nv12, 8, 4);
+        let mut dst = alloc(&r8, 8, 4);
+
+        {
+            let mut mapping = src.map().unwrap();
+            for (i, v) in mapping.as_mut_slice()[..8 * 4].iter_mut().enumerate() {
+                *v = i as u8;
+            }
+        }
+
+        // rows 1..3 of the luma plane
+        let copy = CopyImage {
+            src_plane: 0,
+            src_x: 2,
+            src_y: 1,
+            src_z: 0,
+            dst_plane: 0,
+            dst_x: 0,
+            dst_y: 0,
+            dst_z: 0,
+            width: 4,
+            height: 2,
+            depth: 1,
+        };
+        dst.copy_image(&src, copy, None, true).unwrap();
+
+        let stride = dst.layout().strides[0] as usize;
+        {
+            let mapping = dst.map().unwrap();
+            let data = mapping.as_slice();
+            assert_eq!(data[..4], [10, 11, 12, 13]);
+            assert_eq!(data[stride..stride + 4], [18, 19, 20, 21]);
+        }
+
+        // the chroma plane has 2-byte blocks
+        let chroma = CopyImage {
+            src_plane: 1,
+            width: 1,
+            height: 1,
+            ..copy
+        };
+        assert!(dst.copy_image(&src, chroma, None, true).is_err());
+        // out of bounds
+        let oob = CopyImage { src_x: 6, ..copy };
+        assert!(dst.copy_image(&src, oob, None, true).is_err());
+        let bad_plane = CopyImage {
+            dst_plane: 1,
+            ..copy
+        };
+        assert!(dst.copy_image(&src, bad_plane, None, true).is_err());
+    }
+
+    #[test]
+    fn test_blit() {
+        let dev = crate::Builder::new()
+            .add_backend(MemfdBackend)
+            .build()
+            .unwrap();
+
+        let class_for = |fmt| {
+            let desc = crate::Description::new()
+                .flags(Flags::MAP | Flags::COPY)
+                .format(fmt)
+                .modifier(formats::MOD_LINEAR);
+            dev.classify(desc, &[Usage::Cpu(CpuUsage::WRITE_OFTEN)])
+                .unwrap()
+        };
+        let alloc = |class: &Class, width, height| {
+            let mut bo =
+                Bo::with_constraint(dev.clone(), class, Extent::Image(width, height), None)
+                    .unwrap();
+            bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+            bo
+        };
+
+        let r8 = class_for(formats::R8);
+        let nv12 = class_for(formats::NV12);
+        let src = alloc(&r8, 8, 4);
+        let dst = alloc(&r8, 8, 4);
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 4,
+        };
+
+        // the backend does not support blits
+        assert!(matches!(
+            dst.blit(&src, rect, rect, Filter::Linear, None, true),
+            Err(Error::Unsupported)
+        ));
+
+        // multi-planar formats cannot be blitted
+        let planar = alloc(&nv12, 8, 4);
+        assert!(matches!(
+            dst.blit(&planar, rect, rect, Filter::Linear, None, true),
+            Err(Error::User)
+        ));
+
+        // out of bounds
+        let oob = Rect { y: 1, ..rect };
+        assert!(matches!(
+            dst.blit(&src, oob, rect, Filter::Linear, None, true),
+            Err(Error::User)
+        ));
+        let empty = Rect { width: 0, ..rect };
+        assert!(matches!(
+            dst.blit(&src, rect, empty, Filter::Linear, None, true),
+            Err(Error::User)
+        ));
+    }
+
+    #[test]
+    fn test_copy_stats() {
+        let desc = crate::Description::new().flags(Flags::MAP | Flags::COPY);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+        let alloc = || {
+            let mut bo =
+                Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+            bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+            bo
+        };
+        let src = alloc();
+        let dst = alloc();
+
+        let copy = CopyBuffer {
+            src_offset: 0,
+            dst_offset: 0,
+            size: 64,
+        };
+        assert!(dst.copy_buffer(&src, copy, None, true).unwrap().is_none());
+        assert!(dst.copy_buffer(&src, copy, None, false).unwrap().is_none());
+
+        let stats = dev.stats();
+        assert_eq!(stats.allocation_count, 2);
+        assert_eq!(stats.copy_latency_counts.iter().sum::<u64>(), 2);
+
+        // fds that are not sync files are never counted
+        let not_sync_fd = OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+        dev.record_pending_copy(&not_sync_fd, utils::monotonic_time());
+        assert_eq!(dev.stats().copy_latency_counts.iter().sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn test_read_write() {
+        let buf_desc = crate::Description::new().flags(Flags::MAP | Flags::COPY);
+        let (dev, buf_class) = memfd_class(buf_desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+        let mut buf =
+            Bo::with_constraint(dev.clone(), &buf_class, Extent::Buffer(64), None).unwrap();
+        buf.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+
+        buf.write(&[1, 2, 3, 4], Region::Buffer(8)).unwrap();
+        let mut data = [0; 6];
+        buf.read(&mut data, Region::Buffer(7)).unwrap();
+        assert_eq!(data, [0, 1, 2, 3, 4, 0]);
+        assert!(buf.read(&mut data, Region::Buffer(60)).is_err());
+
+        let img_desc = crate::Description::new()
+            .flags(Flags::MAP | Flags::COPY)
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let img_class = dev
+            .classify(img_desc, &[Usage::Cpu(CpuUsage::WRITE_OFTEN)])
+            .unwrap();
+        let mut img =
+            Bo::with_constraint(dev.clone(), &img_class, Extent::Image(8, 4), None).unwrap();
+        img.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+
+        // a 2x2 region at (1, 1), with a host stride of 3
+        let copy = CopyBufferImage {
+            offset: 1,
+            stride: 3,
+            slice_stride: 0,
+            plane: 0,
+            x: 1,
+            y: 1,
+            z: 0,
+            width: 2,
+            height: 2,
+            depth: 1,
+        };
+        img.write(&[0, 1, 2, 0, 3, 4], Region::Image(copy)).unwrap();
+
+        let stride = img.layout().strides[0] as usize;
+        {
+            let mapping = img.map().unwrap();
+            let data = mapping.as_slice();
+            assert_eq!(data[stride..stride + 4], [0, 1, 2, 0]);
+            assert_eq!(data[stride * 2..stride * 2 + 4], [0, 3, 4, 0]);
+        }
+
+        let mut data = [9; 6];
+        img.read(&mut data, Region::Image(copy)).unwrap();
+        assert_eq!(data, [9, 1, 2, 9, 3, 4]);
+
+        // regions must match the BO
+        assert!(img.read(&mut data, Region::Buffer(0)).is_err());
+        assert!(buf.read(&mut data, Region::Image(copy)).is_err());
+    }
+
+    #[test]
+    fn test_convert_to() {
+        let dev = crate::Builder::new()
+            .add_backend(MemfdBackend)
+            .build()
+            .unwrap();
+
+        let class_for = |fmt| {
+            let desc = crate::Description::new()
+                .flags(Flags::MAP | Flags::COPY)
+                .format(fmt)
+                .modifier(formats::MOD_LINEAR);
+            dev.classify(desc, &[Usage::Cpu(CpuUsage::WRITE_OFTEN)])
+                .unwrap()
+        };
+        let alloc = |class: &Class, width, height| {
+            let mut bo =
+                Bo::with_constraint(dev.clone(), class, Extent::Image(width, height), None)
+                    .unwrap();
+            bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+            bo
+        };
+
+        let nv12 = class_for(formats::NV12);
+        let mut src = alloc(&nv12, 8, 4);
+        let mut dst = alloc(&nv12, 8, 4);
+
+        let layout = src.layout();
+        {
+            let mut mapping = src.map().unwrap();
+            for (i, v) in mapping.as_mut_slice().iter_mut().enumerate() {
+                *v = i as u8;
+            }
+        }
+
+        src.convert_to(&dst, None, true).unwrap();
+
+        // both planes are copied
+        let chroma_offset = dst.layout().offsets[1] as usize;
+        {
+            let mapping = dst.map().unwrap();
+            let data = mapping.as_slice();
+            assert_eq!(data[..8], [0, 1, 2, 3, 4, 5, 6, 7]);
+            let src_chroma = layout.offsets[1] as u8;
+            assert_eq!(data[chroma_offset], src_chroma);
+        }
+
+        // the formats and the extents must match
+        let r8 = alloc(&class_for(formats::R8), 8, 4);
+        assert!(src.convert_to(&r8, None, true).is_err());
+        let small = alloc(&nv12, 4, 4);
+        assert!(src.convert_to(&small, None, true).is_err());
+    }
+
+    #[test]
+    fn test_copy_batch() {
+        let desc = crate::Description::new().flags(Flags::MAP | Flags::COPY);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+        let alloc = || {
+            let mut bo =
+                Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+            bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+            bo
+        };
+
+        let mut a = alloc();
+        let mut b = alloc();
+        let mut c = alloc();
+        a.map().unwrap().as_mut_slice()[..64].fill(1);
+
+        let copy = CopyBuffer {
+            src_offset: 0,
+            dst_offset: 0,
+            size: 32,
+        };
+        let tail = CopyBuffer {
+            src_offset: 32,
+            dst_offset: 32,
+            ..copy
+        };
+
+        // an invalid copy fails the batch before any copy is executed
+        let oob = CopyBuffer { size: 65, ..copy };
+        let batch = CopyBatch::new()
+            .copy_buffer(&b, &a, copy)
+            .copy_buffer(&c, &b, oob);
+        assert!(batch.submit(None, true).is_err());
+        assert!(b.map().unwrap().as_slice()[..32].iter().all(|&v| v == 0));
+        assert!(CopyBatch::new().submit(None, true).is_err());
+
+        // the copies are executed in order
+        let batch = CopyBatch::new()
+            .copy_buffer(&b, &a, copy)
+            .copy_buffer(&c, &b, copy)
+            .copy_buffer(&c, &a, tail);
+        assert!(batch.submit(None, true).unwrap().is_none());
+        assert!(c.map().unwrap().as_slice()[..64].iter().all(|&v| v == 1));
+        assert_eq!(dev.stats().copy_latency_counts.iter().sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn test_bind_host_memory() {
+        let dev = crate::Builder::new()
+            .add_backend(MemfdBackend)
+            .build()
+            .unwrap();
+
+        let mut page = vec![0u64; 512];
+        let ptr = ptr::NonNull::new(page.as_mut_ptr().cast()).unwrap();
+
+        let usage = [Usage::Cpu(CpuUsage::WRITE_OFTEN)];
+        let mt = MemoryType::MAPPABLE;
+
+        let desc = crate::Description::new().flags(Flags::EXTERNAL | Flags::MAP);
+        let class = dev.classify(desc, &usage).unwrap();
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        // SAFETY: external BOs are rejected before the pointer is used
+        assert!(unsafe { bo.bind_host_memory(mt, ptr) }.is_err());
+
+        let desc = crate::Description::new().flags(Flags::MAP);
+        let class = dev.classify(desc, &usage).unwrap();
+        let mut bo = Bo::with_constraint(dev, &class, Extent::Buffer(64), None).unwrap();
+        // SAFETY: the backend does not support host allocations
+        assert!(unsafe { bo.bind_host_memory(mt, ptr) }.is_err());
+        bo.bind_memory(mt, None).unwrap();
+    }
+
+    #[test]
+    fn test_protected_memory_type() {
+        let desc = crate::Description::new().flags(Flags::MAP);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::empty()));
+        let mut bo = Bo::with_constraint(dev, &class, Extent::Buffer(64), None).unwrap();
+        assert!(bo
+            .bind_memory(MemoryType::LOCAL | MemoryType::PROTECTED, None)
+            .is_err());
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+
+        // fallbacks stay protected
+        let mts = vec![
+            MemoryType::LOCAL | MemoryType::PROTECTED,
+            MemoryType::empty(),
+            MemoryType::PROTECTED,
+        ];
+        let fallbacks = fallback_memory_types(mts, MemoryType::LOCAL | MemoryType::PROTECTED);
+        let fallbacks: Vec<u32> = fallbacks.into_iter().map(|mt| mt.bits()).collect();
+        assert_eq!(fallbacks, [MemoryType::PROTECTED.bits()]);
+    }
+
+    #[test]
+    fn test_memory_fallback() {
+        struct FallbackBackend;
+        impl Backend for FallbackBackend {
+            fn memory_types(&self, _handle: &Handle) -> Vec<MemoryType> {
+                vec![MemoryType::LOCAL, MemoryType::MAPPABLE]
+            }
+            fn bind_memory(
+                &self,
+                handle: &mut Handle,
+                mt: MemoryType,
+                dmabuf: Option<OwnedFd>,
+            ) -> Result<()> {
+                if mt.contains(MemoryType::LOCAL) {
+                    return Err(Error::OutOfDeviceMemory);
+                }
+                MemfdBackend.bind_memory(handle, mt, dmabuf)
+            }
+        }
+
+        // all fallbacks fail with other errors
+        struct FailingFallbackBackend;
+        impl Backend for FailingFallbackBackend {
+            fn memory_types(&self, _handle: &Handle) -> Vec<MemoryType> {
+                vec![MemoryType::LOCAL, MemoryType::MAPPABLE]
+            }
+            fn bind_memory(
+                &self,
+                _handle: &mut Handle,
+                mt: MemoryType,
+                _dmabuf: Option<OwnedFd>,
+            ) -> Result<()> {
+                if mt.contains(MemoryType::LOCAL) {
+                    Err(Error::OutOfDeviceMemory)
+                } else {
+                    Error::unsupported()
+                }
+            }
+        }
+
+        let mts = vec![
+            MemoryType::LOCAL,
+            MemoryType::MAPPABLE,
+            MemoryType::MAPPABLE | MemoryType::COHERENT,
+            MemoryType::MAPPABLE,
+        ];
+        let fallbacks = fallback_memory_types(mts, MemoryType::LOCAL | MemoryType::MAPPABLE);
+        let fallbacks: Vec<u32> = fallbacks.into_iter().map(|mt| mt.bits()).collect();
+        assert_eq!(
+            fallbacks,
+            [
+                MemoryType::MAPPABLE.bits(),
+                (MemoryType::MAPPABLE | MemoryType::COHERENT).bits()
+            ]
+        );
+
+        let desc = crate::Description::new().flags(Flags::COPY);
+        let usage = [Usage::Cpu(CpuUsage::empty())];
+
+        let dev = crate::Builder::new()
+            .add_backend(FallbackBackend)
+            .build()
+            .unwrap();
+        let class = dev.classify(desc, &usage).unwrap();
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        assert!(bo.memory_type().is_none());
+        bo.bind_memory(MemoryType::LOCAL, None).unwrap();
+        assert_eq!(
+            bo.memory_type().unwrap().bits(),
+            MemoryType::MAPPABLE.bits()
+        );
+        assert_eq!(dev.stats().allocation_failure_count, 1);
+
+        let dev = crate::Builder::new()
+            .add_backend(FallbackBackend)
+            .disable_memory_fallback(true)
+            .build()
+            .unwrap();
+        let class = dev.classify(desc, &usage).unwrap();
+        let mut bo = Bo::with_constraint(dev, &class, Extent::Buffer(64), None).unwrap();
+        assert!(matches!(
+            bo.bind_memory(MemoryType::LOCAL, None),
+            Err(Error::OutOfDeviceMemory)
+        ));
+        assert!(bo.memory_type().is_none());
+
+        // the original error is kept when no fallback succeeds
+        let dev = crate::Builder::new()
+            .add_backend(FailingFallbackBackend)
+            .build()
+            .unwrap();
+        let class = dev.classify(desc, &usage).unwrap();
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        assert!(matches!(
+            bo.bind_memory(MemoryType::LOCAL, None),
+            Err(Error::OutOfDeviceMemory)
+        ));
+        assert!(bo.memory_type().is_none());
+        assert_eq!(dev.stats().allocation_failure_count, 2);
+    }
+
+    #[test]
+    fn test_copy_timeout() {
+        // copies return the read end of a pipe, which never signals while the write end is open
+        struct WedgedBackend(std::sync::Mutex<Vec<OwnedFd>>);
+        impl Backend for WedgedBackend {
+            fn bind_memory(
+                &self,
+                handle: &mut Handle,
+                mt: MemoryType,
+                dmabuf: Option<OwnedFd>,
+            ) -> Result<()> {
+                MemfdBackend.bind_memory(handle, mt, dmabuf)
+            }
+            fn copy_buffer(
+                &self,
+                _dst: &Handle,
+                _src: &Handle,
+                _copy: CopyBuffer,
+                _sync_fd: Option<OwnedFd>,
+            ) -> Result<Option<OwnedFd>> {
+                let (read, write) = nix::unistd::pipe().unwrap();
+                self.0.lock().unwrap().push(write);
+                Ok(Some(read))
+            }
+        }
+
+        let dev = crate::Builder::new()
+            .add_backend(WedgedBackend(Default::default()))
+            .copy_timeout(Duration::from_millis(1))
+            .build()
+            .unwrap();
+
+        let desc = crate::Description::new().flags(Flags::COPY);
+        let class = dev
+            .classify(desc, &[Usage::Cpu(CpuUsage::empty())])
+            .unwrap();
+        let mut dst = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        let mut src = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(64), None).unwrap();
+        dst.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+        src.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+
+        let copy = CopyBuffer {
+            src_offset: 0,
+            dst_offset: 0,
+            size: 64,
+        };
+        assert!(dst.copy_buffer(&src, copy, None, false).unwrap().is_some());
+        assert!(matches!(
+            dst.copy_buffer(&src, copy, None, true),
+            Err(Error::Device)
+        ));
+    }
+
+    #[cfg(feature = "ash")]
+    #[test]
+    fn test_vk_handles() {
+        let desc = crate::Description::new().flags(Flags::COPY);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::empty()));
+        let mut bo = Bo::with_constraint(dev, &class, Extent::Buffer(64), None).unwrap();
+        bo.bind_memory(MemoryType::empty(), None).unwrap();
+
+        // the handles are only known to the vulkan backend
+        assert!(bo.vk_buffer().is_none());
+        assert!(bo.vk_image().is_none());
+        assert!(bo.vk_device_memory().is_none());
+    }
+
+    #[test]
+    fn test_sparse_region() {
+        struct SparseBackend;
+        impl Backend for SparseBackend {
+            fn sparse_block_size(&self, _handle: &Handle) -> Option<Size> {
+                Some(16)
+            }
+            fn bind_sparse_region(
+                &self,
+                _handle: &mut Handle,
+                _mt: MemoryType,
+                _offset: Size,
+                _size: Size,
+            ) -> Result<()> {
+                Ok(())
+            }
+            fn unbind_sparse_region(
+                &self,
+                _handle: &mut Handle,
+                _offset: Size,
+                size: Size,
+            ) -> Result<Size> {
+                Ok(size)
+            }
+        }
+
+        let dev = crate::Builder::new()
+            .add_backend(SparseBackend)
+            .build()
+            .unwrap();
+
+        let desc = crate::Description::new().flags(Flags::COPY);
+        let class = dev
+            .classify(desc, &[Usage::Cpu(CpuUsage::empty())])
+            .unwrap();
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Buffer(40), None).unwrap();
+        let mt = MemoryType::empty();
+
+        assert!(bo.bind_sparse_region(mt, 8, 16).is_err());
+        assert!(bo.bind_sparse_region(mt, 0, 8).is_err());
+        assert!(bo.bind_sparse_region(mt, 32, 16).is_err());
+        bo.bind_sparse_region(mt, 0, 16).unwrap();
+        bo.bind_sparse_region(mt, 32, 8).unwrap();
+        assert!(bo.bind_sparse_region(MemoryType::MAPPABLE, 16, 16).is_err());
+        assert_eq!(dev.stats().allocated_size, 24);
+
+        // partially bound
+        assert!(bo.memory_type().is_none());
+        let fill = Fill::Buffer(0);
+        assert!(bo.fill(fill, None, true).is_err());
+
+        bo.bind_sparse_region(mt, 16, 16).unwrap();
+        assert!(bo.memory_type().is_some());
+
+        bo.unbind_sparse_region(0, 16).unwrap();
+        assert!(bo.memory_type().is_none());
+        assert_eq!(dev.stats().allocated_size, 24);
+    }
+
+    #[test]
+    fn test_host_copy() {
+        let desc = crate::Description::new()
+            .flags(Flags::MAP | Flags::COPY)
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+        let mut bo = Bo::with_constraint(dev.clone(), &class, Extent::Image(8, 4), None).unwrap();
+
+        let src: Vec<u8> = (0..16).collect();
+        let copy = copy_region(0, 4, 4, 4);
+        assert!(bo.write_image(&src, copy).is_err());
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+        bo.write_image(&src, copy).unwrap();
+
+        // the region must fit in the host memory and in the image
+        assert!(bo.write_image(&src[..15], copy).is_err());
+        assert!(bo.write_image(&src, copy_region(0, 4, 5, 4)).is_err());
+
+        let mut dst = [0u8; 8];
+        let copy = CopyBufferImage {
+            x: 1,
+            y: 2,
+            ..copy_region(0, 4, 2, 2)
+        };
+        bo.read_image(&mut dst, copy).unwrap();
+        assert_eq!(dst, [9, 10, 0, 0, 13, 14, 0, 0]);
+    }
+
+    #[test]
+    fn test_host_copy_3d() {
+        let desc = crate::Description::new()
+            .flags(Flags::MAP | Flags::COPY)
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let (dev, class) = memfd_class(desc, Usage::Cpu(CpuUsage::WRITE_OFTEN));
+        let mut bo =
+            Bo::with_constraint(dev.clone(), &class, Extent::Image3D(4, 2, 3), None).unwrap();
+        bo.bind_memory(MemoryType::MAPPABLE, None).unwrap();
+
+        let layout = bo.layout();
+        assert_eq!(layout.slice_stride, layout.strides[0] * 2);
+        assert_eq!(layout.size, layout.slice_stride * 3);
+
+        // slices 1..3 from a host buffer with padded slices
+        let src: Vec<u8> = (0..24).collect();
+        let copy = CopyBufferImage {
+            slice_stride: 12,
+            z: 1,
+            depth: 2,
+            ..copy_region(0, 4, 4, 2)
+        };
+        bo.write_image(&src, copy).unwrap();
+        assert!(bo
+            .write_image(&src, CopyBufferImage { z: 2, ..copy })
+            .is_err());
+
+        let mut dst = [0u8; 8];
+        let copy = CopyBufferImage {
+            z: 2,
+            ..copy_region(0, 4, 4, 2)
+        };
+        bo.read_image(&mut dst, copy).unwrap();
+        assert_eq!(dst, [12, 13, 14, 15, 16, 17, 18, 19]);
+
+        // 3D images cannot be blitted
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 2,
+        };
+        assert!(matches!(
+            bo.blit(&bo, rect, rect, Filter::Nearest, None, true),
+            Err(Error::User)
+        ));
+    }
+
+    #[test]
+    fn test_linear_region_range() {
+        let mut layout = Layout::new().size(64 * 48).modifier(formats::MOD_LINEAR);
+        layout.plane_count = 2;
+        layout.offsets = [0, 64 * 32, 0, 0];
+        layout.strides = [64, 64, 0, 0];
+
+        // rows 4..8 of the luma plane and rows 2..4 of the chroma plane
+        let range = linear_region_range(&layout, formats::NV12, 8, 4, 8, 4).unwrap();
+        assert_eq!(range, (64 * 4 + 8, 64 * 32 + 64 * 3 + 16 - (64 * 4 + 8)));
+
+        layout.plane_count = 1;
+        layout.offsets[1] = 0;
+        layout.strides[1] = 0;
+        let range = linear_region_range(&layout, formats::R8, 0, 0, 64, 1).unwrap();
+        assert_eq!(range, (0, 64));
     }
 }
@@ -5,19 +5,266 @@
 //!
 //! This module defines `Device` and `Builder`
 
-use super::backends::{Backend, Class, Constraint, Description, Extent, Usage};
-use super::types::{Error, Format, Modifier, Result};
-use std::collections::HashSet;
-use std::sync::Arc;
+#[cfg(feature = "drm")]
+use super::backends::drm_kms;
+#[cfg(feature = "ash")]
+use super::backends::vulkan;
+use super::backends::{
+    Backend, Class, Constraint, CopyBuffer, CopyStats, CpuUsage, Description, DeviceInfo, Extent,
+    ExternalMemory, Features, Flags, MemoryHeap, MemoryType, ModifierFeatures, ModifierHint, Usage,
+};
+use super::bo::Bo;
+#[cfg(feature = "drm")]
+use super::formats;
+use super::types::{Error, Format, Modifier, Result, Size};
+use super::utils;
+use std::collections::HashMap;
+use std::os::fd::OwnedFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{array, fs};
+
+// this is DMA_BUF_NAME_LEN, including the terminating NUL
+const DMA_BUF_NAME_LEN: usize = 32;
+
+// the size of the BOs allocated by self tests
+const SELF_TEST_SIZE: Size = 4096;
+
+// the max number of copies whose sync files are tracked for their latencies
+const MAX_PENDING_COPIES: usize = 64;
+
+// expands a dma-buf name template and truncates the name to fit DMA_BUF_NAME_LEN
+fn expand_name_template(template: &str, process: &str, usage: &str, serial: u64) -> String {
+    let mut name = template
+        .replace("{process}", process)
+        .replace("{usage}", usage)
+        .replace("{serial}", &serial.to_string());
+
+    let mut len = DMA_BUF_NAME_LEN - 1;
+    if name.len() > len {
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        name.truncate(len);
+    }
+
+    name
+}
+
+/// The upper bounds of the copy latency histogram buckets in microseconds.
+pub const COPY_LATENCY_BUCKETS: [u64; 8] = [100, 250, 500, 1000, 2500, 5000, 10000, 25000];
+
+/// Device statistics.
+///
+/// This is a snapshot of the counters of a device since it was built.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of memories allocated for BOs.
+    pub allocation_count: u64,
+    /// Number of memory allocations that failed.
+    pub allocation_failure_count: u64,
+    /// Total size of the memories currently allocated for BOs in bytes.
+    pub allocated_size: Size,
+    /// Total size of the memories that small BOs are suballocated from in bytes.
+    ///
+    /// Only the suballocated ranges of these memories are included in `allocated_size`.
+    pub slab_size: Size,
+
+    /// Number of completed copies in each latency bucket.
+    ///
+    /// The buckets are bounded by `COPY_LATENCY_BUCKETS`, and the last bucket counts the copies
+    /// that are slower than the last bound.  The counts are not cumulative.
+    ///
+    /// Copies that return sync files are counted after the sync files signal, with the latencies
+    /// up to the signal timestamps.
+    pub copy_latency_counts: [u64; COPY_LATENCY_BUCKETS.len() + 1],
+    /// Sum of the latencies of completed copies in microseconds.
+    pub copy_latency_sum: u64,
+}
+
+#[derive(Default)]
+struct DeviceStats {
+    allocation_count: AtomicU64,
+    allocation_failure_count: AtomicU64,
+    copy_latency_counts: [AtomicU64; COPY_LATENCY_BUCKETS.len() + 1],
+    copy_latency_sum: AtomicU64,
+    // the sync files and the start times of copies that are not waited for
+    pending_copies: Mutex<Vec<(OwnedFd, Duration)>>,
+}
 
 /// A device.
 ///
 /// A device consists of one or more backends to interact with the underlying subsystems and hardware.
 pub struct Device {
     backends: Vec<Box<dyn Backend>>,
+
+    max_image_extent: Extent,
+    max_size: Size,
+    memory_fallback: bool,
+    copy_timeout: Option<Duration>,
+
+    allocated_size: AtomicU64,
+    stats: DeviceStats,
+
+    name_template: Option<String>,
+    process_name: String,
+    bo_serial: AtomicU64,
 }
 
 impl Device {
+    /// Returns the device features.
+    ///
+    /// The features are the union of the features of all backends.
+    pub fn features(&self) -> Features {
+        self.backends
+            .iter()
+            .fold(Features::empty(), |feats, backend| {
+                feats | backend.features()
+            })
+    }
+
+    /// Returns the memory heaps.
+    ///
+    /// The memory heaps are the concatenation of the memory heaps of all backends.  Budgets and
+    /// usages are queried anew on each call, such that this can be polled to react to memory
+    /// pressure.
+    pub fn memory_heaps(&self) -> Vec<MemoryHeap> {
+        self.backends
+            .iter()
+            .flat_map(|backend| backend.memory_heaps())
+            .collect()
+    }
+
+    /// Returns the device infos.
+    ///
+    /// There is a device info for each backend that has a device, in the order the backends were
+    /// added.
+    pub fn device_infos(&self) -> Vec<DeviceInfo> {
+        self.backends
+            .iter()
+            .filter_map(|backend| backend.device_info())
+            .collect()
+    }
+
+    /// Returns the copy statistics.
+    ///
+    /// The copy statistics are the concatenation of the copy statistics of all backends.  They
+    /// are collected only by backends that are built with copy profiling, such as the Vulkan
+    /// backend with `vulkan::Builder::profile_copies`.
+    pub fn copy_stats(&self) -> Vec<CopyStats> {
+        self.backends
+            .iter()
+            .flat_map(|backend| backend.copy_stats())
+            .collect()
+    }
+
+    /// Returns the total size of the memories allocated for BOs in bytes.
+    ///
+    /// Imported memories are not included.  BOs that are suballocated count the sizes of their
+    /// suballocations rather than the sizes of the memories they are suballocated from.
+    pub fn allocated_size(&self) -> Size {
+        self.allocated_size.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_allocated_size(&self, size: Size) {
+        self.allocated_size.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sub_allocated_size(&self, size: Size) {
+        self.allocated_size.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    /// Returns the device statistics.
+    pub fn stats(&self) -> Stats {
+        self.retire_pending_copies();
+
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        Stats {
+            allocation_count: load(&self.stats.allocation_count),
+            allocation_failure_count: load(&self.stats.allocation_failure_count),
+            allocated_size: self.allocated_size(),
+            slab_size: self
+                .backends
+                .iter()
+                .map(|backend| backend.slab_sizes().0)
+                .sum(),
+            copy_latency_counts: array::from_fn(|i| load(&self.stats.copy_latency_counts[i])),
+            copy_latency_sum: load(&self.stats.copy_latency_sum),
+        }
+    }
+
+    pub(crate) fn memory_fallback(&self) -> bool {
+        self.memory_fallback
+    }
+
+    pub(crate) fn copy_timeout(&self) -> Option<Duration> {
+        self.copy_timeout
+    }
+
+    pub(crate) fn record_allocation(&self, success: bool) {
+        let counter = if success {
+            &self.stats.allocation_count
+        } else {
+            &self.stats.allocation_failure_count
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_copy(&self, latency: Duration) {
+        let us = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = COPY_LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| us <= bound)
+            .unwrap_or(COPY_LATENCY_BUCKETS.len());
+
+        self.stats.copy_latency_counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.stats.copy_latency_sum.fetch_add(us, Ordering::Relaxed);
+    }
+
+    // Records the latency of a copy when its sync file signals.  start is a monotonic_time.
+    pub(crate) fn record_pending_copy(&self, sync_fd: &OwnedFd, start: Duration) {
+        self.retire_pending_copies();
+
+        let Ok(sync_fd) = sync_fd.try_clone() else {
+            return;
+        };
+
+        let mut pending = self.stats.pending_copies.lock().unwrap();
+        if pending.len() >= MAX_PENDING_COPIES {
+            pending.remove(0);
+        }
+        pending.push((sync_fd, start));
+    }
+
+    // records the latencies of the pending copies whose sync files have signaled
+    fn retire_pending_copies(&self) {
+        let mut pending = self.stats.pending_copies.lock().unwrap();
+        pending.retain(
+            |(sync_fd, start)| match utils::sync_file_timestamp(sync_fd) {
+                Ok(Some(end)) => {
+                    self.record_copy(end.saturating_sub(*start));
+                    false
+                }
+                Ok(None) => true,
+                // the latency is unknown
+                Err(_) => false,
+            },
+        );
+    }
+
+    pub(crate) fn next_bo_serial(&self) -> u64 {
+        self.bo_serial.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // returns the default dma-buf name for a BO, if there is a name template
+    pub(crate) fn dma_buf_name(&self, usage: Usage, serial: u64) -> Option<String> {
+        self.name_template.as_ref().map(|template| {
+            expand_name_template(template, &self.process_name, usage.name(), serial)
+        })
+    }
+
     /// Returns the memory plane count of a format and a modifier.
     ///
     /// The format plane count is a property of a format.  The memory plane count is a property of
@@ -66,27 +313,61 @@ impl Device {
         .inspect(|class| {
             assert_eq!(class.modifiers.is_empty(), desc.is_buffer());
         })
+        .map(|class| self.clamp_class(class))
+    }
+
+    // applies the device limits to a class
+    fn clamp_class(&self, mut class: Class) -> Class {
+        if class.is_buffer() {
+            class.max_extent.intersect(Extent::Buffer(self.max_size));
+        } else {
+            class.max_extent.intersect(self.max_image_extent);
+        }
+        class.max_size = self.max_size;
+
+        class
     }
 
     fn multi_classify(&self, desc: Description, usage: &[Usage]) -> Result<Class> {
         // call classify from all backends and merge the results
         let mut max_extent = Extent::max_supported(&desc);
-        let mut mods: Option<HashSet<Modifier>> = None;
+        let mut max_ranges = Vec::new();
+        let mut cpu_usage = CpuUsage::empty();
+        let mut dropped_flags = Flags::empty();
+        let mut mods: Option<HashMap<Modifier, ModifierFeatures>> = None;
         let mut con = Constraint::new();
         let mut required_idx = None;
+        let mut first_idx = None;
         for (idx, (backend, &usage)) in self.backends.iter().zip(usage.iter()).enumerate() {
             if usage == Usage::Unused {
                 continue;
             }
+            first_idx.get_or_insert(idx);
 
             let class = backend.classify(desc, usage)?;
 
             max_extent.intersect(class.max_extent);
+            max_ranges.extend(class.max_ranges);
+            cpu_usage |= class.cpu_usage;
+            // a flag dropped by any backend is dropped from the merged class
+            dropped_flags |= class.dropped_flags;
 
             if !desc.is_buffer() {
-                let backend_mods: HashSet<Modifier> = class.modifiers.into_iter().collect();
+                let backend_mods: HashMap<Modifier, ModifierFeatures> = class
+                    .modifiers
+                    .into_iter()
+                    .zip(class.modifier_features)
+                    .collect();
+                // a modifier is supported, with a feature, only when all backends support it
                 mods = Some(match mods {
-                    Some(mods) => mods.intersection(&backend_mods).copied().collect(),
+                    Some(mods) => mods
+                        .into_iter()
+                        .filter_map(|(modifier, feats)| {
+                            backend_mods
+                                .get(&modifier)
+                                .map(|backend_feats| (modifier, feats & *backend_feats))
+                        })
+                        .collect(),
                     None => backend_mods,
                 });
             }
@@ -108,24 +389,28 @@ impl Device {
             return Error::unsupported();
         }
 
-        let mods: Vec<Modifier> = if desc.is_buffer() {
-            Vec::new()
+        let (mods, mod_feats): (Vec<Modifier>, Vec<ModifierFeatures>) = if desc.is_buffer() {
+            Default::default()
         } else {
             let mods = mods.unwrap_or_default();
             if mods.is_empty() {
                 return Error::unsupported();
             }
 
-            mods.into_iter().collect()
+            mods.into_iter().unzip()
         };
 
-        let idx = required_idx.unwrap_or(0);
-        let class = Class::new(desc)
+        let idx = required_idx.or(first_idx).unwrap_or(0);
+        let mut class = Class::new(desc)
             .usage(usage[idx])
+            .drop_flags(dropped_flags)
             .max_extent(max_extent)
             .modifiers(mods)
+            .modifier_features(mod_feats)
             .constraint(con)
             .backend_index(idx);
+        class.max_ranges = max_ranges;
+        class.cpu_usage |= cpu_usage;
 
         Ok(class)
     }
@@ -143,6 +428,183 @@ impl Device {
         &class.modifiers
     }
 
+    /// Returns the modifier hints of a BO class.
+    ///
+    /// The returned slice has a hint for each of the supported modifiers, in the same order as
+    /// `Device::modifiers`.  Users can factor in memory bandwidth when choosing between modifiers.
+    pub fn modifier_hints<'a>(&self, class: &'a Class) -> &'a [ModifierHint] {
+        &class.modifier_hints
+    }
+
+    /// Returns the modifier features of a BO class.
+    ///
+    /// The returned slice has the features for each of the supported modifiers, in the same order
+    /// as `Device::modifiers`.  Users can rank modifiers by the features beyond what the BO class
+    /// requires, such as preferring a modifier that can also be used as a color attachment.
+    pub fn modifier_features<'a>(&self, class: &'a Class) -> &'a [ModifierFeatures] {
+        &class.modifier_features
+    }
+
+    /// Returns the flags that were dropped from the BO description of a BO class.
+    ///
+    /// Backends can drop optional flags when that enlarges the set of supported modifiers, such as
+    /// `Flags::COPY` with `vulkan::Builder::minimize_usage`.  BOs of the class do not support the
+    /// dropped flags.
+    pub fn dropped_flags(&self, class: &Class) -> Flags {
+        class.dropped_flags
+    }
+
+    /// Returns the external memory capabilities of a BO class and a modifier.
+    ///
+    /// The BO class must have `Flags::EXTERNAL`.  If the BO class is for a buffer, the modifier
+    /// must be `DRM_FORMAT_MOD_INVALID`.  Otherwise, the modifier must be one of the supported
+    /// modifiers of the BO class.
+    ///
+    /// This allows users to know whether a memory allocated elsewhere can be imported before
+    /// creating a BO.
+    pub fn external_memory(&self, class: &Class, modifier: Modifier) -> Result<ExternalMemory> {
+        if !class.flags.contains(Flags::EXTERNAL) {
+            return Error::user();
+        }
+
+        let valid = if class.is_buffer() {
+            modifier.is_invalid()
+        } else {
+            class.modifiers.contains(&modifier)
+        };
+        if !valid {
+            return Error::user();
+        }
+
+        self.backend(class.backend_index)
+            .external_memory(class, modifier)
+    }
+
+    /// Returns the max range of a buffer BO class that can be used for a usage at a time.
+    ///
+    /// Some usages, such as uniform buffers, limit the range of the BO that can be used at a time
+    /// rather than the size of the BO.  `None` is returned if the usage has no such limit.
+    pub fn max_range(&self, class: &Class, usage: Usage) -> Option<Size> {
+        class
+            .max_ranges
+            .iter()
+            .filter(|(u, _)| *u == usage)
+            .map(|(_, range)| *range)
+            .min()
+    }
+
+    /// Runs a self test on each backend.
+    ///
+    /// For each backend, a small mappable BO is allocated and written by the CPU.  The BO is copied
+    /// to another BO when the backend can copy it, and the result is read back and verified.  The
+    /// results are returned in the order the backends were added.
+    ///
+    /// This is meant to be an init-time confidence check for services.  A service can rebuild the
+    /// device without the backends that fail.
+    pub fn self_test(self: &Arc<Self>) -> Vec<Result<()>> {
+        (0..self.backends.len())
+            .map(|idx| {
+                let res = self.self_test_backend(idx);
+                match &res {
+                    Ok(()) => log::info!("backend {idx} passed the self test"),
+                    Err(err) => log::warn!("backend {idx} failed the self test: {err:?}"),
+                }
+                res
+            })
+            .collect()
+    }
+
+    // returns the first class that the backend supports for self tests
+    fn self_test_class(&self, idx: usize) -> Result<(Class, Extent)> {
+        let buf_desc = Description::new().flags(Flags::MAP | Flags::COPY);
+        let buf_extent = Extent::Buffer(SELF_TEST_SIZE);
+        let candidates = [
+            (
+                buf_desc,
+                Usage::Cpu(CpuUsage::READ_OFTEN | CpuUsage::WRITE_OFTEN),
+                buf_extent,
+            ),
+            #[cfg(feature = "ash")]
+            (buf_desc, Usage::Vulkan(vulkan::Usage::TRANSFER), buf_extent),
+            #[cfg(feature = "drm")]
+            (
+                Description::new()
+                    .flags(Flags::MAP)
+                    .format(formats::XRGB8888)
+                    .modifier(formats::MOD_LINEAR),
+                Usage::DrmKms(drm_kms::Usage::PRIMARY),
+                Extent::Image(SELF_TEST_SIZE as u32 / 4, 1),
+            ),
+        ];
+
+        let mut usage = vec![Usage::Unused; self.backends.len()];
+        for (desc, backend_usage, extent) in candidates {
+            usage[idx] = backend_usage;
+            if let Ok(class) = self.classify(desc, &usage) {
+                return Ok((class, extent));
+            }
+        }
+
+        Error::unsupported()
+    }
+
+    fn self_test_bo(self: &Arc<Self>, class: &Class, extent: Extent) -> Result<Bo> {
+        let mut bo = Bo::with_constraint(self.clone(), class, extent, None)?;
+        let mt = bo
+            .memory_types()
+            .into_iter()
+            .find(|mt| mt.contains(MemoryType::MAPPABLE))
+            .ok_or(Error::Unsupported)?;
+        bo.bind_memory(mt, None)?;
+
+        Ok(bo)
+    }
+
+    fn self_test_backend(self: &Arc<Self>, idx: usize) -> Result<()> {
+        let (class, extent) = self.self_test_class(idx)?;
+        let pattern = |offset: usize| (offset % 251) as u8;
+
+        let mut src = self.self_test_bo(&class, extent)?;
+        let mut mapping = src.map()?;
+        for (offset, val) in mapping.as_mut_slice().iter_mut().enumerate() {
+            *val = pattern(offset);
+        }
+        mapping.bo().flush()?;
+        drop(mapping);
+
+        let mut dst = if class.is_buffer() && class.flags.contains(Flags::COPY) {
+            let dst = self.self_test_bo(&class, extent)?;
+            let copy = CopyBuffer {
+                src_offset: 0,
+                dst_offset: 0,
+                size: SELF_TEST_SIZE,
+            };
+            dst.copy_buffer(&src, copy, None, true)?;
+            dst
+        } else {
+            src
+        };
+
+        let mapping = dst.map()?;
+        mapping.bo().invalidate()?;
+        let data = mapping.as_slice();
+        let len = data.len().min(SELF_TEST_SIZE as usize);
+        let valid = data[..len]
+            .iter()
+            .enumerate()
+            .all(|(offset, &val)| val == pattern(offset));
+
+        if valid {
+            Ok(())
+        } else {
+            Error::device()
+        }
+    }
+
+    pub(crate) fn backend_count(&self) -> usize {
+        self.backends.len()
+    }
+
     pub(crate) fn backend(&self, idx: usize) -> &dyn Backend {
         self.backends[idx].as_ref()
     }
@@ -154,6 +616,11 @@ impl Device {
 #[derive(Default)]
 pub struct Builder {
     backends: Vec<Box<dyn super::Backend>>,
+    name_template: Option<String>,
+    max_image_extent: Option<(u32, u32)>,
+    max_size: Option<Size>,
+    disable_memory_fallback: bool,
+    copy_timeout: Option<Duration>,
 }
 
 impl Builder {
@@ -171,16 +638,351 @@ impl Builder {
         self
     }
 
+    /// Sets the dma-buf name template.
+    ///
+    /// When a BO is exported without a name, the dma-buf is named after the template.  `{process}`
+    /// is replaced by the process name, `{usage}` by the BO usage, and `{serial}` by a per-device
+    /// BO serial number.  Names are truncated to 31 bytes, which is the kernel limit.
+    ///
+    /// For example, "{process}:{usage}:{serial}".
+    pub fn name_template(mut self, template: &str) -> Self {
+        self.name_template = Some(String::from(template));
+        self
+    }
+
+    /// Sets the max width and height of image BOs.
+    ///
+    /// The limits are merged into every BO class, on top of the backend limits.  Image BOs that
+    /// are larger are rejected with `Error::User`.
+    pub fn max_image_extent(mut self, width: u32, height: u32) -> Self {
+        self.max_image_extent = Some((width, height));
+        self
+    }
+
+    /// Sets the max size of BOs in bytes.
+    ///
+    /// The limit is merged into every BO class, on top of the backend limits.  BOs whose layouts
+    /// are larger are rejected with `Error::User`.  This allows services to bound the allocations
+    /// requested by untrusted clients.
+    pub fn max_size(mut self, size: Size) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Disables memory type fallbacks.
+    ///
+    /// By default, when `Bo::bind_memory` runs out of device memory, it retries with the other
+    /// supported memory types that have the same host access, such as host memory instead of
+    /// device-local memory.  When disabled, `Error::OutOfDeviceMemory` is returned instead.
+    pub fn disable_memory_fallback(mut self, disable: bool) -> Self {
+        self.disable_memory_fallback = disable;
+        self
+    }
+
+    /// Sets the timeout of waits for copies.
+    ///
+    /// When a copy is waited for and does not complete before the timeout, it fails with
+    /// `Error::Device`.  The timeout also bounds the waits that backends perform internally, such
+    /// as the waits for the sync files that copies depend on.  By default, copies are waited for
+    /// indefinitely.
+    pub fn copy_timeout(mut self, timeout: Duration) -> Self {
+        self.copy_timeout = Some(timeout);
+        self
+    }
+
     /// Builds a `Device`.
-    pub fn build(self) -> Result<Arc<Device>> {
+    pub fn build(mut self) -> Result<Arc<Device>> {
         if self.backends.is_empty() {
             return Error::user();
         }
 
+        let (max_width, max_height) = self.max_image_extent.unwrap_or((u32::MAX, u32::MAX));
+        let max_size = self.max_size.unwrap_or(Size::MAX);
+        if max_width == 0 || max_height == 0 || max_size == 0 {
+            return Error::user();
+        }
+
+        let process_name = if self.name_template.is_some() {
+            let comm = fs::read_to_string("/proc/self/comm").unwrap_or_default();
+            String::from(comm.trim_end())
+        } else {
+            String::new()
+        };
+
+        for backend in &mut self.backends {
+            backend.set_copy_timeout(self.copy_timeout);
+        }
+
         let dev = Device {
             backends: self.backends,
+            max_image_extent: Extent::Image(max_width, max_height),
+            max_size,
+            memory_fallback: !self.disable_memory_fallback,
+            copy_timeout: self.copy_timeout,
+            allocated_size: AtomicU64::new(0),
+            stats: Default::default(),
+            name_template: self.name_template,
+            process_name,
+            bo_serial: AtomicU64::new(0),
         };
 
         Ok(Arc::new(dev))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_template() {
+        let name = expand_name_template("{process}:{usage}:{serial}", "test", "cpu", 7);
+        assert_eq!(name, "test:cpu:7");
+
+        let name = expand_name_template("{process}-{serial}", &"a".repeat(40), "cpu", 7);
+        assert_eq!(name.len(), DMA_BUF_NAME_LEN - 1);
+
+        // never split a character
+        let name = expand_name_template("{process}", &"\u{e9}".repeat(20), "cpu", 0);
+        assert_eq!(name.len(), DMA_BUF_NAME_LEN - 2);
+    }
+
+    #[test]
+    fn test_default_features() {
+        struct DefaultBackend;
+        impl Backend for DefaultBackend {}
+
+        // backends opt into features and copies
+        let dev = Builder::new().add_backend(DefaultBackend).build().unwrap();
+        assert!(dev.features().is_empty());
+        let handle = crate::backends::Handle::new(0u32);
+        let copy = CopyBuffer {
+            src_offset: 0,
+            dst_offset: 0,
+            size: 1,
+        };
+        let res = DefaultBackend.copy_buffer(&handle, &handle, copy, None);
+        assert!(matches!(res, Err(Error::Unsupported)));
+
+        let dev = Builder::new()
+            .add_backend(crate::dma_buf::tests::MemfdBackend)
+            .build()
+            .unwrap();
+        assert!(dev.features().contains(Features::COPY_ENGINE));
+    }
+
+    #[test]
+    fn test_multi_classify() {
+        struct DefaultBackend;
+        impl Backend for DefaultBackend {}
+
+        let dev = Builder::new()
+            .add_backend(DefaultBackend)
+            .add_backend(DefaultBackend)
+            .build()
+            .unwrap();
+
+        // the class belongs to the first backend in use
+        let desc = Description::new().flags(Flags::MAP);
+        let usage = Usage::Cpu(CpuUsage::READ_OFTEN);
+        let class = dev.classify(desc, &[Usage::Unused, usage]).unwrap();
+        assert_eq!(class.backend_index, 1);
+        assert!(class.usage == usage);
+        assert!(dev.dropped_flags(&class).is_empty());
+    }
+
+    #[test]
+    fn test_multi_classify_dropped_flags() {
+        struct DefaultBackend;
+        impl Backend for DefaultBackend {}
+
+        struct MinimizeBackend;
+        impl Backend for MinimizeBackend {
+            fn classify(&self, desc: Description, usage: Usage) -> Result<Class> {
+                crate::dma_buf::classify(desc, usage).map(|class| class.drop_flags(Flags::COPY))
+            }
+        }
+
+        let dev = Builder::new()
+            .add_backend(DefaultBackend)
+            .add_backend(MinimizeBackend)
+            .build()
+            .unwrap();
+
+        // the flags dropped by the second backend are dropped from the class of the first
+        let desc = Description::new().flags(Flags::MAP | Flags::COPY);
+        let usage = Usage::Cpu(CpuUsage::READ_OFTEN);
+        let class = dev.classify(desc, &[usage, usage]).unwrap();
+        assert_eq!(class.backend_index, 0);
+        assert_eq!(class.flags, Flags::MAP);
+        assert_eq!(dev.dropped_flags(&class), Flags::COPY);
+    }
+
+    #[test]
+    fn test_multi_classify_modifier_features() {
+        use crate::formats;
+
+        struct FeatureBackend(ModifierFeatures);
+        impl Backend for FeatureBackend {
+            fn classify(&self, desc: Description, usage: Usage) -> Result<Class> {
+                crate::dma_buf::classify(desc, usage).map(|class| {
+                    class
+                        .modifiers(vec![formats::MOD_LINEAR])
+                        .modifier_features(vec![self.0])
+                })
+            }
+        }
+
+        let dev = Builder::new()
+            .add_backend(FeatureBackend(
+                ModifierFeatures::TRANSFER | ModifierFeatures::SAMPLED,
+            ))
+            .add_backend(FeatureBackend(
+                ModifierFeatures::TRANSFER | ModifierFeatures::STORAGE,
+            ))
+            .build()
+            .unwrap();
+
+        // only the features supported by all backends are kept
+        let desc = Description::new()
+            .flags(Flags::MAP)
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let usage = Usage::Cpu(CpuUsage::READ_OFTEN);
+        let class = dev.classify(desc, &[usage, usage]).unwrap();
+        assert_eq!(dev.modifiers(&class), [formats::MOD_LINEAR]);
+        assert_eq!(dev.modifier_features(&class), [ModifierFeatures::TRANSFER]);
+    }
+
+    #[test]
+    fn test_limits() {
+        use crate::{formats, Bo};
+
+        struct LimitBackend;
+        impl Backend for LimitBackend {}
+
+        let dev = Builder::new()
+            .add_backend(LimitBackend)
+            .max_image_extent(64, 32)
+            .max_size(1024)
+            .build()
+            .unwrap();
+
+        let desc = Description::new()
+            .flags(Flags::EXTERNAL)
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let class = dev.classify(desc, &[Usage::Unused]).unwrap();
+        // the features parallel the modifiers and are unknown
+        assert_eq!(dev.modifier_features(&class), [ModifierFeatures::empty()]);
+        assert!(Bo::dry_run(&dev, &class, Extent::Image(32, 32), None).is_ok());
+        assert!(Bo::dry_run(&dev, &class, Extent::Image(65, 1), None).is_err());
+        // within the max extent but larger than the max size
+        assert!(Bo::dry_run(&dev, &class, Extent::Image(64, 32), None).is_err());
+        assert!(Bo::with_constraint(dev.clone(), &class, Extent::Image(64, 32), None).is_err());
+
+        let buf_desc = Description::new().flags(Flags::EXTERNAL);
+        let buf_class = dev.classify(buf_desc, &[Usage::Unused]).unwrap();
+        assert!(Bo::dry_run(&dev, &buf_class, Extent::Buffer(1024), None).is_ok());
+        assert!(Bo::dry_run(&dev, &buf_class, Extent::Buffer(1025), None).is_err());
+
+        let res = Builder::new().add_backend(LimitBackend).max_size(0).build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_max_size_before_create() {
+        use crate::{formats, Bo, Handle};
+
+        struct NoCreateBackend;
+        impl Backend for NoCreateBackend {
+            fn with_constraint(
+                &self,
+                _class: &Class,
+                _extent: Extent,
+                _con: Option<Constraint>,
+            ) -> Result<Handle> {
+                unreachable!("oversized BOs are rejected before their handles are created");
+            }
+        }
+
+        let dev = Builder::new()
+            .add_backend(NoCreateBackend)
+            .max_size(1024)
+            .build()
+            .unwrap();
+
+        let desc = Description::new()
+            .flags(Flags::COPY)
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let class = dev.classify(desc, &[Usage::Unused]).unwrap();
+        let res = Bo::with_constraint(dev.clone(), &class, Extent::Image(64, 32), None);
+        assert!(matches!(res, Err(Error::User)));
+    }
+
+    #[test]
+    fn test_slab_sizes() {
+        struct SlabBackend;
+        impl Backend for SlabBackend {
+            fn slab_sizes(&self) -> (Size, Size) {
+                (4096, 1024)
+            }
+        }
+
+        let dev = Builder::new().add_backend(SlabBackend).build().unwrap();
+
+        // the slabs are not allocated for any BO
+        assert_eq!(dev.allocated_size(), 0);
+        let stats = dev.stats();
+        assert_eq!(stats.allocated_size, 0);
+        assert_eq!(stats.slab_size, 4096);
+    }
+
+    #[test]
+    fn test_device_infos() {
+        struct NoDeviceBackend;
+        impl Backend for NoDeviceBackend {}
+
+        struct InfoBackend;
+        impl Backend for InfoBackend {
+            fn device_info(&self) -> Option<DeviceInfo> {
+                Some(DeviceInfo {
+                    name: "test".to_string(),
+                    vendor_id: 0x1234,
+                    ..Default::default()
+                })
+            }
+        }
+
+        let dev = Builder::new()
+            .add_backend(NoDeviceBackend)
+            .add_backend(InfoBackend)
+            .build()
+            .unwrap();
+
+        let infos = dev.device_infos();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].name, "test");
+        assert_eq!(infos[0].vendor_id, 0x1234);
+    }
+
+    #[test]
+    fn test_self_test() {
+        use crate::dma_buf::tests::MemfdBackend;
+
+        struct BrokenBackend;
+        impl Backend for BrokenBackend {}
+
+        let dev = Builder::new()
+            .add_backend(MemfdBackend)
+            .add_backend(BrokenBackend)
+            .build()
+            .unwrap();
+
+        let res = dev.self_test();
+        assert_eq!(res.len(), 2);
+        assert!(res[0].is_ok());
+        assert!(res[1].is_err());
+    }
+}
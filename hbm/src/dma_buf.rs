@@ -6,20 +6,25 @@
 //! This module provides high-level helpers that backends can use to work with dma-bufs.
 
 use super::backends::{
-    Class, Constraint, Description, Extent, Flags, Handle, HandlePayload, Layout, MemoryType, Usage,
+    Class, Constraint, CopyBuffer, CopyBufferImage, CopyImage, Description, Extent, ExternalMemory,
+    Features, Fill, Flags, Handle, Layout, MemoryType, Requirements, Usage,
 };
-use super::types::{Access, Error, Mapping, Result, Size};
+use super::formats;
+use super::types::{Access, Error, Format, Mapping, Modifier, Result, Size};
 use super::utils;
 use std::os::fd::{BorrowedFd, OwnedFd};
+use std::{ffi, ptr, slice};
 
 pub struct Resource {
+    format: Format,
     layout: Layout,
     dmabuf: Option<OwnedFd>,
 }
 
 impl Resource {
-    pub fn new(layout: Layout) -> Self {
+    pub fn new(format: Format, layout: Layout) -> Self {
         Self {
+            format,
             layout,
             dmabuf: None,
         }
@@ -38,18 +43,18 @@ impl Resource {
     }
 }
 
-fn get_resource(handle: &Handle) -> &Resource {
-    match handle.payload {
-        HandlePayload::DmaBuf(ref res) => res,
-        _ => unreachable!(),
-    }
+// handles of other backends are rejected
+fn get_resource(handle: &Handle) -> Result<&Resource> {
+    handle.payload().ok_or(Error::User)
 }
 
-fn get_resource_mut(handle: &mut Handle) -> &mut Resource {
-    match handle.payload {
-        HandlePayload::DmaBuf(ref mut res) => res,
-        _ => unreachable!(),
-    }
+fn get_resource_mut(handle: &mut Handle) -> Result<&mut Resource> {
+    handle.payload_mut().ok_or(Error::User)
+}
+
+pub fn features() -> Features {
+    // copies are done by the cpu, and mappings are placed with MAP_FIXED
+    Features::COPY_ENGINE | Features::PLACED_MAP
 }
 
 pub fn classify(desc: Description, usage: Usage) -> Result<Class> {
@@ -57,28 +62,52 @@ pub fn classify(desc: Description, usage: Usage) -> Result<Class> {
         return Error::unsupported();
     }
 
+    // linear images are never compressed
+    if !desc.fixed_rate.is_empty() {
+        return Error::unsupported();
+    }
+
     let unsupported_flags = Flags::PROTECTED;
     if desc.flags.intersects(unsupported_flags) {
         return Error::unsupported();
     }
 
+    if let Usage::Cpu(cpu_usage) = usage {
+        if !cpu_usage.is_empty() && !desc.flags.contains(Flags::MAP) {
+            return Error::user();
+        }
+    }
+
     let mut class = Class::new(desc)
         .usage(usage)
         .max_extent(Extent::max_supported(&desc));
-    if desc.is_buffer() {
-        class = class.modifiers(vec![desc.modifier]);
+    if !desc.is_buffer() {
+        class = class.modifiers(vec![formats::MOD_LINEAR]);
     }
 
     Ok(class)
 }
 
+pub fn external_memory(_class: &Class, _modifier: Modifier) -> Result<ExternalMemory> {
+    Ok(ExternalMemory::EXPORT | ExternalMemory::IMPORT)
+}
+
 pub fn with_constraint(class: &Class, extent: Extent, con: Option<Constraint>) -> Result<Handle> {
     let layout = Layout::packed(class, extent, con)?;
-    let handle = Handle::from(Resource::new(layout));
+    let handle = Handle::from(Resource::new(class.format, layout));
 
     Ok(handle)
 }
 
+pub fn dry_run(class: &Class, extent: Extent, con: Option<Constraint>) -> Result<Requirements> {
+    let reqs = Requirements {
+        layout: Layout::packed(class, extent, con)?,
+        memory_types: vec![MemoryType::MAPPABLE],
+    };
+
+    Ok(reqs)
+}
+
 pub fn with_layout(
     class: &Class,
     extent: Extent,
@@ -93,13 +122,15 @@ pub fn with_layout(
         return Error::user();
     }
 
-    let handle = Handle::from(Resource::new(layout));
+    let handle = Handle::from(Resource::new(class.format, layout));
 
     Ok(handle)
 }
 
 pub fn layout(handle: &Handle) -> Layout {
-    get_resource(handle).layout.clone()
+    get_resource(handle)
+        .map(|res| res.layout.clone())
+        .unwrap_or_default()
 }
 
 pub fn memory_types(_handle: &Handle) -> Vec<MemoryType> {
@@ -115,7 +146,7 @@ pub fn bind_memory<T>(
 where
     T: FnOnce(Size) -> Result<OwnedFd>,
 {
-    let res = get_resource_mut(handle);
+    let res = get_resource_mut(handle)?;
 
     if !MemoryType::MAPPABLE.contains(mt) {
         return Error::user();
@@ -145,7 +176,7 @@ where
 }
 
 pub fn export_dma_buf(handle: &Handle, name: Option<&str>) -> Result<OwnedFd> {
-    let dmabuf = get_resource(handle).dmabuf();
+    let dmabuf = get_resource(handle)?.dmabuf();
 
     if let Some(name) = name {
         let _ = utils::dma_buf_set_name(dmabuf, name);
@@ -157,10 +188,19 @@ pub fn export_dma_buf(handle: &Handle, name: Option<&str>) -> Result<OwnedFd> {
 }
 
 pub fn map(handle: &Handle) -> Result<Mapping> {
-    let dmabuf = get_resource(handle).dmabuf();
+    let dmabuf = get_resource(handle)?.dmabuf();
 
     let len = utils::seek_end(dmabuf)?;
-    let mapping = utils::mmap(dmabuf, len, Access::ReadWrite)?;
+    let mapping = utils::mmap(dmabuf, len, Access::ReadWrite, None)?;
+
+    Ok(mapping)
+}
+
+pub fn map_placed(handle: &Handle, addr: ptr::NonNull<ffi::c_void>) -> Result<Mapping> {
+    let dmabuf = get_resource(handle)?.dmabuf();
+
+    let len = utils::seek_end(dmabuf)?;
+    let mapping = utils::mmap(dmabuf, len, Access::ReadWrite, Some(addr))?;
 
     Ok(mapping)
 }
@@ -169,6 +209,10 @@ pub fn unmap(_handle: &Handle, mapping: Mapping) {
     let _ = utils::munmap(mapping);
 }
 
+pub fn unmap_placed(_handle: &Handle, mapping: Mapping) {
+    let _ = utils::munmap_reserve(mapping);
+}
+
 // utils::dma_buf_sync is supposed to be used as follows
 //
 //  - utils::dma_buf_sync(dmabuf, access, true)
@@ -189,13 +233,398 @@ pub fn unmap(_handle: &Handle, mapping: Mapping) {
 // utils::dma_buf_sync yet anyway.
 
 pub fn flush(handle: &Handle) {
-    let dmabuf = get_resource(handle).dmabuf();
+    let Ok(res) = get_resource(handle) else {
+        return;
+    };
+    let dmabuf = res.dmabuf();
 
-    let _ = utils::dma_buf_sync(dmabuf, Access::ReadWrite, false);
+    // only cpu writes need to be made available to the device
+    let _ = utils::dma_buf_sync(dmabuf, Access::Write, false);
 }
 
 pub fn invalidate(handle: &Handle) {
-    let dmabuf = get_resource(handle).dmabuf();
+    let Ok(res) = get_resource(handle) else {
+        return;
+    };
+    let dmabuf = res.dmabuf();
+
+    // only the cpu cache for reads needs to be invalidated
+    let _ = utils::dma_buf_sync(dmabuf, Access::Read, true);
+}
+
+// a cpu mapping of a resource for the duration of a copy
+struct CopyMapping<'a> {
+    dmabuf: &'a OwnedFd,
+    access: Access,
+    mapping: Mapping,
+}
+
+impl<'a> CopyMapping<'a> {
+    fn new(res: &'a Resource, access: Access) -> Result<Self> {
+        let dmabuf = res.dmabuf();
+        let mapping = utils::mmap(dmabuf, res.size(), access, None)?;
+        let _ = utils::dma_buf_sync(dmabuf, access, true);
+
+        Ok(Self {
+            dmabuf,
+            access,
+            mapping,
+        })
+    }
+
+    fn range(offset: Size, size: Size) -> Result<(usize, usize)> {
+        let offset = usize::try_from(offset)?;
+        let end = offset
+            .checked_add(usize::try_from(size)?)
+            .ok_or(Error::User)?;
+
+        Ok((offset, end))
+    }
+
+    fn src(&self, offset: Size, size: Size) -> Result<&[u8]> {
+        let (offset, end) = Self::range(offset, size)?;
+        // SAFETY: the mapping is valid until dropped
+        let data = unsafe {
+            slice::from_raw_parts(self.mapping.ptr.as_ptr().cast(), self.mapping.len.get())
+        };
+
+        data.get(offset..end).ok_or(Error::User)
+    }
+
+    fn dst(&mut self, offset: Size, size: Size) -> Result<&mut [u8]> {
+        let (offset, end) = Self::range(offset, size)?;
+        // SAFETY: the mapping is valid until dropped and is not aliased by any other slice
+        let data = unsafe {
+            slice::from_raw_parts_mut(self.mapping.ptr.as_ptr().cast(), self.mapping.len.get())
+        };
+
+        data.get_mut(offset..end).ok_or(Error::User)
+    }
+}
+
+impl Drop for CopyMapping<'_> {
+    fn drop(&mut self) {
+        let _ = utils::dma_buf_sync(self.dmabuf, self.access, false);
+        let _ = utils::munmap(self.mapping);
+    }
+}
+
+fn wait_sync_fd(sync_fd: Option<OwnedFd>) -> Result<()> {
+    if let Some(sync_fd) = sync_fd {
+        utils::poll(sync_fd, Access::Read)?;
+    }
+
+    Ok(())
+}
+
+pub fn copy_buffer(
+    dst: &Handle,
+    src: &Handle,
+    copy: CopyBuffer,
+    sync_fd: Option<OwnedFd>,
+) -> Result<Option<OwnedFd>> {
+    wait_sync_fd(sync_fd)?;
+
+    let src = CopyMapping::new(get_resource(src)?, Access::Read)?;
+    let mut dst = CopyMapping::new(get_resource(dst)?, Access::Write)?;
+
+    dst.dst(copy.dst_offset, copy.size)?
+        .copy_from_slice(src.src(copy.src_offset, copy.size)?);
+
+    Ok(None)
+}
+
+pub fn copy_buffer_image(
+    dst: &Handle,
+    src: &Handle,
+    copy: CopyBufferImage,
+    sync_fd: Option<OwnedFd>,
+) -> Result<Option<OwnedFd>> {
+    wait_sync_fd(sync_fd)?;
+
+    let src_res = get_resource(src)?;
+    let dst_res = get_resource(dst)?;
+    let to_image = src_res.format.is_invalid();
+    let img_res = if to_image { dst_res } else { src_res };
+
+    // only linear images can be copied by the cpu
+    if !img_res.layout.modifier.is_linear() {
+        return Error::unsupported();
+    }
+
+    let fmt_class = formats::format_class(img_res.format)?;
+    let plane = copy.plane as usize;
+    let bpp = fmt_class.block_size[plane] as Size;
+    let img_offset = img_res.layout.offsets[plane] + copy.x as Size * bpp;
+    let img_stride = img_res.layout.strides[plane];
+    let img_slice_stride = img_res.layout.slice_stride;
+    let row_size = copy.width as Size * bpp;
+
+    let src = CopyMapping::new(src_res, Access::Read)?;
+    let mut dst = CopyMapping::new(dst_res, Access::Write)?;
+
+    for (slice, row) in slice_rows(copy.depth, copy.height) {
+        let buf_offset = copy.offset + copy.slice_stride * slice + copy.stride * row;
+        let img_offset = img_offset
+            + img_slice_stride * (copy.z as Size + slice)
+            + img_stride * (copy.y as Size + row);
+        let (src_offset, dst_offset) = if to_image {
+            (buf_offset, img_offset)
+        } else {
+            (img_offset, buf_offset)
+        };
+
+        dst.dst(dst_offset, row_size)?
+            .copy_from_slice(src.src(src_offset, row_size)?);
+    }
 
-    let _ = utils::dma_buf_sync(dmabuf, Access::ReadWrite, true);
+    Ok(None)
+}
+
+// returns the (slice, row) pairs of a region of `depth` slices of `height` rows
+fn slice_rows(depth: u32, height: u32) -> impl Iterator<Item = (Size, Size)> {
+    (0..depth as Size).flat_map(move |slice| (0..height as Size).map(move |row| (slice, row)))
+}
+
+// returns the offset, the stride, the slice stride, and the row size of an image region of a
+// linear resource
+fn linear_image_region(res: &Resource, copy: &CopyBufferImage) -> Result<(Size, Size, Size, Size)> {
+    // only linear images can be copied by the cpu
+    if !res.layout.modifier.is_linear() {
+        return Error::unsupported();
+    }
+
+    let fmt_class = formats::format_class(res.format)?;
+    let plane = copy.plane as usize;
+    let bpp = fmt_class.block_size[plane] as Size;
+    let offset = res.layout.offsets[plane] + copy.x as Size * bpp;
+    let stride = res.layout.strides[plane];
+    let slice_stride = res.layout.slice_stride;
+    let row_size = copy.width as Size * bpp;
+
+    Ok((offset, stride, slice_stride, row_size))
+}
+
+pub fn read_image(handle: &Handle, dst: &mut [u8], copy: CopyBufferImage) -> Result<()> {
+    let res = get_resource(handle)?;
+    let (img_offset, img_stride, img_slice_stride, row_size) = linear_image_region(res, &copy)?;
+
+    let src = CopyMapping::new(res, Access::Read)?;
+    for (slice, row) in slice_rows(copy.depth, copy.height) {
+        let buf_offset = copy.offset + copy.slice_stride * slice + copy.stride * row;
+        let (dst_offset, end) = CopyMapping::range(buf_offset, row_size)?;
+        let img_offset = img_offset
+            + img_slice_stride * (copy.z as Size + slice)
+            + img_stride * (copy.y as Size + row);
+
+        dst.get_mut(dst_offset..end)
+            .ok_or(Error::User)?
+            .copy_from_slice(src.src(img_offset, row_size)?);
+    }
+
+    Ok(())
+}
+
+pub fn write_image(handle: &Handle, src: &[u8], copy: CopyBufferImage) -> Result<()> {
+    let res = get_resource(handle)?;
+    let (img_offset, img_stride, img_slice_stride, row_size) = linear_image_region(res, &copy)?;
+
+    let mut dst = CopyMapping::new(res, Access::Write)?;
+    for (slice, row) in slice_rows(copy.depth, copy.height) {
+        let buf_offset = copy.offset + copy.slice_stride * slice + copy.stride * row;
+        let (src_offset, end) = CopyMapping::range(buf_offset, row_size)?;
+        let img_offset = img_offset
+            + img_slice_stride * (copy.z as Size + slice)
+            + img_stride * (copy.y as Size + row);
+
+        dst.dst(img_offset, row_size)?
+            .copy_from_slice(src.get(src_offset..end).ok_or(Error::User)?);
+    }
+
+    Ok(())
+}
+
+pub fn copy_image(
+    dst: &Handle,
+    src: &Handle,
+    copy: CopyImage,
+    sync_fd: Option<OwnedFd>,
+) -> Result<Option<OwnedFd>> {
+    wait_sync_fd(sync_fd)?;
+
+    let src_res = get_resource(src)?;
+    let dst_res = get_resource(dst)?;
+
+    // only linear images can be copied by the cpu
+    if !src_res.layout.modifier.is_linear() || !dst_res.layout.modifier.is_linear() {
+        return Error::unsupported();
+    }
+
+    let src_plane = copy.src_plane as usize;
+    let dst_plane = copy.dst_plane as usize;
+    let bpp = formats::format_class(src_res.format)?.block_size[src_plane] as Size;
+    let src_offset = src_res.layout.offsets[src_plane] + copy.src_x as Size * bpp;
+    let dst_offset = dst_res.layout.offsets[dst_plane] + copy.dst_x as Size * bpp;
+    let src_stride = src_res.layout.strides[src_plane];
+    let dst_stride = dst_res.layout.strides[dst_plane];
+    let src_slice_stride = src_res.layout.slice_stride;
+    let dst_slice_stride = dst_res.layout.slice_stride;
+    let row_size = copy.width as Size * bpp;
+
+    let src = CopyMapping::new(src_res, Access::Read)?;
+    let mut dst = CopyMapping::new(dst_res, Access::Write)?;
+
+    for (slice, row) in slice_rows(copy.depth, copy.height) {
+        let src_offset = src_offset
+            + src_slice_stride * (copy.src_z as Size + slice)
+            + src_stride * (copy.src_y as Size + row);
+        let dst_offset = dst_offset
+            + dst_slice_stride * (copy.dst_z as Size + slice)
+            + dst_stride * (copy.dst_y as Size + row);
+
+        dst.dst(dst_offset, row_size)?
+            .copy_from_slice(src.src(src_offset, row_size)?);
+    }
+
+    Ok(None)
+}
+
+pub fn fill(handle: &Handle, fill: Fill, sync_fd: Option<OwnedFd>) -> Result<Option<OwnedFd>> {
+    // clearing an image requires packing the color, which is not supported
+    let Fill::Buffer(value) = fill else {
+        return Error::unsupported();
+    };
+
+    wait_sync_fd(sync_fd)?;
+
+    let res = get_resource(handle)?;
+    let mut dst = CopyMapping::new(res, Access::Write)?;
+    for (idx, byte) in dst.dst(0, res.size())?.iter_mut().enumerate() {
+        *byte = value.to_ne_bytes()[idx % 4];
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::backends::{Backend, CpuUsage, Handle};
+
+    // a backend whose memories are memfds
+    pub(crate) struct MemfdBackend;
+    impl Backend for MemfdBackend {
+        fn features(&self) -> Features {
+            features()
+        }
+
+        fn bind_memory(
+            &self,
+            handle: &mut Handle,
+            mt: MemoryType,
+            dmabuf: Option<OwnedFd>,
+        ) -> Result<()> {
+            bind_memory(handle, mt, dmabuf, |size| {
+                utils::memfd_create("hbm-test", size)
+            })
+        }
+
+        fn copy_buffer(
+            &self,
+            dst: &Handle,
+            src: &Handle,
+            copy: CopyBuffer,
+            sync_fd: Option<OwnedFd>,
+        ) -> Result<Option<OwnedFd>> {
+            copy_buffer(dst, src, copy, sync_fd)
+        }
+
+        fn copy_buffer_image(
+            &self,
+            dst: &Handle,
+            src: &Handle,
+            copy: CopyBufferImage,
+            sync_fd: Option<OwnedFd>,
+        ) -> Result<Option<OwnedFd>> {
+            copy_buffer_image(dst, src, copy, sync_fd)
+        }
+
+        fn copy_image(
+            &self,
+            dst: &Handle,
+            src: &Handle,
+            copy: CopyImage,
+            sync_fd: Option<OwnedFd>,
+        ) -> Result<Option<OwnedFd>> {
+            copy_image(dst, src, copy, sync_fd)
+        }
+
+        fn read_image(&self, handle: &Handle, dst: &mut [u8], copy: CopyBufferImage) -> Result<()> {
+            read_image(handle, dst, copy)
+        }
+
+        fn write_image(&self, handle: &Handle, src: &[u8], copy: CopyBufferImage) -> Result<()> {
+            write_image(handle, src, copy)
+        }
+
+        fn fill(
+            &self,
+            handle: &Handle,
+            fill: Fill,
+            sync_fd: Option<OwnedFd>,
+        ) -> Result<Option<OwnedFd>> {
+            super::fill(handle, fill, sync_fd)
+        }
+    }
+
+    #[test]
+    fn test_classify_cpu_usage() {
+        let usage = Usage::Cpu(CpuUsage::READ_OFTEN);
+
+        // a CPU usage requires MAP
+        let desc = Description::new().flags(Flags::COPY);
+        assert!(matches!(classify(desc, usage), Err(Error::User)));
+        assert!(classify(desc, Usage::Cpu(CpuUsage::empty())).is_ok());
+
+        let desc = Description::new().flags(Flags::MAP);
+        assert!(classify(desc, usage).is_ok());
+    }
+
+    #[test]
+    fn test_classify_modifiers() {
+        // images are linear and buffers have no modifier
+        let desc = Description::new()
+            .format(formats::R8)
+            .modifier(formats::MOD_LINEAR);
+        let class = classify(desc, Usage::Unused).unwrap();
+        assert_eq!(class.modifiers, vec![formats::MOD_LINEAR]);
+
+        let desc = Description::new();
+        let class = classify(desc, Usage::Unused).unwrap();
+        assert!(class.modifiers.is_empty());
+    }
+
+    #[test]
+    fn test_foreign_handle() {
+        let desc = Description::new().flags(Flags::MAP);
+        let class = classify(desc, Usage::Unused).unwrap();
+        let mut handle = with_constraint(&class, Extent::Buffer(64), None).unwrap();
+        MemfdBackend
+            .bind_memory(&mut handle, MemoryType::MAPPABLE, None)
+            .unwrap();
+
+        // handles of other backends are rejected rather than panicking
+        let mut foreign = Handle::new(0u32);
+        let copy = CopyBuffer {
+            src_offset: 0,
+            dst_offset: 0,
+            size: 64,
+        };
+        let res = copy_buffer(&handle, &foreign, copy, None);
+        assert!(matches!(res, Err(Error::User)));
+        let res = bind_memory(&mut foreign, MemoryType::MAPPABLE, None, |_| Error::user());
+        assert!(matches!(res, Err(Error::User)));
+        assert_eq!(layout(&foreign), Layout::default());
+        assert!(copy_buffer(&handle, &handle, copy, None).is_ok());
+    }
 }
@@ -5,11 +5,17 @@
 //!
 //! This module provides helpers to work with DRM formats and format modifiers.
 
-use super::backends::{Constraint, Layout};
+#[cfg(feature = "ash")]
+use super::backends::Swizzle;
+use super::backends::{Bandwidth, Constraint, Layout, ModifierHint};
 use super::types::{Error, Format, Modifier, Result, Size};
 #[cfg(feature = "ash")]
 use ash::vk;
+#[cfg(feature = "ash")]
+use std::collections::HashMap;
 use std::str;
+#[cfg(feature = "ash")]
+use std::sync::OnceLock;
 
 // from drm_fourcc.h
 mod consts {
@@ -30,7 +36,10 @@ mod consts {
     pub const DRM_FORMAT_BGR565: u32 = fourcc_code!('B', 'G', '1', '6');
     pub const DRM_FORMAT_RGB565: u32 = fourcc_code!('R', 'G', '1', '6');
     pub const DRM_FORMAT_GR88: u32 = fourcc_code!('G', 'R', '8', '8');
+    pub const DRM_FORMAT_R10: u32 = fourcc_code!('R', '1', '0', ' ');
+    pub const DRM_FORMAT_R12: u32 = fourcc_code!('R', '1', '2', ' ');
     pub const DRM_FORMAT_R16: u32 = fourcc_code!('R', '1', '6', ' ');
+    pub const DRM_FORMAT_GR1616: u32 = fourcc_code!('G', 'R', '3', '2');
     pub const DRM_FORMAT_BGR888: u32 = fourcc_code!('B', 'G', '2', '4');
     pub const DRM_FORMAT_RGB888: u32 = fourcc_code!('R', 'G', '2', '4');
     pub const DRM_FORMAT_ABGR8888: u32 = fourcc_code!('A', 'B', '2', '4');
@@ -51,7 +60,17 @@ mod consts {
     pub const DRM_FORMAT_YUV420: u32 = fourcc_code!('Y', 'U', '1', '2');
     pub const DRM_FORMAT_YVU420: u32 = fourcc_code!('Y', 'V', '1', '2');
 
-    const DRM_FORMAT_MOD_VENDOR_NONE: u64 = 0;
+    pub const DRM_FORMAT_MOD_VENDOR_NONE: u64 = 0;
+    pub const DRM_FORMAT_MOD_VENDOR_INTEL: u64 = 0x01;
+    pub const DRM_FORMAT_MOD_VENDOR_AMD: u64 = 0x02;
+    pub const DRM_FORMAT_MOD_VENDOR_NVIDIA: u64 = 0x03;
+    pub const DRM_FORMAT_MOD_VENDOR_SAMSUNG: u64 = 0x04;
+    pub const DRM_FORMAT_MOD_VENDOR_QCOM: u64 = 0x05;
+    pub const DRM_FORMAT_MOD_VENDOR_VIVANTE: u64 = 0x06;
+    pub const DRM_FORMAT_MOD_VENDOR_BROADCOM: u64 = 0x07;
+    pub const DRM_FORMAT_MOD_VENDOR_ARM: u64 = 0x08;
+    pub const DRM_FORMAT_MOD_VENDOR_ALLWINNER: u64 = 0x09;
+    pub const DRM_FORMAT_MOD_VENDOR_AMLOGIC: u64 = 0x0a;
     const DRM_FORMAT_RESERVED: u64 = (1u64 << 56) - 1;
 
     pub const DRM_FORMAT_MOD_INVALID: u64 =
@@ -60,18 +79,59 @@ mod consts {
 }
 
 pub const INVALID: Format = Format(consts::DRM_FORMAT_INVALID);
+#[cfg(feature = "drm")]
+pub const XRGB8888: Format = Format(consts::DRM_FORMAT_XRGB8888);
 #[cfg(test)]
 pub const R8: Format = Format(consts::DRM_FORMAT_R8);
+#[cfg(test)]
+pub const NV12: Format = Format(consts::DRM_FORMAT_NV12);
+#[cfg(all(test, feature = "v4l2"))]
+pub const YUYV: Format = Format(consts::DRM_FORMAT_YUYV);
+
+// vendor-specific modifier bits for compression detection
+const INTEL_UNCOMPRESSED: [u64; 4] = [
+    1, // I915_FORMAT_MOD_X_TILED
+    2, // I915_FORMAT_MOD_Y_TILED
+    3, // I915_FORMAT_MOD_Yf_TILED
+    9, // I915_FORMAT_MOD_4_TILED
+];
+const AMD_DCC: u64 = 1 << 13;
+const NVIDIA_BLOCK_LINEAR_2D: u64 = 0x10;
+const NVIDIA_COMPRESSION_MASK: u64 = 0x7 << 23;
+const QCOM_COMPRESSED: u64 = 1;
+const VIVANTE_TS_COMP_MASK: u64 = 0xff << 48;
+const ARM_TYPE_MISC: u64 = 1;
+
+// DRM_FORMAT_BIG_ENDIAN is not supported and is repurposed to mark external formats.  A format is
+// external only when the other bits are a valid VkFormat.  Fourccs consist of printable
+// characters and never fall in VK_FORMAT_RANGES, so big-endian fourccs stay DRM formats.
+const EXTERNAL_FORMAT_BIT: u32 = 1 << 31;
+
+// the inclusive ranges of valid VkFormat values, excluding VK_FORMAT_UNDEFINED
+const VK_FORMAT_RANGES: [(u32, u32); 8] = [
+    (1, 184),                 // core 1.0
+    (1000054000, 1000054007), // VK_IMG_format_pvrtc
+    (1000066000, 1000066013), // VK_EXT_texture_compression_astc_hdr
+    (1000156000, 1000156033), // VK_KHR_sampler_ycbcr_conversion
+    (1000330000, 1000330003), // VK_EXT_ycbcr_2plane_444_formats
+    (1000340000, 1000340001), // VK_EXT_4444_formats
+    (1000464000, 1000464000), // VK_NV_optical_flow
+    (1000470000, 1000470001), // VK_KHR_maintenance5
+];
 
 pub const MOD_INVALID: Modifier = Modifier(consts::DRM_FORMAT_MOD_INVALID);
 pub const MOD_LINEAR: Modifier = Modifier(consts::DRM_FORMAT_MOD_LINEAR);
 
-pub const KNOWN_FORMATS: [Format; 24] = [
+#[cfg(any(feature = "ash", test))]
+pub const KNOWN_FORMATS: [Format; 27] = [
     Format(consts::DRM_FORMAT_R8),
     Format(consts::DRM_FORMAT_BGR565),
     Format(consts::DRM_FORMAT_RGB565),
     Format(consts::DRM_FORMAT_GR88),
+    Format(consts::DRM_FORMAT_R10),
+    Format(consts::DRM_FORMAT_R12),
     Format(consts::DRM_FORMAT_R16),
+    Format(consts::DRM_FORMAT_GR1616),
     Format(consts::DRM_FORMAT_BGR888),
     Format(consts::DRM_FORMAT_RGB888),
     Format(consts::DRM_FORMAT_ABGR8888),
@@ -108,7 +168,10 @@ pub fn name(fmt: Format) -> Option<&'static str> {
         consts::DRM_FORMAT_BGR565 => "BGR565",
         consts::DRM_FORMAT_RGB565 => "RGB565",
         consts::DRM_FORMAT_GR88 => "GR88",
+        consts::DRM_FORMAT_R10 => "R10",
+        consts::DRM_FORMAT_R12 => "R12",
         consts::DRM_FORMAT_R16 => "R16",
+        consts::DRM_FORMAT_GR1616 => "GR1616",
         consts::DRM_FORMAT_BGR888 => "BGR888",
         consts::DRM_FORMAT_RGB888 => "RGB888",
         consts::DRM_FORMAT_ABGR8888 => "ABGR8888",
@@ -142,6 +205,45 @@ pub struct FormatClass {
     pub block_extent: [(u8, u8); 3],
 }
 
+fn is_vk_format(raw: u32) -> bool {
+    VK_FORMAT_RANGES
+        .iter()
+        .any(|&(first, last)| (first..=last).contains(&raw))
+}
+
+#[cfg(feature = "ash")]
+pub fn external_format(raw: u32) -> Option<Format> {
+    is_vk_format(raw).then_some(Format(EXTERNAL_FORMAT_BIT | raw))
+}
+
+pub fn is_external(fmt: Format) -> bool {
+    fmt.0 & EXTERNAL_FORMAT_BIT != 0 && is_vk_format(fmt.0 & !EXTERNAL_FORMAT_BIT)
+}
+
+// the format class of an external format, whose block sizes are unknown
+#[cfg(feature = "ash")]
+pub fn external_format_class(plane_count: u8) -> &'static FormatClass {
+    const EXTERNAL_FORMAT_CLASSES: [FormatClass; 3] = [
+        FormatClass {
+            plane_count: 1,
+            block_size: [0, 0, 0],
+            block_extent: [(1, 1), (1, 1), (1, 1)],
+        },
+        FormatClass {
+            plane_count: 2,
+            block_size: [0, 0, 0],
+            block_extent: [(1, 1), (1, 1), (1, 1)],
+        },
+        FormatClass {
+            plane_count: 3,
+            block_size: [0, 0, 0],
+            block_extent: [(1, 1), (1, 1), (1, 1)],
+        },
+    ];
+
+    &EXTERNAL_FORMAT_CLASSES[plane_count.clamp(1, 3) as usize - 1]
+}
+
 pub fn format_class(fmt: Format) -> Result<&'static FormatClass> {
     // this follows Vulkan format compatibility classes
     const FORMAT_CLASS_1B: FormatClass = FormatClass {
@@ -189,12 +291,15 @@ pub fn format_class(fmt: Format) -> Result<&'static FormatClass> {
         consts::DRM_FORMAT_BGR565
         | consts::DRM_FORMAT_RGB565
         | consts::DRM_FORMAT_GR88
+        | consts::DRM_FORMAT_R10
+        | consts::DRM_FORMAT_R12
         | consts::DRM_FORMAT_R16 => &FORMAT_CLASS_2B,
         consts::DRM_FORMAT_BGR888 | consts::DRM_FORMAT_RGB888 => &FORMAT_CLASS_3B,
         consts::DRM_FORMAT_ABGR8888
         | consts::DRM_FORMAT_XBGR8888
         | consts::DRM_FORMAT_ARGB8888
         | consts::DRM_FORMAT_XRGB8888
+        | consts::DRM_FORMAT_GR1616
         | consts::DRM_FORMAT_ABGR2101010
         | consts::DRM_FORMAT_XBGR2101010
         | consts::DRM_FORMAT_ARGB2101010
@@ -214,9 +319,14 @@ pub fn packed_layout(
     fmt: Format,
     width: u32,
     height: u32,
+    depth: u32,
     con: Option<Constraint>,
 ) -> Result<Layout> {
     let fmt_class = format_class(fmt)?;
+    // 3D images must have single-plane formats
+    if depth > 1 && fmt_class.plane_count > 1 {
+        return Error::unsupported();
+    }
 
     let mut layout = Layout::new()
         .modifier(MOD_LINEAR)
@@ -236,11 +346,15 @@ pub fn packed_layout(
         let mut stride = width * bs;
         stride = stride.next_multiple_of(stride_align);
 
-        let mut size = stride * height;
+        let slice_size = stride * height;
+        let mut size = slice_size * depth as Size;
         size = size.next_multiple_of(size_align);
 
         layout.offsets[plane] = offset;
         layout.strides[plane] = stride;
+        if depth > 1 {
+            layout.slice_stride = slice_size;
+        }
         offset += size;
     }
 
@@ -249,15 +363,15 @@ pub fn packed_layout(
     Ok(layout)
 }
 
-#[cfg(feature = "ash")]
-pub enum Swizzle {
-    None,
-    Rgb1,
-    Bgra,
-}
-
+// Returns the Vulkan format of a DRM format, and the swizzle to view the DRM format as the Vulkan
+// format.
 #[cfg(feature = "ash")]
 pub fn to_vk(fmt: Format) -> Result<(vk::Format, Swizzle)> {
+    if is_external(fmt) {
+        let raw = (fmt.0 & !EXTERNAL_FORMAT_BIT) as i32;
+        return Ok((vk::Format::from_raw(raw), Swizzle::None));
+    }
+
     let mapped = match fmt.0 {
         consts::DRM_FORMAT_R8 => (vk::Format::R8_UNORM, Swizzle::None),
         consts::DRM_FORMAT_BGR565 => {
@@ -275,7 +389,10 @@ pub fn to_vk(fmt: Format) -> Result<(vk::Format, Swizzle)> {
             }
         }
         consts::DRM_FORMAT_GR88 => (vk::Format::R8G8_UNORM, Swizzle::None),
+        // R10 and R12 have the padding in the MSBs, unlike R10X6 and R12X4
+        consts::DRM_FORMAT_R10 | consts::DRM_FORMAT_R12 => (vk::Format::UNDEFINED, Swizzle::None),
         consts::DRM_FORMAT_R16 => (vk::Format::R16_UNORM, Swizzle::None),
+        consts::DRM_FORMAT_GR1616 => (vk::Format::R16G16_UNORM, Swizzle::None),
         consts::DRM_FORMAT_BGR888 => (vk::Format::R8G8B8_UNORM, Swizzle::None),
         consts::DRM_FORMAT_RGB888 => (vk::Format::B8G8R8_UNORM, Swizzle::None),
         consts::DRM_FORMAT_ABGR8888 => (vk::Format::R8G8B8A8_UNORM, Swizzle::None),
@@ -332,6 +449,176 @@ pub fn to_vk(fmt: Format) -> Result<(vk::Format, Swizzle)> {
     }
 }
 
+/// Returns the compression and bandwidth hint of a modifier.
+///
+/// This is derived from the vendor encodings in `drm_fourcc.h` and can be refined by backends.
+pub fn modifier_hint(modifier: Modifier) -> ModifierHint {
+    let (compressed, bandwidth) = if modifier.is_linear() {
+        (false, Some(Bandwidth::High))
+    } else if modifier.is_invalid() {
+        (false, None)
+    } else {
+        let vendor = modifier.0 >> 56;
+        let val = modifier.0 & ((1 << 56) - 1);
+        let compressed = match vendor {
+            consts::DRM_FORMAT_MOD_VENDOR_INTEL => Some(!INTEL_UNCOMPRESSED.contains(&val)),
+            consts::DRM_FORMAT_MOD_VENDOR_AMD => Some(val & AMD_DCC != 0),
+            consts::DRM_FORMAT_MOD_VENDOR_NVIDIA => {
+                Some(val & NVIDIA_BLOCK_LINEAR_2D != 0 && val & NVIDIA_COMPRESSION_MASK != 0)
+            }
+            consts::DRM_FORMAT_MOD_VENDOR_QCOM => Some(val == QCOM_COMPRESSED),
+            consts::DRM_FORMAT_MOD_VENDOR_VIVANTE => Some(val & VIVANTE_TS_COMP_MASK != 0),
+            // AFBC and AFRC are compressed
+            consts::DRM_FORMAT_MOD_VENDOR_ARM => Some((val >> 52) != ARM_TYPE_MISC),
+            consts::DRM_FORMAT_MOD_VENDOR_AMLOGIC => Some(true),
+            consts::DRM_FORMAT_MOD_VENDOR_SAMSUNG
+            | consts::DRM_FORMAT_MOD_VENDOR_BROADCOM
+            | consts::DRM_FORMAT_MOD_VENDOR_ALLWINNER => Some(false),
+            _ => None,
+        };
+
+        match compressed {
+            Some(true) => (true, Some(Bandwidth::Low)),
+            Some(false) => (false, Some(Bandwidth::Medium)),
+            None => (false, None),
+        }
+    };
+
+    ModifierHint {
+        modifier,
+        compressed,
+        bandwidth,
+    }
+}
+
+/// Returns the known format that maps to a Vulkan format.
+///
+/// `None` is returned if no known format maps to the Vulkan format.  When several known formats
+/// map to the same Vulkan format, the first one in `KNOWN_FORMATS` is returned.  The reverse
+/// mapping is built on first use.
+#[cfg(feature = "ash")]
+pub fn from_vk(fmt: vk::Format) -> Option<Format> {
+    static REVERSE_MAP: OnceLock<HashMap<vk::Format, Format>> = OnceLock::new();
+
+    let map = REVERSE_MAP.get_or_init(|| {
+        let mut map = HashMap::new();
+        for drm_fmt in KNOWN_FORMATS {
+            if let Ok((vk_fmt, _)) = to_vk(drm_fmt) {
+                map.entry(vk_fmt).or_insert(drm_fmt);
+            }
+        }
+        map
+    });
+
+    map.get(&fmt).copied()
+}
+
+/// Returns the per-plane formats of a YCbCr format.
+///
+/// `None` is returned if the format does not require a sampler YCbCr conversion.
+#[cfg(feature = "ash")]
+pub fn vk_plane_formats(fmt: vk::Format) -> Option<&'static [vk::Format]> {
+    let plane_fmts: &[vk::Format] = match fmt {
+        vk::Format::G8B8G8R8_422_UNORM => &[vk::Format::G8B8G8R8_422_UNORM],
+        vk::Format::B8G8R8G8_422_UNORM => &[vk::Format::B8G8R8G8_422_UNORM],
+        vk::Format::G8_B8R8_2PLANE_420_UNORM => &[vk::Format::R8_UNORM, vk::Format::R8G8_UNORM],
+        vk::Format::G10X6_B10X6R10X6_2PLANE_420_UNORM_3PACK16 => &[
+            vk::Format::R10X6_UNORM_PACK16,
+            vk::Format::R10X6G10X6_UNORM_2PACK16,
+        ],
+        vk::Format::G16_B16R16_2PLANE_420_UNORM => {
+            &[vk::Format::R16_UNORM, vk::Format::R16G16_UNORM]
+        }
+        vk::Format::G8_B8_R8_3PLANE_420_UNORM => &[
+            vk::Format::R8_UNORM,
+            vk::Format::R8_UNORM,
+            vk::Format::R8_UNORM,
+        ],
+        _ => return None,
+    };
+
+    Some(plane_fmts)
+}
+
+/// Returns the sRGB format of a UNORM format.
+///
+/// `None` is returned if the format has no sRGB equivalent.
+#[cfg(feature = "ash")]
+pub fn vk_srgb_format(fmt: vk::Format) -> Option<vk::Format> {
+    let srgb_fmt = match fmt {
+        vk::Format::R8_UNORM => vk::Format::R8_SRGB,
+        vk::Format::R8G8_UNORM => vk::Format::R8G8_SRGB,
+        vk::Format::R8G8B8_UNORM => vk::Format::R8G8B8_SRGB,
+        vk::Format::B8G8R8_UNORM => vk::Format::B8G8R8_SRGB,
+        vk::Format::R8G8B8A8_UNORM => vk::Format::R8G8B8A8_SRGB,
+        vk::Format::B8G8R8A8_UNORM => vk::Format::B8G8R8A8_SRGB,
+        _ => return None,
+    };
+
+    Some(srgb_fmt)
+}
+
+/// The numeric type of the components of a Vulkan format.
+///
+/// Normalized, scaled, and sRGB formats are accessed as floats.
+#[cfg(feature = "ash")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumericType {
+    Float,
+    Uint,
+    Sint,
+}
+
+/// Returns the numeric type of a color format.
+#[cfg(feature = "ash")]
+pub fn vk_numeric_type(fmt: vk::Format) -> NumericType {
+    match fmt {
+        vk::Format::R8_UINT
+        | vk::Format::R8G8_UINT
+        | vk::Format::R8G8B8_UINT
+        | vk::Format::B8G8R8_UINT
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::B8G8R8A8_UINT
+        | vk::Format::A8B8G8R8_UINT_PACK32
+        | vk::Format::A2R10G10B10_UINT_PACK32
+        | vk::Format::A2B10G10R10_UINT_PACK32
+        | vk::Format::R16_UINT
+        | vk::Format::R16G16_UINT
+        | vk::Format::R16G16B16_UINT
+        | vk::Format::R16G16B16A16_UINT
+        | vk::Format::R32_UINT
+        | vk::Format::R32G32_UINT
+        | vk::Format::R32G32B32_UINT
+        | vk::Format::R32G32B32A32_UINT
+        | vk::Format::R64_UINT
+        | vk::Format::R64G64_UINT
+        | vk::Format::R64G64B64_UINT
+        | vk::Format::R64G64B64A64_UINT => NumericType::Uint,
+        vk::Format::R8_SINT
+        | vk::Format::R8G8_SINT
+        | vk::Format::R8G8B8_SINT
+        | vk::Format::B8G8R8_SINT
+        | vk::Format::R8G8B8A8_SINT
+        | vk::Format::B8G8R8A8_SINT
+        | vk::Format::A8B8G8R8_SINT_PACK32
+        | vk::Format::A2R10G10B10_SINT_PACK32
+        | vk::Format::A2B10G10R10_SINT_PACK32
+        | vk::Format::R16_SINT
+        | vk::Format::R16G16_SINT
+        | vk::Format::R16G16B16_SINT
+        | vk::Format::R16G16B16A16_SINT
+        | vk::Format::R32_SINT
+        | vk::Format::R32G32_SINT
+        | vk::Format::R32G32B32_SINT
+        | vk::Format::R32G32B32A32_SINT
+        | vk::Format::R64_SINT
+        | vk::Format::R64G64_SINT
+        | vk::Format::R64G64B64_SINT
+        | vk::Format::R64G64B64A64_SINT => NumericType::Sint,
+        _ => NumericType::Float,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -344,6 +631,35 @@ mod tests {
         assert_eq!(consts::DRM_FORMAT_MOD_LINEAR, 0);
     }
 
+    #[test]
+    fn test_modifier_hint() {
+        let hint = super::modifier_hint(MOD_LINEAR);
+        assert!(!hint.compressed);
+        assert_eq!(hint.bandwidth, Some(Bandwidth::High));
+        assert_eq!(super::modifier_hint(MOD_INVALID).bandwidth, None);
+
+        // I915_FORMAT_MOD_Y_TILED and I915_FORMAT_MOD_Y_TILED_GEN12_RC_CCS
+        let hint = super::modifier_hint(Modifier((1 << 56) | 2));
+        assert!(!hint.compressed);
+        assert_eq!(hint.bandwidth, Some(Bandwidth::Medium));
+        let hint = super::modifier_hint(Modifier((1 << 56) | 6));
+        assert!(hint.compressed);
+        assert_eq!(hint.bandwidth, Some(Bandwidth::Low));
+
+        // AMD GFX9 64K_S with and without DCC
+        let amd = (2 << 56) | (1 << 8) | 9;
+        assert!(!super::modifier_hint(Modifier(amd)).compressed);
+        assert!(super::modifier_hint(Modifier(amd | AMD_DCC)).compressed);
+
+        // AFBC and ARM 16x16 block u-interleaved
+        assert!(super::modifier_hint(Modifier((8 << 56) | 1)).compressed);
+        assert!(!super::modifier_hint(Modifier((8 << 56) | (1 << 52) | 1)).compressed);
+
+        let hint = super::modifier_hint(Modifier(0xff << 56 | 1));
+        assert!(!hint.compressed);
+        assert_eq!(hint.bandwidth, None);
+    }
+
     #[test]
     fn test_fourcc() {
         assert_eq!(super::fourcc(R8), String::from("'R8  '"));
@@ -375,13 +691,29 @@ mod tests {
             .modifier(MOD_LINEAR)
             .plane_count(1)
             .stride(0, w as Size);
-        assert_eq!(super::packed_layout(R8, w, h, None).unwrap(), layout);
+        assert_eq!(super::packed_layout(R8, w, h, 1, None).unwrap(), layout);
 
         let stride = 16;
         let con = Constraint::new().stride_align(stride);
         layout.size = stride * (h as Size);
         layout.strides[0] = stride;
-        assert_eq!(super::packed_layout(R8, w, h, Some(con)).unwrap(), layout);
+        assert_eq!(
+            super::packed_layout(R8, w, h, 1, Some(con)).unwrap(),
+            layout
+        );
+
+        // 10-bit and 12-bit formats are padded to 16 bits
+        for fmt in [consts::DRM_FORMAT_R10, consts::DRM_FORMAT_R12] {
+            let layout = super::packed_layout(Format(fmt), w, h, 1, None).unwrap();
+            assert_eq!(layout.strides[0], (w * 2) as Size);
+            assert_eq!(layout.size, (w * h * 2) as Size);
+        }
+
+        // depth slices are packed
+        let layout = super::packed_layout(R8, w, h, 4, None).unwrap();
+        assert_eq!(layout.slice_stride, (w * h) as Size);
+        assert_eq!(layout.size, (w * h * 4) as Size);
+        assert!(super::packed_layout(NV12, w, h, 4, None).is_err());
     }
 
     #[cfg(feature = "ash")]
@@ -389,8 +721,104 @@ mod tests {
     fn test_to_vk() {
         #[cfg(target_endian = "little")]
         for fmt in KNOWN_FORMATS {
+            if matches!(fmt.0, consts::DRM_FORMAT_R10 | consts::DRM_FORMAT_R12) {
+                assert!(super::to_vk(fmt).is_err());
+                continue;
+            }
+
             let (vk_fmt, _) = super::to_vk(fmt).unwrap();
             assert_ne!(vk_fmt, vk::Format::UNDEFINED);
+
+            // per-plane formats are for YCbCr formats and match the plane count
+            let fmt_class = super::format_class(fmt).unwrap();
+            let is_yuv = matches!(
+                fmt.0,
+                consts::DRM_FORMAT_YUYV
+                    | consts::DRM_FORMAT_UYVY
+                    | consts::DRM_FORMAT_NV12
+                    | consts::DRM_FORMAT_NV21
+                    | consts::DRM_FORMAT_P010
+                    | consts::DRM_FORMAT_P016
+                    | consts::DRM_FORMAT_YUV420
+                    | consts::DRM_FORMAT_YVU420
+            );
+            match super::vk_plane_formats(vk_fmt) {
+                Some(plane_fmts) => {
+                    assert!(is_yuv);
+                    assert_eq!(plane_fmts.len(), fmt_class.plane_count as usize);
+                }
+                None => assert!(!is_yuv),
+            }
+        }
+
+        let (_, swizzle) = super::to_vk(Format(consts::DRM_FORMAT_XRGB8888)).unwrap();
+        assert_eq!(swizzle, Swizzle::Rgb1);
+        let (_, swizzle) = super::to_vk(Format(consts::DRM_FORMAT_NV21)).unwrap();
+        assert_eq!(swizzle, Swizzle::Bgra);
+        let (_, swizzle) = super::to_vk(Format(consts::DRM_FORMAT_NV12)).unwrap();
+        assert_eq!(swizzle, Swizzle::None);
+    }
+
+    #[cfg(feature = "ash")]
+    #[test]
+    fn test_from_vk() {
+        for fmt in KNOWN_FORMATS {
+            let Ok((vk_fmt, _)) = super::to_vk(fmt) else {
+                continue;
+            };
+
+            let drm_fmt = super::from_vk(vk_fmt).unwrap();
+            assert_eq!(super::to_vk(drm_fmt).unwrap().0, vk_fmt);
+        }
+
+        assert_eq!(super::from_vk(vk::Format::R8_UNORM), Some(R8));
+        assert_eq!(super::from_vk(vk::Format::UNDEFINED), None);
+    }
+
+    #[cfg(feature = "ash")]
+    #[test]
+    fn test_vk_srgb_format() {
+        let (vk_fmt, _) = super::to_vk(Format(consts::DRM_FORMAT_XRGB8888)).unwrap();
+        assert_eq!(vk_srgb_format(vk_fmt), Some(vk::Format::B8G8R8A8_SRGB));
+
+        let (vk_fmt, _) = super::to_vk(Format(consts::DRM_FORMAT_ABGR16161616F)).unwrap();
+        assert_eq!(vk_srgb_format(vk_fmt), None);
+        assert_eq!(vk_srgb_format(vk::Format::R8G8B8A8_SRGB), None);
+    }
+
+    #[cfg(feature = "ash")]
+    #[test]
+    fn test_vk_numeric_type() {
+        assert_eq!(vk_numeric_type(vk::Format::R8_UNORM), NumericType::Float);
+        assert_eq!(
+            vk_numeric_type(vk::Format::R8G8B8A8_SRGB),
+            NumericType::Float
+        );
+        assert_eq!(vk_numeric_type(vk::Format::R16G16_UINT), NumericType::Uint);
+        assert_eq!(vk_numeric_type(vk::Format::R32_SINT), NumericType::Sint);
+    }
+
+    #[cfg(feature = "ash")]
+    #[test]
+    fn test_external_format() {
+        let raw = vk::Format::G10X6_B10X6R10X6_2PLANE_422_UNORM_3PACK16.as_raw();
+        let fmt = external_format(raw as u32).unwrap();
+        assert!(is_external(fmt));
+        assert!(format_class(fmt).is_err());
+        assert_eq!(to_vk(fmt).unwrap().0.as_raw(), raw);
+
+        for fmt in KNOWN_FORMATS {
+            assert!(!is_external(fmt));
+            // big-endian fourccs are not external formats
+            let big_endian = Format(fmt.0 | EXTERNAL_FORMAT_BIT);
+            assert!(!is_external(big_endian));
+            assert!(to_vk(big_endian).is_err());
         }
+        assert!(external_format(0).is_none());
+        assert!(external_format(185).is_none());
+        assert!(
+            external_format(vk::Format::R8_UNORM.as_raw() as u32 | EXTERNAL_FORMAT_BIT).is_none()
+        );
+        assert_eq!(external_format_class(2).plane_count, 2);
     }
 }
@@ -13,15 +13,21 @@
 //! physical layout and the supported memory types.  The bind step creates or imports a memory, and
 //! binds the memory to the BO.
 
+#[cfg(feature = "android")]
+pub mod android;
 mod backends;
 mod bo;
 mod device;
 mod dma_buf;
 mod formats;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 #[cfg(feature = "ash")]
 mod sash;
 mod types;
 mod utils;
+#[cfg(feature = "v4l2")]
+pub mod v4l2;
 
 pub use backends::*;
 pub use bo::*;
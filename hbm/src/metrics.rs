@@ -0,0 +1,267 @@
+// Copyright 2024 Google LLC
+// SPDX-License-Identifier: MIT
+
+//! Device metrics reporting.
+//!
+//! This module serializes `Device::stats` in standard metrics text formats.  A `Reporter`
+//! periodically writes the serialized stats to a sink, such as a file collected by a Prometheus
+//! node exporter or a UDP socket connected to a statsd daemon.
+
+use super::device::{Device, Stats, COPY_LATENCY_BUCKETS};
+use super::types::{Error, Result};
+use std::fmt::Write;
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A metrics text format.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The Prometheus text exposition format.
+    #[default]
+    Prometheus,
+    /// The statsd line protocol.
+    ///
+    /// All metrics are reported as gauges because the stats are cumulative.
+    Statsd,
+}
+
+/// Serializes device stats in the Prometheus text exposition format.
+///
+/// Metric names are prefixed by `prefix` and an underscore.
+pub fn prometheus(stats: &Stats, prefix: &str) -> String {
+    let mut out = String::new();
+
+    let mut metric = |name: &str, ty: &str, val: u64| {
+        let _ = writeln!(out, "# TYPE {prefix}_{name} {ty}");
+        let _ = writeln!(out, "{prefix}_{name} {val}");
+    };
+    metric("allocations_total", "counter", stats.allocation_count);
+    metric(
+        "allocation_failures_total",
+        "counter",
+        stats.allocation_failure_count,
+    );
+    metric("allocated_bytes", "gauge", stats.allocated_size);
+    metric("slab_bytes", "gauge", stats.slab_size);
+
+    let name = format!("{prefix}_copy_latency_seconds");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    let mut count = 0;
+    for (idx, bucket_count) in stats.copy_latency_counts.iter().enumerate() {
+        count += bucket_count;
+        let le = match COPY_LATENCY_BUCKETS.get(idx) {
+            Some(&bound) => (bound as f64 / 1e6).to_string(),
+            None => String::from("+Inf"),
+        };
+        let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {count}");
+    }
+    let _ = writeln!(out, "{name}_sum {}", stats.copy_latency_sum as f64 / 1e6);
+    let _ = writeln!(out, "{name}_count {count}");
+
+    out
+}
+
+/// Serializes device stats in the statsd line protocol.
+///
+/// Metric names are prefixed by `prefix` and a dot.
+pub fn statsd(stats: &Stats, prefix: &str) -> String {
+    let mut out = String::new();
+
+    let mut gauge = |name: &str, val: u64| {
+        let _ = writeln!(out, "{prefix}.{name}:{val}|g");
+    };
+    gauge("allocations", stats.allocation_count);
+    gauge("allocation_failures", stats.allocation_failure_count);
+    gauge("allocated_bytes", stats.allocated_size);
+    gauge("slab_bytes", stats.slab_size);
+
+    for (idx, &bucket_count) in stats.copy_latency_counts.iter().enumerate() {
+        let name = match COPY_LATENCY_BUCKETS.get(idx) {
+            Some(bound) => format!("copy_latency.le_{bound}us"),
+            None => String::from("copy_latency.le_inf"),
+        };
+        gauge(&name, bucket_count);
+    }
+    gauge("copy_latency.sum_us", stats.copy_latency_sum);
+    gauge("copy_latency.count", stats.copy_latency_counts.iter().sum());
+
+    out
+}
+
+/// A metrics reporter.
+///
+/// A reporter owns a thread that periodically serializes the stats of a device and passes them
+/// to a sink.  The thread is stopped when the reporter is dropped.
+pub struct Reporter {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        // disconnect the channel to wake up the thread
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A metrics reporter builder.
+pub struct Builder {
+    format: Format,
+    prefix: String,
+    interval: Duration,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            format: Format::default(),
+            prefix: String::from("hbm"),
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Builder {
+    /// Creates a metrics reporter builder.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the metrics text format.
+    ///
+    /// The default is `Format::Prometheus`.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the prefix of the metric names.
+    ///
+    /// The default is "hbm".
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = String::from(prefix);
+        self
+    }
+
+    /// Sets the reporting interval.
+    ///
+    /// The default is 60 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Builds a `Reporter`.
+    ///
+    /// `sink` is called with the serialized stats of `device` once per interval.  Sink errors are
+    /// logged and do not stop the reporter.
+    pub fn build<F>(self, device: Arc<Device>, mut sink: F) -> Result<Reporter>
+    where
+        F: FnMut(&str) -> std::io::Result<()> + Send + 'static,
+    {
+        if self.prefix.is_empty() || self.interval.is_zero() {
+            return Error::user();
+        }
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name(String::from("hbm-metrics"))
+            .spawn(move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(self.interval)
+                {
+                    let stats = device.stats();
+                    let text = match self.format {
+                        Format::Prometheus => prometheus(&stats, &self.prefix),
+                        Format::Statsd => statsd(&stats, &self.prefix),
+                    };
+                    if let Err(err) = sink(&text) {
+                        log::warn!("failed to report metrics: {}", err);
+                    }
+                }
+            })?;
+
+        Ok(Reporter {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_stats() -> Stats {
+        let mut stats = Stats {
+            allocation_count: 3,
+            allocation_failure_count: 1,
+            allocated_size: 4096,
+            slab_size: 1024,
+            copy_latency_sum: 350,
+            ..Default::default()
+        };
+        stats.copy_latency_counts[0] = 2;
+        stats.copy_latency_counts[1] = 1;
+
+        stats
+    }
+
+    #[test]
+    fn test_prometheus() {
+        let text = prometheus(&test_stats(), "hbm");
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert!(lines.contains(&"# TYPE hbm_allocations_total counter"));
+        assert!(lines.contains(&"hbm_allocations_total 3"));
+        assert!(lines.contains(&"hbm_allocation_failures_total 1"));
+        assert!(lines.contains(&"hbm_allocated_bytes 4096"));
+        assert!(lines.contains(&"hbm_slab_bytes 1024"));
+        assert!(lines.contains(&"hbm_copy_latency_seconds_bucket{le=\"0.0001\"} 2"));
+        assert!(lines.contains(&"hbm_copy_latency_seconds_bucket{le=\"0.00025\"} 3"));
+        assert!(lines.contains(&"hbm_copy_latency_seconds_bucket{le=\"+Inf\"} 3"));
+        assert!(lines.contains(&"hbm_copy_latency_seconds_sum 0.00035"));
+        assert!(lines.contains(&"hbm_copy_latency_seconds_count 3"));
+    }
+
+    #[test]
+    fn test_statsd() {
+        let text = statsd(&test_stats(), "gralloc");
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert!(lines.contains(&"gralloc.allocations:3|g"));
+        assert!(lines.contains(&"gralloc.allocated_bytes:4096|g"));
+        assert!(lines.contains(&"gralloc.slab_bytes:1024|g"));
+        assert!(lines.contains(&"gralloc.copy_latency.le_100us:2|g"));
+        assert!(lines.contains(&"gralloc.copy_latency.le_inf:0|g"));
+        assert!(lines.contains(&"gralloc.copy_latency.count:3|g"));
+    }
+
+    #[test]
+    fn test_reporter() {
+        struct NullBackend;
+        impl crate::Backend for NullBackend {}
+
+        let dev = crate::Builder::new()
+            .add_backend(NullBackend)
+            .build()
+            .unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let reporter = Builder::new()
+            .format(Format::Statsd)
+            .interval(Duration::from_millis(1))
+            .build(dev, move |text| {
+                let _ = tx.send(String::from(text));
+                Ok(())
+            })
+            .unwrap();
+
+        let text = rx.recv().unwrap();
+        assert!(text.starts_with("hbm.allocations:0|g\n"));
+        drop(reporter);
+    }
+}
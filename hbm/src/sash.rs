@@ -5,28 +5,56 @@
 //!
 //! This module provides a safe allocator using ash.
 
-use super::backends::{Constraint, CopyBufferImage, Layout};
+use super::backends::{
+    Constraint, CopyBufferImage, CopyImage, DeviceInfo, Features, Filter, Layout, MemoryHeap, Rect,
+    Swizzle,
+};
 use super::formats;
-use super::types::{Error, Modifier, Result};
+use super::types::{Access, Error, Format, Modifier, Result};
 use super::utils;
 use ash::vk;
-use std::collections::HashMap;
+use std::collections::{hash_map, HashMap, VecDeque};
+use std::fmt::Write;
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
-use std::sync::{atomic, Arc, Mutex};
-use std::{cmp, ffi, ptr, slice, thread};
+use std::path::{Path, PathBuf};
+use std::sync::{atomic, Arc, Mutex, OnceLock, RwLock, Weak};
+use std::time::Duration;
+use std::{ffi, fs, mem, process, ptr, slice};
 
 const REQUIRED_API_VERSION: u32 = vk::API_VERSION_1_1;
 
-// TODO VK_KHR_external_semaphore_fd
+// the max number of copies that can be pending before the oldest one is waited for
+const MAX_PENDING_COPIES: usize = 8;
+
+// the max number of queues that copies are submitted to
+const MAX_COPY_QUEUES: u32 = 4;
+
+// non-external buffers up to this size are suballocated from slabs
+const MAX_SUBALLOCATION_SIZE: vk::DeviceSize = 256 * 1024;
+
+// the size of a slab
+const SLAB_SIZE: vk::DeviceSize = 4 * 1024 * 1024;
+
 #[derive(Clone, Copy)]
 enum ExtId {
     KhrDriverProperties,
     KhrExternalMemoryFd,
+    KhrExternalSemaphoreFd,
+    KhrFormatFeatureFlags2,
     KhrImageFormatList,
     KhrMaintenance4,
+    KhrMapMemory2,
+    KhrSynchronization2,
+    ExtDeviceFault,
     ExtExternalMemoryDmaBuf,
+    ExtExternalMemoryHost,
+    ExtGlobalPriority,
+    ExtHostImageCopy,
     ExtImageCompressionControl,
     ExtImageDrmFormatModifier,
+    ExtMapMemoryPlaced,
+    ExtMemoryBudget,
+    ExtMemoryPriority,
     ExtPhysicalDeviceDrm,
     ExtQueueFamilyForeign,
     Count,
@@ -36,15 +64,31 @@ enum ExtId {
 const EXT_TABLE: [(ExtId, &ffi::CStr, bool); ExtId::Count as usize] = [
     (ExtId::KhrDriverProperties,        ash::khr::driver_properties::NAME,          false),
     (ExtId::KhrExternalMemoryFd,        ash::khr::external_memory_fd::NAME,         true),
+    (ExtId::KhrExternalSemaphoreFd,     ash::khr::external_semaphore_fd::NAME,      false),
+    (ExtId::KhrFormatFeatureFlags2,     ash::khr::format_feature_flags2::NAME,      false),
     (ExtId::KhrImageFormatList,         ash::khr::image_format_list::NAME,          false),
     (ExtId::KhrMaintenance4,            ash::khr::maintenance4::NAME,               true),
+    (ExtId::KhrMapMemory2,              ash::khr::map_memory2::NAME,                false),
+    (ExtId::KhrSynchronization2,        ash::khr::synchronization2::NAME,           false),
+    (ExtId::ExtDeviceFault,             ash::ext::device_fault::NAME,               false),
     (ExtId::ExtExternalMemoryDmaBuf,    ash::ext::external_memory_dma_buf::NAME,    true),
+    (ExtId::ExtExternalMemoryHost,      ash::ext::external_memory_host::NAME,       false),
+    (ExtId::ExtGlobalPriority,          ash::ext::global_priority::NAME,            false),
+    (ExtId::ExtHostImageCopy,           ash::ext::host_image_copy::NAME,            false),
     (ExtId::ExtImageCompressionControl, ash::ext::image_compression_control::NAME,  false),
     (ExtId::ExtImageDrmFormatModifier,  ash::ext::image_drm_format_modifier::NAME,  false),
+    (ExtId::ExtMapMemoryPlaced,         ash::ext::map_memory_placed::NAME,          false),
+    (ExtId::ExtMemoryBudget,            ash::ext::memory_budget::NAME,              false),
+    (ExtId::ExtMemoryPriority,          ash::ext::memory_priority::NAME,            false),
     (ExtId::ExtPhysicalDeviceDrm,       ash::ext::physical_device_drm::NAME,        false),
-    (ExtId::ExtQueueFamilyForeign,      ash::ext::queue_family_foreign::NAME,       true),
+    (ExtId::ExtQueueFamilyForeign,      ash::ext::queue_family_foreign::NAME,       false),
 ];
 
+fn c_str_lossy(s: std::result::Result<&ffi::CStr, ffi::FromBytesUntilNulError>) -> String {
+    s.map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
 fn has_api_version(ver: u32) -> Result<()> {
     let req_major = vk::api_version_major(REQUIRED_API_VERSION);
     let req_minor = vk::api_version_minor(REQUIRED_API_VERSION);
@@ -74,19 +118,97 @@ fn has_device_id(props: vk::PhysicalDeviceDrmPropertiesEXT, dev_id: u64) -> Resu
     Error::unsupported()
 }
 
-fn can_export_import(props: vk::ExternalMemoryProperties) -> Result<()> {
+fn can_export_import(feats: vk::ExternalMemoryFeatureFlags) -> Result<()> {
     let flags =
         vk::ExternalMemoryFeatureFlags::EXPORTABLE | vk::ExternalMemoryFeatureFlags::IMPORTABLE;
-    if props.external_memory_features.contains(flags) {
+    if feats.contains(flags) {
         Ok(())
     } else {
         Error::unsupported()
     }
 }
 
+// the legacy format features are the lower 32 bits of the 64-bit ones
+fn format_features2(feats: vk::FormatFeatureFlags) -> vk::FormatFeatureFlags2 {
+    vk::FormatFeatureFlags2::from_raw(feats.as_raw() as u64)
+}
+
+// integer formats can only be blitted to and from integer formats of the same signedness
+fn is_blit_compatible(src_fmt: vk::Format, dst_fmt: vk::Format) -> bool {
+    formats::vk_numeric_type(src_fmt) == formats::vk_numeric_type(dst_fmt)
+}
+
+// returns the per-plane fixed-rate flags for VkImageCompressionControlEXT
+fn fixed_rates_for(
+    img_info: &ImageInfo,
+    compression: vk::ImageCompressionFlagsEXT,
+) -> Vec<vk::ImageCompressionFixedRateFlagsEXT> {
+    if compression != vk::ImageCompressionFlagsEXT::FIXED_RATE_EXPLICIT {
+        return Vec::new();
+    }
+
+    let plane_count = formats::vk_plane_formats(img_info.format).map_or(1, |fmts| fmts.len());
+    vec![img_info.fixed_rate; plane_count]
+}
+
+const DEBUG_SEVERITY_ALL: vk::DebugUtilsMessageSeverityFlagsEXT =
+    vk::DebugUtilsMessageSeverityFlagsEXT::from_raw(
+        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE.as_raw()
+            | vk::DebugUtilsMessageSeverityFlagsEXT::INFO.as_raw()
+            | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING.as_raw()
+            | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR.as_raw(),
+    );
+const DEBUG_TYPE_ALL: vk::DebugUtilsMessageTypeFlagsEXT =
+    vk::DebugUtilsMessageTypeFlagsEXT::from_raw(
+        vk::DebugUtilsMessageTypeFlagsEXT::GENERAL.as_raw()
+            | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION.as_raw()
+            | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE.as_raw(),
+    );
+
+// the debug messages to log, which can be adjusted at runtime because the messenger is created
+// with the instance and captures everything
+struct DebugFilter {
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    types: vk::DebugUtilsMessageTypeFlagsEXT,
+    suppressed_ids: Vec<String>,
+}
+
+impl DebugFilter {
+    fn allows(
+        &self,
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        types: vk::DebugUtilsMessageTypeFlagsEXT,
+        msg_id: Option<&str>,
+    ) -> bool {
+        self.severity.contains(severity)
+            && self.types.intersects(types)
+            && !msg_id.is_some_and(|msg_id| self.suppressed_ids.iter().any(|id| id == msg_id))
+    }
+}
+
+static DEBUG_FILTER: RwLock<DebugFilter> = RwLock::new(DebugFilter {
+    severity: DEBUG_SEVERITY_ALL,
+    types: DEBUG_TYPE_ALL,
+    suppressed_ids: Vec::new(),
+});
+
+// Sets the filter of debug_utils_messenger.  It applies to all instances.
+pub fn set_debug_filter(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    types: vk::DebugUtilsMessageTypeFlagsEXT,
+    suppressed_ids: Vec<String>,
+) {
+    let mut filter = DEBUG_FILTER.write().unwrap();
+    *filter = DebugFilter {
+        severity,
+        types,
+        suppressed_ids,
+    };
+}
+
 unsafe extern "system" fn debug_utils_messenger(
     severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    _types: vk::DebugUtilsMessageTypeFlagsEXT,
+    types: vk::DebugUtilsMessageTypeFlagsEXT,
     data: *const vk::DebugUtilsMessengerCallbackDataEXT,
     _user_data: *mut ffi::c_void,
 ) -> vk::Bool32 {
@@ -109,6 +231,10 @@ unsafe extern "system" fn debug_utils_messenger(
         None
     };
 
+    if !DEBUG_FILTER.read().unwrap().allows(severity, types, msg_id) {
+        return vk::FALSE;
+    }
+
     let msg = if !data.p_message.is_null() {
         // SAFETY: p_message is a valid utf8 c-string
         let cstr = unsafe { ffi::CStr::from_ptr(data.p_message) };
@@ -117,36 +243,68 @@ unsafe extern "system" fn debug_utils_messenger(
         None
     };
 
-    if msg_id.is_some() && msg.is_some() {
-        log::log!(lv, "vulkan: {}: {}", msg_id.unwrap(), msg.unwrap());
-    } else {
-        let msg = msg_id.or(msg);
-        if msg.is_some() {
-            log::log!(lv, "vulkan: {}", msg.unwrap());
-        }
+    match (msg_id, msg) {
+        (Some(msg_id), Some(msg)) => log::log!(lv, "vulkan: {}: {}", msg_id, msg),
+        (Some(msg), None) | (None, Some(msg)) => log::log!(lv, "vulkan: {}", msg),
+        (None, None) => (),
     }
 
     vk::FALSE
 }
 
+// live instances, keyed by app name and debug, that are shared by devices
+static SHARED_INSTANCES: Mutex<Vec<(String, bool, Weak<Instance>)>> = Mutex::new(Vec::new());
+
 struct Instance {
-    // unused, but it keeps the library loaded
-    _entry: ash::Entry,
+    // it keeps the library loaded, and is None when the instance is adopted and is not ours to
+    // destroy
+    entry: Option<ash::Entry>,
     handle: ash::Instance,
+    // VK_EXT_debug_utils is enabled
+    debug_utils: bool,
 }
 
 impl Instance {
     fn new(app_name: &str, debug: bool) -> Result<Self> {
         let entry = Self::create_entry()?;
-        let handle = Self::create_instance(&entry, app_name, debug)?;
+        let (handle, debug_utils) = Self::create_instance(&entry, app_name, debug)?;
         let instance = Self {
-            _entry: entry,
+            entry: Some(entry),
             handle,
+            debug_utils,
         };
 
         Ok(instance)
     }
 
+    // Returns a live instance with the same app name and debug if there is one, to avoid
+    // creating an instance and initializing the loader for each device.
+    fn shared(app_name: &str, debug: bool) -> Result<Arc<Self>> {
+        let mut shared = SHARED_INSTANCES.lock().unwrap();
+        shared.retain(|(_, _, instance)| instance.strong_count() > 0);
+
+        let instance = shared
+            .iter()
+            .find(|(name, dbg, _)| name == app_name && *dbg == debug)
+            .and_then(|(_, _, instance)| instance.upgrade());
+        if let Some(instance) = instance {
+            return Ok(instance);
+        }
+
+        let instance = Arc::new(Self::new(app_name, debug)?);
+        shared.push((app_name.to_string(), debug, Arc::downgrade(&instance)));
+
+        Ok(instance)
+    }
+
+    fn adopt(handle: ash::Instance) -> Self {
+        Self {
+            entry: None,
+            handle,
+            debug_utils: false,
+        }
+    }
+
     fn create_entry() -> Result<ash::Entry> {
         // SAFETY: we trust ash and the vulkan implementation
         let entry = unsafe { ash::Entry::load() }.or(Error::ctx("failed to load ash entry"))?;
@@ -172,7 +330,11 @@ impl Instance {
         }
     }
 
-    fn create_instance(entry: &ash::Entry, app_name: &str, debug: bool) -> Result<ash::Instance> {
+    fn create_instance(
+        entry: &ash::Entry,
+        app_name: &str,
+        debug: bool,
+    ) -> Result<(ash::Instance, bool)> {
         // SAFETY: no VUID violation
         let ver = unsafe { entry.try_enumerate_instance_version() }?;
 
@@ -192,16 +354,10 @@ impl Instance {
 
         let mut msg_info = vk::DebugUtilsMessengerCreateInfoEXT::default();
         if debug && !enabled_exts.is_empty() {
-            let msg_severity = vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
-            let msg_type = vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE;
+            // capture everything and let DEBUG_FILTER decide
             msg_info = msg_info
-                .message_severity(msg_severity)
-                .message_type(msg_type)
+                .message_severity(DEBUG_SEVERITY_ALL)
+                .message_type(DEBUG_TYPE_ALL)
                 .pfn_user_callback(Some(debug_utils_messenger));
 
             instance_info = instance_info
@@ -213,10 +369,14 @@ impl Instance {
         let handle = unsafe { entry.create_instance(&instance_info, None) }
             .or(Error::ctx("failed to create instance"))?;
 
-        Ok(handle)
+        Ok((handle, !enabled_exts.is_empty()))
     }
 
     fn destroy(&self) {
+        if self.entry.is_none() {
+            return;
+        }
+
         // SAFETY: no VUID violation
         unsafe {
             self.handle.destroy_instance(None);
@@ -230,39 +390,257 @@ impl Drop for Instance {
     }
 }
 
-#[derive(Default)]
+pub struct PhysicalDeviceInfo {
+    pub name: String,
+    pub driver_id: vk::DriverId,
+    pub primary_id: Option<u64>,
+    pub render_id: Option<u64>,
+    pub uuid: [u8; vk::UUID_SIZE],
+}
+
+impl PhysicalDeviceInfo {
+    fn new(instance: &Instance, handle: vk::PhysicalDevice) -> Result<Self> {
+        // SAFETY: no VUID violation
+        let exts = unsafe {
+            instance
+                .handle
+                .enumerate_device_extension_properties(handle)
+        }?;
+        let has_drm = exts.iter().any(|ext| {
+            // SAFETY: extension_name is a valid utf8 c-string
+            let ext_name = unsafe { ffi::CStr::from_ptr(ext.extension_name.as_ptr()) };
+            ext_name == ash::ext::physical_device_drm::NAME
+        });
+
+        let mut id_props = vk::PhysicalDeviceIDProperties::default();
+        let mut drv_props = vk::PhysicalDeviceDriverProperties::default();
+        let mut props = vk::PhysicalDeviceProperties2::default()
+            .push_next(&mut id_props)
+            .push_next(&mut drv_props);
+
+        let mut drm_props = vk::PhysicalDeviceDrmPropertiesEXT::default();
+        if has_drm {
+            props = props.push_next(&mut drm_props);
+        }
+
+        // SAFETY: no VUID violation
+        unsafe {
+            instance
+                .handle
+                .get_physical_device_properties2(handle, &mut props);
+        }
+
+        let name = props
+            .properties
+            .device_name_as_c_str()
+            .or(Error::ctx("invalid device name"))?
+            .to_string_lossy()
+            .into_owned();
+
+        let primary_id = (drm_props.has_primary > 0).then(|| {
+            utils::makedev(
+                drm_props.primary_major as u64,
+                drm_props.primary_minor as u64,
+            )
+        });
+        let render_id = (drm_props.has_render > 0)
+            .then(|| utils::makedev(drm_props.render_major as u64, drm_props.render_minor as u64));
+
+        let info = Self {
+            name,
+            driver_id: drv_props.driver_id,
+            primary_id,
+            render_id,
+            uuid: id_props.device_uuid,
+        };
+
+        Ok(info)
+    }
+}
+
+pub fn enumerate_physical_devices(name: &str) -> Result<Vec<PhysicalDeviceInfo>> {
+    let instance = Instance::shared(name, false)?;
+
+    // SAFETY: no VUID violation
+    let handles = unsafe { instance.handle.enumerate_physical_devices() }
+        .or(Error::ctx("failed to enumerate devices"))?;
+
+    handles
+        .into_iter()
+        .map(|handle| PhysicalDeviceInfo::new(&instance, handle))
+        .collect()
+}
+
+#[derive(Clone, Copy, Default)]
 struct DeviceCreateInfo {
     extensions: [bool; ExtId::Count as usize],
+    queue_priority: Option<vk::QueueGlobalPriorityKHR>,
 }
 
 struct FormatProperties {
     format_class: &'static formats::FormatClass,
-    modifiers: Vec<vk::DrmFormatModifierPropertiesEXT>,
+    modifiers: Vec<vk::DrmFormatModifierProperties2EXT>,
+}
+
+// the version of the probe cache file format
+const PROBE_CACHE_VERSION: u32 = 2;
+
+// returns the format class of a vk format that has a drm format equivalent
+fn known_format_class(fmt: vk::Format) -> Option<&'static formats::FormatClass> {
+    // some drm formats map to the same vk formats and share the format class
+    let drm_fmt = formats::from_vk(fmt)?;
+
+    formats::format_class(drm_fmt).ok()
+}
+
+// Serializes the modifiers of probed formats.  The first line identifies the cache by the version
+// and the key, and each following line has a format followed by "-" for unsupported formats or by
+// the modifiers, plane counts, and features of supported formats.
+fn serialize_probe_cache<'a, I>(key: &str, entries: I) -> String
+where
+    I: Iterator<
+        Item = (
+            vk::Format,
+            Option<&'a [vk::DrmFormatModifierProperties2EXT]>,
+        ),
+    >,
+{
+    let mut text = format!("hbm-probe-cache {PROBE_CACHE_VERSION} {key}\n");
+    for (fmt, mods) in entries {
+        let _ = write!(text, "{}", fmt.as_raw());
+        match mods {
+            Some(mods) => {
+                for mod_props in mods {
+                    let _ = write!(
+                        text,
+                        " {:x}:{}:{:x}",
+                        mod_props.drm_format_modifier,
+                        mod_props.drm_format_modifier_plane_count,
+                        mod_props.drm_format_modifier_tiling_features.as_raw()
+                    );
+                }
+            }
+            None => text.push_str(" -"),
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+// a format and its modifiers, or None if the format is unsupported
+type ProbeCacheEntry = (vk::Format, Option<Vec<vk::DrmFormatModifierProperties2EXT>>);
+
+// parses the text of serialize_probe_cache, and returns None if the text is malformed or has a
+// different version or key
+fn parse_probe_cache(text: &str, key: &str) -> Option<Vec<ProbeCacheEntry>> {
+    let mut lines = text.lines();
+    if lines.next()? != format!("hbm-probe-cache {PROBE_CACHE_VERSION} {key}") {
+        return None;
+    }
+
+    let mut entries = Vec::new();
+    for line in lines {
+        let mut fields = line.split_whitespace();
+        let fmt = vk::Format::from_raw(fields.next()?.parse().ok()?);
+
+        let mut mods = Vec::new();
+        for field in fields {
+            if field == "-" {
+                break;
+            }
+
+            let mut parts = field.split(':');
+            let modifier = u64::from_str_radix(parts.next()?, 16).ok()?;
+            let plane_count = parts.next()?.parse().ok()?;
+            let feats = u64::from_str_radix(parts.next()?, 16).ok()?;
+            mods.push(vk::DrmFormatModifierProperties2EXT {
+                drm_format_modifier: modifier,
+                drm_format_modifier_plane_count: plane_count,
+                drm_format_modifier_tiling_features: vk::FormatFeatureFlags2::from_raw(feats),
+            });
+        }
+
+        entries.push((fmt, (!mods.is_empty()).then_some(mods)));
+    }
+
+    Some(entries)
 }
 
 #[derive(Default)]
 struct PhysicalDeviceProperties {
+    khr_external_semaphore_fd: bool,
+    khr_format_feature_flags2: bool,
+    khr_image_format_list: bool,
+    khr_map_memory2: bool,
+    khr_synchronization2: bool,
+    ext_device_fault: bool,
+    ext_external_memory_host: bool,
+    ext_host_image_copy: bool,
     ext_image_drm_format_modifier: bool,
+    ext_map_memory_placed: bool,
+    ext_memory_budget: bool,
+    ext_memory_priority: bool,
+    ext_queue_family_foreign: bool,
 
     driver_id: vk::DriverId,
+    // the probe cache is keyed by these
+    device_uuid: [u8; vk::UUID_SIZE],
+    driver_uuid: [u8; vk::UUID_SIZE],
+    driver_version: u32,
+    info: DeviceInfo,
+    software: bool,
+    device_group_index: u32,
+    device_group_size: u32,
     max_image_dimension_2d: u32,
     max_uniform_buffer_range: u32,
     max_storage_buffer_range: u32,
     max_buffer_size: vk::DeviceSize,
+    non_coherent_atom_size: vk::DeviceSize,
+    buffer_image_granularity: vk::DeviceSize,
+    min_imported_host_pointer_alignment: vk::DeviceSize,
+    min_placed_memory_map_alignment: vk::DeviceSize,
+    // nanoseconds per timestamp tick
+    timestamp_period: f32,
+    max_memory_allocation_count: u32,
 
     protected_memory: bool,
     image_compression_control: bool,
+    sampler_ycbcr_conversion: bool,
+    host_image_copy: bool,
+    memory_priority: bool,
+    synchronization2: bool,
+    device_fault: bool,
+    // requires both memoryMapPlaced and memoryUnmapReserve
+    memory_map_placed: bool,
+    // requires both the feature and a queue family that supports sparse binding
+    sparse_binding: bool,
 
     queue_family: u32,
+    // this is non-zero only when the device is adopted
+    queue_index: u32,
+    // the number of queues that copies are submitted to, which is 1 when the device is adopted
+    queue_count: u32,
+    // blits require graphics and clears require graphics or compute
+    queue_flags: vk::QueueFlags,
+    // timestamps are unsupported by the queue family when this is 0
+    timestamp_valid_bits: u32,
+    // whether a protected-capable queue is created for copies of protected resources
+    protected_queue: bool,
     memory_types: Vec<vk::MemoryPropertyFlags>,
+    // the size of the heap of each memory type
+    memory_type_heap_sizes: Vec<vk::DeviceSize>,
 
-    formats: HashMap<vk::Format, FormatProperties>,
+    // formats are probed on first use and None is cached for unsupported formats
+    formats: RwLock<HashMap<vk::Format, Option<Arc<FormatProperties>>>>,
 
     external_memory_type: vk::ExternalMemoryHandleTypeFlags,
+    sync_fd_import: bool,
+    sync_fd_export: bool,
 }
 
 struct PhysicalDevice {
-    instance: Instance,
+    instance: Arc<Instance>,
     handle: vk::PhysicalDevice,
 
     properties: PhysicalDeviceProperties,
@@ -270,9 +648,10 @@ struct PhysicalDevice {
 
 impl PhysicalDevice {
     fn new(
-        instance: Instance,
+        instance: Arc<Instance>,
         dev_idx: Option<usize>,
         dev_id: Option<u64>,
+        allow_software: bool,
     ) -> Result<(Self, DeviceCreateInfo)> {
         let mut physical_dev = Self {
             instance,
@@ -280,12 +659,51 @@ impl PhysicalDevice {
             properties: Default::default(),
         };
 
-        let dev_info = physical_dev.init(dev_idx, dev_id)?;
+        let dev_info = physical_dev.init(dev_idx, dev_id, allow_software)?;
 
         Ok((physical_dev, dev_info))
     }
 
-    fn init(&mut self, dev_idx: Option<usize>, dev_id: Option<u64>) -> Result<DeviceCreateInfo> {
+    // The enabled extensions of an adopted device are given, but its enabled features are
+    // unknown.  Optional features that must be enabled at device creation are assumed disabled.
+    fn adopt(
+        instance: Arc<Instance>,
+        handle: vk::PhysicalDevice,
+        queue_family: u32,
+        queue_index: u32,
+        extensions: &[&ffi::CStr],
+    ) -> Result<Self> {
+        let mut physical_dev = Self {
+            instance,
+            handle: Default::default(),
+            properties: Default::default(),
+        };
+
+        physical_dev.probe(handle, None, true, Some(extensions))?;
+
+        let props = &mut physical_dev.properties;
+        props.protected_memory = false;
+        props.image_compression_control = false;
+        props.host_image_copy = false;
+        props.memory_priority = false;
+        props.synchronization2 = false;
+        props.sparse_binding = false;
+        props.device_fault = false;
+        props.memory_map_placed = false;
+
+        physical_dev.probe_queue_families(Some(queue_family))?;
+        physical_dev.properties.queue_index = queue_index;
+        physical_dev.properties.queue_count = 1;
+
+        Ok(physical_dev)
+    }
+
+    fn init(
+        &mut self,
+        dev_idx: Option<usize>,
+        dev_id: Option<u64>,
+        allow_software: bool,
+    ) -> Result<DeviceCreateInfo> {
         // SAFETY: no VUID violation
         let handles = unsafe { self.instance.handle.enumerate_physical_devices() }
             .or(Error::ctx("failed to enumerate devices"))?;
@@ -297,7 +715,7 @@ impl PhysicalDevice {
                 }
             }
 
-            self.probe(handle, dev_id).ok()
+            self.probe(handle, dev_id, allow_software, None).ok()
         });
 
         dev_info.ok_or(Error::Context("failed to find any device"))
@@ -307,27 +725,32 @@ impl PhysicalDevice {
         &mut self,
         handle: vk::PhysicalDevice,
         dev_id: Option<u64>,
+        allow_software: bool,
+        enabled_exts: Option<&[&ffi::CStr]>,
     ) -> Result<DeviceCreateInfo> {
         // reset handle and properties
         self.handle = handle;
         self.properties = Default::default();
 
         let mut dev_info = Default::default();
-        self.probe_extensions(dev_id, &mut dev_info)?;
-        self.probe_properties(dev_id)?;
+        self.probe_extensions(dev_id, enabled_exts, &mut dev_info)?;
+        self.probe_properties(dev_id, allow_software)?;
+        self.probe_device_group();
         self.probe_features();
-        self.probe_queue_families()?;
+        self.probe_queue_families(None)?;
         self.probe_memory_types();
-        self.probe_formats();
 
         self.probe_external_memory();
+        self.probe_external_semaphore();
 
         Ok(dev_info)
     }
 
+    // when enabled_exts is set, only those extensions are considered available
     fn probe_extensions(
         &mut self,
         dev_id: Option<u64>,
+        enabled_exts: Option<&[&ffi::CStr]>,
         dev_info: &mut DeviceCreateInfo,
     ) -> Result<()> {
         // SAFETY: no VUID violation
@@ -346,7 +769,7 @@ impl PhysicalDevice {
                 // SAFETY: extension_name is a valid utf8 c-string
                 let ext_name = unsafe { ffi::CStr::from_ptr(ext.extension_name.as_ptr()) };
                 ext_name == name
-            });
+            }) && enabled_exts.map_or(true, |exts| exts.contains(&name));
 
             if required && !dev_info.extensions[idx] {
                 return Error::unsupported();
@@ -357,24 +780,61 @@ impl PhysicalDevice {
             return Error::unsupported();
         }
 
+        // VK_EXT_map_memory_placed depends on VK_KHR_map_memory2
+        if !dev_info.extensions[ExtId::KhrMapMemory2 as usize] {
+            dev_info.extensions[ExtId::ExtMapMemoryPlaced as usize] = false;
+        }
+
+        self.properties.khr_external_semaphore_fd =
+            dev_info.extensions[ExtId::KhrExternalSemaphoreFd as usize];
+        self.properties.khr_format_feature_flags2 =
+            dev_info.extensions[ExtId::KhrFormatFeatureFlags2 as usize];
+        self.properties.khr_image_format_list =
+            dev_info.extensions[ExtId::KhrImageFormatList as usize];
+        self.properties.khr_map_memory2 = dev_info.extensions[ExtId::KhrMapMemory2 as usize];
+        self.properties.khr_synchronization2 =
+            dev_info.extensions[ExtId::KhrSynchronization2 as usize];
+        self.properties.ext_device_fault = dev_info.extensions[ExtId::ExtDeviceFault as usize];
+        self.properties.ext_external_memory_host =
+            dev_info.extensions[ExtId::ExtExternalMemoryHost as usize];
+        self.properties.ext_host_image_copy = dev_info.extensions[ExtId::ExtHostImageCopy as usize];
         self.properties.ext_image_drm_format_modifier =
             dev_info.extensions[ExtId::ExtImageDrmFormatModifier as usize];
+        self.properties.ext_map_memory_placed =
+            dev_info.extensions[ExtId::ExtMapMemoryPlaced as usize];
+        self.properties.ext_memory_budget = dev_info.extensions[ExtId::ExtMemoryBudget as usize];
+        self.properties.ext_memory_priority =
+            dev_info.extensions[ExtId::ExtMemoryPriority as usize];
+        self.properties.ext_queue_family_foreign =
+            dev_info.extensions[ExtId::ExtQueueFamilyForeign as usize];
 
         Ok(())
     }
 
-    fn probe_properties(&mut self, dev_id: Option<u64>) -> Result<()> {
+    fn probe_properties(&mut self, dev_id: Option<u64>, allow_software: bool) -> Result<()> {
         let mut maint4_props = vk::PhysicalDeviceMaintenance4Properties::default();
         let mut drv_props = vk::PhysicalDeviceDriverProperties::default();
+        let mut id_props = vk::PhysicalDeviceIDProperties::default();
         let mut props = vk::PhysicalDeviceProperties2::default()
             .push_next(&mut maint4_props)
-            .push_next(&mut drv_props);
+            .push_next(&mut drv_props)
+            .push_next(&mut id_props);
 
         let mut drm_props = vk::PhysicalDeviceDrmPropertiesEXT::default();
         if dev_id.is_some() {
             props = props.push_next(&mut drm_props);
         }
 
+        let mut host_mem_props = vk::PhysicalDeviceExternalMemoryHostPropertiesEXT::default();
+        if self.properties.ext_external_memory_host {
+            props = props.push_next(&mut host_mem_props);
+        }
+
+        let mut placed_props = vk::PhysicalDeviceMapMemoryPlacedPropertiesEXT::default();
+        if self.properties.ext_map_memory_placed {
+            props = props.push_next(&mut placed_props);
+        }
+
         // SAFETY: no VUID violation
         unsafe {
             self.instance
@@ -390,6 +850,32 @@ impl PhysicalDevice {
         }
 
         self.properties.driver_id = drv_props.driver_id;
+        self.properties.device_uuid = id_props.device_uuid;
+        self.properties.driver_uuid = id_props.driver_uuid;
+        self.properties.driver_version = props.driver_version;
+
+        let conformance = drv_props.conformance_version;
+        self.properties.info = DeviceInfo {
+            name: c_str_lossy(props.device_name_as_c_str()),
+            vendor_id: props.vendor_id,
+            device_id: props.device_id,
+            driver_id: drv_props.driver_id.as_raw(),
+            driver_name: c_str_lossy(drv_props.driver_name_as_c_str()),
+            driver_info: c_str_lossy(drv_props.driver_info_as_c_str()),
+            driver_version: props.driver_version,
+            conformance_version: [
+                conformance.major,
+                conformance.minor,
+                conformance.subminor,
+                conformance.patch,
+            ],
+        };
+
+        // software implementations such as lavapipe are functional but slow
+        self.properties.software = props.device_type == vk::PhysicalDeviceType::CPU;
+        if self.properties.software && !allow_software {
+            return Error::unsupported();
+        }
 
         if !self.properties.ext_image_drm_format_modifier {
             // If we have to go ahead without VK_EXT_image_drm_format_modifier,
@@ -405,7 +891,11 @@ impl PhysicalDevice {
             //
             // In other words, this is utterly wrong.
             //
-            // TODO add modifiers to amdgpu gfx8
+            // This is expected only on radv+gfx8 and older.  AMD modifiers cannot describe them:
+            // the tile versions of AMD modifiers start at gfx9, and older tiling is conveyed by
+            // amdgpu BO metadata that radv sets on export.  That is why tiled images have
+            // DRM_FORMAT_MOD_INVALID, which tells KMS and other processes to use the metadata,
+            // rather than a made-up modifier.
             if self.properties.driver_id == vk::DriverId::MESA_RADV {
                 log::warn!("no VK_EXT_image_drm_format_modifier support");
             } else {
@@ -418,16 +908,75 @@ impl PhysicalDevice {
         self.properties.max_uniform_buffer_range = limits.max_uniform_buffer_range;
         self.properties.max_storage_buffer_range = limits.max_storage_buffer_range;
         self.properties.max_buffer_size = maint4_props.max_buffer_size;
+        self.properties.non_coherent_atom_size = limits.non_coherent_atom_size;
+        self.properties.buffer_image_granularity = limits.buffer_image_granularity;
+        self.properties.timestamp_period = limits.timestamp_period;
+        self.properties.max_memory_allocation_count = limits.max_memory_allocation_count;
+        self.properties.min_imported_host_pointer_alignment =
+            host_mem_props.min_imported_host_pointer_alignment;
+        self.properties.min_placed_memory_map_alignment =
+            placed_props.min_placed_memory_map_alignment;
 
         Ok(())
     }
 
+    fn probe_device_group(&mut self) {
+        self.properties.device_group_index = 0;
+        self.properties.device_group_size = 1;
+
+        // SAFETY: no VUID violation
+        let count = unsafe { self.instance.handle.enumerate_physical_device_groups_len() };
+        let Ok(count) = count else {
+            return;
+        };
+
+        let mut groups = vec![vk::PhysicalDeviceGroupProperties::default(); count];
+        // SAFETY: no VUID violation
+        let res = unsafe {
+            self.instance
+                .handle
+                .enumerate_physical_device_groups(&mut groups)
+        };
+        if res.is_err() {
+            return;
+        }
+
+        for group in groups {
+            let handles = group.physical_devices_as_slice();
+            if let Some(idx) = handles.iter().position(|&handle| handle == self.handle) {
+                self.properties.device_group_index = idx as u32;
+                self.properties.device_group_size = handles.len() as u32;
+                break;
+            }
+        }
+
+        if self.properties.device_group_size > 1 {
+            log::info!(
+                "physical device {} of a device group of {}",
+                self.properties.device_group_index,
+                self.properties.device_group_size
+            );
+        }
+    }
+
     fn probe_features(&mut self) {
         let mut mem_prot_feats = vk::PhysicalDeviceProtectedMemoryFeatures::default();
         let mut img_comp_feats = vk::PhysicalDeviceImageCompressionControlFeaturesEXT::default();
+        let mut ycbcr_feats = vk::PhysicalDeviceSamplerYcbcrConversionFeatures::default();
+        let mut host_copy_feats = vk::PhysicalDeviceHostImageCopyFeaturesEXT::default();
+        let mut mem_prio_feats = vk::PhysicalDeviceMemoryPriorityFeaturesEXT::default();
+        let mut sync2_feats = vk::PhysicalDeviceSynchronization2Features::default();
+        let mut fault_feats = vk::PhysicalDeviceFaultFeaturesEXT::default();
+        let mut placed_feats = vk::PhysicalDeviceMapMemoryPlacedFeaturesEXT::default();
         let mut feats = vk::PhysicalDeviceFeatures2::default()
             .push_next(&mut mem_prot_feats)
-            .push_next(&mut img_comp_feats);
+            .push_next(&mut img_comp_feats)
+            .push_next(&mut ycbcr_feats)
+            .push_next(&mut host_copy_feats)
+            .push_next(&mut mem_prio_feats)
+            .push_next(&mut sync2_feats)
+            .push_next(&mut fault_feats)
+            .push_next(&mut placed_feats);
 
         // SAFETY: no VUID violation
         unsafe {
@@ -436,11 +985,49 @@ impl PhysicalDevice {
                 .get_physical_device_features2(self.handle, &mut feats);
         }
 
+        self.properties.sparse_binding = feats.features.sparse_binding > 0;
         self.properties.protected_memory = mem_prot_feats.protected_memory > 0;
         self.properties.image_compression_control = img_comp_feats.image_compression_control > 0;
+        self.properties.sampler_ycbcr_conversion = ycbcr_feats.sampler_ycbcr_conversion > 0;
+        self.properties.host_image_copy =
+            self.properties.ext_host_image_copy && host_copy_feats.host_image_copy > 0;
+        self.properties.memory_priority =
+            self.properties.ext_memory_priority && mem_prio_feats.memory_priority > 0;
+        self.properties.synchronization2 =
+            self.properties.khr_synchronization2 && sync2_feats.synchronization2 > 0;
+        self.properties.device_fault =
+            self.properties.ext_device_fault && fault_feats.device_fault > 0;
+        self.properties.memory_map_placed = self.properties.ext_map_memory_placed
+            && placed_feats.memory_map_placed > 0
+            && placed_feats.memory_unmap_reserve > 0;
+    }
+
+    // Enabling some features has a cost on some drivers, such as protected queue creation.  This
+    // lets integrators opt out of the features that they know are unused.
+    fn disable_features(&mut self, feats: Features, dev_info: &mut DeviceCreateInfo) {
+        if feats.contains(Features::PROTECTED) {
+            self.properties.protected_memory = false;
+            self.properties.protected_queue = false;
+        }
+        if feats.contains(Features::COMPRESSION_CONTROL) {
+            self.properties.image_compression_control = false;
+            dev_info.extensions[ExtId::ExtImageCompressionControl as usize] = false;
+        }
+    }
+
+    fn set_queue_priority(
+        &self,
+        prio: Option<vk::QueueGlobalPriorityKHR>,
+        dev_info: &mut DeviceCreateInfo,
+    ) {
+        // the priority is a hint and is ignored when unsupported
+        if dev_info.extensions[ExtId::ExtGlobalPriority as usize] {
+            dev_info.queue_priority = prio;
+        }
     }
 
-    fn probe_queue_families(&mut self) -> Result<()> {
+    // when queue_family is set, only that queue family is considered
+    fn probe_queue_families(&mut self, queue_family: Option<u32>) -> Result<()> {
         // SAFETY: no VUID violation
         let props_list = unsafe {
             self.instance
@@ -455,19 +1042,43 @@ impl PhysicalDevice {
         };
         let required_flags = vk::QueueFlags::TRANSFER;
 
-        self.properties.queue_family = props_list
+        let family_queue_count;
+        (
+            self.properties.queue_family,
+            self.properties.queue_flags,
+            self.properties.timestamp_valid_bits,
+            family_queue_count,
+        ) = props_list
             .into_iter()
             .enumerate()
             .find_map(|(idx, props)| {
-                if props.min_image_transfer_granularity == required_granularity
+                if queue_family.map_or(true, |family| family == idx as u32)
+                    && props.min_image_transfer_granularity == required_granularity
                     && props.queue_flags.contains(required_flags)
                 {
-                    Some(idx as u32)
+                    Some((
+                        idx as u32,
+                        props.queue_flags,
+                        props.timestamp_valid_bits,
+                        props.queue_count,
+                    ))
                 } else {
                     None
                 }
             })
             .ok_or(Error::Unsupported)?;
+        self.properties.queue_count = family_queue_count.min(MAX_COPY_QUEUES);
+
+        self.properties.protected_queue = self.properties.protected_memory
+            && self
+                .properties
+                .queue_flags
+                .contains(vk::QueueFlags::PROTECTED);
+        self.properties.sparse_binding = self.properties.sparse_binding
+            && self
+                .properties
+                .queue_flags
+                .contains(vk::QueueFlags::SPARSE_BINDING);
 
         Ok(())
     }
@@ -485,52 +1096,110 @@ impl PhysicalDevice {
             .iter()
             .map(|mt| mt.property_flags)
             .collect();
+        self.properties.memory_type_heap_sizes = props
+            .memory_types_as_slice()
+            .iter()
+            .map(|mt| props.memory_heaps[mt.heap_index as usize].size)
+            .collect();
     }
 
-    fn get_format_properties(
+    fn get_physical_device_format_properties(
         &self,
         fmt: vk::Format,
-        fmt_plane_count: u32,
-    ) -> Vec<vk::DrmFormatModifierPropertiesEXT> {
-        let mut mod_props_list = vk::DrmFormatModifierPropertiesListEXT::default();
-        let mut props = vk::FormatProperties2::default().push_next(&mut mod_props_list);
-
+        props: &mut vk::FormatProperties2,
+    ) {
         // SAFETY: no VUID violation
         unsafe {
-            self.instance.handle.get_physical_device_format_properties2(
-                self.handle,
-                fmt,
-                &mut props,
-            );
+            self.instance
+                .handle
+                .get_physical_device_format_properties2(self.handle, fmt, props);
+        }
+    }
+
+    fn get_tiling_features(
+        &self,
+        fmt: vk::Format,
+    ) -> (vk::FormatFeatureFlags2, vk::FormatFeatureFlags2) {
+        if self.properties.khr_format_feature_flags2 {
+            let mut props3 = vk::FormatProperties3::default();
+            let mut props = vk::FormatProperties2::default().push_next(&mut props3);
+            self.get_physical_device_format_properties(fmt, &mut props);
+
+            (
+                props3.linear_tiling_features,
+                props3.optimal_tiling_features,
+            )
+        } else {
+            let mut props = vk::FormatProperties2::default();
+            self.get_physical_device_format_properties(fmt, &mut props);
+
+            (
+                format_features2(props.format_properties.linear_tiling_features),
+                format_features2(props.format_properties.optimal_tiling_features),
+            )
         }
+    }
+
+    fn get_modifier_properties(&self, fmt: vk::Format) -> Vec<vk::DrmFormatModifierProperties2EXT> {
+        if self.properties.khr_format_feature_flags2 {
+            let mut mod_props_list = vk::DrmFormatModifierPropertiesList2EXT::default();
+            let mut props = vk::FormatProperties2::default().push_next(&mut mod_props_list);
+            self.get_physical_device_format_properties(fmt, &mut props);
 
-        if self.properties.ext_image_drm_format_modifier {
             let mod_count = mod_props_list.drm_format_modifier_count as usize;
             let mut mods = vec![Default::default(); mod_count];
             if mod_count == 0 {
                 return mods;
             }
 
-            let mut mod_props_list = vk::DrmFormatModifierPropertiesListEXT::default()
+            let mut mod_props_list = vk::DrmFormatModifierPropertiesList2EXT::default()
                 .drm_format_modifier_properties(&mut mods);
             let mut props = vk::FormatProperties2::default().push_next(&mut mod_props_list);
+            self.get_physical_device_format_properties(fmt, &mut props);
 
-            // SAFETY: no VUID violation
-            unsafe {
-                self.instance.handle.get_physical_device_format_properties2(
-                    self.handle,
-                    fmt,
-                    &mut props,
-                );
+            mods
+        } else {
+            let mut mod_props_list = vk::DrmFormatModifierPropertiesListEXT::default();
+            let mut props = vk::FormatProperties2::default().push_next(&mut mod_props_list);
+            self.get_physical_device_format_properties(fmt, &mut props);
+
+            let mod_count = mod_props_list.drm_format_modifier_count as usize;
+            let mut mods: Vec<vk::DrmFormatModifierPropertiesEXT> =
+                vec![Default::default(); mod_count];
+            if mod_count == 0 {
+                return Vec::new();
             }
 
-            // vk::ImageAspectFlags supports up to 4 memory planes
+            let mut mod_props_list = vk::DrmFormatModifierPropertiesListEXT::default()
+                .drm_format_modifier_properties(&mut mods);
+            let mut props = vk::FormatProperties2::default().push_next(&mut mod_props_list);
+            self.get_physical_device_format_properties(fmt, &mut props);
+
             mods.into_iter()
+                .map(|mod_props| vk::DrmFormatModifierProperties2EXT {
+                    drm_format_modifier: mod_props.drm_format_modifier,
+                    drm_format_modifier_plane_count: mod_props.drm_format_modifier_plane_count,
+                    drm_format_modifier_tiling_features: format_features2(
+                        mod_props.drm_format_modifier_tiling_features,
+                    ),
+                })
+                .collect()
+        }
+    }
+
+    fn get_format_properties(
+        &self,
+        fmt: vk::Format,
+        fmt_plane_count: u32,
+    ) -> Vec<vk::DrmFormatModifierProperties2EXT> {
+        if self.properties.ext_image_drm_format_modifier {
+            // vk::ImageAspectFlags supports up to 4 memory planes
+            self.get_modifier_properties(fmt)
+                .into_iter()
                 .filter(|mod_props| mod_props.drm_format_modifier_plane_count <= 4)
                 .collect()
         } else {
-            let linear_feats = props.format_properties.linear_tiling_features;
-            let optimal_feats = props.format_properties.optimal_tiling_features;
+            let (linear_feats, optimal_feats) = self.get_tiling_features(fmt);
             let mod_count = !linear_feats.is_empty() as usize + !optimal_feats.is_empty() as usize;
             let mut mods = Vec::with_capacity(mod_count);
             if mod_count == 0 {
@@ -538,7 +1207,7 @@ impl PhysicalDevice {
             }
 
             if !linear_feats.is_empty() {
-                let linear_props = vk::DrmFormatModifierPropertiesEXT {
+                let linear_props = vk::DrmFormatModifierProperties2EXT {
                     drm_format_modifier: formats::MOD_LINEAR.0,
                     drm_format_modifier_plane_count: fmt_plane_count,
                     drm_format_modifier_tiling_features: linear_feats,
@@ -547,7 +1216,7 @@ impl PhysicalDevice {
             }
             // limit optimal tiling to non-planar formats
             if !optimal_feats.is_empty() && fmt_plane_count == 1 {
-                let optimal_props = vk::DrmFormatModifierPropertiesEXT {
+                let optimal_props = vk::DrmFormatModifierProperties2EXT {
                     drm_format_modifier: formats::MOD_INVALID.0,
                     drm_format_modifier_plane_count: fmt_plane_count,
                     drm_format_modifier_tiling_features: optimal_feats,
@@ -559,32 +1228,17 @@ impl PhysicalDevice {
         }
     }
 
-    fn probe_formats(&mut self) {
-        for drm_fmt in formats::KNOWN_FORMATS {
-            /* some drm formats cannot be mapped */
-            let fmt = formats::to_vk(drm_fmt);
-            if fmt.is_err() {
-                continue;
-            }
-
-            /* some drm formats map to the same vk formats */
-            let fmt = fmt.unwrap().0;
-            if self.properties.formats.contains_key(&fmt) {
-                continue;
-            }
-
-            let fmt_class = formats::format_class(drm_fmt).unwrap();
-            let mods = self.get_format_properties(fmt, fmt_class.plane_count as u32);
-            if mods.is_empty() {
-                continue;
-            }
-
-            let fmt_props = FormatProperties {
-                format_class: fmt_class,
-                modifiers: mods,
-            };
-            self.properties.formats.insert(fmt, fmt_props);
+    fn probe_format(&self, fmt: vk::Format) -> Option<FormatProperties> {
+        let fmt_class = known_format_class(fmt)?;
+        let mods = self.get_format_properties(fmt, fmt_class.plane_count as u32);
+        if mods.is_empty() {
+            return None;
         }
+
+        Some(FormatProperties {
+            format_class: fmt_class,
+            modifiers: mods,
+        })
     }
 
     fn probe_external_memory(&mut self) {
@@ -595,30 +1249,118 @@ impl PhysicalDevice {
             vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD
         };
     }
+
+    fn probe_external_semaphore(&mut self) {
+        if !self.properties.khr_external_semaphore_fd {
+            return;
+        }
+
+        let sema_info = vk::PhysicalDeviceExternalSemaphoreInfo::default()
+            .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+        let mut sema_props = vk::ExternalSemaphoreProperties::default();
+
+        // SAFETY: no VUID violation
+        unsafe {
+            self.instance
+                .handle
+                .get_physical_device_external_semaphore_properties(
+                    self.handle,
+                    &sema_info,
+                    &mut sema_props,
+                );
+        }
+
+        let sema_feats = sema_props.external_semaphore_features;
+        self.properties.sync_fd_import =
+            sema_feats.contains(vk::ExternalSemaphoreFeatureFlags::IMPORTABLE);
+        self.properties.sync_fd_export =
+            sema_feats.contains(vk::ExternalSemaphoreFeatureFlags::EXPORTABLE);
+    }
 }
 
 pub struct BufferInfo {
     pub flags: vk::BufferCreateFlags,
     pub usage: vk::BufferUsageFlags,
     pub external: bool,
+    // overrides the handle type of external memory, which is chosen by the device by default
+    pub external_memory_type: Option<vk::ExternalMemoryHandleTypeFlags>,
+    // the buffer can be bound to a host allocation, and external must be false
+    pub host_pointer: bool,
+    // the memory priority, which is ignored without VK_EXT_memory_priority
+    pub priority: Option<f32>,
 }
 
 pub struct BufferProperties {
     pub max_size: vk::DeviceSize,
+    pub max_uniform_range: Option<vk::DeviceSize>,
+    pub max_storage_range: Option<vk::DeviceSize>,
 }
 
 pub struct ImageInfo {
     pub flags: vk::ImageCreateFlags,
     pub usage: vk::ImageUsageFlags,
     pub format: vk::Format,
+    // the DRM format, which copy profiles are aggregated by
+    pub drm_format: Format,
+    // 3D images are limited to LINEAR and OPTIMAL tilings
+    pub image_type: vk::ImageType,
     pub external: bool,
+    // overrides the handle type of external memory, which is chosen by the device by default
+    pub external_memory_type: Option<vk::ExternalMemoryHandleTypeFlags>,
     pub no_compression: bool,
+    // when non-empty, the image has fixed-rate compression at one of the rates
+    pub fixed_rate: vk::ImageCompressionFixedRateFlagsEXT,
     pub scanout_hack: bool,
+    // the memory priority, which is ignored without VK_EXT_memory_priority
+    pub priority: Option<f32>,
+    // the image is sampled with a sampler YCbCr conversion
+    pub ycbcr_conversion: bool,
+    // when non-empty, the image is created with a format list
+    pub view_formats: Vec<vk::Format>,
 }
 
 pub struct ImageProperties {
-    pub max_extent: u32,
     pub modifiers: Vec<Modifier>,
+    // the tiling features of the modifiers
+    pub modifier_features: Vec<vk::FormatFeatureFlags2>,
+}
+
+// the image info fields that affect has_image_support
+#[derive(Clone, Eq, Hash, PartialEq)]
+struct ImageSupportKey {
+    flags: vk::ImageCreateFlags,
+    usage: vk::ImageUsageFlags,
+    format: vk::Format,
+    image_type: vk::ImageType,
+    external: bool,
+    external_memory_type: Option<vk::ExternalMemoryHandleTypeFlags>,
+    fixed_rate: vk::ImageCompressionFixedRateFlagsEXT,
+    scanout_hack: bool,
+    view_formats: Vec<vk::Format>,
+    compression: vk::ImageCompressionFlagsEXT,
+    modifier: Modifier,
+}
+
+impl ImageSupportKey {
+    fn new(
+        img_info: &ImageInfo,
+        compression: vk::ImageCompressionFlagsEXT,
+        modifier: Modifier,
+    ) -> Self {
+        Self {
+            flags: img_info.flags,
+            usage: img_info.usage,
+            format: img_info.format,
+            image_type: img_info.image_type,
+            external: img_info.external,
+            external_memory_type: img_info.external_memory_type,
+            fixed_rate: img_info.fixed_rate,
+            scanout_hack: img_info.scanout_hack,
+            view_formats: img_info.view_formats.clone(),
+            compression,
+            modifier,
+        }
+    }
 }
 
 // this is for scanout hack
@@ -648,15 +1390,116 @@ unsafe impl vk::ExtendsPhysicalDeviceImageFormatInfo2 for WsiImageCreateInfoMESA
 // SAFETY: only on radv+gfx8
 unsafe impl vk::ExtendsImageCreateInfo for WsiImageCreateInfoMESA {}
 
-struct DeviceDispatch {
+// A memory that small buffers are suballocated from.
+//
+// This keeps the number of allocations below maxMemoryAllocationCount.  Host-visible slabs are
+// persistently mapped because a memory can only be mapped once.
+struct Slab {
+    handle: vk::DeviceMemory,
+    mt_idx: u32,
+    ptr: Option<ptr::NonNull<ffi::c_void>>,
+    // the free ranges, sorted by offset and never adjacent
+    free_ranges: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+// SAFETY: ptr is only dereferenced by the owners of the suballocations
+unsafe impl Send for Slab {}
+
+impl Slab {
+    fn new(handle: vk::DeviceMemory, mt_idx: u32, ptr: Option<ptr::NonNull<ffi::c_void>>) -> Self {
+        Self {
+            handle,
+            mt_idx,
+            ptr,
+            free_ranges: vec![(0, SLAB_SIZE)],
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.free_ranges == [(0, SLAB_SIZE)]
+    }
+
+    fn free_size(&self) -> vk::DeviceSize {
+        self.free_ranges.iter().map(|&(_, len)| len).sum()
+    }
+
+    // returns the offset of the first free range that fits
+    fn alloc(&mut self, size: vk::DeviceSize, align: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let (idx, offset) =
+            self.free_ranges
+                .iter()
+                .enumerate()
+                .find_map(|(idx, &(start, len))| {
+                    let offset = start.next_multiple_of(align);
+                    (offset + size <= start + len).then_some((idx, offset))
+                })?;
+
+        let (start, len) = self.free_ranges[idx];
+        let mut split = Vec::with_capacity(2);
+        if offset > start {
+            split.push((start, offset - start));
+        }
+        if offset + size < start + len {
+            split.push((offset + size, start + len - offset - size));
+        }
+        self.free_ranges.splice(idx..idx + 1, split);
+
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let idx = self
+            .free_ranges
+            .partition_point(|&(start, _)| start < offset);
+        self.free_ranges.insert(idx, (offset, size));
+
+        // merge with the next range and then the previous range
+        if let Some(&(next_start, next_len)) = self.free_ranges.get(idx + 1) {
+            if offset + size == next_start {
+                self.free_ranges[idx].1 += next_len;
+                self.free_ranges.remove(idx + 1);
+            }
+        }
+        if idx > 0 {
+            let (prev_start, prev_len) = self.free_ranges[idx - 1];
+            if prev_start + prev_len == offset {
+                self.free_ranges[idx - 1].1 += self.free_ranges[idx].1;
+                self.free_ranges.remove(idx);
+            }
+        }
+    }
+}
+
+struct DeviceDispatch {
+    debug_utils: Option<ash::ext::debug_utils::Device>,
+    compression: ash::ext::image_compression_control::Device,
+    fault: ash::ext::device_fault::Device,
+    host_copy: ash::ext::host_image_copy::Device,
+    host_memory: ash::ext::external_memory_host::Device,
+    maintenance4: ash::khr::maintenance4::Device,
+    map_memory2: ash::khr::map_memory2::Device,
     memory: ash::khr::external_memory_fd::Device,
     modifier: ash::ext::image_drm_format_modifier::Device,
+    semaphore: ash::khr::external_semaphore_fd::Device,
+    sync2: ash::khr::synchronization2::Device,
 }
 
 pub struct Device {
     physical_device: PhysicalDevice,
     handle: ash::Device,
     dispatch: DeviceDispatch,
+    slabs: Mutex<Vec<Slab>>,
+    // an adopted device is not ours to destroy
+    owned: bool,
+    // None is cached for unsupported images
+    image_support: Mutex<HashMap<ImageSupportKey, Option<vk::ExternalMemoryFeatureFlags>>>,
+    // the fault report is logged once
+    fault_reported: atomic::AtomicBool,
+    // the number of live device memories, including slabs, that we have allocated
+    memory_count: atomic::AtomicU32,
+    // newly probed formats are written back to the probe cache when the device is dropped
+    probe_cache: OnceLock<PathBuf>,
+    probe_cache_dirty: atomic::AtomicBool,
 }
 
 impl Device {
@@ -665,14 +1508,54 @@ impl Device {
         dev_idx: Option<usize>,
         dev_id: Option<u64>,
         debug: bool,
+        allow_software: bool,
+        disabled_features: Features,
+        queue_priority: Option<vk::QueueGlobalPriorityKHR>,
     ) -> Result<Arc<Device>> {
-        let instance = Instance::new(name, debug)?;
-        let (physical_dev, dev_info) = PhysicalDevice::new(instance, dev_idx, dev_id)?;
+        let instance = Instance::shared(name, debug)?;
+        let (mut physical_dev, mut dev_info) =
+            PhysicalDevice::new(instance, dev_idx, dev_id, allow_software)?;
+        physical_dev.disable_features(disabled_features, &mut dev_info);
+        physical_dev.set_queue_priority(queue_priority, &mut dev_info);
         let dev = Self::new(physical_dev, dev_info)?;
 
         Ok(Arc::new(dev))
     }
 
+    // Adopts a device created by someone else.  The handles must be valid and must outlive us.
+    pub fn adopt(
+        instance: ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: ash::Device,
+        queue_family: u32,
+        queue_index: u32,
+        extensions: &[&ffi::CStr],
+    ) -> Result<Arc<Device>> {
+        let instance = Arc::new(Instance::adopt(instance));
+        let physical_dev = PhysicalDevice::adopt(
+            instance,
+            physical_device,
+            queue_family,
+            queue_index,
+            extensions,
+        )?;
+        let dispatch = Self::create_dispatch(&device, &physical_dev);
+        let dev = Self {
+            physical_device: physical_dev,
+            handle: device,
+            dispatch,
+            slabs: Default::default(),
+            owned: false,
+            image_support: Default::default(),
+            fault_reported: Default::default(),
+            memory_count: Default::default(),
+            probe_cache: Default::default(),
+            probe_cache_dirty: Default::default(),
+        };
+
+        Ok(Arc::new(dev))
+    }
+
     // We might want to add a recreate fn to handle device lost.  Existing resources will keep the
     // old vk::Device alive, but gpu copies will no longer work for them.  We will also need to
     // check that resources have the same vk::Device handle as we do.
@@ -683,6 +1566,13 @@ impl Device {
             physical_device,
             handle,
             dispatch,
+            slabs: Default::default(),
+            owned: true,
+            image_support: Default::default(),
+            fault_reported: Default::default(),
+            memory_count: Default::default(),
+            probe_cache: Default::default(),
+            probe_cache_dirty: Default::default(),
         };
 
         Ok(dev)
@@ -692,12 +1582,49 @@ impl Device {
         physical_dev: &PhysicalDevice,
         dev_info: DeviceCreateInfo,
     ) -> Result<ash::Device> {
+        let res = Self::try_create_device(physical_dev, dev_info);
+
+        // higher priorities than MEDIUM can require privileges
+        if matches!(res, Err(vk::Result::ERROR_NOT_PERMITTED_KHR)) {
+            log::warn!("queue global priority not permitted");
+
+            let dev_info = DeviceCreateInfo {
+                queue_priority: None,
+                ..dev_info
+            };
+            return Self::try_create_device(physical_dev, dev_info)
+                .or(Error::ctx("failed to create device"));
+        }
+
+        res.or(Error::ctx("failed to create device"))
+    }
+
+    fn try_create_device(
+        physical_dev: &PhysicalDevice,
+        dev_info: DeviceCreateInfo,
+    ) -> std::result::Result<ash::Device, vk::Result> {
         let props = &physical_dev.properties;
 
         let queue_prio = 1.0;
-        let queue_info = vk::DeviceQueueCreateInfo::default()
+        let queue_prios = [queue_prio; MAX_COPY_QUEUES as usize];
+        let mut global_prio_info = vk::DeviceQueueGlobalPriorityCreateInfoKHR::default();
+        let mut queue_info = vk::DeviceQueueCreateInfo::default()
+            .queue_family_index(props.queue_family)
+            .queue_priorities(&queue_prios[..props.queue_count as usize]);
+        // a queue family can have both unprotected and protected-capable queues
+        let mut protected_global_prio_info = vk::DeviceQueueGlobalPriorityCreateInfoKHR::default();
+        let mut protected_queue_info = vk::DeviceQueueCreateInfo::default()
+            .flags(vk::DeviceQueueCreateFlags::PROTECTED)
             .queue_family_index(props.queue_family)
             .queue_priorities(slice::from_ref(&queue_prio));
+        if let Some(prio) = dev_info.queue_priority {
+            global_prio_info = global_prio_info.global_priority(prio);
+            queue_info = queue_info.push_next(&mut global_prio_info);
+            protected_global_prio_info = protected_global_prio_info.global_priority(prio);
+            protected_queue_info = protected_queue_info.push_next(&mut protected_global_prio_info);
+        }
+        let queue_infos = [queue_info, protected_queue_info];
+        let queue_count = if props.protected_queue { 2 } else { 1 };
 
         let enabled_exts: Vec<*const ffi::c_char> = dev_info
             .extensions
@@ -716,42 +1643,235 @@ impl Device {
             .protected_memory(props.protected_memory);
         let mut img_comp_feats = vk::PhysicalDeviceImageCompressionControlFeaturesEXT::default()
             .image_compression_control(props.image_compression_control);
+        let mut host_copy_feats = vk::PhysicalDeviceHostImageCopyFeaturesEXT::default()
+            .host_image_copy(props.host_image_copy);
+        let core_feats = vk::PhysicalDeviceFeatures::default().sparse_binding(props.sparse_binding);
         let mut feats = vk::PhysicalDeviceFeatures2::default()
+            .features(core_feats)
             .push_next(&mut mem_prot_feats)
             .push_next(&mut img_comp_feats);
+        if dev_info.extensions[ExtId::ExtHostImageCopy as usize] {
+            feats = feats.push_next(&mut host_copy_feats);
+        }
+        let mut mem_prio_feats = vk::PhysicalDeviceMemoryPriorityFeaturesEXT::default()
+            .memory_priority(props.memory_priority);
+        if dev_info.extensions[ExtId::ExtMemoryPriority as usize] {
+            feats = feats.push_next(&mut mem_prio_feats);
+        }
+        let mut sync2_feats = vk::PhysicalDeviceSynchronization2Features::default()
+            .synchronization2(props.synchronization2);
+        if dev_info.extensions[ExtId::KhrSynchronization2 as usize] {
+            feats = feats.push_next(&mut sync2_feats);
+        }
+        let mut fault_feats =
+            vk::PhysicalDeviceFaultFeaturesEXT::default().device_fault(props.device_fault);
+        if dev_info.extensions[ExtId::ExtDeviceFault as usize] {
+            feats = feats.push_next(&mut fault_feats);
+        }
+        let mut placed_feats = vk::PhysicalDeviceMapMemoryPlacedFeaturesEXT::default()
+            .memory_map_placed(props.memory_map_placed)
+            .memory_unmap_reserve(props.memory_map_placed);
+        if dev_info.extensions[ExtId::ExtMapMemoryPlaced as usize] {
+            feats = feats.push_next(&mut placed_feats);
+        }
 
         let dev_info = vk::DeviceCreateInfo::default()
-            .queue_create_infos(slice::from_ref(&queue_info))
+            .queue_create_infos(&queue_infos[..queue_count])
             .enabled_extension_names(&enabled_exts)
             .push_next(&mut feats);
 
         // SAFETY: no VUID violation
-        let handle = unsafe {
+        unsafe {
             physical_dev
                 .instance
                 .handle
                 .create_device(physical_dev.handle, &dev_info, None)
         }
-        .or(Error::ctx("failed to create device"))?;
-
-        Ok(handle)
     }
 
     fn create_dispatch(handle: &ash::Device, physical_dev: &PhysicalDevice) -> DeviceDispatch {
         let instance_handle = &physical_dev.instance.handle;
         DeviceDispatch {
+            debug_utils: physical_dev
+                .instance
+                .debug_utils
+                .then(|| ash::ext::debug_utils::Device::new(instance_handle, handle)),
+            compression: ash::ext::image_compression_control::Device::new(instance_handle, handle),
+            fault: ash::ext::device_fault::Device::new(instance_handle, handle),
+            host_copy: ash::ext::host_image_copy::Device::new(instance_handle, handle),
+            host_memory: ash::ext::external_memory_host::Device::new(instance_handle, handle),
+            maintenance4: ash::khr::maintenance4::Device::new(instance_handle, handle),
+            map_memory2: ash::khr::map_memory2::Device::new(instance_handle, handle),
             memory: ash::khr::external_memory_fd::Device::new(instance_handle, handle),
             modifier: ash::ext::image_drm_format_modifier::Device::new(instance_handle, handle),
+            semaphore: ash::khr::external_semaphore_fd::Device::new(instance_handle, handle),
+            sync2: ash::khr::synchronization2::Device::new(instance_handle, handle),
         }
     }
 
     fn destroy(&self) {
+        // all suballocations have been freed, and each memory type has at most one empty slab
+        for slab in self.slabs.lock().unwrap().drain(..) {
+            self.free_slab(slab);
+        }
+
+        if !self.owned {
+            return;
+        }
+
         // SAFETY: no VUID violation
         unsafe {
             self.handle.destroy_device(None);
         }
     }
 
+    // Reserves a device memory before allocating it.  Drivers fail allocations unpredictably
+    // beyond maxMemoryAllocationCount.  Memories allocated by the owner of an adopted device are
+    // not accounted for.
+    fn reserve_memory(&self) -> Result<()> {
+        let max_count = self.properties().max_memory_allocation_count;
+        let res = self.memory_count.fetch_update(
+            atomic::Ordering::Relaxed,
+            atomic::Ordering::Relaxed,
+            |count| (count < max_count).then_some(count + 1),
+        );
+        if res.is_err() {
+            log::warn!("reached maxMemoryAllocationCount ({max_count})");
+            return Err(Error::TooManyAllocations);
+        }
+
+        Ok(())
+    }
+
+    // releases a device memory reserved by reserve_memory after freeing it or failing to
+    // allocate it
+    fn release_memory(&self) {
+        self.memory_count.fetch_sub(1, atomic::Ordering::Relaxed);
+    }
+
+    // returns true when the remaining device memories are running low, such that memories should
+    // be suballocated whenever possible
+    fn is_memory_count_low(&self) -> bool {
+        let max_count = self.properties().max_memory_allocation_count;
+        let count = self.memory_count.load(atomic::Ordering::Relaxed);
+        count >= max_count - max_count / 8
+    }
+
+    fn create_slab(&self, mt_idx: u32) -> Result<Slab> {
+        let handle = Memory::allocate_memory(
+            self,
+            SLAB_SIZE,
+            mt_idx,
+            None,
+            Default::default(),
+            None,
+            None,
+        )?;
+
+        let mt_flags = self.properties().memory_types[mt_idx as usize];
+        let ptr = if mt_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            let flags = vk::MemoryMapFlags::empty();
+
+            // SAFETY: no VUID violation
+            let ptr = unsafe { self.handle.map_memory(handle, 0, vk::WHOLE_SIZE, flags) };
+
+            match ptr {
+                Ok(ptr) => ptr::NonNull::new(ptr),
+                Err(err) => {
+                    self.free_slab(Slab::new(handle, mt_idx, None));
+                    return Err(err.into());
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Slab::new(handle, mt_idx, ptr))
+    }
+
+    fn free_slab(&self, slab: Slab) {
+        if slab.ptr.is_some() {
+            // SAFETY: no VUID violation
+            unsafe { self.handle.unmap_memory(slab.handle) };
+        }
+
+        // SAFETY: no VUID violation
+        unsafe { self.handle.free_memory(slab.handle, None) };
+        self.release_memory();
+    }
+
+    // Suballocations are aligned to nonCoherentAtomSize such that flushing or invalidating one
+    // does not affect its neighbors.  They are also aligned to bufferImageGranularity, although
+    // slabs only hold buffers for now.
+    fn suballocate(
+        &self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+        mt_idx: u32,
+    ) -> Result<(vk::DeviceMemory, vk::DeviceSize, vk::DeviceSize)> {
+        let props = self.properties();
+        let align = alignment
+            .max(props.non_coherent_atom_size)
+            .max(props.buffer_image_granularity);
+        let size = size.next_multiple_of(align);
+
+        let mut slabs = self.slabs.lock().unwrap();
+        for slab in slabs.iter_mut().filter(|slab| slab.mt_idx == mt_idx) {
+            if let Some(offset) = slab.alloc(size, align) {
+                return Ok((slab.handle, offset, size));
+            }
+        }
+
+        slabs.push(self.create_slab(mt_idx)?);
+        let slab = slabs.last_mut().unwrap();
+        let offset = slab.alloc(size, align).ok_or(Error::Unsupported)?;
+
+        Ok((slab.handle, offset, size))
+    }
+
+    fn free_suballocation(
+        &self,
+        handle: vk::DeviceMemory,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) {
+        let mut slabs = self.slabs.lock().unwrap();
+        let Some(idx) = slabs.iter().position(|slab| slab.handle == handle) else {
+            return;
+        };
+
+        slabs[idx].free(offset, size);
+
+        // keep one empty slab per memory type to avoid reallocating slabs when a suballocation
+        // is freed and reallocated repeatedly
+        let mt_idx = slabs[idx].mt_idx;
+        let empty_count = slabs
+            .iter()
+            .filter(|slab| slab.mt_idx == mt_idx && slab.is_empty())
+            .count();
+        if slabs[idx].is_empty() && empty_count > 1 {
+            let slab = slabs.swap_remove(idx);
+            self.free_slab(slab);
+        }
+    }
+
+    // returns the total size and the free size of the slabs
+    pub fn slab_sizes(&self) -> (vk::DeviceSize, vk::DeviceSize) {
+        let slabs = self.slabs.lock().unwrap();
+        let size = SLAB_SIZE * slabs.len() as vk::DeviceSize;
+        let free_size = slabs.iter().map(Slab::free_size).sum();
+
+        (size, free_size)
+    }
+
+    fn slab_ptr(&self, handle: vk::DeviceMemory) -> Option<ptr::NonNull<ffi::c_void>> {
+        let slabs = self.slabs.lock().unwrap();
+        slabs
+            .iter()
+            .find(|slab| slab.handle == handle)
+            .and_then(|slab| slab.ptr)
+    }
+
     fn instance_handle(&self) -> &ash::Instance {
         &self.physical_device.instance.handle
     }
@@ -760,42 +1880,508 @@ impl Device {
         &self.physical_device.properties
     }
 
-    fn get_queue(&self) -> vk::Queue {
-        // SAFETY: queue_family has 1 queue
-        unsafe {
-            self.handle
-                .get_device_queue(self.properties().queue_family, 0)
+    // names an object for validation layers and graphics debuggers
+    fn set_object_name<T: vk::Handle>(&self, object: T, name: &str) {
+        let Some(debug_utils) = &self.dispatch.debug_utils else {
+            return;
+        };
+        let Ok(name) = ffi::CString::new(name) else {
+            return;
+        };
+
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(object)
+            .object_name(&name);
+
+        // SAFETY: no VUID violation
+        let _ = unsafe { debug_utils.set_debug_utils_object_name(&name_info) };
+    }
+
+    // Logs the device fault report on VK_ERROR_DEVICE_LOST, and converts the result to an error.
+    fn device_error(&self, res: vk::Result) -> Error {
+        if res == vk::Result::ERROR_DEVICE_LOST
+            && !self.fault_reported.swap(true, atomic::Ordering::Relaxed)
+        {
+            match self.get_fault_report() {
+                Some(report) => log::error!("device lost: {}", report),
+                None => log::error!("device lost"),
+            }
+        }
+
+        Error::from(res)
+    }
+
+    fn get_fault_report(&self) -> Option<String> {
+        if !self.properties().device_fault {
+            return None;
+        }
+
+        let get_fault_info = self.dispatch.fault.fp().get_device_fault_info_ext;
+
+        let mut counts = vk::DeviceFaultCountsEXT::default();
+        // SAFETY: no VUID violation
+        unsafe { get_fault_info(self.handle.handle(), &mut counts, ptr::null_mut()) }
+            .result()
+            .ok()?;
+
+        let mut addr_infos =
+            vec![vk::DeviceFaultAddressInfoEXT::default(); counts.address_info_count as usize];
+        let mut vendor_infos =
+            vec![vk::DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as usize];
+        // vendor binary data is not useful in logs
+        counts.vendor_binary_size = 0;
+        let mut info = vk::DeviceFaultInfoEXT {
+            p_address_infos: addr_infos.as_mut_ptr(),
+            p_vendor_infos: vendor_infos.as_mut_ptr(),
+            ..Default::default()
+        };
+
+        // SAFETY: the arrays have the sizes specified by counts
+        let res = unsafe { get_fault_info(self.handle.handle(), &mut counts, &mut info) };
+        if res != vk::Result::SUCCESS && res != vk::Result::INCOMPLETE {
+            return None;
+        }
+        addr_infos.truncate(counts.address_info_count as usize);
+        vendor_infos.truncate(counts.vendor_info_count as usize);
+
+        let mut report = info
+            .description_as_c_str()
+            .map(|desc| desc.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for addr_info in addr_infos {
+            let _ = write!(
+                report,
+                "; {:?} at {:#x} (precision {:#x})",
+                addr_info.address_type, addr_info.reported_address, addr_info.address_precision
+            );
+        }
+        for vendor_info in vendor_infos {
+            let desc = vendor_info
+                .description_as_c_str()
+                .map(|desc| desc.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let _ = write!(
+                report,
+                "; {} (code {:#x}, data {:#x})",
+                desc, vendor_info.vendor_fault_code, vendor_info.vendor_fault_data
+            );
+        }
+
+        Some(report)
+    }
+
+    fn get_queues(&self) -> Vec<vk::Queue> {
+        let props = self.properties();
+
+        (0..props.queue_count)
+            .map(|idx| {
+                // SAFETY: queue_family has queue_count queues, or the adopted device has the queue
+                unsafe {
+                    self.handle
+                        .get_device_queue(props.queue_family, props.queue_index + idx)
+                }
+            })
+            .collect()
+    }
+
+    fn get_protected_queue(&self) -> Option<vk::Queue> {
+        if !self.properties().protected_queue {
+            return None;
+        }
+
+        let queue_info = vk::DeviceQueueInfo2::default()
+            .flags(vk::DeviceQueueCreateFlags::PROTECTED)
+            .queue_family_index(self.properties().queue_family)
+            .queue_index(0);
+
+        // SAFETY: queue_family has 1 protected-capable queue
+        let queue = unsafe { self.handle.get_device_queue2(&queue_info) };
+
+        Some(queue)
+    }
+
+    // Returns the properties of a format.  Known formats are probed on first use, which keeps
+    // device creation cheap.  External formats are added by probe_external_format.
+    fn format_properties(&self, fmt: vk::Format) -> Option<Arc<FormatProperties>> {
+        if let Some(fmt_props) = self.properties().formats.read().unwrap().get(&fmt) {
+            return fmt_props.clone();
+        }
+
+        let fmt_props = self.physical_device.probe_format(fmt).map(Arc::new);
+        let mut formats = self.properties().formats.write().unwrap();
+        match formats.entry(fmt) {
+            hash_map::Entry::Occupied(entry) => entry.get().clone(),
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(fmt_props.clone());
+                self.probe_cache_dirty
+                    .store(true, atomic::Ordering::Relaxed);
+                fmt_props
+            }
+        }
+    }
+
+    // The probe cache key, which changes with the device, the driver, and the extensions that
+    // affect format probing.  The driver id and the limits are also cached such that a driver
+    // update that changes them without bumping the version invalidates the cache.
+    fn probe_cache_key(&self) -> String {
+        let props = self.properties();
+        let hex = |uuid: &[u8]| {
+            uuid.iter()
+                .fold(String::new(), |s, b| s + &format!("{b:02x}"))
+        };
+
+        format!(
+            "{} {} {} {} {} {} {} {} {} {} {}",
+            hex(&props.device_uuid),
+            hex(&props.driver_uuid),
+            props.driver_version,
+            props.ext_image_drm_format_modifier as u8,
+            props.khr_format_feature_flags2 as u8,
+            props.driver_id.as_raw(),
+            props.max_image_dimension_2d,
+            props.max_buffer_size,
+            props.max_memory_allocation_count,
+            props.non_coherent_atom_size,
+            props.buffer_image_granularity
+        )
+    }
+
+    // Loads the format properties from the probe cache.  Formats that are probed later are
+    // written back to the cache once, when the device is dropped.  The cache is ignored when it
+    // is missing or stale.
+    pub fn load_probe_cache(&self, path: &Path) {
+        if self.probe_cache.set(path.to_path_buf()).is_err() {
+            return;
+        }
+
+        let Ok(text) = fs::read_to_string(path) else {
+            return;
+        };
+        let Some(entries) = parse_probe_cache(&text, &self.probe_cache_key()) else {
+            log::info!("ignoring stale probe cache {}", path.display());
+            return;
+        };
+
+        let mut formats = self.properties().formats.write().unwrap();
+        for (fmt, mods) in entries {
+            let Some(fmt_class) = known_format_class(fmt) else {
+                continue;
+            };
+            let fmt_props = mods.map(|mods| {
+                Arc::new(FormatProperties {
+                    format_class: fmt_class,
+                    modifiers: mods,
+                })
+            });
+            formats.entry(fmt).or_insert(fmt_props);
+        }
+    }
+
+    fn save_probe_cache(&self) {
+        if !self
+            .probe_cache_dirty
+            .swap(false, atomic::Ordering::Relaxed)
+        {
+            return;
+        }
+        let Some(path) = self.probe_cache.get() else {
+            return;
+        };
+
+        let text = {
+            let formats = self.properties().formats.read().unwrap();
+            // external formats are not cached because they are probed differently
+            let entries = formats
+                .iter()
+                .filter(|(&fmt, _)| known_format_class(fmt).is_some())
+                .map(|(&fmt, fmt_props)| {
+                    (
+                        fmt,
+                        fmt_props
+                            .as_ref()
+                            .map(|fmt_props| fmt_props.modifiers.as_slice()),
+                    )
+                });
+            serialize_probe_cache(&self.probe_cache_key(), entries)
+        };
+
+        // the cache is replaced by a rename such that concurrent processes never see partial
+        // writes
+        let tmp_path = path.with_extension(format!("tmp{}", process::id()));
+        if let Err(err) = fs::write(&tmp_path, text).and_then(|_| fs::rename(&tmp_path, path)) {
+            log::warn!("failed to save probe cache {}: {err}", path.display());
+            let _ = fs::remove_file(&tmp_path);
         }
     }
 
     fn format_plane_count(&self, fmt: vk::Format) -> u32 {
-        let fmt_props = self.properties().formats.get(&fmt).unwrap();
+        let fmt_props = self.format_properties(fmt).unwrap();
         fmt_props.format_class.plane_count as u32
     }
 
     fn format_block_size(&self, fmt: vk::Format, plane: u32) -> u32 {
-        let fmt_props = self.properties().formats.get(&fmt).unwrap();
+        let fmt_props = self.format_properties(fmt).unwrap();
         fmt_props.format_class.block_size[plane as usize] as u32
     }
 
+    fn format_features(&self, fmt: vk::Format, modifier: Modifier) -> vk::FormatFeatureFlags2 {
+        let fmt_props = self.format_properties(fmt).unwrap();
+        fmt_props
+            .modifiers
+            .iter()
+            .find(|mod_props| mod_props.drm_format_modifier == modifier.0)
+            .map_or(vk::FormatFeatureFlags2::empty(), |mod_props| {
+                mod_props.drm_format_modifier_tiling_features
+            })
+    }
+
+    pub fn probe_external_format(&self, fmt: vk::Format) -> Result<()> {
+        // the format plane count is derived from the modifiers
+        if !self.properties().ext_image_drm_format_modifier {
+            return Error::unsupported();
+        }
+
+        if self.format_properties(fmt).is_some() {
+            return Ok(());
+        }
+
+        let mods = self.physical_device.get_format_properties(fmt, 1);
+        // the memory plane count of linear is equal to the format plane count, and other modifiers
+        // can only add planes
+        let plane_count = mods
+            .iter()
+            .find(|mod_props| mod_props.drm_format_modifier == formats::MOD_LINEAR.0)
+            .or_else(|| {
+                mods.iter()
+                    .min_by_key(|mod_props| mod_props.drm_format_modifier_plane_count)
+            })
+            .ok_or(Error::Unsupported)?
+            .drm_format_modifier_plane_count;
+        if plane_count > 3 {
+            return Error::unsupported();
+        }
+
+        let fmt_props = FormatProperties {
+            format_class: formats::external_format_class(plane_count as u8),
+            modifiers: mods,
+        };
+        let mut formats = self.properties().formats.write().unwrap();
+        formats
+            .entry(fmt)
+            .or_default()
+            .get_or_insert(Arc::new(fmt_props));
+
+        Ok(())
+    }
+
+    pub fn features(&self) -> Features {
+        let props = self.properties();
+
+        let mut feats = Features::COPY_ENGINE;
+        if props.ext_image_drm_format_modifier {
+            feats |= Features::MODIFIERS;
+        }
+        if props.protected_memory {
+            feats |= Features::PROTECTED;
+        }
+        if props.image_compression_control {
+            feats |= Features::COMPRESSION_CONTROL;
+        }
+        if props.host_image_copy {
+            feats |= Features::HOST_IMAGE_COPY;
+        }
+        if props.software {
+            feats |= Features::SOFTWARE;
+        }
+        if props.device_group_size > 1 {
+            feats |= Features::DEVICE_GROUP;
+        }
+        if props.ext_external_memory_host {
+            feats |= Features::HOST_MEMORY;
+        }
+        if props.sparse_binding {
+            feats |= Features::SPARSE;
+        }
+        if props.memory_map_placed {
+            feats |= Features::PLACED_MAP;
+        }
+        if props.sync_fd_export {
+            feats |= Features::SYNC_FD_EXPORT;
+        }
+
+        feats
+    }
+
+    pub fn device_group(&self) -> (u32, u32) {
+        let props = self.properties();
+        (props.device_group_index, props.device_group_size)
+    }
+
+    // The logical device is always created from a single physical device, and its memories are
+    // not replicated across the device group.  But OPAQUE_FD memories can only be imported by a
+    // physical device with the same device and driver UUIDs, and another process may well pick
+    // another member of the group.  Refuse to share them rather than failing on import.
+    fn filter_external_memory(
+        &self,
+        feats: vk::ExternalMemoryFeatureFlags,
+        external_memory_type: vk::ExternalMemoryHandleTypeFlags,
+    ) -> vk::ExternalMemoryFeatureFlags {
+        if self.properties().device_group_size > 1
+            && external_memory_type == vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD
+        {
+            feats
+                & !(vk::ExternalMemoryFeatureFlags::EXPORTABLE
+                    | vk::ExternalMemoryFeatureFlags::IMPORTABLE)
+        } else {
+            feats
+        }
+    }
+
+    pub fn device_info(&self) -> DeviceInfo {
+        self.properties().info.clone()
+    }
+
+    pub fn memory_heaps(&self) -> Vec<MemoryHeap> {
+        let ext_memory_budget = self.properties().ext_memory_budget;
+
+        let mut budget_props = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut props = vk::PhysicalDeviceMemoryProperties2::default();
+        if ext_memory_budget {
+            props = props.push_next(&mut budget_props);
+        }
+
+        // SAFETY: no VUID violation
+        unsafe {
+            self.instance_handle()
+                .get_physical_device_memory_properties2(self.physical_device.handle, &mut props);
+        }
+
+        let heaps = props.memory_properties.memory_heaps_as_slice().to_vec();
+
+        heaps
+            .into_iter()
+            .enumerate()
+            .map(|(idx, heap)| {
+                let (budget, usage) = if ext_memory_budget {
+                    (budget_props.heap_budget[idx], budget_props.heap_usage[idx])
+                } else {
+                    (heap.size, 0)
+                };
+
+                MemoryHeap {
+                    size: heap.size,
+                    budget,
+                    usage,
+                    local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                }
+            })
+            .collect()
+    }
+
     pub fn memory_plane_count(&self, fmt: vk::Format, modifier: Modifier) -> Result<u32> {
-        let fmt_props = self
-            .properties()
-            .formats
-            .get(&fmt)
-            .ok_or(Error::Unsupported)?;
+        let fmt_props = self.format_properties(fmt).ok_or(Error::Unsupported)?;
+
+        fmt_props
+            .modifiers
+            .iter()
+            .find_map(|mod_props| {
+                if mod_props.drm_format_modifier == modifier.0 {
+                    Some(mod_props.drm_format_modifier_plane_count)
+                } else {
+                    None
+                }
+            })
+            .ok_or(Error::Unsupported)
+    }
+
+    // Returns the queue family that owns external resources between copies.  Without
+    // VK_EXT_queue_family_foreign, the resources are assumed to be used by other vulkan instances
+    // only.
+    fn foreign_queue_family(&self) -> u32 {
+        if self.properties().ext_queue_family_foreign {
+            vk::QUEUE_FAMILY_FOREIGN_EXT
+        } else {
+            vk::QUEUE_FAMILY_EXTERNAL
+        }
+    }
+
+    // Returns the handle type of external memory, which is empty when the memory is not external.
+    // The handle type chosen by the device is always a dma-buf, even when it is OPAQUE_FD.
+    fn external_memory_type(
+        &self,
+        external: bool,
+        external_memory_type: Option<vk::ExternalMemoryHandleTypeFlags>,
+    ) -> vk::ExternalMemoryHandleTypeFlags {
+        if !external {
+            return vk::ExternalMemoryHandleTypeFlags::empty();
+        }
+
+        external_memory_type.unwrap_or(self.properties().external_memory_type)
+    }
+
+    // returns true if imported fds of the handle type are known to be dma-bufs
+    fn is_dma_buf_type(&self, external_memory_type: vk::ExternalMemoryHandleTypeFlags) -> bool {
+        external_memory_type == vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT
+            || external_memory_type == self.properties().external_memory_type
+    }
+
+    pub fn buffer_external_memory(&self, buf_info: &BufferInfo) -> vk::ExternalMemoryFeatureFlags {
+        let external_memory_type = self.external_memory_type(true, buf_info.external_memory_type);
+        let external_info = vk::PhysicalDeviceExternalBufferInfo::default()
+            .flags(buf_info.flags)
+            .usage(buf_info.usage)
+            .handle_type(external_memory_type);
+        let mut external_props = vk::ExternalBufferProperties::default();
+
+        // SAFETY: no VUID violation
+        unsafe {
+            self.instance_handle()
+                .get_physical_device_external_buffer_properties(
+                    self.physical_device.handle,
+                    &external_info,
+                    &mut external_props,
+                );
+        }
+
+        self.filter_external_memory(
+            external_props
+                .external_memory_properties
+                .external_memory_features,
+            external_memory_type,
+        )
+    }
+
+    fn has_host_pointer_support(&self, buf_info: &BufferInfo) -> Result<()> {
+        if !self.properties().ext_external_memory_host || buf_info.external {
+            return Error::unsupported();
+        }
+
+        let external_info = vk::PhysicalDeviceExternalBufferInfo::default()
+            .flags(buf_info.flags)
+            .usage(buf_info.usage)
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT);
+        let mut external_props = vk::ExternalBufferProperties::default();
+
+        // SAFETY: no VUID violation
+        unsafe {
+            self.instance_handle()
+                .get_physical_device_external_buffer_properties(
+                    self.physical_device.handle,
+                    &external_info,
+                    &mut external_props,
+                );
+        }
+
+        let external_feats = external_props
+            .external_memory_properties
+            .external_memory_features;
+        if !external_feats.contains(vk::ExternalMemoryFeatureFlags::IMPORTABLE)
+            || external_feats.contains(vk::ExternalMemoryFeatureFlags::DEDICATED_ONLY)
+        {
+            return Error::unsupported();
+        }
 
-        fmt_props
-            .modifiers
-            .iter()
-            .find_map(|mod_props| {
-                if mod_props.drm_format_modifier == modifier.0 {
-                    Some(mod_props.drm_format_modifier_plane_count)
-                } else {
-                    None
-                }
-            })
-            .ok_or(Error::Unsupported)
+        Ok(())
     }
 
     pub fn buffer_properties(&self, buf_info: BufferInfo) -> Result<BufferProperties> {
@@ -804,42 +2390,43 @@ impl Device {
         {
             return Error::unsupported();
         }
+        if buf_info
+            .flags
+            .contains(vk::BufferCreateFlags::SPARSE_BINDING)
+            && !self.properties().sparse_binding
+        {
+            return Error::unsupported();
+        }
 
         if buf_info.external {
-            let external_info = vk::PhysicalDeviceExternalBufferInfo::default()
-                .flags(buf_info.flags)
-                .usage(buf_info.usage)
-                .handle_type(self.properties().external_memory_type);
-            let mut external_props = vk::ExternalBufferProperties::default();
-
-            // SAFETY: no VUID violation
-            unsafe {
-                self.instance_handle()
-                    .get_physical_device_external_buffer_properties(
-                        self.physical_device.handle,
-                        &external_info,
-                        &mut external_props,
-                    );
-            }
-
-            can_export_import(external_props.external_memory_properties)?;
+            can_export_import(self.buffer_external_memory(&buf_info))?;
+        }
+        if buf_info.host_pointer {
+            self.has_host_pointer_support(&buf_info)?;
         }
 
-        let mut max_size = self.properties().max_buffer_size;
+        // range limits apply to descriptors rather than to buffers
+        let max_size = self.properties().max_buffer_size;
+        let mut max_uniform_range = None;
         if buf_info
             .usage
             .contains(vk::BufferUsageFlags::UNIFORM_BUFFER)
         {
-            max_size = cmp::min(max_size, self.properties().max_uniform_buffer_range as _);
+            max_uniform_range = Some(self.properties().max_uniform_buffer_range as _);
         }
+        let mut max_storage_range = None;
         if buf_info
             .usage
             .contains(vk::BufferUsageFlags::STORAGE_BUFFER)
         {
-            max_size = cmp::min(max_size, self.properties().max_storage_buffer_range as _);
+            max_storage_range = Some(self.properties().max_storage_buffer_range as _);
         }
 
-        let props = BufferProperties { max_size };
+        let props = BufferProperties {
+            max_size,
+            max_uniform_range,
+            max_storage_range,
+        };
 
         Ok(props)
     }
@@ -854,26 +2441,61 @@ impl Device {
         }
     }
 
+    // returns the external memory features when img_info.external is set
+    //
+    // Classification queries the same images repeatedly.  The results are cached.
     fn has_image_support(
         &self,
         img_info: &ImageInfo,
         compression: vk::ImageCompressionFlagsEXT,
         modifier: Modifier,
-    ) -> Result<()> {
+    ) -> Result<vk::ExternalMemoryFeatureFlags> {
+        let key = ImageSupportKey::new(img_info, compression, modifier);
+        if let Some(&cached) = self.image_support.lock().unwrap().get(&key) {
+            return cached.ok_or(Error::Unsupported);
+        }
+
+        let res = self.query_image_support(img_info, compression, modifier);
+
+        // do not cache transient errors
+        let cached = match res {
+            Ok(external_feats) => Some(external_feats),
+            Err(Error::Unsupported) => None,
+            Err(Error::Code(code)) if code == vk::Result::ERROR_FORMAT_NOT_SUPPORTED.as_raw() => {
+                None
+            }
+            Err(_) => return res,
+        };
+        self.image_support.lock().unwrap().insert(key, cached);
+
+        res
+    }
+
+    fn query_image_support(
+        &self,
+        img_info: &ImageInfo,
+        compression: vk::ImageCompressionFlagsEXT,
+        modifier: Modifier,
+    ) -> Result<vk::ExternalMemoryFeatureFlags> {
         let tiling = self.get_image_tiling(modifier);
 
-        let mut comp_info = vk::ImageCompressionControlEXT::default().flags(compression);
+        let mut fixed_rates = fixed_rates_for(img_info, compression);
+        let mut comp_info = vk::ImageCompressionControlEXT::default()
+            .flags(compression)
+            .fixed_rate_flags(&mut fixed_rates);
         let mut fmt_info = vk::PhysicalDeviceImageFormatInfo2::default()
             .format(img_info.format)
-            .ty(vk::ImageType::TYPE_2D)
+            .ty(img_info.image_type)
             .tiling(tiling)
             .usage(img_info.usage)
             .flags(img_info.flags)
             .push_next(&mut comp_info);
 
+        let external_memory_type =
+            self.external_memory_type(img_info.external, img_info.external_memory_type);
         let mut external_info = vk::PhysicalDeviceExternalImageFormatInfo::default();
         if img_info.external {
-            external_info = external_info.handle_type(self.properties().external_memory_type);
+            external_info = external_info.handle_type(external_memory_type);
             fmt_info = fmt_info.push_next(&mut external_info);
         }
 
@@ -883,6 +2505,12 @@ impl Device {
             fmt_info = fmt_info.push_next(&mut mod_info);
         }
 
+        let mut fmt_list_info =
+            vk::ImageFormatListCreateInfo::default().view_formats(&img_info.view_formats);
+        if !img_info.view_formats.is_empty() {
+            fmt_info = fmt_info.push_next(&mut fmt_list_info);
+        }
+
         let mut wsi_info = WsiImageCreateInfoMESA::default();
         if img_info.scanout_hack && tiling != vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT {
             fmt_info = fmt_info.push_next(&mut wsi_info);
@@ -906,17 +2534,123 @@ impl Device {
                 )
         }?;
 
+        let external_feats = self.filter_external_memory(
+            external_props
+                .external_memory_properties
+                .external_memory_features,
+            external_memory_type,
+        );
         if img_info.external {
-            can_export_import(external_props.external_memory_properties)?;
+            can_export_import(external_feats)?;
         }
 
         if !comp_props.image_compression_flags.contains(compression) {
             return Error::unsupported();
         }
+        if compression == vk::ImageCompressionFlagsEXT::FIXED_RATE_EXPLICIT
+            && !comp_props
+                .image_compression_fixed_rate_flags
+                .intersects(img_info.fixed_rate)
+        {
+            return Error::unsupported();
+        }
+
+        Ok(external_feats)
+    }
+
+    pub fn image_external_memory(
+        &self,
+        img_info: &ImageInfo,
+        modifier: Modifier,
+    ) -> Result<vk::ExternalMemoryFeatureFlags> {
+        let compression = if !self.properties().image_compression_control {
+            vk::ImageCompressionFlagsEXT::DEFAULT
+        } else if img_info.no_compression {
+            vk::ImageCompressionFlagsEXT::DISABLED
+        } else if !img_info.fixed_rate.is_empty() {
+            vk::ImageCompressionFlagsEXT::FIXED_RATE_EXPLICIT
+        } else {
+            vk::ImageCompressionFlagsEXT::DEFAULT
+        };
+
+        self.has_image_support(img_info, compression, modifier)
+    }
+
+    // returns the tiling of a 3D image, which cannot have an explicit modifier
+    fn get_3d_image_tiling(&self, mods: &[Modifier]) -> Result<vk::ImageTiling> {
+        if mods.contains(&formats::MOD_LINEAR) {
+            Ok(vk::ImageTiling::LINEAR)
+        } else if mods.contains(&formats::MOD_INVALID) {
+            Ok(vk::ImageTiling::OPTIMAL)
+        } else {
+            Error::unsupported()
+        }
+    }
+
+    // checks that a 3D image of the extent is supported
+    fn has_3d_image_support(
+        &self,
+        img_info: &ImageInfo,
+        tiling: vk::ImageTiling,
+        extent: vk::Extent3D,
+    ) -> Result<()> {
+        let mut fmt_info = vk::PhysicalDeviceImageFormatInfo2::default()
+            .format(img_info.format)
+            .ty(vk::ImageType::TYPE_3D)
+            .tiling(tiling)
+            .usage(img_info.usage)
+            .flags(img_info.flags);
+
+        let external_memory_type =
+            self.external_memory_type(img_info.external, img_info.external_memory_type);
+        let mut external_info = vk::PhysicalDeviceExternalImageFormatInfo::default();
+        if img_info.external {
+            external_info = external_info.handle_type(external_memory_type);
+            fmt_info = fmt_info.push_next(&mut external_info);
+        }
+
+        let mut external_props = vk::ExternalImageFormatProperties::default();
+        let mut fmt_props = vk::ImageFormatProperties2::default();
+        if img_info.external {
+            fmt_props = fmt_props.push_next(&mut external_props)
+        }
+
+        // SAFETY: no VUID violation
+        unsafe {
+            self.instance_handle()
+                .get_physical_device_image_format_properties2(
+                    self.physical_device.handle,
+                    &fmt_info,
+                    &mut fmt_props,
+                )
+        }?;
+
+        let max_extent = fmt_props.image_format_properties.max_extent;
+        if extent.width > max_extent.width
+            || extent.height > max_extent.height
+            || extent.depth > max_extent.depth
+        {
+            return Error::unsupported();
+        }
+
+        if img_info.external {
+            can_export_import(
+                self.filter_external_memory(
+                    external_props
+                        .external_memory_properties
+                        .external_memory_features,
+                    external_memory_type,
+                ),
+            )?;
+        }
 
         Ok(())
     }
 
+    pub fn max_image_extent(&self) -> u32 {
+        self.properties().max_image_dimension_2d
+    }
+
     pub fn image_properties(
         &self,
         img_info: ImageInfo,
@@ -938,30 +2672,62 @@ impl Device {
             } else {
                 return Error::unsupported();
             }
+        } else if !img_info.fixed_rate.is_empty() {
+            if !self.properties().image_compression_control {
+                return Error::unsupported();
+            }
+            compression = vk::ImageCompressionFlagsEXT::FIXED_RATE_EXPLICIT;
+        }
+
+        if img_info.ycbcr_conversion && !self.properties().sampler_ycbcr_conversion {
+            return Error::unsupported();
+        }
+        if img_info
+            .usage
+            .contains(vk::ImageUsageFlags::HOST_TRANSFER_EXT)
+            && !self.properties().host_image_copy
+        {
+            return Error::unsupported();
+        }
+        if !img_info.view_formats.is_empty() && !self.properties().khr_image_format_list {
+            return Error::unsupported();
         }
 
-        let mut required_feats = vk::FormatFeatureFlags::empty();
+        let mut required_feats = vk::FormatFeatureFlags2::empty();
         if img_info.usage.contains(vk::ImageUsageFlags::SAMPLED) {
-            required_feats |= vk::FormatFeatureFlags::SAMPLED_IMAGE;
+            required_feats |= vk::FormatFeatureFlags2::SAMPLED_IMAGE;
         }
         if img_info.usage.contains(vk::ImageUsageFlags::STORAGE) {
-            required_feats |= vk::FormatFeatureFlags::STORAGE_IMAGE;
+            required_feats |= vk::FormatFeatureFlags2::STORAGE_IMAGE;
         }
         if img_info
             .usage
             .contains(vk::ImageUsageFlags::COLOR_ATTACHMENT)
         {
-            required_feats |= vk::FormatFeatureFlags::COLOR_ATTACHMENT;
+            required_feats |= vk::FormatFeatureFlags2::COLOR_ATTACHMENT;
+        }
+        // this is only reported in the 64-bit features, which VK_EXT_host_image_copy depends on
+        if img_info
+            .usage
+            .contains(vk::ImageUsageFlags::HOST_TRANSFER_EXT)
+            && self.properties().khr_format_feature_flags2
+        {
+            required_feats |= vk::FormatFeatureFlags2::HOST_IMAGE_TRANSFER_EXT;
         }
+        if img_info.flags.contains(vk::ImageCreateFlags::DISJOINT) {
+            required_feats |= vk::FormatFeatureFlags2::DISJOINT;
+        }
+
+        // a sampler YCbCr conversion requires at least one of the chroma locations
+        let ycbcr_feats = vk::FormatFeatureFlags2::MIDPOINT_CHROMA_SAMPLES
+            | vk::FormatFeatureFlags2::COSITED_CHROMA_SAMPLES;
 
         let fmt_props = self
-            .properties()
-            .formats
-            .get(&img_info.format)
+            .format_properties(img_info.format)
             .ok_or(Error::Unsupported)?;
 
-        // get supported modifiers
-        let mut mods: Vec<Modifier> = fmt_props
+        // get supported modifiers and their tiling features
+        let mut mods: Vec<(Modifier, vk::FormatFeatureFlags2)> = fmt_props
             .modifiers
             .iter()
             .filter_map(|mod_props| {
@@ -969,10 +2735,11 @@ impl Device {
                 if !modifier.is_invalid() && candidate != modifier {
                     return None;
                 }
-                if !mod_props
-                    .drm_format_modifier_tiling_features
-                    .contains(required_feats)
-                {
+                let tiling_feats = mod_props.drm_format_modifier_tiling_features;
+                if !tiling_feats.contains(required_feats) {
+                    return None;
+                }
+                if img_info.ycbcr_conversion && !tiling_feats.intersects(ycbcr_feats) {
                     return None;
                 }
 
@@ -980,7 +2747,7 @@ impl Device {
                     .has_image_support(&img_info, compression, candidate)
                     .is_ok()
                 {
-                    Some(candidate)
+                    Some((candidate, tiling_feats))
                 } else {
                     None
                 }
@@ -993,17 +2760,38 @@ impl Device {
 
         // without modifier support, pick optimal when both are supported
         if !self.properties().ext_image_drm_format_modifier && mods.len() > 1 {
-            mods = vec![formats::MOD_INVALID];
+            mods.retain(|(candidate, _)| candidate.is_invalid());
         }
 
         let props = ImageProperties {
-            max_extent: self.properties().max_image_dimension_2d,
-            modifiers: mods,
+            modifiers: mods.iter().map(|(candidate, _)| *candidate).collect(),
+            modifier_features: mods.iter().map(|(_, feats)| *feats).collect(),
         };
 
         Ok(props)
     }
 
+    fn get_host_pointer_mt_mask(&self, ptr: ptr::NonNull<ffi::c_void>) -> u32 {
+        let mut ptr_props = vk::MemoryHostPointerPropertiesEXT::default();
+
+        // SAFETY: VUID-vkGetMemoryHostPointerPropertiesEXT-pHostPointer-01754 violation if ptr is
+        // not a host allocation
+        let _ = unsafe {
+            (self
+                .dispatch
+                .host_memory
+                .fp()
+                .get_memory_host_pointer_properties_ext)(
+                self.handle.handle(),
+                vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT,
+                ptr.as_ptr(),
+                &mut ptr_props,
+            )
+        };
+
+        ptr_props.memory_type_bits
+    }
+
     fn get_dma_buf_mt_mask(&self, dmabuf: BorrowedFd) -> u32 {
         // ignore self.properties().external_memory_type
         let external_memory_type = vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT;
@@ -1041,17 +2829,44 @@ impl Device {
             })
             .collect()
     }
+
+    pub fn memory_type_heap_size(&self, mt_idx: u32) -> vk::DeviceSize {
+        self.properties().memory_type_heap_sizes[mt_idx as usize]
+    }
 }
 
 impl Drop for Device {
     fn drop(&mut self) {
+        self.save_probe_cache();
         self.destroy();
     }
 }
 
+// The mapping of a memory.  Nested maps share the mapping, and the memory is unmapped when the
+// last map is unmapped.
+#[derive(Default)]
+struct MemoryMapping {
+    ptr: Option<ptr::NonNull<ffi::c_void>>,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    count: u32,
+    placed: bool,
+}
+
+// SAFETY: ptr is only dereferenced by the owners of the mappings
+unsafe impl Send for MemoryMapping {}
+
 pub struct Memory {
     device: Arc<Device>,
     handle: vk::DeviceMemory,
+    // the offset is non-zero only when the memory is suballocated from a slab
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    suballocated: bool,
+    // the handle type that the memory is exported as, which is empty when not external
+    external_memory_type: vk::ExternalMemoryHandleTypeFlags,
+    // suballocations are never mapped here because slabs stay mapped
+    mapping: Mutex<MemoryMapping>,
 }
 
 impl Memory {
@@ -1059,38 +2874,147 @@ impl Memory {
         device: Arc<Device>,
         size: vk::DeviceSize,
         mt_idx: u32,
-        dedicated_info: vk::MemoryDedicatedAllocateInfo,
-        external: bool,
+        dedicated_info: Option<vk::MemoryDedicatedAllocateInfo>,
+        external_memory_type: vk::ExternalMemoryHandleTypeFlags,
         dmabuf: Option<OwnedFd>,
+        priority: Option<f32>,
+    ) -> Result<Self> {
+        let handle = Self::allocate_memory(
+            &device,
+            size,
+            mt_idx,
+            dedicated_info,
+            external_memory_type,
+            dmabuf,
+            priority,
+        )?;
+        let mem = Self {
+            device,
+            handle,
+            offset: 0,
+            size,
+            suballocated: false,
+            external_memory_type,
+            mapping: Default::default(),
+        };
+
+        Ok(mem)
+    }
+
+    fn suballocate(
+        device: Arc<Device>,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+        mt_idx: u32,
+    ) -> Result<Self> {
+        let (handle, offset, size) = device.suballocate(size, alignment, mt_idx)?;
+        let mem = Self {
+            device,
+            handle,
+            offset,
+            size,
+            suballocated: true,
+            external_memory_type: Default::default(),
+            mapping: Default::default(),
+        };
+
+        Ok(mem)
+    }
+
+    fn with_host_pointer(
+        buf: &Buffer,
+        mt_idx: u32,
+        ptr: ptr::NonNull<ffi::c_void>,
     ) -> Result<Self> {
-        let handle =
-            Self::allocate_memory(&device, size, mt_idx, dedicated_info, external, dmabuf)?;
-        let mem = Self { device, handle };
+        let dev = &buf.device;
+
+        // both the pointer and the size must be aligned
+        let align = dev.properties().min_imported_host_pointer_alignment;
+        if (ptr.as_ptr() as vk::DeviceSize) % align != 0 {
+            return Error::user();
+        }
+        let size = buf.size.next_multiple_of(align);
+
+        let mut import_info = vk::ImportMemoryHostPointerInfoEXT::default()
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT)
+            .host_pointer(ptr.as_ptr());
+        let mem_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(mt_idx)
+            .push_next(&mut import_info);
+
+        dev.reserve_memory()?;
+        // SAFETY: VUID-VkImportMemoryHostPointerInfoEXT-pHostPointer-01758 violation if ptr does
+        // not point to size bytes
+        let handle = unsafe { dev.handle.allocate_memory(&mem_info, None) }
+            .inspect_err(|_| dev.release_memory())?;
+        let mem = Self {
+            device: dev.clone(),
+            handle,
+            offset: 0,
+            size,
+            suballocated: false,
+            external_memory_type: Default::default(),
+            mapping: Default::default(),
+        };
 
         Ok(mem)
     }
 
     fn with_buffer(buf: &Buffer, mt_idx: u32, dmabuf: Option<OwnedFd>) -> Result<Self> {
-        let dedicated_info = vk::MemoryDedicatedAllocateInfo::default().buffer(buf.handle);
+        // small buffers that are never exported or imported share slabs, unless they have their
+        // own priorities and device memories are not running low
+        if !buf.dedicated
+            && buf.external_memory_type.is_empty()
+            && (buf.priority.is_none() || buf.device.is_memory_count_low())
+            && dmabuf.is_none()
+            && buf.size <= MAX_SUBALLOCATION_SIZE
+        {
+            return Self::suballocate(buf.device.clone(), buf.size, buf.alignment, mt_idx);
+        }
+
+        // dedicate when required or when importing
+        let dedicated_info = if buf.dedicated || dmabuf.is_some() {
+            Some(vk::MemoryDedicatedAllocateInfo::default().buffer(buf.handle))
+        } else {
+            None
+        };
         Self::new(
             buf.device.clone(),
             buf.size,
             mt_idx,
             dedicated_info,
-            buf.external,
+            buf.external_memory_type,
             dmabuf,
+            buf.priority,
         )
     }
 
-    fn with_image(img: &Image, mt_idx: u32, dmabuf: Option<OwnedFd>) -> Result<Self> {
-        let dedicated_info = vk::MemoryDedicatedAllocateInfo::default().image(img.handle);
+    // plane is the memory plane of a disjoint image
+    fn with_image(
+        img: &Image,
+        plane: Option<usize>,
+        mt_idx: u32,
+        dmabuf: Option<OwnedFd>,
+    ) -> Result<Self> {
+        let (size, dedicated_info) = match plane {
+            // disjoint images cannot have dedicated memories
+            Some(plane) => (img.disjoint_sizes[plane], None),
+            // dedicate when required or when importing
+            None if img.dedicated || dmabuf.is_some() => (
+                img.size,
+                Some(vk::MemoryDedicatedAllocateInfo::default().image(img.handle)),
+            ),
+            None => (img.size, None),
+        };
         Self::new(
             img.device.clone(),
-            img.size,
+            size,
             mt_idx,
             dedicated_info,
-            img.external,
+            img.external_memory_type,
             dmabuf,
+            img.priority,
         )
     }
 
@@ -1098,108 +3022,297 @@ impl Memory {
         dev: &Device,
         size: vk::DeviceSize,
         mt_idx: u32,
-        mut dedicated_info: vk::MemoryDedicatedAllocateInfo,
-        external: bool,
+        dedicated_info: Option<vk::MemoryDedicatedAllocateInfo>,
+        external_memory_type: vk::ExternalMemoryHandleTypeFlags,
         dmabuf: Option<OwnedFd>,
+        priority: Option<f32>,
     ) -> Result<vk::DeviceMemory> {
         let mut mem_info = vk::MemoryAllocateInfo::default()
             .allocation_size(size)
-            .memory_type_index(mt_idx)
-            .push_next(&mut dedicated_info);
+            .memory_type_index(mt_idx);
+
+        let mut dedicated_info = dedicated_info;
+        if let Some(dedicated_info) = dedicated_info.as_mut() {
+            mem_info = mem_info.push_next(dedicated_info);
+        }
 
         let mut export_info = vk::ExportMemoryAllocateInfo::default();
-        if external {
-            export_info = export_info.handle_types(dev.properties().external_memory_type);
+        if !external_memory_type.is_empty() {
+            export_info = export_info.handle_types(external_memory_type);
             mem_info = mem_info.push_next(&mut export_info);
         }
 
+        let mut prio_info = vk::MemoryPriorityAllocateInfoEXT::default();
+        if let Some(prio) = priority {
+            // the priority is a hint and is ignored when unsupported
+            if dev.properties().memory_priority {
+                prio_info = prio_info.priority(prio);
+                mem_info = mem_info.push_next(&mut prio_info);
+            }
+        }
+
         let mut raw_fd: RawFd = -1;
         let mut import_info = vk::ImportMemoryFdInfoKHR::default();
         if let Some(dmabuf) = dmabuf {
-            let mt_mask = dev.get_dma_buf_mt_mask(dmabuf.as_fd());
-            if mt_mask & (1 << mt_idx) == 0 {
-                return Error::user();
+            if dev.is_dma_buf_type(external_memory_type) {
+                // undersized dma-bufs would fault on access rather than fail here on some drivers
+                if utils::seek_end(&dmabuf)? < size {
+                    return Error::user();
+                }
+
+                let mt_mask = dev.get_dma_buf_mt_mask(dmabuf.as_fd());
+                if mt_mask & (1 << mt_idx) == 0 {
+                    return Error::user();
+                }
             }
 
             raw_fd = dmabuf.into_raw_fd();
-            import_info = import_info
-                .handle_type(dev.properties().external_memory_type)
-                .fd(raw_fd);
+            import_info = import_info.handle_type(external_memory_type).fd(raw_fd);
             mem_info = mem_info.push_next(&mut import_info);
         }
 
-        // SAFETY:
-        //
-        //  - VUID-VkImportMemoryFdInfoKHR-fd-00668 violation which seems bogus
-        //  - VUID-VkImportMemoryFdInfoKHR-handleType-00670 violation if dmabuf does not have the
-        //    correct memory handle type
-        //  - we don't validate dma-buf size because drivers are required to perform sufficient
-        //    validations
-        //  - on radv+gfx, potential VUID violations for
-        //    - VUID-VkMemoryAllocateInfo-allocationSize-01742
-        //    - VUID-VkMemoryDedicatedAllocateInfo-image-01878
-        //    - VUID-VkMemoryDedicatedAllocateInfo-buffer-01879
-        let handle = unsafe { dev.handle.allocate_memory(&mem_info, None) };
-
-        let handle = handle.map_err(|err| {
+        let handle = dev.reserve_memory().and_then(|_| {
+            // SAFETY:
+            //
+            //  - VUID-VkImportMemoryFdInfoKHR-fd-00668 violation which seems bogus
+            //  - VUID-VkImportMemoryFdInfoKHR-handleType-00670 violation if dmabuf does not have
+            //    the correct memory handle type
+            //  - on radv+gfx, potential VUID violations for
+            //    - VUID-VkMemoryAllocateInfo-allocationSize-01742
+            //    - VUID-VkMemoryDedicatedAllocateInfo-image-01878
+            //    - VUID-VkMemoryDedicatedAllocateInfo-buffer-01879
+            unsafe { dev.handle.allocate_memory(&mem_info, None) }.map_err(|res| {
+                dev.release_memory();
+                Error::from(res)
+            })
+        });
+
+        let handle = handle.inspect_err(|_| {
             if raw_fd >= 0 {
                 // SAFETY: raw_fd is from dmabuf.into_raw_fd
                 unsafe {
                     OwnedFd::from_raw_fd(raw_fd);
                 }
             }
-
-            err
         })?;
 
         Ok(handle)
     }
 
     fn destroy(&self) {
+        if self.suballocated {
+            self.device
+                .free_suballocation(self.handle, self.offset, self.size);
+            return;
+        }
+
         // SAFETY: no VUID violation
         unsafe {
             self.device.handle.free_memory(self.handle, None);
         }
+        self.device.release_memory();
+    }
+
+    fn set_name(&self, name: &str) {
+        // slabs are shared
+        if !self.suballocated {
+            self.device.set_object_name(self.handle, name);
+        }
+    }
+
+    // the offset is non-zero only when the memory is suballocated
+    pub fn handle(&self) -> (vk::DeviceMemory, vk::DeviceSize) {
+        (self.handle, self.offset)
     }
 
     pub fn export_dma_buf(&self) -> Result<OwnedFd> {
+        if self.suballocated || self.external_memory_type.is_empty() {
+            return Error::user();
+        }
+
         let fd_info = vk::MemoryGetFdInfoKHR::default()
             .memory(self.handle)
-            .handle_type(self.device.properties().external_memory_type);
+            .handle_type(self.external_memory_type);
+
+        // SAFETY: no VUID violation
+        let raw_fd = unsafe { self.device.dispatch.memory.get_memory_fd(&fd_info) }?;
+        // SAFETY: raw_fd is a valid dma-buf
+        let dmabuf = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+
+        Ok(dmabuf)
+    }
+
+    pub fn map(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<*mut ffi::c_void> {
+        if self.suballocated {
+            let ptr = self.device.slab_ptr(self.handle).ok_or(Error::User)?;
+            let offset = usize::try_from(self.offset + offset)?;
+            return Ok(ptr.as_ptr().cast::<u8>().wrapping_add(offset).cast());
+        }
+
+        let size = if size == vk::WHOLE_SIZE {
+            self.size.checked_sub(offset).ok_or(Error::User)?
+        } else {
+            size
+        };
+
+        let mut mapping = self.mapping.lock().unwrap();
+        if let Some(ptr) = mapping.ptr {
+            // nested maps return the existing mapping, which must contain the range
+            let end = offset.checked_add(size).ok_or(Error::User)?;
+            if offset < mapping.offset || end > mapping.offset + mapping.size {
+                return Error::user();
+            }
+            let offset = usize::try_from(offset - mapping.offset)?;
+            mapping.count += 1;
+            return Ok(ptr.as_ptr().cast::<u8>().wrapping_add(offset).cast());
+        }
+
+        let ptr = self.map_memory(offset, size, None)?;
+        *mapping = MemoryMapping {
+            ptr: ptr::NonNull::new(ptr),
+            offset,
+            size,
+            count: 1,
+            placed: false,
+        };
+
+        Ok(ptr)
+    }
+
+    // maps the entire memory at addr
+    pub fn map_placed(&self, addr: ptr::NonNull<ffi::c_void>) -> Result<*mut ffi::c_void> {
+        // slabs are shared and cannot be placed
+        if self.suballocated || !self.device.properties().memory_map_placed {
+            return Error::unsupported();
+        }
+
+        let align = self.device.properties().min_placed_memory_map_alignment;
+        if (addr.as_ptr() as vk::DeviceSize) % align != 0 {
+            return Error::user();
+        }
+
+        // an existing mapping cannot be moved
+        let mut mapping = self.mapping.lock().unwrap();
+        if mapping.ptr.is_some() {
+            return Error::user();
+        }
+
+        let ptr = self.map_memory(0, vk::WHOLE_SIZE, Some(addr))?;
+        *mapping = MemoryMapping {
+            ptr: ptr::NonNull::new(ptr),
+            offset: 0,
+            size: self.size,
+            count: 1,
+            placed: true,
+        };
+
+        Ok(ptr)
+    }
+
+    fn map_memory(
+        &self,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        addr: Option<ptr::NonNull<ffi::c_void>>,
+    ) -> Result<*mut ffi::c_void> {
+        if !self.device.properties().khr_map_memory2 {
+            let flags = vk::MemoryMapFlags::empty();
+
+            // SAFETY: no VUID violation because the caller always maps the entire memory
+            let ptr = unsafe {
+                self.device
+                    .handle
+                    .map_memory(self.handle, offset, size, flags)
+            }?;
+
+            return Ok(ptr);
+        }
+
+        let mut map_info = vk::MemoryMapInfoKHR::default()
+            .memory(self.handle)
+            .offset(offset)
+            .size(size);
+        let mut placed_info = vk::MemoryMapPlacedInfoEXT::default();
+        if let Some(addr) = addr {
+            placed_info = placed_info.placed_address(addr.as_ptr());
+            map_info = map_info
+                .flags(vk::MemoryMapFlags::PLACED_EXT)
+                .push_next(&mut placed_info);
+        }
 
-        // SAFETY: no VUID violation
-        let raw_fd = unsafe { self.device.dispatch.memory.get_memory_fd(&fd_info) }?;
-        // SAFETY: raw_fd is a valid dma-buf
-        let dmabuf = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+        let map_memory2 = self.device.dispatch.map_memory2.fp().map_memory2_khr;
+        let mut ptr = ptr::null_mut();
+        // SAFETY: no VUID violation because the caller always maps the entire memory, and addr
+        // is aligned to minPlacedMemoryMapAlignment
+        unsafe { map_memory2(self.device.handle.handle(), &map_info, &mut ptr) }.result()?;
 
-        Ok(dmabuf)
+        Ok(ptr)
     }
 
-    pub fn map(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<*mut ffi::c_void> {
-        let flags = vk::MemoryMapFlags::empty();
+    // Unmaps a map.  When the last map of a placed mapping is unmapped, the address range stays
+    // reserved.
+    pub fn unmap(&self) {
+        // slabs stay mapped
+        if self.suballocated {
+            return;
+        }
 
-        // SAFETY: no VUID violation because the caller always maps the entire memory
-        let ptr = unsafe {
-            self.device
-                .handle
-                .map_memory(self.handle, offset, size, flags)
-        }?;
+        let mut mapping = self.mapping.lock().unwrap();
+        if mapping.ptr.is_none() {
+            return;
+        }
+        mapping.count -= 1;
+        if mapping.count > 0 {
+            return;
+        }
+        let reserve = mapping.placed;
+        *mapping = Default::default();
 
-        Ok(ptr)
+        if !self.device.properties().khr_map_memory2 {
+            // SAFETY: no VUID violation
+            unsafe { self.device.handle.unmap_memory(self.handle) };
+            return;
+        }
+
+        let mut unmap_info = vk::MemoryUnmapInfoKHR::default().memory(self.handle);
+        if reserve {
+            unmap_info = unmap_info.flags(vk::MemoryUnmapFlagsKHR::RESERVE_EXT);
+        }
+
+        let unmap_memory2 = self.device.dispatch.map_memory2.fp().unmap_memory2_khr;
+        // SAFETY: no VUID violation because reserve is true only for placed mappings
+        let _ = unsafe { unmap_memory2(self.device.handle.handle(), &unmap_info) };
     }
 
-    pub fn unmap(&self) {
-        // SAFETY: no VUID violation
-        unsafe { self.device.handle.unmap_memory(self.handle) };
+    // aligns a mapped memory range to nonCoherentAtomSize, or extends it to the end of the memory
+    fn mapped_range(
+        &self,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> vk::MappedMemoryRange<'static> {
+        let atom = self.device.properties().non_coherent_atom_size;
+        let offset = self.offset + offset;
+        let start = offset / atom * atom;
+        let end = offset.saturating_add(size).next_multiple_of(atom);
+        let size = if self.suballocated {
+            // suballocations are aligned to nonCoherentAtomSize
+            end.min(self.offset + self.size) - start
+        } else if end >= self.size {
+            vk::WHOLE_SIZE
+        } else {
+            end - start
+        };
+
+        vk::MappedMemoryRange::default()
+            .memory(self.handle)
+            .offset(start)
+            .size(size)
     }
 
     pub fn flush(&self, offset: vk::DeviceSize, size: vk::DeviceSize) {
-        let range = vk::MappedMemoryRange::default()
-            .memory(self.handle)
-            .offset(offset)
-            .size(size);
+        let range = self.mapped_range(offset, size);
 
-        // SAFETY: no VUID violation because the caller always flushes the entire memory
+        // SAFETY: no VUID violation because the range is aligned to nonCoherentAtomSize
         let _ = unsafe {
             self.device
                 .handle
@@ -1208,12 +3321,9 @@ impl Memory {
     }
 
     pub fn invalidate(&self, offset: vk::DeviceSize, size: vk::DeviceSize) {
-        let range = vk::MappedMemoryRange::default()
-            .memory(self.handle)
-            .offset(offset)
-            .size(size);
+        let range = self.mapped_range(offset, size);
 
-        // SAFETY: no VUID violation because the caller always invalidates the entire memory
+        // SAFETY: no VUID violation because the range is aligned to nonCoherentAtomSize
         let _ = unsafe {
             self.device
                 .handle
@@ -1233,24 +3343,52 @@ pub struct Buffer {
     handle: vk::Buffer,
 
     size: vk::DeviceSize,
+    alignment: vk::DeviceSize,
     mt_mask: u32,
-    external: bool,
+    // whether the memory is dedicated, because the driver requires it or because the external
+    // memory is DEDICATED_ONLY
+    dedicated: bool,
+    // the handle type of external memory, which is empty when not external
+    external_memory_type: vk::ExternalMemoryHandleTypeFlags,
+    host_pointer: bool,
+    protected: bool,
+    priority: Option<f32>,
 
     memory: Option<Memory>,
+    // the offsets, the sizes, and the memories of the bound regions of a sparse buffer
+    sparse: bool,
+    sparse_memories: Vec<(vk::DeviceSize, vk::DeviceSize, Memory)>,
 }
 
 impl Buffer {
     fn new(device: Arc<Device>, buf_info: BufferInfo, size: vk::DeviceSize) -> Result<Self> {
-        let handle = Self::create_buffer(&device, &buf_info, size)?;
+        let external_memory_type =
+            device.external_memory_type(buf_info.external, buf_info.external_memory_type);
+        let handle = Self::create_buffer(&device, &buf_info, external_memory_type, size)?;
         let mut buf = Self {
             device,
             handle,
             size: 0,
+            alignment: 1,
             mt_mask: 0,
-            external: buf_info.external,
+            dedicated: false,
+            external_memory_type,
+            host_pointer: buf_info.host_pointer,
+            protected: buf_info.flags.contains(vk::BufferCreateFlags::PROTECTED),
+            priority: buf_info.priority,
             memory: None,
+            sparse: buf_info
+                .flags
+                .contains(vk::BufferCreateFlags::SPARSE_BINDING),
+            sparse_memories: Vec::new(),
         };
         buf.init_memory_requirements();
+        if buf_info.external {
+            buf.dedicated |= buf
+                .device
+                .buffer_external_memory(&buf_info)
+                .contains(vk::ExternalMemoryFeatureFlags::DEDICATED_ONLY);
+        }
 
         Ok(buf)
     }
@@ -1270,6 +3408,39 @@ impl Buffer {
         Ok(buf)
     }
 
+    // computes the layout and the memory types of the buffer that with_constraint would create,
+    // without creating the buffer
+    pub fn dry_run(
+        dev: &Device,
+        buf_info: &BufferInfo,
+        size: vk::DeviceSize,
+        con: Option<Constraint>,
+    ) -> Result<(Layout, Vec<(u32, vk::MemoryPropertyFlags)>)> {
+        let external_memory_type =
+            dev.external_memory_type(buf_info.external, buf_info.external_memory_type);
+        let reqs = Self::with_create_info(buf_info, external_memory_type, size, |create_info| {
+            let reqs_info = vk::DeviceBufferMemoryRequirements::default().create_info(create_info);
+            let mut reqs = vk::MemoryRequirements2::default();
+
+            // SAFETY: no VUID violation
+            unsafe {
+                dev.dispatch
+                    .maintenance4
+                    .get_device_buffer_memory_requirements(&reqs_info, &mut reqs);
+            }
+
+            reqs.memory_requirements
+        });
+
+        let mut size = reqs.size;
+        if let Some(con) = con {
+            size = size.next_multiple_of(con.size_align);
+        }
+        let mts = dev.memory_types(reqs.memory_type_bits, vk::MemoryPropertyFlags::empty());
+
+        Ok((Layout::new().size(size), mts))
+    }
+
     pub fn with_layout(
         dev: Arc<Device>,
         buf_info: BufferInfo,
@@ -1282,7 +3453,13 @@ impl Buffer {
         if buf.size > layout.size {
             return Error::user();
         }
-        if let Some(dmabuf) = dmabuf {
+        if let Some(dmabuf) =
+            dmabuf.filter(|_| buf.device.is_dma_buf_type(buf.external_memory_type))
+        {
+            if utils::seek_end(dmabuf)? < layout.size {
+                return Error::user();
+            }
+
             buf.mt_mask &= buf.device.get_dma_buf_mt_mask(dmabuf);
             if buf.mt_mask == 0 {
                 return Error::user();
@@ -1295,9 +3472,34 @@ impl Buffer {
     fn create_buffer(
         dev: &Device,
         buf_info: &BufferInfo,
+        external_memory_type: vk::ExternalMemoryHandleTypeFlags,
         size: vk::DeviceSize,
     ) -> Result<vk::Buffer> {
-        let external = buf_info.external;
+        Self::with_create_info(buf_info, external_memory_type, size, |create_info| {
+            // SAFETY: no VUID violation
+            let handle = unsafe { dev.handle.create_buffer(create_info, None) }?;
+
+            Ok(handle)
+        })
+    }
+
+    // calls f with the create info of a buffer
+    fn with_create_info<T, F>(
+        buf_info: &BufferInfo,
+        external_memory_type: vk::ExternalMemoryHandleTypeFlags,
+        size: vk::DeviceSize,
+        f: F,
+    ) -> T
+    where
+        F: FnOnce(&vk::BufferCreateInfo) -> T,
+    {
+        let handle_types = if !external_memory_type.is_empty() {
+            external_memory_type
+        } else if buf_info.host_pointer {
+            vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT
+        } else {
+            vk::ExternalMemoryHandleTypeFlags::empty()
+        };
 
         let mut buf_info = vk::BufferCreateInfo::default()
             .flags(buf_info.flags)
@@ -1305,20 +3507,18 @@ impl Buffer {
             .usage(buf_info.usage);
 
         let mut external_info = vk::ExternalMemoryBufferCreateInfo::default();
-        if external {
-            external_info = external_info.handle_types(dev.properties().external_memory_type);
+        if !handle_types.is_empty() {
+            external_info = external_info.handle_types(handle_types);
             buf_info = buf_info.push_next(&mut external_info);
         }
 
-        // SAFETY: no VUID violation
-        let handle = unsafe { dev.handle.create_buffer(&buf_info, None) }?;
-
-        Ok(handle)
+        f(&buf_info)
     }
 
     fn init_memory_requirements(&mut self) {
         let reqs_info = vk::BufferMemoryRequirementsInfo2::default().buffer(self.handle);
-        let mut reqs = vk::MemoryRequirements2::default();
+        let mut dedicated_reqs = vk::MemoryDedicatedRequirements::default();
+        let mut reqs = vk::MemoryRequirements2::default().push_next(&mut dedicated_reqs);
 
         // SAFETY: no VUID violation
         unsafe {
@@ -1329,7 +3529,10 @@ impl Buffer {
 
         let reqs = reqs.memory_requirements;
         self.size = reqs.size;
+        self.alignment = reqs.alignment;
         self.mt_mask = reqs.memory_type_bits;
+        // the preference is ignored such that the memory can be suballocated
+        self.dedicated = dedicated_reqs.requires_dedicated_allocation != 0;
     }
 
     fn destroy(&self) {
@@ -1354,12 +3557,147 @@ impl Buffer {
         self.device.memory_types(self.mt_mask, required_flags)
     }
 
+    pub fn host_pointer_memory_types(
+        &self,
+        ptr: ptr::NonNull<ffi::c_void>,
+        required_flags: vk::MemoryPropertyFlags,
+    ) -> Vec<(u32, vk::MemoryPropertyFlags)> {
+        let mt_mask = self.mt_mask & self.device.get_host_pointer_mt_mask(ptr);
+        self.device.memory_types(mt_mask, required_flags)
+    }
+
     pub fn bind_memory(&mut self, mt_idx: u32, dmabuf: Option<OwnedFd>) -> Result<()> {
+        if self.sparse {
+            return Error::user();
+        }
+
         let mem = Memory::with_buffer(self, mt_idx, dmabuf)?;
+        self.bind(mem)
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.handle
+    }
+
+    pub fn set_name(&self, name: &str) {
+        self.device.set_object_name(self.handle, name);
+        if let Some(mem) = &self.memory {
+            mem.set_name(name);
+        }
+        for (_, _, mem) in &self.sparse_memories {
+            mem.set_name(name);
+        }
+    }
+
+    pub fn bound_memory(&self) -> Option<&Memory> {
+        self.memory.as_ref()
+    }
+
+    pub fn sparse_block_size(&self) -> Option<vk::DeviceSize> {
+        self.sparse.then_some(self.alignment)
+    }
+
+    // Allocates a memory and binds it to a region of a sparse buffer.  The region can extend to
+    // the end of the buffer without being aligned.
+    pub fn bind_sparse(
+        &mut self,
+        queue: &CopyQueue,
+        mt_idx: u32,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> Result<()> {
+        let end = offset.checked_add(size).ok_or(Error::User)?;
+        if !self.sparse
+            || size == 0
+            || end > self.size
+            || offset % self.alignment != 0
+            || (size % self.alignment != 0 && end != self.size)
+        {
+            return Error::user();
+        }
+        if self
+            .sparse_memories
+            .iter()
+            .any(|&(mem_offset, mem_size, _)| offset < mem_offset + mem_size && mem_offset < end)
+        {
+            return Error::user();
+        }
+
+        let mem = Memory::new(
+            self.device.clone(),
+            size.next_multiple_of(self.alignment),
+            mt_idx,
+            None,
+            Default::default(),
+            None,
+            self.priority,
+        )?;
+
+        let bind = vk::SparseMemoryBind::default()
+            .resource_offset(offset)
+            .size(size)
+            .memory(mem.handle);
+        queue.bind_sparse(self.handle, slice::from_ref(&bind))?;
+
+        self.sparse_memories.push((offset, size, mem));
+
+        Ok(())
+    }
+
+    // Unbinds and frees the memories of the bound regions that are in a region of a sparse
+    // buffer.  Bound regions that are partially in the region are not allowed.  This returns the
+    // total size of the unbound regions.
+    pub fn unbind_sparse(
+        &mut self,
+        queue: &CopyQueue,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> Result<vk::DeviceSize> {
+        let end = offset.checked_add(size).ok_or(Error::User)?;
+        if !self.sparse {
+            return Error::user();
+        }
+
+        let mut binds = Vec::new();
+        for &(mem_offset, mem_size, _) in &self.sparse_memories {
+            let mem_end = mem_offset + mem_size;
+            if mem_offset >= offset && mem_end <= end {
+                let bind = vk::SparseMemoryBind::default()
+                    .resource_offset(mem_offset)
+                    .size(mem_size);
+                binds.push(bind);
+            } else if mem_offset < end && offset < mem_end {
+                return Error::user();
+            }
+        }
+        if binds.is_empty() {
+            return Ok(0);
+        }
+
+        queue.bind_sparse(self.handle, &binds)?;
+
+        let unbound_size = binds.iter().map(|bind| bind.size).sum();
+        self.sparse_memories.retain(|&(mem_offset, mem_size, _)| {
+            mem_offset < offset || mem_offset + mem_size > end
+        });
 
+        Ok(unbound_size)
+    }
+
+    pub fn bind_host_pointer(&mut self, mt_idx: u32, ptr: ptr::NonNull<ffi::c_void>) -> Result<()> {
+        if !self.host_pointer || self.dedicated {
+            return Error::user();
+        }
+
+        let mem = Memory::with_host_pointer(self, mt_idx, ptr)?;
+        self.bind(mem)
+    }
+
+    fn bind(&mut self, mem: Memory) -> Result<()> {
         let bind_info = vk::BindBufferMemoryInfo::default()
             .buffer(self.handle)
-            .memory(mem.handle);
+            .memory(mem.handle)
+            .memory_offset(mem.offset);
 
         // SAFETY: no VUID violation
         unsafe {
@@ -1385,20 +3723,73 @@ impl Drop for Buffer {
     }
 }
 
+// Returns a layout that satisfies the constraint by aligning the strides, the offsets, and the
+// plane sizes of an implicit layout.  The row counts of the memory planes are preserved.  Only
+// linear layouts are supported, because the tiling rules of other modifiers are unknown.
+fn align_layout(layout: &Layout, con: &Constraint) -> Result<Layout> {
+    if layout.modifier != formats::MOD_LINEAR {
+        return Error::unsupported();
+    }
+
+    let count = layout.plane_count as usize;
+    let mut aligned = layout.clone();
+
+    let mut offset: vk::DeviceSize = 0;
+    for plane in 0..count {
+        let next_offset = if plane < count - 1 {
+            layout.offsets[plane + 1]
+        } else {
+            layout.size
+        };
+        let stride = layout.strides[plane];
+        // memory planes are expected to be ordered
+        if stride == 0 || next_offset < layout.offsets[plane] {
+            return Error::unsupported();
+        }
+        let rows = (next_offset - layout.offsets[plane]).div_ceil(stride);
+
+        let stride = stride.next_multiple_of(con.stride_align);
+        let size = rows
+            .checked_mul(stride)
+            .ok_or(Error::Unsupported)?
+            .next_multiple_of(con.size_align);
+
+        offset = offset.next_multiple_of(con.offset_align);
+        aligned.offsets[plane] = offset;
+        aligned.strides[plane] = stride;
+        offset = offset.checked_add(size).ok_or(Error::Unsupported)?;
+    }
+    aligned.size = offset;
+
+    Ok(aligned)
+}
+
 pub struct Image {
     device: Arc<Device>,
     handle: vk::Image,
 
     tiling: vk::ImageTiling,
+    image_type: vk::ImageType,
     format: vk::Format,
     format_plane_count: u32,
+    drm_format: Format,
     modifier: Modifier,
 
     size: vk::DeviceSize,
     mt_mask: u32,
-    external: bool,
+    // whether the memory is dedicated, because the driver requires it or because the external
+    // memory is DEDICATED_ONLY
+    dedicated: bool,
+    // the handle type of external memory, which is empty when not external
+    external_memory_type: vk::ExternalMemoryHandleTypeFlags,
+    protected: bool,
+    host_copy: bool,
+    priority: Option<f32>,
+    // the memory plane sizes when the memory planes are bound to separate memories
+    disjoint_sizes: Vec<vk::DeviceSize>,
 
     memory: Option<Memory>,
+    disjoint_memories: Vec<Memory>,
 }
 
 impl Image {
@@ -1406,25 +3797,50 @@ impl Image {
         device: Arc<Device>,
         handle: vk::Image,
         tiling: vk::ImageTiling,
-        format: vk::Format,
-        external: bool,
+        img_info: &ImageInfo,
+        disjoint: bool,
     ) -> Result<Self> {
+        let format = img_info.format;
         let format_plane_count = device.format_plane_count(format);
+        let external_memory_type =
+            device.external_memory_type(img_info.external, img_info.external_memory_type);
         let mut img = Self {
             device,
             handle,
             tiling,
+            image_type: img_info.image_type,
             format,
             format_plane_count,
+            drm_format: img_info.drm_format,
             modifier: formats::MOD_INVALID,
             size: 0,
             mt_mask: 0,
-            external,
+            dedicated: false,
+            external_memory_type,
+            protected: img_info.flags.contains(vk::ImageCreateFlags::PROTECTED),
+            priority: img_info.priority,
+            host_copy: img_info
+                .usage
+                .contains(vk::ImageUsageFlags::HOST_TRANSFER_EXT),
+            disjoint_sizes: Vec::new(),
             memory: None,
+            disjoint_memories: Vec::new(),
         };
 
         img.init_modifier()?;
-        img.init_memory_requirements();
+        if disjoint {
+            img.init_disjoint_memory_requirements();
+        } else {
+            img.init_memory_requirements();
+            if img_info.external {
+                img.dedicated |= img
+                    .device
+                    .image_external_memory(img_info, img.modifier)
+                    .is_ok_and(|feats| {
+                        feats.contains(vk::ExternalMemoryFeatureFlags::DEDICATED_ONLY)
+                    });
+            }
+        }
 
         Ok(img)
     }
@@ -1432,28 +3848,60 @@ impl Image {
     pub fn with_constraint(
         dev: Arc<Device>,
         img_info: ImageInfo,
-        width: u32,
-        height: u32,
+        extent: vk::Extent3D,
         mods: &[Modifier],
         con: Option<Constraint>,
     ) -> Result<Self> {
-        let mut mods = mods;
-        if let Some(con) = &con {
-            if !con.modifiers.is_empty() {
-                mods = &con.modifiers;
-            }
+        // restrict the modifiers in the order of the constraint, such that consumers such as KMS
+        // can limit the image to the modifiers they support and list their preferred ones first
+        let mods: Vec<Modifier> = match &con {
+            Some(con) if !con.modifiers.is_empty() => con
+                .modifiers
+                .iter()
+                .filter(|m| mods.contains(m))
+                .copied()
+                .collect(),
+            _ => mods.to_vec(),
+        };
+        if mods.is_empty() {
+            return Error::unsupported();
         }
+        let mods = mods.as_slice();
 
-        let tiling = dev.get_image_tiling(mods[0]);
-        let handle = Self::create_implicit_image(&dev, tiling, &img_info, width, height, mods)?;
-        let mut img = Self::new(dev, handle, tiling, img_info.format, img_info.external)?;
+        let tiling = if img_info.image_type == vk::ImageType::TYPE_3D {
+            let tiling = dev.get_3d_image_tiling(mods)?;
+            dev.has_3d_image_support(&img_info, tiling, extent)?;
+            tiling
+        } else {
+            dev.get_image_tiling(mods[0])
+        };
+        // the driver picks any of the modifiers, so try the preferred one alone first
+        let preferred = con
+            .as_ref()
+            .is_some_and(|con| con.preferred_modifiers.contains(&mods[0]));
+        let handle = if preferred && mods.len() > 1 {
+            Self::create_implicit_image(&dev, tiling, &img_info, extent, &mods[..1])
+                .or_else(|_| Self::create_implicit_image(&dev, tiling, &img_info, extent, mods))?
+        } else {
+            Self::create_implicit_image(&dev, tiling, &img_info, extent, mods)?
+        };
+        let disjoint = img_info.flags.contains(vk::ImageCreateFlags::DISJOINT);
+        let mut img = Self::new(dev, handle, tiling, &img_info, disjoint)?;
 
         if let Some(con) = con {
-            img.size = img.size.next_multiple_of(con.size_align);
+            // fall back to an explicit layout when the implicit layout does not satisfy the
+            // constraint
+            if tiling == vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT
+                && !img.layout().fit(Some(con.clone()))
+            {
+                if disjoint {
+                    return Error::unsupported();
+                }
 
-            if tiling == vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT {
-                // TODO fall back to explicit layout if constraint is not satisfied
+                img = img.realign(&img_info, extent, &con)?;
             }
+
+            img.size = img.size.next_multiple_of(con.size_align);
         }
 
         Ok(img)
@@ -1462,31 +3910,55 @@ impl Image {
     pub fn with_layout(
         dev: Arc<Device>,
         img_info: ImageInfo,
-        width: u32,
-        height: u32,
+        extent: vk::Extent3D,
         layout: Layout,
         dmabuf: Option<BorrowedFd>,
     ) -> Result<Self> {
-        let tiling = dev.get_image_tiling(layout.modifier);
+        let is_3d = img_info.image_type == vk::ImageType::TYPE_3D;
+        let tiling = if is_3d {
+            let tiling = dev.get_3d_image_tiling(slice::from_ref(&layout.modifier))?;
+            dev.has_3d_image_support(&img_info, tiling, extent)?;
+            tiling
+        } else {
+            dev.get_image_tiling(layout.modifier)
+        };
         let handle = if tiling == vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT {
-            Self::create_explicit_image(&dev, tiling, &img_info, width, height, &layout)?
+            Self::create_explicit_image(&dev, tiling, &img_info, extent, &layout)?
         } else {
             // ignore layout and hope for the best
             Self::create_implicit_image(
                 &dev,
                 tiling,
                 &img_info,
-                width,
-                height,
+                extent,
                 slice::from_ref(&layout.modifier),
             )?
         };
-        let mut img = Self::new(dev, handle, tiling, img_info.format, img_info.external)?;
+        let disjoint = img_info.flags.contains(vk::ImageCreateFlags::DISJOINT);
+        let mut img = Self::new(dev, handle, tiling, &img_info, disjoint)?;
 
         if img.size > layout.size {
             return Error::user();
         }
-        if let Some(dmabuf) = dmabuf {
+        // 3D images are linear and their layouts are not left to hope
+        if is_3d && tiling == vk::ImageTiling::LINEAR {
+            let img_layout = img.layout();
+            if img_layout.offsets[0] != layout.offsets[0]
+                || img_layout.strides[0] != layout.strides[0]
+                || img_layout.slice_stride != layout.slice_stride
+            {
+                return Error::user();
+            }
+        }
+        if let Some(dmabuf) =
+            dmabuf.filter(|_| img.device.is_dma_buf_type(img.external_memory_type))
+        {
+            // the memory planes of disjoint images are in separate dma-bufs and are checked when
+            // they are imported
+            if !disjoint && utils::seek_end(dmabuf)? < layout.size {
+                return Error::user();
+            }
+
             img.mt_mask &= img.device.get_dma_buf_mt_mask(dmabuf);
             if img.mt_mask == 0 {
                 return Error::user();
@@ -1500,8 +3972,7 @@ impl Image {
         dev: &Device,
         tiling: vk::ImageTiling,
         img_info: &ImageInfo,
-        width: u32,
-        height: u32,
+        extent: vk::Extent3D,
         mods: &[Modifier],
     ) -> Result<vk::Image> {
         // make Modifier #[repr(transparent)]?
@@ -1509,15 +3980,31 @@ impl Image {
         let mod_info =
             vk::ImageDrmFormatModifierListCreateInfoEXT::default().drm_format_modifiers(&mods);
 
-        Self::create_image(dev, tiling, img_info, width, height, mod_info)
+        Self::create_image(dev, tiling, img_info, extent, mod_info)
+    }
+
+    // creates an image with an explicit layout that aligns the layout of this image to the
+    // constraint
+    fn realign(
+        &self,
+        img_info: &ImageInfo,
+        extent: vk::Extent3D,
+        con: &Constraint,
+    ) -> Result<Self> {
+        let layout = align_layout(&self.layout(), con)?;
+        let dev = self.device.clone();
+        let handle = Self::create_explicit_image(&dev, self.tiling, img_info, extent, &layout)?;
+        let mut img = Self::new(dev, handle, self.tiling, img_info, false)?;
+        img.size = img.size.max(layout.size);
+
+        Ok(img)
     }
 
     fn create_explicit_image(
         dev: &Device,
         tiling: vk::ImageTiling,
         img_info: &ImageInfo,
-        width: u32,
-        height: u32,
+        extent: vk::Extent3D,
         layout: &Layout,
     ) -> Result<vk::Image> {
         let count = layout.plane_count as usize;
@@ -1533,34 +4020,33 @@ impl Image {
             .drm_format_modifier(layout.modifier.0)
             .plane_layouts(&plane_layouts);
 
-        Self::create_image(dev, tiling, img_info, width, height, mod_info)
+        Self::create_image(dev, tiling, img_info, extent, mod_info)
     }
 
     fn create_image<T: vk::ExtendsImageCreateInfo>(
         dev: &Device,
         tiling: vk::ImageTiling,
         img_info: &ImageInfo,
-        width: u32,
-        height: u32,
+        extent: vk::Extent3D,
         mut mod_info: T,
     ) -> Result<vk::Image> {
-        let external = img_info.external;
+        let external_memory_type =
+            dev.external_memory_type(img_info.external, img_info.external_memory_type);
+        // a modifier implies no compression, but not the fixed-rate compression level
         let compression = if tiling == vk::ImageTiling::OPTIMAL && img_info.no_compression {
             vk::ImageCompressionFlagsEXT::DISABLED
+        } else if tiling != vk::ImageTiling::LINEAR && !img_info.fixed_rate.is_empty() {
+            vk::ImageCompressionFlagsEXT::FIXED_RATE_EXPLICIT
         } else {
             vk::ImageCompressionFlagsEXT::DEFAULT
         };
+        let mut fixed_rates = fixed_rates_for(img_info, compression);
         let scanout_hack = img_info.scanout_hack;
-
-        let extent = vk::Extent3D {
-            width,
-            height,
-            depth: 1,
-        };
+        let view_formats = &img_info.view_formats;
 
         let mut img_info = vk::ImageCreateInfo::default()
             .flags(img_info.flags)
-            .image_type(vk::ImageType::TYPE_2D)
+            .image_type(img_info.image_type)
             .format(img_info.format)
             .extent(extent)
             .mip_levels(1)
@@ -1572,17 +4058,24 @@ impl Image {
             .push_next(&mut mod_info);
 
         let mut external_info = vk::ExternalMemoryImageCreateInfo::default();
-        if external {
-            external_info = external_info.handle_types(dev.properties().external_memory_type);
+        if !external_memory_type.is_empty() {
+            external_info = external_info.handle_types(external_memory_type);
             img_info = img_info.push_next(&mut external_info);
         }
 
         let mut comp_info = vk::ImageCompressionControlEXT::default();
         if compression != vk::ImageCompressionFlagsEXT::DEFAULT {
-            comp_info = comp_info.flags(compression);
+            comp_info = comp_info
+                .flags(compression)
+                .fixed_rate_flags(&mut fixed_rates);
             img_info = img_info.push_next(&mut comp_info);
         }
 
+        let mut fmt_list_info = vk::ImageFormatListCreateInfo::default().view_formats(view_formats);
+        if !view_formats.is_empty() {
+            img_info = img_info.push_next(&mut fmt_list_info);
+        }
+
         let mut wsi_info = WsiImageCreateInfoMESA::default();
         if scanout_hack && tiling != vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT {
             img_info = img_info.push_next(&mut wsi_info);
@@ -1621,7 +4114,8 @@ impl Image {
 
     fn init_memory_requirements(&mut self) {
         let reqs_info = vk::ImageMemoryRequirementsInfo2::default().image(self.handle);
-        let mut reqs = vk::MemoryRequirements2::default();
+        let mut dedicated_reqs = vk::MemoryDedicatedRequirements::default();
+        let mut reqs = vk::MemoryRequirements2::default().push_next(&mut dedicated_reqs);
 
         // SAFETY: no VUID violation
         unsafe {
@@ -1633,6 +4127,39 @@ impl Image {
         let reqs = reqs.memory_requirements;
         self.size = reqs.size;
         self.mt_mask = reqs.memory_type_bits;
+        self.dedicated = dedicated_reqs.requires_dedicated_allocation != 0;
+    }
+
+    fn init_disjoint_memory_requirements(&mut self) {
+        let mem_plane_count = self
+            .device
+            .memory_plane_count(self.format, self.modifier)
+            .unwrap();
+
+        self.size = 0;
+        self.mt_mask = !0;
+        for plane in 0..mem_plane_count {
+            let aspect = self.get_image_subresource_aspect(mem_plane_count, plane);
+            let mut plane_info =
+                vk::ImagePlaneMemoryRequirementsInfo::default().plane_aspect(aspect);
+            let reqs_info = vk::ImageMemoryRequirementsInfo2::default()
+                .image(self.handle)
+                .push_next(&mut plane_info);
+            let mut reqs = vk::MemoryRequirements2::default();
+
+            // SAFETY: no VUID violation
+            unsafe {
+                self.device
+                    .handle
+                    .get_image_memory_requirements2(&reqs_info, &mut reqs);
+            }
+
+            // disjoint images cannot have dedicated memories
+            let reqs = reqs.memory_requirements;
+            self.size += reqs.size;
+            self.mt_mask &= reqs.memory_type_bits;
+            self.disjoint_sizes.push(reqs.size);
+        }
     }
 
     fn destroy(&self) {
@@ -1646,6 +4173,14 @@ impl Image {
         self.size
     }
 
+    pub fn is_disjoint(&self) -> bool {
+        !self.disjoint_sizes.is_empty()
+    }
+
+    pub fn format_plane_count(&self) -> u32 {
+        self.format_plane_count
+    }
+
     fn get_image_subresource_aspect(
         &self,
         mem_plane_count: u32,
@@ -1680,10 +4215,12 @@ impl Image {
             .device
             .memory_plane_count(self.format, self.modifier)
             .unwrap();
+        let swizzle = formats::to_vk(self.drm_format).map_or(Swizzle::None, |(_, swizzle)| swizzle);
         let mut layout = Layout::new()
             .size(self.size)
             .modifier(self.modifier)
-            .plane_count(mem_plane_count);
+            .plane_count(mem_plane_count)
+            .swizzle(swizzle);
 
         for plane in 0..mem_plane_count {
             let aspect = self.get_image_subresource_aspect(mem_plane_count, plane);
@@ -1699,34 +4236,138 @@ impl Image {
 
             layout.offsets[plane as usize] = subres_layout.offset;
             layout.strides[plane as usize] = subres_layout.row_pitch;
+            // 3D images are single-plane
+            if self.image_type == vk::ImageType::TYPE_3D {
+                layout.slice_stride = subres_layout.depth_pitch;
+            }
         }
 
         layout
     }
 
-    pub fn memory_types(
-        &self,
-        required_flags: vk::MemoryPropertyFlags,
-    ) -> Vec<(u32, vk::MemoryPropertyFlags)> {
-        self.device.memory_types(self.mt_mask, required_flags)
+    // Returns the compression flags and the fixed rate that the implementation chose, or None if
+    // VK_EXT_image_compression_control is unavailable.
+    pub fn compression(
+        &self,
+    ) -> Option<(
+        vk::ImageCompressionFlagsEXT,
+        vk::ImageCompressionFixedRateFlagsEXT,
+    )> {
+        if !self.device.properties().image_compression_control {
+            return None;
+        }
+
+        let mem_plane_count = self
+            .device
+            .memory_plane_count(self.format, self.modifier)
+            .unwrap();
+        let aspect = self.get_image_subresource_aspect(mem_plane_count, 0);
+        let subres = vk::ImageSubresource2EXT::default()
+            .image_subresource(vk::ImageSubresource::default().aspect_mask(aspect));
+        let mut comp_props = vk::ImageCompressionPropertiesEXT::default();
+        let mut subres_layout = vk::SubresourceLayout2EXT::default().push_next(&mut comp_props);
+
+        // SAFETY: no VUID violation
+        unsafe {
+            self.device
+                .dispatch
+                .compression
+                .get_image_subresource_layout2(self.handle, &subres, &mut subres_layout);
+        }
+
+        Some((
+            comp_props.image_compression_flags,
+            comp_props.image_compression_fixed_rate_flags,
+        ))
+    }
+
+    pub fn memory_types(
+        &self,
+        required_flags: vk::MemoryPropertyFlags,
+    ) -> Vec<(u32, vk::MemoryPropertyFlags)> {
+        self.device.memory_types(self.mt_mask, required_flags)
+    }
+
+    pub fn bind_memory(&mut self, mt_idx: u32, dmabuf: Option<OwnedFd>) -> Result<()> {
+        if self.is_disjoint() {
+            return Error::user();
+        }
+
+        let mem = Memory::with_image(self, None, mt_idx, dmabuf)?;
+        self.bind_memories(slice::from_ref(&mem))?;
+        self.memory = Some(mem);
+
+        Ok(())
+    }
+
+    pub fn bind_disjoint_memories(
+        &mut self,
+        mt_idx: u32,
+        dmabufs: Option<Vec<OwnedFd>>,
+    ) -> Result<()> {
+        let plane_count = self.disjoint_sizes.len();
+        if plane_count == 0 {
+            return Error::user();
+        }
+
+        let dmabufs: Vec<Option<OwnedFd>> = match dmabufs {
+            Some(dmabufs) if dmabufs.len() == plane_count => {
+                dmabufs.into_iter().map(Some).collect()
+            }
+            Some(_) => return Error::user(),
+            None => (0..plane_count).map(|_| None).collect(),
+        };
+
+        // check the dma-buf of every memory plane before allocating any memory
+        if self.device.is_dma_buf_type(self.external_memory_type) {
+            for (&size, dmabuf) in self.disjoint_sizes.iter().zip(&dmabufs) {
+                if let Some(dmabuf) = dmabuf {
+                    if utils::seek_end(dmabuf)? < size {
+                        return Error::user();
+                    }
+                }
+            }
+        }
+
+        let mems = dmabufs
+            .into_iter()
+            .enumerate()
+            .map(|(plane, dmabuf)| Memory::with_image(self, Some(plane), mt_idx, dmabuf))
+            .collect::<Result<Vec<_>>>()?;
+        self.bind_memories(&mems)?;
+        self.disjoint_memories = mems;
+
+        Ok(())
     }
 
-    pub fn bind_memory(&mut self, mt_idx: u32, dmabuf: Option<OwnedFd>) -> Result<()> {
-        let mem = Memory::with_image(self, mt_idx, dmabuf)?;
+    // binds the memory, or the memories of all memory planes when the image is disjoint
+    fn bind_memories(&self, mems: &[Memory]) -> Result<()> {
+        let disjoint = self.is_disjoint();
+        let plane_count = mems.len() as u32;
 
-        let bind_info = vk::BindImageMemoryInfo::default()
-            .image(self.handle)
-            .memory(mem.handle);
+        let mut plane_infos: Vec<vk::BindImagePlaneMemoryInfo> = (0..plane_count)
+            .map(|plane| {
+                let aspect = self.get_image_subresource_aspect(plane_count, plane);
+                vk::BindImagePlaneMemoryInfo::default().plane_aspect(aspect)
+            })
+            .collect();
+        let bind_infos: Vec<vk::BindImageMemoryInfo> = mems
+            .iter()
+            .zip(plane_infos.iter_mut())
+            .map(|(mem, plane_info)| {
+                let bind_info = vk::BindImageMemoryInfo::default()
+                    .image(self.handle)
+                    .memory(mem.handle);
+                if disjoint {
+                    bind_info.push_next(plane_info)
+                } else {
+                    bind_info
+                }
+            })
+            .collect();
 
         // SAFETY: no VUID violation
-        unsafe {
-            self.device
-                .handle
-                .bind_image_memory2(slice::from_ref(&bind_info))
-        }
-        .map_err(Error::from)?;
-
-        self.memory = Some(mem);
+        unsafe { self.device.handle.bind_image_memory2(&bind_infos) }.map_err(Error::from)?;
 
         Ok(())
     }
@@ -1735,8 +4376,30 @@ impl Image {
         self.memory.as_ref().unwrap()
     }
 
-    pub fn get_copy_region(&self, copy: CopyBufferImage) -> vk::BufferImageCopy {
-        let aspect = match copy.plane {
+    pub fn disjoint_memories(&self) -> &[Memory] {
+        &self.disjoint_memories
+    }
+
+    pub fn handle(&self) -> vk::Image {
+        self.handle
+    }
+
+    pub fn set_name(&self, name: &str) {
+        self.device.set_object_name(self.handle, name);
+        if let Some(mem) = &self.memory {
+            mem.set_name(name);
+        }
+        for mem in &self.disjoint_memories {
+            mem.set_name(name);
+        }
+    }
+
+    pub fn bound_memory(&self) -> Option<&Memory> {
+        self.memory.as_ref()
+    }
+
+    fn get_copy_aspect(&self, plane: u32) -> vk::ImageAspectFlags {
+        match plane {
             0 => {
                 if self.format_plane_count > 1 {
                     vk::ImageAspectFlags::PLANE_0
@@ -1747,27 +4410,168 @@ impl Image {
             1 => vk::ImageAspectFlags::PLANE_1,
             2 => vk::ImageAspectFlags::PLANE_2,
             _ => unreachable!(),
+        }
+    }
+
+    // returns the size of a copy in bytes
+    fn get_copy_size(&self, aspect: vk::ImageAspectFlags, extent: vk::Extent3D) -> vk::DeviceSize {
+        let plane = match aspect {
+            vk::ImageAspectFlags::PLANE_1 => 1,
+            vk::ImageAspectFlags::PLANE_2 => 2,
+            _ => 0,
         };
+        let bpp = self.device.format_block_size(self.format, plane) as vk::DeviceSize;
+
+        extent.width as vk::DeviceSize
+            * extent.height as vk::DeviceSize
+            * extent.depth as vk::DeviceSize
+            * bpp
+    }
+
+    pub fn get_copy_region(&self, copy: CopyBufferImage) -> vk::BufferImageCopy {
+        let aspect = self.get_copy_aspect(copy.plane);
 
         let bpp = self.device.format_block_size(self.format, copy.plane);
-        let row_len = copy.stride as u32 / bpp;
+        // the row length and the image height have been validated to fit in 32 bits
+        let row_len = (copy.stride / bpp as vk::DeviceSize) as u32;
+        let img_height = if copy.depth > 1 {
+            (copy.slice_stride / copy.stride) as u32
+        } else {
+            0
+        };
 
         let subres = vk::ImageSubresourceLayers::default()
             .aspect_mask(aspect)
             .layer_count(1);
-        let offset = vk::Offset3D::default().x(copy.x as i32).y(copy.y as i32);
+        let offset = vk::Offset3D::default()
+            .x(copy.x as i32)
+            .y(copy.y as i32)
+            .z(copy.z as i32);
         let extent = vk::Extent3D::default()
             .width(copy.width)
             .height(copy.height)
-            .depth(1);
+            .depth(copy.depth);
 
         vk::BufferImageCopy::default()
             .buffer_offset(copy.offset)
             .buffer_row_length(row_len)
+            .buffer_image_height(img_height)
             .image_subresource(subres)
             .image_offset(offset)
             .image_extent(extent)
     }
+
+    // The image is assumed to be in the GENERAL layout (see get_pipeline_barrier_scope), which is
+    // a valid layout for host copies.  The caller must ensure the image is idle.
+    pub fn copy_to_memory(&self, region: vk::BufferImageCopy, dst: &mut [u8]) -> Result<()> {
+        if !self.host_copy {
+            return Error::unsupported();
+        }
+
+        let offset = usize::try_from(region.buffer_offset)?;
+        let ptr = dst.get_mut(offset..).ok_or(Error::User)?.as_mut_ptr();
+        let host_region = vk::ImageToMemoryCopyEXT::default()
+            .host_pointer(ptr.cast())
+            .memory_row_length(region.buffer_row_length)
+            .memory_image_height(region.buffer_image_height)
+            .image_subresource(region.image_subresource)
+            .image_offset(region.image_offset)
+            .image_extent(region.image_extent);
+        let copy_info = vk::CopyImageToMemoryInfoEXT::default()
+            .src_image(self.handle)
+            .src_image_layout(vk::ImageLayout::GENERAL)
+            .regions(slice::from_ref(&host_region));
+
+        // SAFETY: the region has been validated against dst
+        unsafe {
+            self.device
+                .dispatch
+                .host_copy
+                .copy_image_to_memory(&copy_info)
+        }?;
+
+        Ok(())
+    }
+
+    // See copy_to_memory.
+    pub fn copy_from_memory(&self, region: vk::BufferImageCopy, src: &[u8]) -> Result<()> {
+        if !self.host_copy {
+            return Error::unsupported();
+        }
+
+        let offset = usize::try_from(region.buffer_offset)?;
+        let ptr = src.get(offset..).ok_or(Error::User)?.as_ptr();
+        let host_region = vk::MemoryToImageCopyEXT::default()
+            .host_pointer(ptr.cast())
+            .memory_row_length(region.buffer_row_length)
+            .memory_image_height(region.buffer_image_height)
+            .image_subresource(region.image_subresource)
+            .image_offset(region.image_offset)
+            .image_extent(region.image_extent);
+        let copy_info = vk::CopyMemoryToImageInfoEXT::default()
+            .dst_image(self.handle)
+            .dst_image_layout(vk::ImageLayout::GENERAL)
+            .regions(slice::from_ref(&host_region));
+
+        // SAFETY: the region has been validated against src
+        unsafe {
+            self.device
+                .dispatch
+                .host_copy
+                .copy_memory_to_image(&copy_info)
+        }?;
+
+        Ok(())
+    }
+
+    pub fn get_image_copy_region(&self, dst: &Image, copy: CopyImage) -> vk::ImageCopy {
+        let src_subres = vk::ImageSubresourceLayers::default()
+            .aspect_mask(self.get_copy_aspect(copy.src_plane))
+            .layer_count(1);
+        let src_offset = vk::Offset3D::default()
+            .x(copy.src_x as i32)
+            .y(copy.src_y as i32)
+            .z(copy.src_z as i32);
+        let dst_subres = vk::ImageSubresourceLayers::default()
+            .aspect_mask(dst.get_copy_aspect(copy.dst_plane))
+            .layer_count(1);
+        let dst_offset = vk::Offset3D::default()
+            .x(copy.dst_x as i32)
+            .y(copy.dst_y as i32)
+            .z(copy.dst_z as i32);
+        let extent = vk::Extent3D::default()
+            .width(copy.width)
+            .height(copy.height)
+            .depth(copy.depth);
+
+        vk::ImageCopy::default()
+            .src_subresource(src_subres)
+            .src_offset(src_offset)
+            .dst_subresource(dst_subres)
+            .dst_offset(dst_offset)
+            .extent(extent)
+    }
+
+    pub fn get_blit_region(&self, src_rect: Rect, dst_rect: Rect) -> vk::ImageBlit {
+        let subres = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        let offsets = |rect: Rect| {
+            [
+                vk::Offset3D::default().x(rect.x as i32).y(rect.y as i32),
+                vk::Offset3D::default()
+                    .x((rect.x + rect.width) as i32)
+                    .y((rect.y + rect.height) as i32)
+                    .z(1),
+            ]
+        };
+
+        vk::ImageBlit::default()
+            .src_subresource(subres)
+            .src_offsets(offsets(src_rect))
+            .dst_subresource(subres)
+            .dst_offsets(offsets(dst_rect))
+    }
 }
 
 impl Drop for Image {
@@ -1781,18 +4585,42 @@ struct SimpleCommandBuffer {
     pool: vk::CommandPool,
     handle: vk::CommandBuffer,
     fence: vk::Fence,
-    // this is atomic only because rust does not know this is per-thread
+    // the cmd is a protected command buffer
+    protected: bool,
+    // these are atomic or locked only because rust does not know this is used exclusively
     pending: atomic::AtomicBool,
+    // the semaphore that submissions signal, when sync fds can be exported
+    signal_semaphore: Mutex<Option<vk::Semaphore>>,
+    // the wait semaphore of the pending submission
+    wait_semaphore: Mutex<Option<vk::Semaphore>>,
+    // the timestamp queries of the profiled copies
+    queries: Mutex<CommandQueries>,
+    // the timeout of fence waits in nanoseconds
+    timeout: u64,
+}
+
+// Each profiled copy has a pair of timestamp queries.  The query pool is reallocated when it is
+// too small.
+#[derive(Default)]
+struct CommandQueries {
+    pool: vk::QueryPool,
+    capacity: u32,
+    copies: Vec<(Format, vk::DeviceSize)>,
 }
 
 impl SimpleCommandBuffer {
-    fn new(device: Arc<Device>) -> Result<Self> {
+    fn new(device: Arc<Device>, protected: bool, timeout: u64) -> Result<Self> {
         let mut cmd = Self {
             device,
             pool: Default::default(),
             handle: Default::default(),
             fence: Default::default(),
+            protected,
             pending: atomic::AtomicBool::new(false),
+            signal_semaphore: Default::default(),
+            wait_semaphore: Default::default(),
+            queries: Default::default(),
+            timeout,
         };
         cmd.init()?;
 
@@ -1803,13 +4631,19 @@ impl SimpleCommandBuffer {
         self.init_command_pool()?;
         self.init_command_buffer()?;
         self.init_fence()?;
+        self.init_signal_semaphore()?;
 
         Ok(())
     }
 
     fn init_command_pool(&mut self) -> Result<()> {
+        let mut pool_flags = vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER;
+        if self.protected {
+            pool_flags |= vk::CommandPoolCreateFlags::PROTECTED;
+        }
+
         let pool_info = vk::CommandPoolCreateInfo::default()
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .flags(pool_flags)
             .queue_family_index(self.device.properties().queue_family);
 
         // SAFETY: no VUID violation
@@ -1842,8 +4676,32 @@ impl SimpleCommandBuffer {
         Ok(())
     }
 
+    fn init_signal_semaphore(&mut self) -> Result<()> {
+        if !self.device.properties().sync_fd_export {
+            return Ok(());
+        }
+
+        let mut export_info = vk::ExportSemaphoreCreateInfo::default()
+            .handle_types(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+        let sema_info = vk::SemaphoreCreateInfo::default().push_next(&mut export_info);
+
+        // SAFETY: no VUID violation
+        let sema = unsafe { self.device.handle.create_semaphore(&sema_info, None) }?;
+        *self.signal_semaphore.get_mut().unwrap() = Some(sema);
+
+        Ok(())
+    }
+
     fn destroy(&self) {
-        let _ = self.ensure_idle_fence();
+        if self.ensure_idle_fence().is_err() {
+            // SAFETY: no VUID violation
+            let signaled = unsafe { self.device.handle.get_fence_status(self.fence) };
+            if signaled == Ok(false) {
+                // the gpu might be wedged and it is not safe to destroy anything
+                log::error!("leaking a copy command that is still pending");
+                return;
+            }
+        }
 
         // SAFETY: no VUID violation unless pending is true
         unsafe {
@@ -1854,6 +4712,133 @@ impl SimpleCommandBuffer {
         unsafe {
             self.device.handle.destroy_fence(self.fence, None);
         }
+
+        self.destroy_signal_semaphore();
+        if let Some(sema) = self.take_wait_semaphore() {
+            // SAFETY: no VUID violation unless pending is true
+            unsafe {
+                self.device.handle.destroy_semaphore(sema, None);
+            }
+        }
+
+        let pool = self.queries.lock().unwrap().pool;
+        if pool != vk::QueryPool::null() {
+            // SAFETY: no VUID violation unless pending is true
+            unsafe {
+                self.device.handle.destroy_query_pool(pool, None);
+            }
+        }
+    }
+
+    // Resets the timestamp queries for the copies about to be recorded.  The cmd must be
+    // recording.
+    fn begin_queries(&self, copies: Vec<(Format, vk::DeviceSize)>) -> Result<()> {
+        let mut queries = self.queries.lock().unwrap();
+        let count = u32::try_from(copies.len() * 2)?;
+
+        if queries.capacity < count {
+            if queries.pool != vk::QueryPool::null() {
+                // SAFETY: no VUID violation because the cmd is idle and has been reset by begin
+                unsafe {
+                    self.device.handle.destroy_query_pool(queries.pool, None);
+                }
+                queries.pool = vk::QueryPool::null();
+                queries.capacity = 0;
+            }
+
+            let capacity = count.next_power_of_two();
+            let pool_info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(capacity);
+
+            // SAFETY: no VUID violation
+            queries.pool = unsafe { self.device.handle.create_query_pool(&pool_info, None) }?;
+            queries.capacity = capacity;
+        }
+
+        // SAFETY: no VUID violation
+        unsafe {
+            self.device
+                .handle
+                .cmd_reset_query_pool(self.handle, queries.pool, 0, count);
+        }
+        queries.copies = copies;
+
+        Ok(())
+    }
+
+    // writes a timestamp after all previous commands complete
+    fn write_timestamp(&self, query: u32) {
+        let pool = self.queries.lock().unwrap().pool;
+
+        // SAFETY: no VUID violation because the query has been reset by begin_queries
+        unsafe {
+            self.device.handle.cmd_write_timestamp(
+                self.handle,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                pool,
+                query,
+            );
+        }
+    }
+
+    // Returns the profiled copies and their gpu time in timestamp ticks.  The cmd must be idle.
+    fn take_queries(&self) -> Vec<(Format, vk::DeviceSize, u64)> {
+        let mut queries = self.queries.lock().unwrap();
+        let copies = mem::take(&mut queries.copies);
+        if copies.is_empty() {
+            return Vec::new();
+        }
+
+        let mut timestamps = vec![0u64; copies.len() * 2];
+        // SAFETY: no VUID violation
+        let res = unsafe {
+            self.device.handle.get_query_pool_results(
+                queries.pool,
+                0,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        // the queries are unavailable when the submission failed
+        if res.is_err() {
+            return Vec::new();
+        }
+
+        let valid_bits = self.device.properties().timestamp_valid_bits;
+        let mask = if valid_bits >= 64 {
+            u64::MAX
+        } else {
+            (1 << valid_bits) - 1
+        };
+
+        copies
+            .into_iter()
+            .zip(timestamps.chunks_exact(2))
+            .map(|((fmt, size), ts)| (fmt, size, ts[1].wrapping_sub(ts[0]) & mask))
+            .collect()
+    }
+
+    fn signal_semaphore(&self) -> Option<vk::Semaphore> {
+        *self.signal_semaphore.lock().unwrap()
+    }
+
+    // the semaphore must not have any pending operation
+    fn destroy_signal_semaphore(&self) {
+        if let Some(sema) = self.signal_semaphore.lock().unwrap().take() {
+            // SAFETY: no VUID violation unless pending is true
+            unsafe {
+                self.device.handle.destroy_semaphore(sema, None);
+            }
+        }
+    }
+
+    fn set_wait_semaphore(&self, sema: vk::Semaphore) {
+        *self.wait_semaphore.lock().unwrap() = Some(sema);
+    }
+
+    fn take_wait_semaphore(&self) -> Option<vk::Semaphore> {
+        self.wait_semaphore.lock().unwrap().take()
     }
 
     fn ensure_idle_fence(&self) -> Result<()> {
@@ -1869,6 +4854,22 @@ impl SimpleCommandBuffer {
         }
     }
 
+    // returns true if the cmd has no pending submission, without waiting
+    fn is_idle(&self) -> bool {
+        if !self.pending.load(atomic::Ordering::Relaxed) {
+            return true;
+        }
+
+        // SAFETY: no VUID violation
+        let signaled = unsafe { self.device.handle.get_fence_status(self.fence) };
+        if signaled == Ok(true) {
+            self.pending.store(false, atomic::Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
     fn reset_fence(&self) -> Result<()> {
         self.ensure_idle_fence()?;
 
@@ -1904,13 +4905,17 @@ impl SimpleCommandBuffer {
         unsafe {
             self.device
                 .handle
-                .wait_for_fences(slice::from_ref(&self.fence), true, u64::MAX)
+                .wait_for_fences(slice::from_ref(&self.fence), true, self.timeout)
         }
         .map_err(|res| {
             if res != vk::Result::ERROR_DEVICE_LOST {
                 self.pending.store(true, atomic::Ordering::Relaxed);
             }
-            Error::from(res)
+            if res == vk::Result::TIMEOUT {
+                log::error!("copy timed out");
+                return Error::Device;
+            }
+            self.device.device_error(res)
         })
     }
 }
@@ -1921,6 +4926,92 @@ impl Drop for SimpleCommandBuffer {
     }
 }
 
+// Semaphores that sync fds are imported to.  Creating semaphores is relatively expensive on some
+// drivers.  A semaphore reverts to its permanent (unsignaled) payload once the wait on the
+// imported temporary payload completes, and can be reused afterwards.
+struct SemaphorePool {
+    device: Arc<Device>,
+    max_size: usize,
+    semaphores: Mutex<Vec<vk::Semaphore>>,
+}
+
+impl SemaphorePool {
+    fn new(device: Arc<Device>, max_size: usize) -> Self {
+        Self {
+            device,
+            max_size,
+            semaphores: Default::default(),
+        }
+    }
+
+    fn get(&self) -> Result<vk::Semaphore> {
+        if let Some(sema) = self.semaphores.lock().unwrap().pop() {
+            return Ok(sema);
+        }
+
+        let sema_info = vk::SemaphoreCreateInfo::default();
+        // SAFETY: no VUID violation
+        unsafe { self.device.handle.create_semaphore(&sema_info, None) }.map_err(Error::from)
+    }
+
+    // the semaphore must not have any pending operation
+    fn put(&self, sema: vk::Semaphore) {
+        let mut semaphores = self.semaphores.lock().unwrap();
+        if semaphores.len() < self.max_size {
+            semaphores.push(sema);
+        } else {
+            self.destroy_semaphore(sema);
+        }
+    }
+
+    fn destroy_semaphore(&self, sema: vk::Semaphore) {
+        // SAFETY: no VUID violation because the semaphore has no pending operation
+        unsafe {
+            self.device.handle.destroy_semaphore(sema, None);
+        }
+    }
+
+    fn import_sync_fd(&self, sync_fd: OwnedFd) -> Result<vk::Semaphore> {
+        let sema = self.get()?;
+
+        let import_info = vk::ImportSemaphoreFdInfoKHR::default()
+            .semaphore(sema)
+            .flags(vk::SemaphoreImportFlags::TEMPORARY)
+            .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD)
+            .fd(sync_fd.as_raw_fd());
+
+        // SAFETY: no VUID violation
+        let res = unsafe {
+            self.device
+                .dispatch
+                .semaphore
+                .import_semaphore_fd(&import_info)
+        };
+        match res {
+            Ok(()) => {
+                // the ownership of the fd has been transferred
+                let _ = sync_fd.into_raw_fd();
+                Ok(sema)
+            }
+            Err(err) => {
+                self.put(sema);
+                Err(Error::from(err))
+            }
+        }
+    }
+}
+
+impl Drop for SemaphorePool {
+    fn drop(&mut self) {
+        for sema in self.semaphores.get_mut().unwrap().drain(..) {
+            // SAFETY: no VUID violation because pooled semaphores have no pending operation
+            unsafe {
+                self.device.handle.destroy_semaphore(sema, None);
+            }
+        }
+    }
+}
+
 #[derive(PartialEq)]
 enum PipelineBarrierType {
     AcquireSrc,
@@ -1929,60 +5020,295 @@ enum PipelineBarrierType {
     ReleaseDst,
 }
 
+// The masks use VK_KHR_synchronization2 semantics.  They are converted to the legacy masks when
+// the extension is unsupported.
 struct PipelineBarrierScope {
     dependency_flags: vk::DependencyFlags,
 
     src_queue_family: u32,
-    src_stage_mask: vk::PipelineStageFlags,
-    src_access_mask: vk::AccessFlags,
+    src_stage_mask: vk::PipelineStageFlags2,
+    src_access_mask: vk::AccessFlags2,
     src_image_layout: vk::ImageLayout,
 
     dst_queue_family: u32,
-    dst_stage_mask: vk::PipelineStageFlags,
-    dst_access_mask: vk::AccessFlags,
+    dst_stage_mask: vk::PipelineStageFlags2,
+    dst_access_mask: vk::AccessFlags2,
     dst_image_layout: vk::ImageLayout,
 }
 
+impl PipelineBarrierScope {
+    fn legacy_stage_mask(mask: vk::PipelineStageFlags2) -> vk::PipelineStageFlags {
+        // the transfer sub-stages are new in VK_KHR_synchronization2
+        let transfer_stages = vk::PipelineStageFlags2::COPY
+            | vk::PipelineStageFlags2::RESOLVE
+            | vk::PipelineStageFlags2::BLIT
+            | vk::PipelineStageFlags2::CLEAR;
+        // the other bits are identical
+        let mut legacy =
+            vk::PipelineStageFlags::from_raw((mask & !transfer_stages).as_raw() as u32);
+        if mask.intersects(transfer_stages) {
+            legacy |= vk::PipelineStageFlags::TRANSFER;
+        }
+
+        legacy
+    }
+
+    fn legacy_access_mask(mask: vk::AccessFlags2) -> vk::AccessFlags {
+        // the bits of the legacy accesses are identical
+        vk::AccessFlags::from_raw(mask.as_raw() as u32)
+    }
+}
+
+// a copy region and its source and destination resources
+pub enum CopyRegion<'a> {
+    Buffer(&'a Buffer, &'a Buffer, vk::BufferCopy),
+    ImageToBuffer(&'a Image, &'a Buffer, vk::BufferImageCopy),
+    BufferToImage(&'a Buffer, &'a Image, vk::BufferImageCopy),
+    Image(&'a Image, &'a Image, vk::ImageCopy),
+}
+
+impl CopyRegion<'_> {
+    // returns whether the source and the destination are protected
+    fn protection(&self) -> (bool, bool) {
+        match self {
+            Self::Buffer(src, dst, _) => (src.protected, dst.protected),
+            Self::ImageToBuffer(src, dst, _) => (src.protected, dst.protected),
+            Self::BufferToImage(src, dst, _) => (src.protected, dst.protected),
+            Self::Image(src, dst, _) => (src.protected, dst.protected),
+        }
+    }
+
+    // returns the format and the size of the copy for profiling
+    fn profile_key(&self) -> (Format, vk::DeviceSize) {
+        match self {
+            Self::Buffer(_, _, region) => (Format::default(), region.size),
+            Self::ImageToBuffer(img, _, region) | Self::BufferToImage(_, img, region) => {
+                let aspect = region.image_subresource.aspect_mask;
+                let size = img.get_copy_size(aspect, region.image_extent);
+                (img.drm_format, size)
+            }
+            Self::Image(src, _, region) => {
+                let aspect = region.src_subresource.aspect_mask;
+                let size = src.get_copy_size(aspect, region.extent);
+                (src.drm_format, size)
+            }
+        }
+    }
+}
+
+// copy profiles are aggregated by the formats and the size classes of copies
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub struct CopyProfileKey {
+    pub format: Format,
+    // the log2 of the copy sizes
+    pub size_class: u32,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct CopyProfile {
+    pub count: u64,
+    pub size: vk::DeviceSize,
+    // the sum of the gpu time in nanoseconds
+    pub duration: u64,
+}
+
 pub struct CopyQueue {
     device: Arc<Device>,
-    handle: Mutex<vk::Queue>,
-
-    per_thread_cmds: Mutex<HashMap<thread::ThreadId, Arc<SimpleCommandBuffer>>>,
+    // Each thread submits to one of the queues, such that the copies of a thread execute in
+    // submission order while copies of different threads can execute concurrently.  There is a
+    // single queue unless concurrent copies are enabled.
+    handles: Vec<Mutex<vk::Queue>>,
+    // copies to protected resources are submitted to this queue
+    protected_handle: Option<Mutex<vk::Queue>>,
+
+    // Each cmd is used by one thread at a time.  Idle cmds are reused, and pending cmds are
+    // tracked in submission order until their fences signal.
+    idle_cmds: Mutex<Vec<SimpleCommandBuffer>>,
+    pending_cmds: Mutex<VecDeque<SimpleCommandBuffer>>,
+    semaphore_pool: SemaphorePool,
+
+    // when copies are profiled, the profiles of completed copies are aggregated here
+    profiles: Option<Mutex<HashMap<CopyProfileKey, CopyProfile>>>,
+    // waits for copies and sync fds fail with Error::Device after the timeout
+    timeout: Option<Duration>,
 }
 
-impl CopyQueue {
-    pub fn new(device: Arc<Device>) -> Self {
-        let handle = device.get_queue();
-        Self {
-            device,
-            handle: Mutex::new(handle),
-            per_thread_cmds: Default::default(),
+impl CopyQueue {
+    pub fn new(
+        device: Arc<Device>,
+        semaphore_pool_size: usize,
+        profile: bool,
+        concurrent: bool,
+    ) -> Self {
+        let mut handles = device.get_queues();
+        if !concurrent {
+            handles.truncate(1);
+        }
+        let protected_handle = device.get_protected_queue();
+        let semaphore_pool = SemaphorePool::new(device.clone(), semaphore_pool_size);
+
+        let profile = if profile && device.properties().timestamp_valid_bits == 0 {
+            log::warn!("no timestamp support for copy profiling");
+            false
+        } else {
+            profile
+        };
+
+        Self {
+            device,
+            handles: handles.into_iter().map(Mutex::new).collect(),
+            protected_handle: protected_handle.map(Mutex::new),
+            idle_cmds: Default::default(),
+            pending_cmds: Default::default(),
+            semaphore_pool,
+            profiles: profile.then(Default::default),
+            timeout: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    // returns the timeout of fence waits in nanoseconds
+    fn timeout_ns(&self) -> u64 {
+        self.timeout.map_or(u64::MAX, |timeout| {
+            timeout.as_nanos().try_into().unwrap_or(u64::MAX)
+        })
+    }
+
+    // Returns the aggregated profiles of completed copies.  Pending copies that have completed
+    // are retired first.
+    pub fn copy_profiles(&self) -> Vec<(CopyProfileKey, CopyProfile)> {
+        let Some(profiles) = &self.profiles else {
+            return Vec::new();
+        };
+
+        self.retire_cmds();
+
+        profiles
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&key, &profile)| (key, profile))
+            .collect()
+    }
+
+    // returns the queue of the current thread, which is assigned on first use
+    fn queue(&self) -> &Mutex<vk::Queue> {
+        static NEXT_QUEUE_SLOT: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+        thread_local! {
+            static QUEUE_SLOT: usize = NEXT_QUEUE_SLOT.fetch_add(1, atomic::Ordering::Relaxed);
+        }
+
+        let slot = QUEUE_SLOT.with(|slot| *slot);
+        &self.handles[slot % self.handles.len()]
+    }
+
+    // aggregates the profiled copies of an idle cmd
+    fn collect_profiles(&self, cmd: &SimpleCommandBuffer) {
+        let Some(profiles) = &self.profiles else {
+            return;
+        };
+
+        let copies = cmd.take_queries();
+        if copies.is_empty() {
+            return;
+        }
+
+        let period = self.device.properties().timestamp_period as f64;
+        let mut profiles = profiles.lock().unwrap();
+        for (format, size, ticks) in copies {
+            let key = CopyProfileKey {
+                format,
+                size_class: size.checked_ilog2().unwrap_or(0),
+            };
+            let profile = profiles.entry(key).or_default();
+            profile.count += 1;
+            profile.size += size;
+            profile.duration += (ticks as f64 * period) as u64;
         }
     }
 
-    fn lookup_per_thread_cmd(&self) -> Option<Arc<SimpleCommandBuffer>> {
-        let tid = thread::current().id();
-        let cmds = self.per_thread_cmds.lock().unwrap();
+    // the cmd must be idle
+    fn recycle_cmd(&self, cmd: SimpleCommandBuffer) {
+        self.collect_profiles(&cmd);
+
+        if let Some(sema) = cmd.take_wait_semaphore() {
+            self.semaphore_pool.put(sema);
+        }
 
-        cmds.get(&tid).cloned()
+        self.idle_cmds.lock().unwrap().push(cmd);
     }
 
-    fn create_per_thread_cmd(&self) -> Result<Arc<SimpleCommandBuffer>> {
-        let cmd = SimpleCommandBuffer::new(self.device.clone())?;
-        let cmd = Arc::new(cmd);
+    // moves the pending cmds whose fences have signaled to the idle cmds
+    fn retire_cmds(&self) {
+        let retired: VecDeque<SimpleCommandBuffer> = {
+            let mut pending_cmds = self.pending_cmds.lock().unwrap();
+            let (retired, pending) = pending_cmds.drain(..).partition(|cmd| cmd.is_idle());
+            *pending_cmds = pending;
+            retired
+        };
 
-        let tid = thread::current().id();
-        let mut cmds = self.per_thread_cmds.lock().unwrap();
+        for cmd in retired {
+            self.recycle_cmd(cmd);
+        }
+    }
 
-        cmds.insert(tid, cmd.clone());
+    // Returns true if a copy must be recorded to a protected cmd.  Protected resources can only
+    // be written by protected cmds, and must not be copied to unprotected resources.
+    fn is_protected_copy(&self, src_protected: bool, dst_protected: bool) -> Result<bool> {
+        if src_protected && !dst_protected {
+            return Error::user();
+        }
+        if dst_protected && self.protected_handle.is_none() {
+            return Error::unsupported();
+        }
 
-        Ok(cmd)
+        Ok(dst_protected)
     }
 
-    fn get_per_thread_cmd(&self) -> Result<Arc<SimpleCommandBuffer>> {
-        let cmd = match self.lookup_per_thread_cmd() {
+    fn get_cmd(&self, protected: bool) -> Result<SimpleCommandBuffer> {
+        self.retire_cmds();
+
+        let cmd = {
+            let mut idle_cmds = self.idle_cmds.lock().unwrap();
+            idle_cmds
+                .iter()
+                .rposition(|cmd| cmd.protected == protected)
+                .map(|idx| idle_cmds.swap_remove(idx))
+        };
+        let cmd = match cmd {
             Some(cmd) => cmd,
-            None => self.create_per_thread_cmd()?,
+            None => {
+                // bound the number of pending cmds by waiting for the oldest one
+                let mut pending_cmds = self.pending_cmds.lock().unwrap();
+                let oldest = if pending_cmds.len() >= MAX_PENDING_COPIES {
+                    pending_cmds.pop_front()
+                } else {
+                    None
+                };
+                drop(pending_cmds);
+
+                match oldest {
+                    Some(cmd) if cmd.protected == protected => {
+                        cmd.ensure_idle_fence()?;
+                        self.collect_profiles(&cmd);
+                        if let Some(sema) = cmd.take_wait_semaphore() {
+                            self.semaphore_pool.put(sema);
+                        }
+                        cmd
+                    }
+                    Some(cmd) => {
+                        cmd.ensure_idle_fence()?;
+                        self.recycle_cmd(cmd);
+                        SimpleCommandBuffer::new(self.device.clone(), protected, self.timeout_ns())?
+                    }
+                    None => {
+                        SimpleCommandBuffer::new(self.device.clone(), protected, self.timeout_ns())?
+                    }
+                }
+            }
         };
 
         cmd.reset_fence()?;
@@ -1991,29 +5317,156 @@ impl CopyQueue {
         Ok(cmd)
     }
 
-    fn submit_cmd(&self, cmd: &SimpleCommandBuffer) -> Result<()> {
-        let submit_info = vk::SubmitInfo::default().command_buffers(slice::from_ref(&cmd.handle));
-        let handle = *self.handle.lock().unwrap();
+    fn submit_cmd(
+        &self,
+        cmd: &SimpleCommandBuffer,
+        wait_sema: Option<vk::Semaphore>,
+        signal_sema: Option<vk::Semaphore>,
+    ) -> Result<()> {
+        let wait_stage = vk::PipelineStageFlags::ALL_COMMANDS;
+        let mut submit_info =
+            vk::SubmitInfo::default().command_buffers(slice::from_ref(&cmd.handle));
+        if let Some(sema) = &wait_sema {
+            submit_info = submit_info
+                .wait_semaphores(slice::from_ref(sema))
+                .wait_dst_stage_mask(slice::from_ref(&wait_stage));
+        }
+        if let Some(sema) = &signal_sema {
+            submit_info = submit_info.signal_semaphores(slice::from_ref(sema));
+        }
+
+        let mut protected_info = vk::ProtectedSubmitInfo::default().protected_submit(true);
+        let queue = if cmd.protected {
+            submit_info = submit_info.push_next(&mut protected_info);
+            // get_cmd never returns protected cmds without the protected queue
+            self.protected_handle.as_ref().unwrap()
+        } else {
+            self.queue()
+        };
+
+        let handle = *queue.lock().unwrap();
         // SAFETY: no VUID violation
         unsafe {
             self.device
                 .handle
                 .queue_submit(handle, slice::from_ref(&submit_info), cmd.fence)
         }
-        .map_err(Error::from)
+        .map_err(|res| self.device.device_error(res))
+    }
+
+    // Waits for a sync fd on the gpu if it can be imported, or on the cpu otherwise.
+    fn wait_sync_fd(&self, sync_fd: Option<OwnedFd>) -> Result<Option<vk::Semaphore>> {
+        let Some(sync_fd) = sync_fd else {
+            return Ok(None);
+        };
+
+        if self.device.properties().sync_fd_import {
+            self.semaphore_pool.import_sync_fd(sync_fd).map(Some)
+        } else if utils::poll_timeout(sync_fd, Access::Read, self.timeout)? {
+            Ok(None)
+        } else {
+            log::error!("sync fd wait timed out");
+            Error::device()
+        }
+    }
+
+    // Exports the signal semaphore of a submitted cmd as a sync fd.  None is returned if the
+    // semaphore has already signaled.
+    fn export_sync_fd(&self, sema: vk::Semaphore) -> Result<Option<OwnedFd>> {
+        let fd_info = vk::SemaphoreGetFdInfoKHR::default()
+            .semaphore(sema)
+            .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+
+        // SAFETY: no VUID violation because the signal operation has been submitted
+        let fd = unsafe { self.device.dispatch.semaphore.get_semaphore_fd(&fd_info) }?;
+        if fd < 0 {
+            return Ok(None);
+        }
+
+        // SAFETY: fd is valid and is owned by us
+        Ok(Some(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    // Returns a sync fd for a submitted cmd if possible, or waits for the cmd on the cpu
+    // otherwise.
+    fn finish_cmd(
+        &self,
+        cmd: &SimpleCommandBuffer,
+        signal_sema: Option<vk::Semaphore>,
+    ) -> Result<Option<OwnedFd>> {
+        if let Some(sema) = signal_sema {
+            match self.export_sync_fd(sema) {
+                Ok(Some(sync_fd)) => {
+                    cmd.pending.store(true, atomic::Ordering::Relaxed);
+                    return Ok(Some(sync_fd));
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    // the semaphore might remain signaled and cannot be signaled again
+                    log::warn!("failed to export sync fd: {err:?}");
+                    cmd.wait_fence()?;
+                    cmd.destroy_signal_semaphore();
+                    return Ok(None);
+                }
+            }
+        }
+
+        cmd.wait_fence().and(Ok(None))
     }
 
-    fn execute_per_thread_cmd(&self, cmd: Arc<SimpleCommandBuffer>) -> Result<()> {
+    // Submits a cmd.  When a sync fd is returned, the cmd is tracked as pending and the submission
+    // is not waited for.  Otherwise, the submission has completed.
+    fn execute_cmd(
+        &self,
+        cmd: SimpleCommandBuffer,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
         cmd.end()?;
-        self.submit_cmd(&cmd)?;
-        cmd.wait_fence()
+
+        let wait_sema = self.wait_sync_fd(sync_fd)?;
+        let signal_sema = cmd.signal_semaphore();
+        let res = self
+            .submit_cmd(&cmd, wait_sema, signal_sema)
+            .and_then(|_| self.finish_cmd(&cmd, signal_sema));
+
+        if let Some(sema) = wait_sema {
+            // the temporary payload might still be pending on failures
+            match res {
+                // the cmd is still pending and the semaphore is recycled with the cmd
+                Ok(Some(_)) => cmd.set_wait_semaphore(sema),
+                Ok(None) => self.semaphore_pool.put(sema),
+                Err(_) => {
+                    if cmd.ensure_idle_fence().is_ok() {
+                        self.semaphore_pool.destroy_semaphore(sema);
+                    }
+                }
+            }
+        }
+
+        match res {
+            Ok(Some(_)) => self.pending_cmds.lock().unwrap().push_back(cmd),
+            _ => {
+                // a cmd that is still pending after a failure is dropped
+                if cmd.ensure_idle_fence().is_ok() {
+                    self.recycle_cmd(cmd);
+                }
+            }
+        }
+
+        res
     }
 
-    fn get_pipeline_barrier_scope(&self, ty: PipelineBarrierType) -> PipelineBarrierScope {
-        // We assume all resources are owned by the foreign queue and, in the case of images, have
-        // been initialized to the GENERAL layout.  Strictly speaking, the layout part is not
-        // guaranteed unless we always explicitly transition the layout and release the ownership
-        // during image creation.
+    // Returns the scope of a barrier.  `stage` is the transfer stage of the command that accesses
+    // the resource, such as COPY or BLIT.
+    fn get_pipeline_barrier_scope(
+        &self,
+        ty: PipelineBarrierType,
+        stage: vk::PipelineStageFlags2,
+    ) -> PipelineBarrierScope {
+        // We assume all resources are owned by the foreign (or the external, without
+        // VK_EXT_queue_family_foreign) queue and, in the case of images, have been initialized to
+        // the GENERAL layout.  Images that we allocate are explicitly transitioned and released by
+        // initialize_image.  Imported images are assumed to have been initialized by the exporter.
         let src_queue_family;
         let src_stage_mask;
         let src_access_mask;
@@ -2024,35 +5477,35 @@ impl CopyQueue {
         let dst_image_layout;
         match ty {
             PipelineBarrierType::AcquireSrc | PipelineBarrierType::AcquireDst => {
-                src_queue_family = vk::QUEUE_FAMILY_FOREIGN_EXT;
-                src_stage_mask = vk::PipelineStageFlags::ALL_COMMANDS;
-                src_access_mask = vk::AccessFlags::NONE;
+                src_queue_family = self.device.foreign_queue_family();
+                src_stage_mask = vk::PipelineStageFlags2::ALL_COMMANDS;
+                src_access_mask = vk::AccessFlags2::NONE;
                 src_image_layout = vk::ImageLayout::GENERAL;
 
                 dst_queue_family = self.device.properties().queue_family;
-                dst_stage_mask = vk::PipelineStageFlags::TRANSFER;
+                dst_stage_mask = stage;
                 if ty == PipelineBarrierType::AcquireSrc {
-                    dst_access_mask = vk::AccessFlags::TRANSFER_READ;
+                    dst_access_mask = vk::AccessFlags2::TRANSFER_READ;
                     dst_image_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
                 } else {
-                    dst_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+                    dst_access_mask = vk::AccessFlags2::TRANSFER_WRITE;
                     dst_image_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
                 }
             }
             PipelineBarrierType::ReleaseSrc | PipelineBarrierType::ReleaseDst => {
                 src_queue_family = self.device.properties().queue_family;
-                src_stage_mask = vk::PipelineStageFlags::TRANSFER;
+                src_stage_mask = stage;
                 if ty == PipelineBarrierType::ReleaseSrc {
-                    src_access_mask = vk::AccessFlags::NONE;
+                    src_access_mask = vk::AccessFlags2::NONE;
                     src_image_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
                 } else {
-                    src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+                    src_access_mask = vk::AccessFlags2::TRANSFER_WRITE;
                     src_image_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
                 }
 
-                dst_queue_family = vk::QUEUE_FAMILY_FOREIGN_EXT;
-                dst_stage_mask = vk::PipelineStageFlags::ALL_COMMANDS;
-                dst_access_mask = vk::AccessFlags::NONE;
+                dst_queue_family = self.device.foreign_queue_family();
+                dst_stage_mask = vk::PipelineStageFlags2::ALL_COMMANDS;
+                dst_access_mask = vk::AccessFlags2::NONE;
                 dst_image_layout = vk::ImageLayout::GENERAL;
             }
         }
@@ -2076,9 +5529,38 @@ impl CopyQueue {
         buf: vk::Buffer,
         scope: PipelineBarrierScope,
     ) {
+        if self.device.properties().synchronization2 {
+            let buf_barrier = vk::BufferMemoryBarrier2::default()
+                .src_stage_mask(scope.src_stage_mask)
+                .src_access_mask(scope.src_access_mask)
+                .dst_stage_mask(scope.dst_stage_mask)
+                .dst_access_mask(scope.dst_access_mask)
+                .src_queue_family_index(scope.src_queue_family)
+                .dst_queue_family_index(scope.dst_queue_family)
+                .buffer(buf)
+                .size(vk::WHOLE_SIZE);
+            let dep_info = vk::DependencyInfo::default()
+                .dependency_flags(scope.dependency_flags)
+                .buffer_memory_barriers(slice::from_ref(&buf_barrier));
+
+            // SAFETY: no VUID violation
+            unsafe {
+                self.device
+                    .dispatch
+                    .sync2
+                    .cmd_pipeline_barrier2(cmd, &dep_info);
+            }
+
+            return;
+        }
+
         let buf_barrier = vk::BufferMemoryBarrier::default()
-            .src_access_mask(scope.src_access_mask)
-            .dst_access_mask(scope.dst_access_mask)
+            .src_access_mask(PipelineBarrierScope::legacy_access_mask(
+                scope.src_access_mask,
+            ))
+            .dst_access_mask(PipelineBarrierScope::legacy_access_mask(
+                scope.dst_access_mask,
+            ))
             .src_queue_family_index(scope.src_queue_family)
             .dst_queue_family_index(scope.dst_queue_family)
             .buffer(buf)
@@ -2088,8 +5570,8 @@ impl CopyQueue {
         unsafe {
             self.device.handle.cmd_pipeline_barrier(
                 cmd,
-                scope.src_stage_mask,
-                scope.dst_stage_mask,
+                PipelineBarrierScope::legacy_stage_mask(scope.src_stage_mask),
+                PipelineBarrierScope::legacy_stage_mask(scope.dst_stage_mask),
                 scope.dependency_flags,
                 &[],
                 slice::from_ref(&buf_barrier),
@@ -2109,9 +5591,42 @@ impl CopyQueue {
             .aspect_mask(aspect)
             .level_count(1)
             .layer_count(1);
+
+        if self.device.properties().synchronization2 {
+            let img_barrier = vk::ImageMemoryBarrier2::default()
+                .src_stage_mask(scope.src_stage_mask)
+                .src_access_mask(scope.src_access_mask)
+                .dst_stage_mask(scope.dst_stage_mask)
+                .dst_access_mask(scope.dst_access_mask)
+                .old_layout(scope.src_image_layout)
+                .new_layout(scope.dst_image_layout)
+                .src_queue_family_index(scope.src_queue_family)
+                .dst_queue_family_index(scope.dst_queue_family)
+                .image(img)
+                .subresource_range(img_subres);
+            let dep_info = vk::DependencyInfo::default()
+                .dependency_flags(scope.dependency_flags)
+                .image_memory_barriers(slice::from_ref(&img_barrier));
+
+            // SAFETY: VUID-VkImageMemoryBarrier2-oldLayout-01197 violation on first acquire of an
+            // imported image that the exporter did not initialize (see get_pipeline_barrier_scope)
+            unsafe {
+                self.device
+                    .dispatch
+                    .sync2
+                    .cmd_pipeline_barrier2(cmd, &dep_info);
+            }
+
+            return;
+        }
+
         let img_barrier = vk::ImageMemoryBarrier::default()
-            .src_access_mask(scope.src_access_mask)
-            .dst_access_mask(scope.dst_access_mask)
+            .src_access_mask(PipelineBarrierScope::legacy_access_mask(
+                scope.src_access_mask,
+            ))
+            .dst_access_mask(PipelineBarrierScope::legacy_access_mask(
+                scope.dst_access_mask,
+            ))
             .old_layout(scope.src_image_layout)
             .new_layout(scope.dst_image_layout)
             .src_queue_family_index(scope.src_queue_family)
@@ -2119,13 +5634,13 @@ impl CopyQueue {
             .image(img)
             .subresource_range(img_subres);
 
-        // SAFETY: VUID-VkImageMemoryBarrier-oldLayout-01197 violation on first image acquire (see
-        // get_pipeline_barrier_scope)
+        // SAFETY: VUID-VkImageMemoryBarrier-oldLayout-01197 violation on first acquire of an
+        // imported image that the exporter did not initialize (see get_pipeline_barrier_scope)
         unsafe {
             self.device.handle.cmd_pipeline_barrier(
                 cmd,
-                scope.src_stage_mask,
-                scope.dst_stage_mask,
+                PipelineBarrierScope::legacy_stage_mask(scope.src_stage_mask),
+                PipelineBarrierScope::legacy_stage_mask(scope.dst_stage_mask),
                 scope.dependency_flags,
                 &[],
                 &[],
@@ -2134,100 +5649,577 @@ impl CopyQueue {
         }
     }
 
-    pub fn copy_buffer(&self, src: &Buffer, dst: &Buffer, region: vk::BufferCopy) -> Result<()> {
-        let cmd = self.get_per_thread_cmd()?;
+    // records a copy region to a cmd, with the resources acquired before and released after
+    fn cmd_copy(&self, cmd: vk::CommandBuffer, region: &CopyRegion) {
+        let stage = vk::PipelineStageFlags2::COPY;
+        let src_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireSrc, stage);
+        let dst_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireDst, stage);
+        let src_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseSrc, stage);
+        let dst_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseDst, stage);
+
+        match *region {
+            CopyRegion::Buffer(src, dst, region) => {
+                self.cmd_buffer_barrier(cmd, src.handle, src_acquire);
+                self.cmd_buffer_barrier(cmd, dst.handle, dst_acquire);
+
+                // SAFETY: no VUID violation
+                unsafe {
+                    self.device.handle.cmd_copy_buffer(
+                        cmd,
+                        src.handle,
+                        dst.handle,
+                        slice::from_ref(&region),
+                    );
+                }
+
+                self.cmd_buffer_barrier(cmd, src.handle, src_release);
+                self.cmd_buffer_barrier(cmd, dst.handle, dst_release);
+            }
+            CopyRegion::ImageToBuffer(img, buf, region) => {
+                let img_aspect = region.image_subresource.aspect_mask;
+                let img_layout = src_acquire.dst_image_layout;
+
+                self.cmd_image_barrier(cmd, img.handle, img_aspect, src_acquire);
+                self.cmd_buffer_barrier(cmd, buf.handle, dst_acquire);
+
+                // SAFETY: no VUID violation
+                unsafe {
+                    self.device.handle.cmd_copy_image_to_buffer(
+                        cmd,
+                        img.handle,
+                        img_layout,
+                        buf.handle,
+                        slice::from_ref(&region),
+                    );
+                }
+
+                self.cmd_image_barrier(cmd, img.handle, img_aspect, src_release);
+                self.cmd_buffer_barrier(cmd, buf.handle, dst_release);
+            }
+            CopyRegion::BufferToImage(buf, img, region) => {
+                let img_aspect = region.image_subresource.aspect_mask;
+                let img_layout = dst_acquire.dst_image_layout;
+
+                self.cmd_buffer_barrier(cmd, buf.handle, src_acquire);
+                self.cmd_image_barrier(cmd, img.handle, img_aspect, dst_acquire);
+
+                // SAFETY: no VUID violation
+                unsafe {
+                    self.device.handle.cmd_copy_buffer_to_image(
+                        cmd,
+                        buf.handle,
+                        img.handle,
+                        img_layout,
+                        slice::from_ref(&region),
+                    );
+                }
+
+                self.cmd_buffer_barrier(cmd, buf.handle, src_release);
+                self.cmd_image_barrier(cmd, img.handle, img_aspect, dst_release);
+            }
+            CopyRegion::Image(src, dst, region) => {
+                let src_aspect = region.src_subresource.aspect_mask;
+                let dst_aspect = region.dst_subresource.aspect_mask;
+                let src_layout = src_acquire.dst_image_layout;
+                let dst_layout = dst_acquire.dst_image_layout;
+
+                self.cmd_image_barrier(cmd, src.handle, src_aspect, src_acquire);
+                self.cmd_image_barrier(cmd, dst.handle, dst_aspect, dst_acquire);
+
+                // SAFETY: no VUID violation
+                unsafe {
+                    self.device.handle.cmd_copy_image(
+                        cmd,
+                        src.handle,
+                        src_layout,
+                        dst.handle,
+                        dst_layout,
+                        slice::from_ref(&region),
+                    );
+                }
+
+                self.cmd_image_barrier(cmd, src.handle, src_aspect, src_release);
+                self.cmd_image_barrier(cmd, dst.handle, dst_aspect, dst_release);
+            }
+        }
+    }
+
+    // Records all copy regions to a single cmd and submits it once.  The regions must agree on
+    // whether the copies are protected.
+    pub fn copy_batch(
+        &self,
+        regions: &[CopyRegion],
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        let mut protected = None;
+        for region in regions {
+            let (src_protected, dst_protected) = region.protection();
+            let region_protected = self.is_protected_copy(src_protected, dst_protected)?;
+            if *protected.get_or_insert(region_protected) != region_protected {
+                return Error::unsupported();
+            }
+        }
+        let Some(protected) = protected else {
+            return Error::user();
+        };
+
+        let cmd = self.get_cmd(protected)?;
+
+        // timestamps cannot be written in protected cmds
+        let profile = self.profiles.is_some() && !protected;
+        if profile {
+            cmd.begin_queries(regions.iter().map(CopyRegion::profile_key).collect())?;
+        }
+
+        for (idx, region) in regions.iter().enumerate() {
+            let query = idx as u32 * 2;
+            if profile {
+                cmd.write_timestamp(query);
+            }
+
+            self.cmd_copy(cmd.handle, region);
+
+            if profile {
+                cmd.write_timestamp(query + 1);
+            }
+        }
+
+        self.execute_cmd(cmd, sync_fd)
+    }
+
+    // Binds memories to or unbinds memories from regions of a sparse buffer, and waits for the
+    // binding on the cpu.
+    pub fn bind_sparse(&self, buf: vk::Buffer, binds: &[vk::SparseMemoryBind]) -> Result<()> {
+        let buf_bind = vk::SparseBufferMemoryBindInfo::default()
+            .buffer(buf)
+            .binds(binds);
+        let bind_info = vk::BindSparseInfo::default().buffer_binds(slice::from_ref(&buf_bind));
+
+        let fence_info = vk::FenceCreateInfo::default();
+        // SAFETY: no VUID violation
+        let fence = unsafe { self.device.handle.create_fence(&fence_info, None) }?;
+
+        // pending copies can use the memories that are about to be unbound
+        let unbind = binds
+            .iter()
+            .any(|bind| bind.memory == vk::DeviceMemory::null());
+        let res = self.submit_bind_sparse(&bind_info, fence, unbind);
+        if matches!(res, Err(Error::Device)) {
+            // the binding timed out and the fence is leaked
+            return res;
+        }
+
+        // SAFETY: no VUID violation unless the fence is still pending on errors
+        unsafe {
+            self.device.handle.destroy_fence(fence, None);
+        }
+
+        res
+    }
+
+    fn submit_bind_sparse(
+        &self,
+        bind_info: &vk::BindSparseInfo,
+        fence: vk::Fence,
+        unbind: bool,
+    ) -> Result<()> {
+        if unbind {
+            // copies on any queue can access the memory
+            for handle in &self.handles {
+                let queue = handle.lock().unwrap();
+                // SAFETY: no VUID violation
+                unsafe { self.device.handle.queue_wait_idle(*queue) }
+                    .map_err(|res| self.device.device_error(res))?;
+            }
+        }
 
-        let src_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireSrc);
-        let dst_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireDst);
-        let src_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseSrc);
-        let dst_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseDst);
+        let queue = self.queue().lock().unwrap();
 
-        self.cmd_buffer_barrier(cmd.handle, src.handle, src_acquire);
-        self.cmd_buffer_barrier(cmd.handle, dst.handle, dst_acquire);
+        // SAFETY: no VUID violation
+        unsafe {
+            self.device
+                .handle
+                .queue_bind_sparse(*queue, slice::from_ref(bind_info), fence)
+        }
+        .map_err(|res| self.device.device_error(res))?;
+        drop(queue);
 
         // SAFETY: no VUID violation
         unsafe {
-            self.device.handle.cmd_copy_buffer(
-                cmd.handle,
-                src.handle,
-                dst.handle,
-                slice::from_ref(&region),
-            );
+            self.device
+                .handle
+                .wait_for_fences(slice::from_ref(&fence), true, self.timeout_ns())
         }
+        .map_err(|res| match res {
+            vk::Result::TIMEOUT => Error::Device,
+            res => self.device.device_error(res),
+        })?;
+
+        Ok(())
+    }
 
-        self.cmd_buffer_barrier(cmd.handle, src.handle, src_release);
-        self.cmd_buffer_barrier(cmd.handle, dst.handle, dst_release);
+    // Transitions a newly allocated image from the UNDEFINED layout to the GENERAL layout and
+    // releases it to the foreign queue family, as assumed by get_pipeline_barrier_scope.  This
+    // waits for the transition on the cpu.
+    pub fn initialize_image(&self, img: &Image) -> Result<()> {
+        let protected = self.is_protected_copy(false, img.protected)?;
+        let cmd = self.get_cmd(protected)?;
+
+        let img_init = PipelineBarrierScope {
+            dependency_flags: vk::DependencyFlags::empty(),
+            src_queue_family: self.device.properties().queue_family,
+            src_stage_mask: vk::PipelineStageFlags2::ALL_COMMANDS,
+            src_access_mask: vk::AccessFlags2::NONE,
+            src_image_layout: vk::ImageLayout::UNDEFINED,
+            dst_queue_family: self.device.foreign_queue_family(),
+            dst_stage_mask: vk::PipelineStageFlags2::ALL_COMMANDS,
+            dst_access_mask: vk::AccessFlags2::NONE,
+            dst_image_layout: vk::ImageLayout::GENERAL,
+        };
+        self.cmd_image_barrier(
+            cmd.handle,
+            img.handle,
+            vk::ImageAspectFlags::COLOR,
+            img_init,
+        );
+
+        if let Some(sync_fd) = self.execute_cmd(cmd, None)? {
+            if !utils::poll_timeout(sync_fd, Access::Read, self.timeout)? {
+                return Error::device();
+            }
+        }
 
-        self.execute_per_thread_cmd(cmd)
+        Ok(())
     }
 
-    pub fn copy_image_to_buffer(
+    pub fn fill_buffer(
         &self,
-        img: &Image,
         buf: &Buffer,
-        region: vk::BufferImageCopy,
-    ) -> Result<()> {
-        let cmd = self.get_per_thread_cmd()?;
+        value: u32,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        let protected = self.is_protected_copy(false, buf.protected)?;
+        let cmd = self.get_cmd(protected)?;
 
-        let img_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireSrc);
-        let buf_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireDst);
-        let img_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseSrc);
-        let buf_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseDst);
-        let img_aspect = region.image_subresource.aspect_mask;
-        let img_layout = img_acquire.dst_image_layout;
+        let stage = vk::PipelineStageFlags2::CLEAR;
+        let buf_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireDst, stage);
+        let buf_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseDst, stage);
 
-        self.cmd_image_barrier(cmd.handle, img.handle, img_aspect, img_acquire);
         self.cmd_buffer_barrier(cmd.handle, buf.handle, buf_acquire);
 
         // SAFETY: no VUID violation
         unsafe {
-            self.device.handle.cmd_copy_image_to_buffer(
-                cmd.handle,
-                img.handle,
-                img_layout,
-                buf.handle,
-                slice::from_ref(&region),
-            );
+            self.device
+                .handle
+                .cmd_fill_buffer(cmd.handle, buf.handle, 0, vk::WHOLE_SIZE, value);
         }
 
-        self.cmd_image_barrier(cmd.handle, img.handle, img_aspect, img_release);
         self.cmd_buffer_barrier(cmd.handle, buf.handle, buf_release);
 
-        self.execute_per_thread_cmd(cmd)
+        self.execute_cmd(cmd, sync_fd)
     }
 
-    pub fn copy_buffer_to_image(
+    pub fn clear_image(
         &self,
-        buf: &Buffer,
         img: &Image,
-        region: vk::BufferImageCopy,
-    ) -> Result<()> {
-        let cmd = self.get_per_thread_cmd()?;
+        color: vk::ClearColorValue,
+        integer: bool,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        // the color is interpreted according to the numeric type of the format
+        let is_integer = formats::vk_numeric_type(img.format) != formats::NumericType::Float;
+        if is_integer != integer {
+            return Error::user();
+        }
+
+        let clear_flags = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE;
+        if !self.device.properties().queue_flags.intersects(clear_flags) {
+            return Error::unsupported();
+        }
+
+        let protected = self.is_protected_copy(false, img.protected)?;
+        let cmd = self.get_cmd(protected)?;
 
-        let buf_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireSrc);
-        let img_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireDst);
-        let buf_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseSrc);
-        let img_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseDst);
-        let img_aspect = region.image_subresource.aspect_mask;
+        let stage = vk::PipelineStageFlags2::CLEAR;
+        let img_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireDst, stage);
+        let img_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseDst, stage);
+        let img_aspect = vk::ImageAspectFlags::COLOR;
         let img_layout = img_acquire.dst_image_layout;
+        let img_subres = vk::ImageSubresourceRange::default()
+            .aspect_mask(img_aspect)
+            .level_count(1)
+            .layer_count(1);
 
-        self.cmd_buffer_barrier(cmd.handle, buf.handle, buf_acquire);
         self.cmd_image_barrier(cmd.handle, img.handle, img_aspect, img_acquire);
 
         // SAFETY: no VUID violation
         unsafe {
-            self.device.handle.cmd_copy_buffer_to_image(
+            self.device.handle.cmd_clear_color_image(
                 cmd.handle,
-                buf.handle,
                 img.handle,
                 img_layout,
-                slice::from_ref(&region),
+                &color,
+                slice::from_ref(&img_subres),
             );
         }
 
-        self.cmd_buffer_barrier(cmd.handle, buf.handle, buf_release);
         self.cmd_image_barrier(cmd.handle, img.handle, img_aspect, img_release);
 
-        self.execute_per_thread_cmd(cmd)
+        self.execute_cmd(cmd, sync_fd)
+    }
+
+    pub fn blit_image(
+        &self,
+        src: &Image,
+        dst: &Image,
+        region: vk::ImageBlit,
+        filter: Filter,
+        sync_fd: Option<OwnedFd>,
+    ) -> Result<Option<OwnedFd>> {
+        if !is_blit_compatible(src.format, dst.format) {
+            return Error::user();
+        }
+
+        if !self
+            .device
+            .properties()
+            .queue_flags
+            .contains(vk::QueueFlags::GRAPHICS)
+        {
+            return Error::unsupported();
+        }
+
+        let (filter, mut src_feats) = match filter {
+            Filter::Nearest => (vk::Filter::NEAREST, vk::FormatFeatureFlags2::BLIT_SRC),
+            Filter::Linear => (
+                vk::Filter::LINEAR,
+                vk::FormatFeatureFlags2::BLIT_SRC
+                    | vk::FormatFeatureFlags2::SAMPLED_IMAGE_FILTER_LINEAR,
+            ),
+        };
+        src_feats &= !self.device.format_features(src.format, src.modifier);
+        let dst_feats = vk::FormatFeatureFlags2::BLIT_DST
+            & !self.device.format_features(dst.format, dst.modifier);
+        if !src_feats.is_empty() || !dst_feats.is_empty() {
+            return Error::unsupported();
+        }
+
+        let protected = self.is_protected_copy(src.protected, dst.protected)?;
+        let cmd = self.get_cmd(protected)?;
+
+        let stage = vk::PipelineStageFlags2::BLIT;
+        let src_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireSrc, stage);
+        let dst_acquire = self.get_pipeline_barrier_scope(PipelineBarrierType::AcquireDst, stage);
+        let src_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseSrc, stage);
+        let dst_release = self.get_pipeline_barrier_scope(PipelineBarrierType::ReleaseDst, stage);
+        let aspect = vk::ImageAspectFlags::COLOR;
+        let src_layout = src_acquire.dst_image_layout;
+        let dst_layout = dst_acquire.dst_image_layout;
+
+        self.cmd_image_barrier(cmd.handle, src.handle, aspect, src_acquire);
+        self.cmd_image_barrier(cmd.handle, dst.handle, aspect, dst_acquire);
+
+        // SAFETY: no VUID violation
+        unsafe {
+            self.device.handle.cmd_blit_image(
+                cmd.handle,
+                src.handle,
+                src_layout,
+                dst.handle,
+                dst_layout,
+                slice::from_ref(&region),
+                filter,
+            );
+        }
+
+        self.cmd_image_barrier(cmd.handle, src.handle, aspect, src_release);
+        self.cmd_image_barrier(cmd.handle, dst.handle, aspect, dst_release);
+
+        self.execute_cmd(cmd, sync_fd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slab() {
+        let mut slab = Slab::new(vk::DeviceMemory::null(), 0, None);
+        assert!(slab.is_empty());
+
+        assert_eq!(slab.alloc(100, 64), Some(0));
+        assert_eq!(slab.alloc(64, 256), Some(256));
+        assert_eq!(slab.free_ranges, [(100, 156), (320, SLAB_SIZE - 320)]);
+        assert_eq!(slab.alloc(SLAB_SIZE, 1), None);
+        assert_eq!(slab.free_size(), SLAB_SIZE - 164);
+
+        slab.free(0, 100);
+        assert_eq!(slab.free_ranges, [(0, 256), (320, SLAB_SIZE - 320)]);
+        slab.free(256, 64);
+        assert!(slab.is_empty());
+    }
+
+    #[test]
+    fn test_legacy_barrier_masks() {
+        let legacy = |mask| PipelineBarrierScope::legacy_stage_mask(mask);
+        assert_eq!(
+            legacy(vk::PipelineStageFlags2::COPY),
+            vk::PipelineStageFlags::TRANSFER
+        );
+        assert_eq!(
+            legacy(vk::PipelineStageFlags2::BLIT | vk::PipelineStageFlags2::CLEAR),
+            vk::PipelineStageFlags::TRANSFER
+        );
+        assert_eq!(
+            legacy(vk::PipelineStageFlags2::ALL_COMMANDS),
+            vk::PipelineStageFlags::ALL_COMMANDS
+        );
+        assert_eq!(
+            PipelineBarrierScope::legacy_access_mask(vk::AccessFlags2::TRANSFER_WRITE),
+            vk::AccessFlags::TRANSFER_WRITE
+        );
+    }
+
+    #[test]
+    fn test_format_features2() {
+        assert_eq!(
+            format_features2(
+                vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::DISJOINT
+            ),
+            vk::FormatFeatureFlags2::SAMPLED_IMAGE | vk::FormatFeatureFlags2::DISJOINT
+        );
+        assert_eq!(
+            format_features2(vk::FormatFeatureFlags::COSITED_CHROMA_SAMPLES),
+            vk::FormatFeatureFlags2::COSITED_CHROMA_SAMPLES
+        );
+    }
+
+    #[test]
+    fn test_is_blit_compatible() {
+        assert!(is_blit_compatible(
+            vk::Format::R8G8B8A8_UNORM,
+            vk::Format::R16G16B16A16_SFLOAT
+        ));
+        assert!(is_blit_compatible(
+            vk::Format::R8_UINT,
+            vk::Format::R32_UINT
+        ));
+        assert!(!is_blit_compatible(
+            vk::Format::R8_UINT,
+            vk::Format::R8_UNORM
+        ));
+        assert!(!is_blit_compatible(
+            vk::Format::R8_UINT,
+            vk::Format::R8_SINT
+        ));
+    }
+
+    #[test]
+    fn test_probe_cache() {
+        let mod_props = vk::DrmFormatModifierProperties2EXT {
+            drm_format_modifier: formats::MOD_LINEAR.0,
+            drm_format_modifier_plane_count: 1,
+            drm_format_modifier_tiling_features: vk::FormatFeatureFlags2::SAMPLED_IMAGE
+                | vk::FormatFeatureFlags2::HOST_IMAGE_TRANSFER_EXT,
+        };
+        let entries = [
+            (vk::Format::R8_UNORM, Some(slice::from_ref(&mod_props))),
+            (vk::Format::R16G16B16A16_SFLOAT, None),
+        ];
+        let text = serialize_probe_cache("key", entries.into_iter());
+
+        let parsed = parse_probe_cache(&text, "key").unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].0, vk::Format::R8_UNORM);
+        let mods = parsed[0].1.as_ref().unwrap();
+        assert_eq!(mods.len(), 1);
+        assert_eq!(mods[0].drm_format_modifier, mod_props.drm_format_modifier);
+        assert_eq!(mods[0].drm_format_modifier_plane_count, 1);
+        assert_eq!(
+            mods[0].drm_format_modifier_tiling_features,
+            mod_props.drm_format_modifier_tiling_features
+        );
+        assert_eq!(parsed[1].0, vk::Format::R16G16B16A16_SFLOAT);
+        assert!(parsed[1].1.is_none());
+
+        // stale or malformed caches are ignored
+        assert!(parse_probe_cache(&text, "other key").is_none());
+        assert!(parse_probe_cache(&format!("{text}9 zz:1:0\n"), "key").is_none());
+        assert!(parse_probe_cache("", "key").is_none());
+    }
+
+    #[test]
+    fn test_align_layout() {
+        // a 100x64 NV12 image with 100-byte strides
+        let mut layout = Layout::new()
+            .size(100 * 96)
+            .modifier(formats::MOD_LINEAR)
+            .plane_count(2);
+        layout.offsets[1] = 100 * 64;
+        layout.strides[..2].copy_from_slice(&[100, 100]);
+
+        let con = Constraint::new().stride_align(64).offset_align(4096);
+        assert!(!layout.fit(Some(con.clone())));
+
+        let aligned = align_layout(&layout, &con).unwrap();
+        assert!(aligned.fit(Some(con.clone())));
+        assert_eq!(aligned.offsets[..2], [0, 8192]);
+        assert_eq!(aligned.strides[..2], [128, 128]);
+        assert_eq!(aligned.size, 8192 + 128 * 32);
+
+        // the tiling rules of other modifiers are unknown
+        let tiled = Layout {
+            modifier: Modifier(1),
+            ..layout.clone()
+        };
+        assert!(align_layout(&tiled, &con).is_err());
+
+        // unordered memory planes
+        layout.offsets[1] = 0;
+        layout.offsets[0] = 100 * 32;
+        assert!(align_layout(&layout, &Constraint::new()).is_err());
+    }
+
+    #[test]
+    fn test_debug_filter() {
+        let filter = DebugFilter {
+            severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            types: vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            suppressed_ids: vec!["VUID-benign".to_string()],
+        };
+
+        let err = vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+        let info = vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
+        let validation = vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION;
+        let perf = vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE;
+
+        assert!(filter.allows(err, validation, None));
+        assert!(filter.allows(err, validation, Some("VUID-other")));
+        assert!(!filter.allows(err, validation, Some("VUID-benign")));
+        assert!(!filter.allows(info, validation, None));
+        assert!(!filter.allows(err, perf, None));
+    }
+
+    #[test]
+    fn test_shared_instance() {
+        // the vulkan loader might be missing
+        let Ok(instance) = Instance::shared("hbm-test", false) else {
+            return;
+        };
+
+        let other = Instance::shared("hbm-test", false).unwrap();
+        assert!(Arc::ptr_eq(&instance, &other));
+
+        if let Ok(other) = Instance::shared("hbm-test-other", false) {
+            assert!(!Arc::ptr_eq(&instance, &other));
+        }
+
+        // the instance is not kept alive by the registry
+        let weak = Arc::downgrade(&instance);
+        drop(instance);
+        drop(other);
+        assert!(weak.upgrade().is_none());
     }
 }
@@ -26,6 +26,12 @@ pub enum Error {
     /// A runtime device error that may or may no be persistent.
     #[error("device error")]
     Device,
+    /// Indicates the device limit on the number of memory allocations has been reached.
+    #[error("too many memory allocations")]
+    TooManyAllocations,
+    /// Indicates the device is out of memory.
+    #[error("out of device memory")]
+    OutOfDeviceMemory,
     #[error("{0}")]
     /// A generic IO error.
     Io(#[from] io::Error),
@@ -41,6 +47,7 @@ pub enum Error {
 }
 
 impl Error {
+    #[cfg(feature = "ash")]
     pub(crate) fn ctx<T>(s: &'static str) -> Result<T> {
         Err(Error::Context(s))
     }
@@ -83,7 +90,10 @@ impl From<nix::Error> for Error {
 #[cfg(feature = "ash")]
 impl From<ash::vk::Result> for Error {
     fn from(err: ash::vk::Result) -> Self {
-        Self::Code(err.as_raw())
+        match err {
+            ash::vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => Self::OutOfDeviceMemory,
+            err => Self::Code(err.as_raw()),
+        }
     }
 }
 
@@ -109,12 +119,9 @@ impl Default for Format {
     }
 }
 
-impl<T> From<T> for Format
-where
-    T: Into<u32>,
-{
-    fn from(val: T) -> Self {
-        Self(val.into())
+impl From<u32> for Format {
+    fn from(val: u32) -> Self {
+        Self(val)
     }
 }
 
@@ -128,6 +135,23 @@ impl fmt::Display for Format {
     }
 }
 
+// every DrmFourcc is a valid format, and TryFrom is provided by the From impl
+#[cfg(feature = "drm-fourcc")]
+impl From<drm_fourcc::DrmFourcc> for Format {
+    fn from(fmt: drm_fourcc::DrmFourcc) -> Self {
+        Self(fmt as u32)
+    }
+}
+
+#[cfg(feature = "drm-fourcc")]
+impl TryFrom<Format> for drm_fourcc::DrmFourcc {
+    type Error = drm_fourcc::UnrecognizedFourcc;
+
+    fn try_from(fmt: Format) -> result::Result<Self, Self::Error> {
+        Self::try_from(fmt.0)
+    }
+}
+
 /// A 64-bit DRM format modifier.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Modifier(pub u64);
@@ -157,10 +181,18 @@ where
     }
 }
 
+// DrmModifier implements Into<u64> and is handled by the generic From impl above
+#[cfg(feature = "drm-fourcc")]
+impl From<Modifier> for drm_fourcc::DrmModifier {
+    fn from(modifier: Modifier) -> Self {
+        Self::from(modifier.0)
+    }
+}
+
 /// An access type for memory mapping.
+#[derive(Clone, Copy)]
 pub(crate) enum Access {
     Read,
-    #[allow(dead_code)]
     Write,
     ReadWrite,
 }
@@ -207,4 +239,24 @@ mod tests {
     fn test_modifier() {
         assert_eq!(Modifier::default(), formats::MOD_INVALID);
     }
+
+    #[cfg(feature = "drm-fourcc")]
+    #[test]
+    fn test_drm_fourcc() {
+        use drm_fourcc::{DrmFourcc, DrmModifier};
+
+        let fmt = Format::from(DrmFourcc::R8);
+        assert_eq!(fmt, formats::R8);
+        assert_eq!(Format::from(DrmFourcc::Nv12), formats::NV12);
+        assert_eq!(DrmFourcc::try_from(fmt).unwrap(), DrmFourcc::R8);
+        assert!(DrmFourcc::try_from(formats::INVALID).is_err());
+
+        let modifier = Modifier::from(DrmModifier::Linear);
+        assert_eq!(modifier, formats::MOD_LINEAR);
+        assert_eq!(DrmModifier::from(modifier), DrmModifier::Linear);
+        assert_eq!(
+            DrmModifier::from(formats::MOD_INVALID),
+            DrmModifier::Invalid
+        );
+    }
 }
@@ -10,8 +10,10 @@ use nix::{fcntl, poll, sys, unistd};
 use std::ffi::CString;
 use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
 use std::path::Path;
-use std::{num, slice};
+use std::time::Duration;
+use std::{ffi, num, ptr, slice};
 
+#[cfg(feature = "ash")]
 pub fn makedev(major: u64, minor: u64) -> u64 {
     libc::makedev(major as _, minor as _) as u64
 }
@@ -28,19 +30,42 @@ pub fn open(path: impl AsRef<Path>) -> Result<OwnedFd> {
     Ok(owned_fd)
 }
 
+// returns the time of CLOCK_MONOTONIC, which is also the clock of sync file timestamps
+pub fn monotonic_time() -> Duration {
+    nix::time::clock_gettime(nix::time::ClockId::CLOCK_MONOTONIC)
+        .map_or(Duration::ZERO, Duration::from)
+}
+
 pub fn seek_end(fd: impl AsFd) -> Result<Size> {
     let offset = unistd::lseek(fd.as_fd().as_raw_fd(), 0, unistd::Whence::SeekEnd)?;
     Ok(offset.try_into()?)
 }
 
-pub fn mmap(fd: impl AsFd, size: Size, access: Access) -> Result<Mapping> {
+#[cfg(feature = "v4l2")]
+pub fn same_file(fd1: impl AsFd, fd2: impl AsFd) -> Result<bool> {
+    let st1 = sys::stat::fstat(fd1.as_fd().as_raw_fd())?;
+    let st2 = sys::stat::fstat(fd2.as_fd().as_raw_fd())?;
+    Ok(st1.st_dev == st2.st_dev && st1.st_ino == st2.st_ino)
+}
+
+// when addr is set, the mapping is placed at addr and replaces any existing mapping there
+pub fn mmap(
+    fd: impl AsFd,
+    size: Size,
+    access: Access,
+    addr: Option<ptr::NonNull<ffi::c_void>>,
+) -> Result<Mapping> {
     let prot = access.into();
-    let flags = sys::mman::MapFlags::MAP_SHARED;
+    let mut flags = sys::mman::MapFlags::MAP_SHARED;
+    if addr.is_some() {
+        flags |= sys::mman::MapFlags::MAP_FIXED;
+    }
 
+    let addr = addr.and_then(|addr| num::NonZeroUsize::new(addr.as_ptr() as usize));
     let len = num::NonZeroUsize::try_from(usize::try_from(size)?)?;
     let ptr =
         // SAFETY: clients assume the responsibility
-        unsafe { sys::mman::mmap(None, len, prot, flags, fd, 0) }?;
+        unsafe { sys::mman::mmap(addr, len, prot, flags, fd, 0) }?;
 
     Ok(Mapping { ptr, len })
 }
@@ -50,23 +75,47 @@ pub fn munmap(mapping: Mapping) -> Result<()> {
     unsafe { sys::mman::munmap(mapping.ptr, mapping.len.into()) }.map_err(Error::from)
 }
 
+// replaces a mapping by an inaccessible mapping, such that the address range stays reserved
+pub fn munmap_reserve(mapping: Mapping) -> Result<()> {
+    let prot = sys::mman::ProtFlags::PROT_NONE;
+    let flags = sys::mman::MapFlags::MAP_PRIVATE
+        | sys::mman::MapFlags::MAP_FIXED
+        | sys::mman::MapFlags::MAP_NORESERVE;
+    let addr = num::NonZeroUsize::new(mapping.ptr.as_ptr() as usize);
+
+    // SAFETY: ptr and len are from sys::mman::mmap
+    unsafe { sys::mman::mmap_anonymous(addr, mapping.len, prot, flags) }?;
+
+    Ok(())
+}
+
 pub fn poll(fd: impl AsFd, access: Access) -> Result<()> {
-    let timeout = poll::PollTimeout::NONE;
+    let ready = poll_timeout(fd, access, None)?;
+    // this should always be ready because we don't have a timeout
+    assert!(ready);
+
+    Ok(())
+}
+
+// returns false on timeout
+pub fn poll_timeout(fd: impl AsFd, access: Access, timeout: Option<Duration>) -> Result<bool> {
+    let timeout = timeout.map_or(poll::PollTimeout::NONE, |timeout| {
+        poll::PollTimeout::try_from(timeout).unwrap_or(poll::PollTimeout::MAX)
+    });
 
     let events = access.into();
     loop {
         let mut poll_fd = poll::PollFd::new(fd.as_fd(), events);
 
         match poll::poll(slice::from_mut(&mut poll_fd), timeout) {
-            Ok(ret) => {
-                // ret should always be positive because we don't have a timeout
-                assert!(ret > 0);
+            Ok(0) => return Ok(false),
+            Ok(_) => {
                 let revents = poll_fd.revents().unwrap_or(poll::PollFlags::POLLNVAL);
                 if revents.intersects(events.complement()) {
                     return Error::errno(nix::Error::EINVAL);
                 }
 
-                return Ok(());
+                return Ok(true);
             }
             Err(nix::Error::EINTR) | Err(nix::Error::EAGAIN) => continue,
             Err(err) => {
@@ -111,10 +160,22 @@ mod dma_buf {
         pub flags: u64,
     }
 
+    #[repr(C)]
+    struct dma_buf_export_sync_file {
+        pub flags: u32,
+        pub fd: i32,
+    }
+
     const DMA_BUF_BASE: u8 = b'b';
 
     nix::ioctl_write_ptr!(dma_buf_ioctl_sync, DMA_BUF_BASE, 0, dma_buf_sync);
     nix::ioctl_write_ptr!(dma_buf_ioctl_set_name, DMA_BUF_BASE, 1, u64);
+    nix::ioctl_readwrite!(
+        dma_buf_ioctl_export_sync_file,
+        DMA_BUF_BASE,
+        2,
+        dma_buf_export_sync_file
+    );
 
     pub fn dma_buf_sync(dmabuf: impl AsFd, access: Access, start: bool) -> Result<()> {
         let flags = match access {
@@ -145,6 +206,23 @@ mod dma_buf {
         }
     }
 
+    pub fn dma_buf_export_sync_file(dmabuf: impl AsFd, access: Access) -> Result<OwnedFd> {
+        let flags = match access {
+            Access::Read => DMA_BUF_SYNC_READ,
+            Access::Write => DMA_BUF_SYNC_WRITE,
+            Access::ReadWrite => DMA_BUF_SYNC_READ | DMA_BUF_SYNC_WRITE,
+        } as u32;
+
+        let dmabuf = dmabuf.as_fd().as_raw_fd();
+        let mut arg = dma_buf_export_sync_file { flags, fd: -1 };
+        // SAFETY: dmabuf and arg are valid
+        unsafe { dma_buf_ioctl_export_sync_file(dmabuf, &mut arg) }?;
+
+        // SAFETY: arg.fd is valid
+        let sync_fd = unsafe { OwnedFd::from_raw_fd(arg.fd) };
+        Ok(sync_fd)
+    }
+
     pub fn dma_buf_set_name(dmabuf: impl AsFd, name: &str) -> Result<()> {
         let dmabuf = dmabuf.as_fd().as_raw_fd();
         let c_name = CString::new(name)?;
@@ -156,7 +234,69 @@ mod dma_buf {
     }
 }
 
-pub use dma_buf::{dma_buf_set_name, dma_buf_sync};
+pub use dma_buf::{dma_buf_export_sync_file, dma_buf_set_name, dma_buf_sync};
+
+// Based on
+//
+//   $ bindgen --no-doc-comments --no-layout-tests \
+//       --allowlist-item '(sync|SYNC)_.*' \
+//       /usr/include/linux/sync_file.h
+mod sync_file {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct sync_fence_info {
+        obj_name: [u8; 32],
+        driver_name: [u8; 32],
+        status: i32,
+        flags: u32,
+        timestamp_ns: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct sync_file_info {
+        name: [u8; 32],
+        status: i32,
+        flags: u32,
+        num_fences: u32,
+        pad: u32,
+        sync_fence_info: u64,
+    }
+
+    const SYNC_IOC_MAGIC: u8 = b'>';
+
+    nix::ioctl_readwrite!(sync_ioc_file_info, SYNC_IOC_MAGIC, 4, sync_file_info);
+
+    // Returns the CLOCK_MONOTONIC time when a sync file signaled, or None if it has not signaled.
+    pub fn sync_file_timestamp(sync_fd: impl AsFd) -> Result<Option<Duration>> {
+        let sync_fd = sync_fd.as_fd().as_raw_fd();
+
+        // query the status and the fence count
+        let mut info = sync_file_info::default();
+        // SAFETY: sync_fd and info are valid
+        unsafe { sync_ioc_file_info(sync_fd, &mut info) }?;
+        match info.status {
+            0 => return Ok(None),
+            1 => (),
+            _ => return Error::device(),
+        }
+
+        let mut fences: Vec<sync_fence_info> =
+            (0..info.num_fences).map(|_| Default::default()).collect();
+        info.sync_fence_info = fences.as_mut_ptr() as u64;
+        // SAFETY: sync_fd and info are valid, and fences has room for num_fences fences
+        unsafe { sync_ioc_file_info(sync_fd, &mut info) }?;
+
+        // a sync file signals when its last fence signals
+        let timestamp = fences.iter().map(|fence| fence.timestamp_ns).max();
+
+        Ok(timestamp.map(Duration::from_nanos))
+    }
+}
+
+pub use sync_file::sync_file_timestamp;
 
 // Based on
 //
@@ -303,6 +443,19 @@ mod drm {
         modifier: u64,
     }
 
+    #[cfg(feature = "drm-legacy")]
+    #[repr(C)]
+    struct drm_gem_flink {
+        handle: u32,
+        name: u32,
+    }
+
+    #[cfg(feature = "drm-legacy")]
+    const DRM_IOCTL_BASE: u8 = b'd';
+
+    #[cfg(feature = "drm-legacy")]
+    nix::ioctl_readwrite!(drm_ioctl_gem_flink, DRM_IOCTL_BASE, 0x0a, drm_gem_flink);
+
     pub const DRM_DIR_NAME: &str = "/dev/dri";
     pub const DRM_PRIMARY_MINOR_NAME: &str = "card";
 
@@ -342,7 +495,7 @@ mod drm {
         }
     }
 
-    pub fn drm_parse_in_formats_blob(blob: &[u8]) -> Result<InFormatsIter> {
+    pub fn drm_parse_in_formats_blob(blob: &[u8]) -> Result<InFormatsIter<'_>> {
         let hdr_size = mem::size_of::<drm_format_modifier_blob>();
         if hdr_size > blob.len() {
             return Error::user();
@@ -390,6 +543,17 @@ mod drm {
         Ok(iter)
     }
 
+    #[cfg(feature = "drm-legacy")]
+    pub fn drm_gem_flink(drm_fd: impl AsFd, handle: u32) -> Result<u32> {
+        let mut arg = drm_gem_flink { handle, name: 0 };
+
+        let drm_fd = drm_fd.as_fd().as_raw_fd();
+        // SAFETY: drm_fd and arg are valid
+        unsafe { drm_ioctl_gem_flink(drm_fd, &mut arg) }?;
+
+        Ok(arg.name)
+    }
+
     pub fn drm_scan_primary() -> Result<impl Iterator<Item = PathBuf>> {
         let primary_iter = fs::read_dir(DRM_DIR_NAME)?.filter_map(|entry| {
             if let Ok(entry) = entry {
@@ -506,5 +670,7 @@ mod drm {
     }
 }
 
+#[cfg(feature = "drm-legacy")]
+pub use drm::drm_gem_flink;
 #[cfg(feature = "drm")]
 pub use drm::{drm_exists, drm_parse_in_formats_blob, drm_scan_primary};
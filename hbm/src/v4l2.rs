@@ -0,0 +1,237 @@
+// Copyright 2024 Google LLC
+// SPDX-License-Identifier: MIT
+
+//! V4L2 buffer helpers.
+//!
+//! This module provides helpers to import V4L2 buffers of the multi-planar API.  A dequeued
+//! `v4l2_buffer` and its `v4l2_plane`s are converted to a `Description`, an `Extent`, a `Layout`,
+//! and the dma-bufs.  Middleware written in Rust can use the converted values with
+//! `Bo::with_layout` and `Bo::bind_memory` (or `Bo::bind_memory_planes`) to import buffers from
+//! cameras and video decoders.
+
+use super::backends::{Description, Extent, Flags, Layout};
+use super::formats;
+use super::types::{Error, Format, Result, Size};
+use super::utils;
+use std::os::fd::{BorrowedFd, OwnedFd};
+
+/// A V4L2 plane.
+///
+/// This combines the relevant fields of `v4l2_plane` and `v4l2_plane_pix_format`.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane<'a> {
+    /// The dma-buf of the plane (`m.fd`).
+    pub fd: BorrowedFd<'a>,
+    /// The number of bytes used by the plane, including `data_offset` (`bytesused`).
+    ///
+    /// When 0, the plane is assumed to extend to the end of the dma-buf.
+    pub bytesused: u32,
+    /// The offset of the data from the start of the dma-buf (`data_offset`).
+    pub data_offset: u32,
+    /// The row stride of the plane (`bytesperline`).
+    pub bytesperline: u32,
+}
+
+/// An imported V4L2 buffer.
+#[derive(Debug)]
+pub struct Buffer {
+    /// Description of the BO.
+    pub desc: Description,
+    /// Extent of the BO.
+    pub extent: Extent,
+    /// Physical layout of the BO.
+    pub layout: Layout,
+    /// The dma-bufs of the BO.
+    ///
+    /// There is a single dma-buf, to be bound with `Bo::bind_memory`, unless the V4L2 planes have
+    /// their own dma-bufs.  Then there is a dma-buf for each memory plane, to be bound with
+    /// `Bo::bind_memory_planes`.
+    pub dmabufs: Vec<OwnedFd>,
+}
+
+/// Converts the V4L2 planes of a buffer.
+///
+/// `planes` has either a single plane that contains all format planes (e.g.,
+/// `V4L2_PIX_FMT_NV12`), or one plane per format plane (e.g., `V4L2_PIX_FMT_NV12M`).  In the
+/// former case, the strides of the chroma planes are derived from `bytesperline` of the luma
+/// plane.  In the latter case, the planes can share a dma-buf or have their own dma-bufs.  When
+/// they have their own dma-bufs, the offsets are relative to the dma-bufs, the layout size is the
+/// total size of the dma-bufs, and the BO must support disjoint memory planes.
+///
+/// The dma-bufs are duplicated and the caller keeps the ownership of the fds.
+pub fn import(fmt: Format, width: u32, height: u32, planes: &[Plane]) -> Result<Buffer> {
+    let fmt_class = formats::format_class(fmt)?;
+    let plane_count = fmt_class.plane_count as usize;
+    if width == 0 || height == 0 || (planes.len() != 1 && planes.len() != plane_count) {
+        return Error::user();
+    }
+
+    // the planes have their own dma-bufs unless they all share the first one
+    let mut disjoint = false;
+    for plane in &planes[1..] {
+        disjoint |= !utils::same_file(planes[0].fd, plane.fd)?;
+    }
+
+    let fd_planes = if disjoint { planes } else { &planes[..1] };
+    let mut dmabufs = Vec::with_capacity(fd_planes.len());
+    let mut sizes = Vec::with_capacity(fd_planes.len());
+    for plane in fd_planes {
+        let dmabuf = plane.fd.try_clone_to_owned()?;
+        sizes.push(utils::seek_end(&dmabuf)?);
+        dmabufs.push(dmabuf);
+    }
+
+    let mut layout = Layout::new()
+        .size(sizes.iter().sum())
+        .modifier(formats::MOD_LINEAR)
+        .plane_count(plane_count as u32);
+
+    let contiguous = planes.len() == 1;
+    let mut next_offset = planes[0].data_offset as Size;
+    for idx in 0..plane_count {
+        let bpp = fmt_class.block_size[idx] as Size;
+        let (block_width, block_height) = fmt_class.block_extent[idx];
+        let row_size = width.div_ceil(block_width as u32) as Size * bpp;
+        let row_count = height.div_ceil(block_height as u32) as Size;
+
+        let (plane, offset, stride) = if contiguous {
+            let plane = &planes[0];
+            // scale the chroma strides by their bytes per pixel relative to the luma plane
+            let stride = if idx == 0 {
+                plane.bytesperline as Size
+            } else {
+                let luma_width = fmt_class.block_extent[0].0 as Size;
+                let luma_bpp = fmt_class.block_size[0] as Size;
+                plane.bytesperline as Size * bpp * luma_width / (luma_bpp * block_width as Size)
+            };
+            (plane, next_offset, stride)
+        } else {
+            let plane = &planes[idx];
+            (plane, plane.data_offset as Size, plane.bytesperline as Size)
+        };
+
+        let size = sizes[if disjoint { idx } else { 0 }];
+        let used = if plane.bytesused != 0 {
+            plane.bytesused as Size
+        } else {
+            size
+        };
+        if stride < row_size || plane.data_offset as Size > used || used > size {
+            return Error::user();
+        }
+
+        let end = stride
+            .checked_mul(row_count - 1)
+            .and_then(|s| s.checked_add(offset))
+            .and_then(|s| s.checked_add(row_size))
+            .ok_or(Error::User)?;
+        if end > used {
+            return Error::user();
+        }
+
+        layout = layout.offset(idx, offset).stride(idx, stride);
+        next_offset = offset + stride * row_count;
+    }
+
+    let desc = Description::new()
+        .flags(Flags::EXTERNAL)
+        .format(fmt)
+        .modifier(formats::MOD_LINEAR);
+
+    Ok(Buffer {
+        desc,
+        extent: Extent::Image(width, height),
+        layout,
+        dmabufs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsFd;
+
+    #[test]
+    fn test_import() {
+        let memfd = utils::memfd_create("v4l2", 8192).unwrap();
+        let fd = memfd.as_fd();
+
+        let plane = Plane {
+            fd,
+            bytesused: 64 * 32 * 3 / 2,
+            data_offset: 0,
+            bytesperline: 64,
+        };
+        let buf = import(formats::NV12, 64, 32, &[plane]).unwrap();
+        assert!(matches!(buf.extent, Extent::Image(64, 32)));
+        assert_eq!(buf.layout.size, 8192);
+        assert_eq!(buf.layout.plane_count, 2);
+        assert_eq!(buf.layout.offsets[..2], [0, 2048]);
+        assert_eq!(buf.layout.strides[..2], [64, 64]);
+
+        let short = Plane {
+            bytesused: 64 * 32,
+            ..plane
+        };
+        assert!(import(formats::NV12, 64, 32, &[short]).is_err());
+        let narrow = Plane {
+            bytesperline: 32,
+            ..plane
+        };
+        assert!(import(formats::NV12, 64, 32, &[narrow]).is_err());
+
+        let packed = Plane {
+            fd,
+            bytesused: 64 * 2 * 32,
+            data_offset: 0,
+            bytesperline: 64 * 2,
+        };
+        let buf = import(formats::YUYV, 64, 32, &[packed]).unwrap();
+        assert_eq!(buf.layout.plane_count, 1);
+        assert_eq!(buf.layout.strides[0], 128);
+        let narrow = Plane {
+            bytesperline: 64,
+            ..packed
+        };
+        assert!(import(formats::YUYV, 64, 32, &[narrow]).is_err());
+
+        let luma = Plane {
+            fd,
+            bytesused: 2048,
+            data_offset: 0,
+            bytesperline: 64,
+        };
+        let chroma = Plane {
+            fd,
+            bytesused: 8192,
+            data_offset: 4096,
+            bytesperline: 128,
+        };
+        let buf = import(formats::NV12, 64, 32, &[luma, chroma]).unwrap();
+        assert_eq!(buf.layout.offsets[..2], [0, 4096]);
+        assert_eq!(buf.layout.strides[..2], [64, 128]);
+
+        assert_eq!(buf.dmabufs.len(), 1);
+
+        // the planes have their own dma-bufs
+        let other = utils::memfd_create("v4l2", 4096).unwrap();
+        let chroma = Plane {
+            fd: other.as_fd(),
+            bytesused: 0,
+            data_offset: 1024,
+            bytesperline: 64,
+        };
+        let buf = import(formats::NV12, 64, 32, &[luma, chroma]).unwrap();
+        assert_eq!(buf.dmabufs.len(), 2);
+        assert_eq!(buf.layout.size, 8192 + 4096);
+        assert_eq!(buf.layout.offsets[..2], [0, 1024]);
+        assert_eq!(buf.layout.strides[..2], [64, 64]);
+
+        // the chroma plane exceeds its own dma-buf
+        let chroma = Plane {
+            data_offset: 3584,
+            ..chroma
+        };
+        assert!(import(formats::NV12, 64, 32, &[luma, chroma]).is_err());
+    }
+}